@@ -1,16 +1,23 @@
 use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT};
 use std::process::Command;
-use tauri::State;
+use tauri::{AppHandle, Emitter, State};
 
+use crate::error::{AppError, AppResult};
 use crate::http::BridgeState;
+use crate::importers::{self, IMPORT_PROGRESS_EVENT};
 use crate::models::{
-    Attachment, ConversationDetail, Folder, ImportBatch, ImportResult, ListConversationsInput,
-    LiveCaptureRequest, SearchResult, SessionResponse,
+    AppSettings, ApplyImportRulesInput, ApplyImportRulesReport, ArchiveImportInput, AssetGcInput,
+    AssetGcReport, AssetVerifyReport, Attachment, ConversationCounts, ConversationDetail,
+    ConversationPage, ConversationRevision, ConversationRevisionDetail, ConversationSummary,
+    DownloadQueueStatus, Folder, ImportBatch, ImportResult, ImportRule, ImportRuleInput,
+    ListConversationsInput, LiveCaptureRequest, Message, PurgeResult, RevisionDiff, SearchInput,
+    SearchPage, SemanticIndexReport, SemanticSearchInput, SessionResponse, SmartFolderInput,
+    StorageReport, Tag, VerifyAssetsInput,
 };
 use crate::AppState;
 
 #[tauri::command]
-pub async fn list_folders(state: State<'_, AppState>) -> Result<Vec<Folder>, String> {
+pub async fn list_folders(state: State<'_, AppState>) -> AppResult<Vec<Folder>> {
     state.db.list_folders()
 }
 
@@ -19,7 +26,7 @@ pub async fn create_folder(
     state: State<'_, AppState>,
     name: String,
     parent_id: Option<String>,
-) -> Result<Folder, String> {
+) -> AppResult<Folder> {
     state.db.create_folder(name, parent_id)
 }
 
@@ -28,13 +35,163 @@ pub async fn move_folder(
     state: State<'_, AppState>,
     id: String,
     parent_id: Option<String>,
-) -> Result<(), String> {
+) -> AppResult<()> {
     state.db.move_folder(id, parent_id)
 }
 
 #[tauri::command]
-pub async fn delete_folder(state: State<'_, AppState>, id: String) -> Result<(), String> {
-    state.db.delete_folder(id)
+pub async fn rename_folder(
+    state: State<'_, AppState>,
+    id: String,
+    name: String,
+) -> AppResult<Folder> {
+    state.db.rename_folder(id, name)
+}
+
+#[tauri::command]
+pub async fn reorder_folders(
+    state: State<'_, AppState>,
+    parent_id: Option<String>,
+    ids: Vec<String>,
+) -> AppResult<Vec<Folder>> {
+    state.db.reorder_folders(parent_id, ids)
+}
+
+#[tauri::command]
+pub async fn delete_folder(
+    state: State<'_, AppState>,
+    id: String,
+    recursive: Option<bool>,
+) -> AppResult<()> {
+    state.db.delete_folder(id, recursive.unwrap_or(false))
+}
+
+#[tauri::command]
+pub async fn list_tags(state: State<'_, AppState>) -> AppResult<Vec<Tag>> {
+    state.db.list_tags()
+}
+
+#[tauri::command]
+pub async fn create_tag(
+    state: State<'_, AppState>,
+    name: String,
+    color: Option<String>,
+) -> AppResult<Tag> {
+    state.db.create_tag(name, color)
+}
+
+#[tauri::command]
+pub async fn rename_tag(state: State<'_, AppState>, id: String, name: String) -> AppResult<Tag> {
+    state.db.rename_tag(id, name)
+}
+
+#[tauri::command]
+pub async fn set_tag_color(
+    state: State<'_, AppState>,
+    id: String,
+    color: Option<String>,
+) -> AppResult<Tag> {
+    state.db.set_tag_color(id, color)
+}
+
+#[tauri::command]
+pub async fn merge_tags(
+    state: State<'_, AppState>,
+    source_ids: Vec<String>,
+    target_id: String,
+) -> AppResult<Tag> {
+    state.db.merge_tags(source_ids, target_id)
+}
+
+#[tauri::command]
+pub async fn delete_tag(state: State<'_, AppState>, id: String) -> AppResult<()> {
+    state.db.delete_tag(id)
+}
+
+#[tauri::command]
+pub async fn tag_conversations(
+    state: State<'_, AppState>,
+    conversation_ids: Vec<String>,
+    tag_ids: Vec<String>,
+) -> AppResult<i64> {
+    state.db.tag_conversations(conversation_ids, tag_ids)
+}
+
+#[tauri::command]
+pub async fn untag_conversations(
+    state: State<'_, AppState>,
+    conversation_ids: Vec<String>,
+    tag_ids: Vec<String>,
+) -> AppResult<i64> {
+    state.db.untag_conversations(conversation_ids, tag_ids)
+}
+
+#[tauri::command]
+pub async fn list_import_rules(state: State<'_, AppState>) -> AppResult<Vec<ImportRule>> {
+    state.db.list_import_rules()
+}
+
+#[tauri::command]
+pub async fn create_import_rule(
+    state: State<'_, AppState>,
+    input: ImportRuleInput,
+) -> AppResult<ImportRule> {
+    state.db.create_import_rule(input)
+}
+
+#[tauri::command]
+pub async fn update_import_rule(
+    state: State<'_, AppState>,
+    id: String,
+    input: ImportRuleInput,
+) -> AppResult<ImportRule> {
+    state.db.update_import_rule(id, input)
+}
+
+#[tauri::command]
+pub async fn delete_import_rule(state: State<'_, AppState>, id: String) -> AppResult<()> {
+    state.db.delete_import_rule(id)
+}
+
+#[tauri::command]
+pub async fn reorder_import_rules(
+    state: State<'_, AppState>,
+    ids: Vec<String>,
+) -> AppResult<Vec<ImportRule>> {
+    state.db.reorder_import_rules(ids)
+}
+
+#[tauri::command]
+pub async fn apply_import_rules(
+    state: State<'_, AppState>,
+    input: Option<ApplyImportRulesInput>,
+) -> AppResult<ApplyImportRulesReport> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || db.apply_import_rules(input.unwrap_or_default()))
+        .await
+        .map_err(|e| AppError::Internal(format!("apply import rules task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn create_smart_folder(
+    state: State<'_, AppState>,
+    input: SmartFolderInput,
+) -> AppResult<Folder> {
+    state.db.create_smart_folder(input)
+}
+
+#[tauri::command]
+pub async fn update_smart_folder(
+    state: State<'_, AppState>,
+    id: String,
+    input: SmartFolderInput,
+) -> AppResult<Folder> {
+    state.db.update_smart_folder(id, input)
+}
+
+#[tauri::command]
+pub async fn delete_smart_folder(state: State<'_, AppState>, id: String) -> AppResult<()> {
+    state.db.delete_smart_folder(id)
 }
 
 #[tauri::command]
@@ -42,7 +199,7 @@ pub async fn move_conversation(
     state: State<'_, AppState>,
     id: String,
     folder_id: Option<String>,
-) -> Result<(), String> {
+) -> AppResult<()> {
     state.db.move_conversation(id, folder_id)
 }
 
@@ -50,57 +207,279 @@ pub async fn move_conversation(
 pub async fn list_conversations(
     state: State<'_, AppState>,
     input: Option<ListConversationsInput>,
-) -> Result<Vec<crate::models::ConversationSummary>, String> {
+) -> AppResult<ConversationPage> {
     state.db.list_conversations(input)
 }
 
+#[tauri::command]
+pub async fn conversation_counts(state: State<'_, AppState>) -> AppResult<ConversationCounts> {
+    state.db.conversation_counts()
+}
+
+#[tauri::command]
+pub async fn trash_conversation(state: State<'_, AppState>, id: String) -> AppResult<()> {
+    state.db.trash_conversation(id)
+}
+
+#[tauri::command]
+pub async fn restore_conversation(state: State<'_, AppState>, id: String) -> AppResult<()> {
+    state.db.restore_conversation(id)
+}
+
+#[tauri::command]
+pub async fn list_trash(state: State<'_, AppState>) -> AppResult<Vec<ConversationSummary>> {
+    state.db.list_trash()
+}
+
+#[tauri::command]
+pub async fn purge_trash(
+    state: State<'_, AppState>,
+    ids: Option<Vec<String>>,
+) -> AppResult<PurgeResult> {
+    state.db.purge_trash(ids)
+}
+
 #[tauri::command]
 pub async fn open_conversation(
     state: State<'_, AppState>,
     id: String,
-) -> Result<Option<ConversationDetail>, String> {
+) -> AppResult<Option<ConversationDetail>> {
     state.db.open_conversation(id)
 }
 
+#[tauri::command]
+pub async fn open_conversation_branch(
+    state: State<'_, AppState>,
+    id: String,
+    message_id: String,
+) -> AppResult<Option<ConversationDetail>> {
+    state.db.open_conversation_branch(id, message_id)
+}
+
+#[tauri::command]
+pub async fn list_message_branches(
+    state: State<'_, AppState>,
+    message_id: String,
+) -> AppResult<Vec<Message>> {
+    state.db.list_message_branches(message_id)
+}
+
+#[tauri::command]
+pub async fn list_conversation_revisions(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> AppResult<Vec<ConversationRevision>> {
+    state.db.list_conversation_revisions(conversation_id)
+}
+
+#[tauri::command]
+pub async fn open_conversation_revision(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    revision: i64,
+) -> AppResult<ConversationRevisionDetail> {
+    state.db.open_conversation_revision(conversation_id, revision)
+}
+
+#[tauri::command]
+pub async fn diff_conversation_revisions(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    from_revision: i64,
+    to_revision: Option<i64>,
+) -> AppResult<RevisionDiff> {
+    state
+        .db
+        .diff_conversation_revisions(conversation_id, from_revision, to_revision)
+}
+
 #[tauri::command]
 pub async fn list_conversation_attachments(
     state: State<'_, AppState>,
     conversation_id: String,
-) -> Result<Vec<Attachment>, String> {
+) -> AppResult<Vec<Attachment>> {
     state.db.list_conversation_attachments(conversation_id)
 }
 
+#[tauri::command]
+pub async fn download_queue_status(state: State<'_, AppState>) -> AppResult<DownloadQueueStatus> {
+    state.db.download_queue_status()
+}
+
+#[tauri::command]
+pub async fn pause_downloads(state: State<'_, AppState>) -> AppResult<DownloadQueueStatus> {
+    state.db.pause_downloads()
+}
+
+#[tauri::command]
+pub async fn resume_downloads(state: State<'_, AppState>) -> AppResult<DownloadQueueStatus> {
+    state.db.resume_downloads()
+}
+
+#[tauri::command]
+pub async fn retry_all_downloads(state: State<'_, AppState>) -> AppResult<DownloadQueueStatus> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || db.retry_all_downloads())
+        .await
+        .map_err(|e| AppError::Internal(format!("retry downloads task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn collect_asset_garbage(
+    state: State<'_, AppState>,
+    input: Option<AssetGcInput>,
+) -> AppResult<AssetGcReport> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        db.collect_asset_garbage(input.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("asset gc task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn storage_report(state: State<'_, AppState>) -> AppResult<StorageReport> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || db.storage_report())
+        .await
+        .map_err(|e| AppError::Internal(format!("storage report task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn verify_assets(
+    state: State<'_, AppState>,
+    input: Option<VerifyAssetsInput>,
+) -> AppResult<AssetVerifyReport> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || db.verify_assets(input.unwrap_or_default()))
+        .await
+        .map_err(|e| AppError::Internal(format!("asset verify task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn last_asset_verify_report(
+    state: State<'_, AppState>,
+) -> AppResult<Option<AssetVerifyReport>> {
+    state.db.last_asset_verify_report()
+}
+
 #[tauri::command]
 pub async fn import_files(
     state: State<'_, AppState>,
     batch: ImportBatch,
-) -> Result<ImportResult, String> {
+) -> AppResult<ImportResult> {
     state.db.import_files(batch)
 }
 
+/// Imports a ChatGPT "Export data" zip from disk, emitting `import-progress`
+/// after every batch.
+#[tauri::command]
+pub async fn import_chatgpt_export(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    input: ArchiveImportInput,
+) -> AppResult<ImportResult> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        importers::chatgpt::import_export_zip(&db, &input, |progress| {
+            let _ = app.emit(IMPORT_PROGRESS_EVENT, progress.clone());
+        })
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("import task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn import_google_takeout(
+    app: AppHandle,
+    state: State<'_, AppState>,
+    input: ArchiveImportInput,
+) -> AppResult<ImportResult> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        importers::takeout::import_takeout_zip(&db, &input, |progress| {
+            let _ = app.emit(IMPORT_PROGRESS_EVENT, progress.clone());
+        })
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("import task failed: {e}")))?
+}
+
 #[tauri::command]
 pub async fn import_live_capture(
     state: State<'_, AppState>,
     request: LiveCaptureRequest,
-) -> Result<ImportResult, String> {
+) -> AppResult<ImportResult> {
     state.db.import_live_capture(request)
 }
 
 #[tauri::command]
 pub async fn search_conversations(
     state: State<'_, AppState>,
-    query: String,
-) -> Result<Vec<SearchResult>, String> {
-    state.db.search_conversations(query)
+    input: SearchInput,
+) -> AppResult<SearchPage> {
+    state.db.search_conversations(input)
 }
 
 #[tauri::command]
-pub async fn export_backup_zip(state: State<'_, AppState>) -> Result<String, String> {
+pub async fn rebuild_search_index(state: State<'_, AppState>) -> AppResult<i64> {
+    state.db.rebuild_search_index()
+}
+
+/// Emitted by `update_semantic_index` after every embedded batch.
+const SEMANTIC_INDEX_PROGRESS_EVENT: &str = "semantic-index-progress";
+
+#[tauri::command]
+pub async fn update_semantic_index(
+    app: AppHandle,
+    state: State<'_, AppState>,
+) -> AppResult<SemanticIndexReport> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        db.update_semantic_index(|progress| {
+            let _ = app.emit(SEMANTIC_INDEX_PROGRESS_EVENT, progress.clone());
+        })
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("semantic index task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn clear_semantic_index(state: State<'_, AppState>) -> AppResult<i64> {
+    state.db.clear_semantic_index()
+}
+
+#[tauri::command]
+pub async fn semantic_search(
+    state: State<'_, AppState>,
+    input: SemanticSearchInput,
+) -> AppResult<SearchPage> {
+    let db = state.db.clone();
+    tauri::async_runtime::spawn_blocking(move || db.semantic_search(input))
+        .await
+        .map_err(|e| AppError::Internal(format!("semantic search task failed: {e}")))?
+}
+
+#[tauri::command]
+pub async fn get_settings(state: State<'_, AppState>) -> AppResult<AppSettings> {
+    state.db.get_settings()
+}
+
+#[tauri::command]
+pub async fn update_settings(
+    state: State<'_, AppState>,
+    settings: AppSettings,
+) -> AppResult<AppSettings> {
+    state.db.update_settings(settings)
+}
+
+#[tauri::command]
+pub async fn export_backup_zip(state: State<'_, AppState>) -> AppResult<String> {
     state.db.export_backup_zip()
 }
 
 #[tauri::command]
-pub async fn fetch_url_html(_state: State<'_, AppState>, url: String) -> Result<String, String> {
+pub async fn fetch_url_html(_state: State<'_, AppState>, url: String) -> AppResult<String> {
     let mut headers = HeaderMap::new();
     headers.insert(
         USER_AGENT,
@@ -112,66 +491,71 @@ pub async fn fetch_url_html(_state: State<'_, AppState>, url: String) -> Result<
         .default_headers(headers)
         .redirect(reqwest::redirect::Policy::limited(8))
         .build()
-        .map_err(|e| format!("build client failed: {e}"))?;
+        .map_err(|e| AppError::Internal(format!("build client failed: {e}")))?;
 
     let response = client
         .get(url)
         .send()
         .await
-        .map_err(|e| format!("fetch failed: {e}"))?;
+        .map_err(|e| AppError::Network(format!("fetch failed: {e}")))?;
 
     let status = response.status();
     if !status.is_success() {
-        return Err(format!("fetch returned status {status}"));
+        return Err(AppError::Network(format!("fetch returned status {status}")));
     }
 
-    response.text().await.map_err(|e| format!("read html failed: {e}"))
+    response
+        .text()
+        .await
+        .map_err(|e| AppError::Network(format!("read html failed: {e}")))
 }
 
 #[tauri::command]
-pub async fn start_bridge_session(bridge: State<'_, BridgeState>) -> Result<SessionResponse, String> {
+pub async fn start_bridge_session(bridge: State<'_, BridgeState>) -> AppResult<SessionResponse> {
     bridge.issue_session()
 }
 
-fn open_target_with_system(target: &str) -> Result<(), String> {
+fn open_target_with_system(target: &str) -> AppResult<()> {
     let normalized = target.trim();
     if normalized.is_empty() {
-        return Err("open target is empty".to_string());
+        return Err(AppError::InvalidInput("open target is empty".to_string()));
     }
     if normalized.starts_with('-') {
-        return Err("unsupported open target".to_string());
+        return Err(AppError::InvalidInput(
+            "unsupported open target".to_string(),
+        ));
     }
     if normalized.contains('\0') {
-        return Err("invalid open target".to_string());
+        return Err(AppError::InvalidInput("invalid open target".to_string()));
     }
 
     #[cfg(target_os = "macos")]
     let status = Command::new("open")
         .arg(normalized)
         .status()
-        .map_err(|e| format!("open failed: {e}"))?;
+        .map_err(|e| AppError::Io(format!("open failed: {e}")))?;
 
     #[cfg(target_os = "windows")]
     let status = Command::new("rundll32")
         .arg("url.dll,FileProtocolHandler")
         .arg(normalized)
         .status()
-        .map_err(|e| format!("open failed: {e}"))?;
+        .map_err(|e| AppError::Io(format!("open failed: {e}")))?;
 
     #[cfg(all(unix, not(target_os = "macos")))]
     let status = Command::new("xdg-open")
         .arg(normalized)
         .status()
-        .map_err(|e| format!("open failed: {e}"))?;
+        .map_err(|e| AppError::Io(format!("open failed: {e}")))?;
 
     if status.success() {
         Ok(())
     } else {
-        Err(format!("open exited with status {status}"))
+        Err(AppError::Io(format!("open exited with status {status}")))
     }
 }
 
 #[tauri::command]
-pub async fn open_external(target: String) -> Result<(), String> {
+pub async fn open_external(target: String) -> AppResult<()> {
     open_target_with_system(&target)
 }
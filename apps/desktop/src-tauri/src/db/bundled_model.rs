@@ -0,0 +1,96 @@
+//! Sentence-embedding model shipped with the app and run on the CPU, for the
+//! `bundled` semantic search provider.
+//!
+//! The files come from `paraphrase-multilingual-MiniLM-L12-v2` (a BERT
+//! encoder, mean pooled, 384 dimensions, trained on 50+ languages including
+//! Chinese) and are copied into the bundle's resources by
+//! `scripts/fetch-embedding-model.mjs`. Nothing is downloaded at runtime.
+
+use candle_core::{Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::models::bert::{BertModel, Config, DTYPE};
+use std::fs;
+use std::path::Path;
+use tokenizers::{PaddingParams, Tokenizer, TruncationParams};
+
+use crate::error::{AppError, AppResult};
+
+/// Directory name under the resource dir, or under the data dir when the
+/// app did not set one.
+pub(super) const BUNDLED_MODEL_DIR_NAME: &str = "embedding-model";
+/// Stored with every vector the bundled model writes.
+pub(super) const BUNDLED_MODEL_ID: &str = "bundled:paraphrase-multilingual-MiniLM-L12-v2";
+const MODEL_FILES: &[&str] = &["config.json", "tokenizer.json", "model.safetensors"];
+/// The model was trained on 128-token inputs; longer ones still embed well
+/// up to about twice that, and the rest of a chunk is cut off.
+const MAX_TOKENS: usize = 256;
+
+pub(super) struct BundledModel {
+    model: BertModel,
+    tokenizer: Tokenizer,
+}
+
+impl BundledModel {
+    pub(super) fn load(dir: &Path) -> AppResult<Self> {
+        if let Some(missing) = MODEL_FILES.iter().find(|name| !dir.join(name).is_file()) {
+            return Err(AppError::NotFound(format!(
+                "内置嵌入模型缺少文件 {missing}（{}），请重新安装应用或改用嵌入服务",
+                dir.display()
+            )));
+        }
+        let config: Config = serde_json::from_str(&fs::read_to_string(dir.join("config.json"))?)?;
+        let mut tokenizer =
+            Tokenizer::from_file(dir.join("tokenizer.json")).map_err(model_error)?;
+        tokenizer.with_padding(Some(PaddingParams::default()));
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: MAX_TOKENS,
+                ..TruncationParams::default()
+            }))
+            .map_err(model_error)?;
+        // SAFETY: the weights are read-only files inside the app bundle.
+        let weights = unsafe {
+            VarBuilder::from_mmaped_safetensors(
+                &[dir.join("model.safetensors")],
+                DTYPE,
+                &Device::Cpu,
+            )
+        }
+        .map_err(model_error)?;
+        let model = BertModel::load(weights, &config).map_err(model_error)?;
+        Ok(Self { model, tokenizer })
+    }
+
+    /// Mean of the token embeddings, ignoring padding; normalised by the
+    /// caller.
+    pub(super) fn embed(&self, texts: &[String]) -> AppResult<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let encodings = self
+            .tokenizer
+            .encode_batch(texts.to_vec(), true)
+            .map_err(model_error)?;
+        let device = &self.model.device;
+        let stack = |rows: Vec<&[u32]>| -> candle_core::Result<Tensor> {
+            let rows = rows
+                .into_iter()
+                .map(|row| Tensor::new(row, device))
+                .collect::<candle_core::Result<Vec<_>>>()?;
+            Tensor::stack(&rows, 0)
+        };
+        let run = || -> candle_core::Result<Vec<Vec<f32>>> {
+            let ids = stack(encodings.iter().map(|e| e.get_ids()).collect())?;
+            let mask = stack(encodings.iter().map(|e| e.get_attention_mask()).collect())?;
+            let hidden = self.model.forward(&ids, &ids.zeros_like()?, Some(&mask))?;
+            let mask = mask.to_dtype(DTYPE)?.unsqueeze(2)?;
+            let summed = hidden.broadcast_mul(&mask)?.sum(1)?;
+            summed.broadcast_div(&mask.sum(1)?)?.to_vec2()
+        };
+        run().map_err(model_error)
+    }
+}
+
+fn model_error(err: impl std::fmt::Display) -> AppError {
+    AppError::Internal(format!("内置嵌入模型出错：{err}"))
+}
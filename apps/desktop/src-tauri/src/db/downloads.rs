@@ -0,0 +1,727 @@
+//! App-wide queue that caches remote attachments locally.
+//!
+//! Every pending attachment gets a row in `attachment_downloads`, so the queue
+//! survives restarts. One dispatcher thread claims due rows, at most
+//! `MAX_CONCURRENT_DOWNLOADS` URLs at a time and `MAX_DOWNLOADS_PER_HOST` per
+//! host. Attachments sharing a URL are fetched once. Failures are retried with
+//! exponential backoff until `MAX_ATTEMPTS`, after which the row stays
+//! `failed` until `retry_all_downloads`. Bodies are read against the size
+//! limits in `AttachmentSettings` and typed by their content, not their headers.
+
+use chrono::{Duration as ChronoDuration, Utc};
+use reqwest::blocking::Client as BlockingHttpClient;
+use rusqlite::{params, OptionalExtension};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{ErrorKind, Read};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use super::integrity::damaged_path;
+use super::sniff::sniff_mime;
+use super::{
+    classify_attachment_kind, decode_data_url, is_data_url, is_http_or_https_url,
+    is_virtual_attachment_url, looks_like_cloud_drive_file_url, looks_like_file_url,
+    normalize_attachment_kind, normalize_attachment_url, now_iso, truncate_error, Database,
+};
+use crate::error::AppResult;
+use crate::models::DownloadQueueStatus;
+
+const MAX_CONCURRENT_DOWNLOADS: usize = 6;
+const MAX_DOWNLOADS_PER_HOST: usize = 2;
+const MAX_ATTEMPTS: i64 = 5;
+const RETRY_BASE_DELAY_SECS: i64 = 30;
+const RETRY_MAX_DELAY_SECS: i64 = 60 * 60;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+/// Longest the dispatcher sleeps without being woken.
+const IDLE_WAIT: Duration = Duration::from_secs(60);
+const DOWNLOAD_QUEUE_SETTINGS_KEY: &str = "download_queue";
+const BROWSER_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/122.0.0.0 Safari/537.36";
+
+/// In-memory side of the queue, shared by every clone of a `Database`.
+#[derive(Default)]
+pub(super) struct DownloadQueue {
+    state: Mutex<QueueState>,
+    wake: Condvar,
+}
+
+#[derive(Default)]
+struct QueueState {
+    started: bool,
+    paused: bool,
+    /// Set whenever rows or limits change, so a wake-up is never lost while the
+    /// dispatcher is between passes.
+    dirty: bool,
+    in_flight: HashSet<String>,
+    per_host: HashMap<String, usize>,
+}
+
+impl DownloadQueue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn notify(&self) {
+        self.lock().dirty = true;
+        self.wake.notify_all();
+    }
+}
+
+struct Fetched {
+    bytes: Vec<u8>,
+    /// Recognised from the content; outranks every claimed type.
+    detected_mime: Option<String>,
+    /// `Content-Type` of the response, or the media type of a data URL.
+    claimed_mime: Option<String>,
+}
+
+struct FetchError {
+    message: String,
+    retryable: bool,
+}
+
+impl FetchError {
+    fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            retryable: false,
+        }
+    }
+
+    fn transient(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            retryable: true,
+        }
+    }
+}
+
+impl Database {
+    /// Starts the dispatcher thread once per process. Rows a previous run left
+    /// `running` are queued again.
+    pub fn start_download_worker(&self) -> AppResult<()> {
+        let paused = self.downloads_paused()?;
+        {
+            let mut state = self.downloads.lock();
+            if state.started {
+                return Ok(());
+            }
+            state.started = true;
+            state.paused = paused;
+        }
+        let conn = self.open()?;
+        conn.execute(
+            "UPDATE attachment_downloads SET status = 'queued', updated_at = ?1 WHERE status = 'running'",
+            params![now_iso()],
+        )?;
+
+        let db = self.clone();
+        std::thread::Builder::new()
+            .name("attachment-downloads".to_string())
+            .spawn(move || db.run_download_dispatcher())?;
+        Ok(())
+    }
+
+    pub fn download_queue_status(&self) -> AppResult<DownloadQueueStatus> {
+        let conn = self.open()?;
+        let (queued, running, failed, next_attempt_at) = conn.query_row(
+            r#"
+            SELECT
+              COALESCE(SUM(status = 'queued'), 0),
+              COALESCE(SUM(status = 'running'), 0),
+              COALESCE(SUM(status = 'failed'), 0),
+              MIN(CASE WHEN status = 'queued' THEN next_attempt_at END)
+            FROM attachment_downloads
+            "#,
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        Ok(DownloadQueueStatus {
+            paused: self.downloads_paused()?,
+            queued,
+            running,
+            failed,
+            next_attempt_at,
+        })
+    }
+
+    /// Stops claiming new downloads; ones already running finish. Persists
+    /// across restarts.
+    pub fn pause_downloads(&self) -> AppResult<DownloadQueueStatus> {
+        self.set_downloads_paused(true)?;
+        self.download_queue_status()
+    }
+
+    pub fn resume_downloads(&self) -> AppResult<DownloadQueueStatus> {
+        self.set_downloads_paused(false)?;
+        self.download_queue_status()
+    }
+
+    /// Makes every queued or given-up download due now with a fresh attempt
+    /// budget, and queues failed attachments that have no queue row yet.
+    pub fn retry_all_downloads(&self) -> AppResult<DownloadQueueStatus> {
+        {
+            let conn = self.open()?;
+            let now = now_iso();
+            conn.execute(
+                r#"
+                UPDATE attachment_downloads
+                SET status = 'queued', attempts = 0, next_attempt_at = ?1, updated_at = ?1
+                WHERE status IN ('queued', 'failed')
+                "#,
+                params![now],
+            )?;
+        }
+        self.queue_attachment_downloads(None)?;
+        self.downloads.notify();
+        self.download_queue_status()
+    }
+
+    /// Queues the conversation's uncached attachments. Errors are logged, not
+    /// returned: caching never fails the import or open that triggered it.
+    pub(super) fn schedule_attachment_cache(&self, conversation_id: String) {
+        if let Err(err) = self.queue_attachment_downloads(Some(&conversation_id)) {
+            eprintln!(
+                "queue attachment downloads failed: conversation_id={}, error={}",
+                conversation_id, err
+            );
+        }
+    }
+
+    /// Adds a row for every cacheable attachment of `conversation_id` (or of
+    /// all conversations) not already queued. Returns how many were added.
+    pub(super) fn queue_attachment_downloads(
+        &self,
+        conversation_id: Option<&str>,
+    ) -> AppResult<usize> {
+        let conn = self.open()?;
+        let pending = {
+            let mut stmt = conn.prepare_cached(
+                r#"
+                SELECT a.id, a.kind, a.original_url, a.mime, c.source
+                FROM attachments a
+                JOIN conversations c ON c.id = a.conversation_id
+                WHERE (?1 IS NULL OR a.conversation_id = ?1)
+                  AND (
+                    a.status = 'remote_only'
+                    OR a.status = 'failed'
+                    OR (a.status = 'cached' AND a.local_path IS NULL AND a.original_url LIKE 'data:%')
+                  )
+                  AND NOT EXISTS (SELECT 1 FROM attachment_downloads d WHERE d.attachment_id = a.id)
+                ORDER BY a.created_at ASC
+                "#,
+            )?;
+            let rows = stmt.query_map(params![conversation_id], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let now = now_iso();
+        let mut queued = 0;
+        for (attachment_id, kind, original_url, mime_hint, source) in pending {
+            let url = normalize_attachment_url(&original_url);
+            if url.is_empty() {
+                conn.execute(
+                    "UPDATE attachments SET status = 'failed', error = 'invalid_url' WHERE id = ?1",
+                    params![attachment_id],
+                )?;
+                continue;
+            }
+            if is_virtual_attachment_url(&url) {
+                continue;
+            }
+            let host = if is_data_url(&url) {
+                String::new()
+            } else {
+                let kind = classify_attachment_kind(
+                    &normalize_attachment_kind(&kind),
+                    &url,
+                    mime_hint.as_deref(),
+                );
+                let should_attempt_download =
+                    is_http_or_https_url(&url) && (kind != "file" || looks_like_file_url(&url));
+                if (source == "gemini" || source == "ai_studio")
+                    && looks_like_cloud_drive_file_url(&url)
+                {
+                    continue;
+                }
+                if !should_attempt_download {
+                    continue;
+                }
+                url_host(&url)
+            };
+            queued += conn.execute(
+                r#"
+                INSERT OR IGNORE INTO attachment_downloads
+                  (attachment_id, url, host, status, attempts, next_attempt_at, created_at, updated_at)
+                VALUES (?1, ?2, ?3, 'queued', 0, ?4, ?4, ?4)
+                "#,
+                params![attachment_id, url, host, now],
+            )?;
+        }
+        if queued > 0 {
+            self.downloads.notify();
+        }
+        Ok(queued)
+    }
+
+    fn downloads_paused(&self) -> AppResult<bool> {
+        let conn = self.open()?;
+        let raw: Option<String> = conn
+            .query_row(
+                "SELECT value_json FROM app_settings WHERE key = ?1",
+                params![DOWNLOAD_QUEUE_SETTINGS_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(raw
+            .and_then(|raw| serde_json::from_str::<serde_json::Value>(&raw).ok())
+            .and_then(|value| value.get("paused").and_then(|paused| paused.as_bool()))
+            .unwrap_or(false))
+    }
+
+    fn set_downloads_paused(&self, paused: bool) -> AppResult<()> {
+        let conn = self.open()?;
+        conn.execute(
+            r#"
+            INSERT INTO app_settings (key, value_json, updated_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(key) DO UPDATE SET value_json = excluded.value_json, updated_at = excluded.updated_at
+            "#,
+            params![
+                DOWNLOAD_QUEUE_SETTINGS_KEY,
+                json!({ "paused": paused }).to_string(),
+                now_iso()
+            ],
+        )?;
+        self.downloads.lock().paused = paused;
+        self.downloads.notify();
+        Ok(())
+    }
+
+    fn run_download_dispatcher(&self) {
+        let client = match BlockingHttpClient::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(REQUEST_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::limited(5))
+            .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                eprintln!("attachment download client failed: {err}");
+                return;
+            }
+        };
+
+        loop {
+            let wait = self.dispatch_due_downloads(&client).unwrap_or_else(|err| {
+                eprintln!("attachment download dispatch failed: {err}");
+                IDLE_WAIT
+            });
+            let state = self.downloads.lock();
+            let (mut state, _) = self
+                .downloads
+                .wake
+                .wait_timeout_while(state, wait, |state| !state.dirty)
+                .unwrap_or_else(PoisonError::into_inner);
+            state.dirty = false;
+        }
+    }
+
+    /// Claims as many due URLs as the limits allow and returns how long to
+    /// sleep before the next pass.
+    fn dispatch_due_downloads(&self, client: &BlockingHttpClient) -> AppResult<Duration> {
+        if self.downloads.lock().paused {
+            return Ok(IDLE_WAIT);
+        }
+
+        let conn = self.open()?;
+        let now = now_iso();
+        let due = {
+            let mut stmt = conn.prepare_cached(
+                r#"
+                SELECT url, host FROM attachment_downloads
+                WHERE status = 'queued' AND next_attempt_at <= ?1
+                GROUP BY url
+                ORDER BY MIN(next_attempt_at) ASC
+                "#,
+            )?;
+            let rows = stmt.query_map(params![now], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let mut claimed = Vec::new();
+        for (url, host) in due {
+            {
+                let mut state = self.downloads.lock();
+                if state.paused || state.in_flight.len() >= MAX_CONCURRENT_DOWNLOADS {
+                    break;
+                }
+                let host_busy = !host.is_empty()
+                    && state.per_host.get(&host).copied().unwrap_or(0) >= MAX_DOWNLOADS_PER_HOST;
+                if host_busy || !state.in_flight.insert(url.clone()) {
+                    continue;
+                }
+                *state.per_host.entry(host.clone()).or_insert(0) += 1;
+            }
+            conn.execute(
+                "UPDATE attachment_downloads SET status = 'running', updated_at = ?1 WHERE url = ?2 AND status = 'queued'",
+                params![now, url],
+            )?;
+            claimed.push((url, host));
+        }
+
+        let next: Option<String> = conn.query_row(
+            "SELECT MIN(next_attempt_at) FROM attachment_downloads WHERE status = 'queued' AND next_attempt_at > ?1",
+            params![now],
+            |row| row.get(0),
+        )?;
+        // Workers check out their own connections; holding this one while
+        // they run would tie up a pool slot for the whole pass.
+        drop(conn);
+
+        for (url, host) in claimed {
+            let db = self.clone();
+            let client = client.clone();
+            std::thread::spawn(move || {
+                db.run_download(&client, &url);
+                let mut state = db.downloads.lock();
+                state.in_flight.remove(&url);
+                if let Some(count) = state.per_host.get_mut(&host) {
+                    *count = count.saturating_sub(1);
+                }
+                state.dirty = true;
+                drop(state);
+                db.downloads.wake.notify_all();
+            });
+        }
+        Ok(next
+            .and_then(|next| chrono::DateTime::parse_from_rfc3339(&next).ok())
+            .and_then(|next| (next.with_timezone(&Utc) - Utc::now()).to_std().ok())
+            .map_or(IDLE_WAIT, |until| until.min(IDLE_WAIT)))
+    }
+
+    fn run_download(&self, client: &BlockingHttpClient, url: &str) {
+        let result = self.size_limit().and_then(|limit| {
+            let expects_binary = self.download_expects_binary(url)?;
+            match fetch_attachment(client, url, limit, expects_binary) {
+                Ok(fetched) => self.finish_download(url, fetched),
+                Err(err) => self.fail_download(url, &err),
+            }
+        });
+        if let Err(err) = result {
+            eprintln!("attachment download bookkeeping failed: url={url}, error={err}");
+            let _ = self.fail_download(url, &FetchError::transient(err.to_string()));
+        }
+    }
+
+    /// Most bytes the next download may take under the configured per-file and
+    /// total limits. Concurrent downloads are checked against the same total,
+    /// so it can be overshot by the ones already running.
+    fn size_limit(&self) -> AppResult<SizeLimit> {
+        let settings = self.stored_settings()?.attachments;
+        let mut limit = SizeLimit {
+            max_bytes: None,
+            error: "file_too_large",
+        };
+        if settings.max_file_size_mb > 0 {
+            limit.max_bytes = Some(mib_to_bytes(settings.max_file_size_mb));
+        }
+        if settings.max_total_size_mb > 0 {
+            let conn = self.open()?;
+            let used: i64 = conn.query_row(
+                r#"
+                SELECT COALESCE(SUM(size_bytes), 0) FROM (
+                  SELECT MAX(size_bytes) AS size_bytes FROM attachments
+                  WHERE local_path IS NOT NULL GROUP BY local_path
+                )
+                "#,
+                [],
+                |row| row.get(0),
+            )?;
+            let remaining =
+                mib_to_bytes(settings.max_total_size_mb).saturating_sub(used.max(0) as u64);
+            if limit.max_bytes.is_none_or(|max| remaining < max) {
+                limit = SizeLimit {
+                    max_bytes: Some(remaining),
+                    error: "storage_limit_reached",
+                };
+            }
+        }
+        Ok(limit)
+    }
+
+    /// Stores the bytes once and points every attachment queued for `url` at
+    /// them, including ones queued while the download was running.
+    fn finish_download(&self, url: &str, fetched: Fetched) -> AppResult<()> {
+        let targets = {
+            let conn = self.open()?;
+            let mut stmt = conn.prepare_cached(
+                r#"
+                SELECT a.id, a.kind, a.mime
+                FROM attachment_downloads d
+                JOIN attachments a ON a.id = d.attachment_id
+                WHERE d.url = ?1
+                "#,
+            )?;
+            let rows = stmt.query_map(params![url], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let Some(first_hint) = targets.first().map(|(_, _, mime)| mime.clone()) else {
+            return Ok(());
+        };
+
+        let stored = self.store_asset(
+            &fetched.bytes,
+            url,
+            fetched.claimed_mime.clone().or(first_hint).as_deref(),
+        )?;
+        for (attachment_id, kind, mime_hint) in &targets {
+            let current_kind = normalize_attachment_kind(kind);
+            // A detected type settles the kind on its own; the URL and the
+            // recorded kind only matter when the content is unrecognised.
+            let (mime, final_kind) = match fetched.detected_mime.clone() {
+                Some(detected) => {
+                    let kind = classify_attachment_kind("file", "", Some(&detected));
+                    (Some(detected), kind)
+                }
+                None => {
+                    let mime = fetched.claimed_mime.clone().or(mime_hint.clone());
+                    let kind = classify_attachment_kind(&current_kind, url, mime.as_deref());
+                    (mime, kind)
+                }
+            };
+            self.mark_attachment_cached(
+                attachment_id,
+                stored.local_path.clone(),
+                mime,
+                stored.size_bytes,
+                stored.sha256.clone(),
+            )?;
+            if final_kind != current_kind {
+                self.update_attachment_kind(attachment_id, final_kind)?;
+            }
+        }
+
+        // Only the rows read above: one queued for this URL since then still
+        // needs its own download.
+        let conn = self.open()?;
+        for (attachment_id, _, _) in &targets {
+            conn.execute(
+                "DELETE FROM attachment_downloads WHERE attachment_id = ?1",
+                params![attachment_id],
+            )?;
+        }
+
+        // A damaged copy `verify_assets` moved aside has been replaced now.
+        let damaged = damaged_path(&self.resolve_asset_path(&stored.local_path));
+        if let Err(err) = fs::remove_file(&damaged) {
+            if err.kind() != ErrorKind::NotFound {
+                eprintln!(
+                    "remove damaged asset failed: path={}, error={err}",
+                    damaged.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether a claimed row for `url` is typed as an image or PDF, so an HTML
+    /// page served in its place is an error page rather than the file.
+    fn download_expects_binary(&self, url: &str) -> AppResult<bool> {
+        let conn = self.open()?;
+        Ok(conn.query_row(
+            r#"
+            SELECT EXISTS (
+              SELECT 1 FROM attachment_downloads d
+              JOIN attachments a ON a.id = d.attachment_id
+              WHERE d.url = ?1 AND d.status = 'running' AND a.kind IN ('image', 'pdf')
+            )
+            "#,
+            params![url],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Backs the claimed rows off, or gives up on them once out of attempts or
+    /// when retrying cannot help.
+    fn fail_download(&self, url: &str, err: &FetchError) -> AppResult<()> {
+        let conn = self.open()?;
+        let rows = {
+            let mut stmt = conn.prepare_cached(
+                "SELECT attachment_id, attempts FROM attachment_downloads WHERE url = ?1 AND status = 'running'",
+            )?;
+            let rows = stmt.query_map(params![url], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let error = truncate_error(&err.message);
+        let now = Utc::now();
+        for (attachment_id, attempts) in rows {
+            let attempts = attempts + 1;
+            if err.retryable && attempts < MAX_ATTEMPTS {
+                conn.execute(
+                    r#"
+                    UPDATE attachment_downloads
+                    SET status = 'queued', attempts = ?1, next_attempt_at = ?2, last_error = ?3, updated_at = ?4
+                    WHERE attachment_id = ?5
+                    "#,
+                    params![
+                        attempts,
+                        (now + retry_delay(attempts)).to_rfc3339(),
+                        error,
+                        now.to_rfc3339(),
+                        attachment_id
+                    ],
+                )?;
+            } else {
+                conn.execute(
+                    r#"
+                    UPDATE attachment_downloads
+                    SET status = 'failed', attempts = ?1, last_error = ?2, updated_at = ?3
+                    WHERE attachment_id = ?4
+                    "#,
+                    params![attempts, error, now.to_rfc3339(), attachment_id],
+                )?;
+                conn.execute(
+                    "UPDATE attachments SET status = 'failed', error = ?1 WHERE id = ?2",
+                    params![error, attachment_id],
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 30 s after the first failure, doubling up to an hour.
+fn retry_delay(attempts: i64) -> ChronoDuration {
+    let exponent = (attempts - 1).clamp(0, 16) as u32;
+    ChronoDuration::seconds(
+        RETRY_BASE_DELAY_SECS
+            .saturating_mul(1 << exponent)
+            .min(RETRY_MAX_DELAY_SECS),
+    )
+}
+
+fn url_host(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_lowercase))
+        .unwrap_or_default()
+}
+
+fn mib_to_bytes(mib: i64) -> u64 {
+    (mib.max(0) as u64).saturating_mul(1024 * 1024)
+}
+
+/// Downloads are rejected, not truncated, past `max_bytes`; `error` says which
+/// limit applied.
+#[derive(Clone, Copy)]
+struct SizeLimit {
+    max_bytes: Option<u64>,
+    error: &'static str,
+}
+
+impl SizeLimit {
+    fn check(&self, size: u64) -> Result<(), FetchError> {
+        match self.max_bytes {
+            Some(max) if size > max => Err(FetchError::permanent(self.error)),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn fetch_attachment(
+    client: &BlockingHttpClient,
+    url: &str,
+    limit: SizeLimit,
+    expects_binary: bool,
+) -> Result<Fetched, FetchError> {
+    if is_data_url(url) {
+        let (mime, bytes) =
+            decode_data_url(url).ok_or_else(|| FetchError::permanent("invalid_data_url"))?;
+        if bytes.is_empty() {
+            return Err(FetchError::permanent("empty_data_url"));
+        }
+        limit.check(bytes.len() as u64)?;
+        return checked_content(bytes, mime, expects_binary);
+    }
+
+    let mut response = client
+        .get(url)
+        .header(reqwest::header::USER_AGENT, BROWSER_USER_AGENT)
+        .header(reqwest::header::ACCEPT, "*/*")
+        .send()
+        .map_err(|err| FetchError::transient(err.to_string()))?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let message = format!("http_status_{}", status.as_u16());
+        // Rate limits, timeouts and server errors may clear up; other client
+        // errors will not.
+        let retryable = status.is_server_error()
+            || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            || status == reqwest::StatusCode::REQUEST_TIMEOUT;
+        return Err(FetchError { message, retryable });
+    }
+
+    if let Some(length) = response.content_length() {
+        limit.check(length)?;
+    }
+    let mime = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string());
+
+    // Reads one byte past the limit to tell "exactly at" from "over" without
+    // buffering an oversized body.
+    let mut bytes = Vec::new();
+    let read = match limit.max_bytes {
+        Some(max) => response.take(max.saturating_add(1)).read_to_end(&mut bytes),
+        None => response.read_to_end(&mut bytes),
+    };
+    read.map_err(|err| FetchError::transient(err.to_string()))?;
+    limit.check(bytes.len() as u64)?;
+    if bytes.is_empty() {
+        return Err(FetchError::permanent("empty_response"));
+    }
+    checked_content(bytes, mime, expects_binary)
+}
+
+/// Types the bytes by their signature and refuses markup posing as an image
+/// or PDF, such as a login page served for an image URL.
+fn checked_content(
+    bytes: Vec<u8>,
+    claimed: Option<String>,
+    expects_binary: bool,
+) -> Result<Fetched, FetchError> {
+    let detected = sniff_mime(&bytes, claimed.as_deref());
+    if expects_binary && detected.as_deref() == Some("text/html") {
+        return Err(FetchError::permanent("html_instead_of_attachment"));
+    }
+    Ok(Fetched {
+        bytes,
+        detected_mime: detected,
+        claimed_mime: claimed,
+    })
+}
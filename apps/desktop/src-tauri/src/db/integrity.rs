@@ -0,0 +1,399 @@
+//! Checks cached attachment files against the hashes recorded for them and
+//! repairs what it can: from the assets a backup archive carries, else by
+//! downloading the attachment again.
+
+use rusqlite::{params, OptionalExtension};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+use zip::ZipArchive;
+
+use super::sniff::looks_like_html;
+use super::{is_data_url, is_http_or_https_url, now_iso, Database};
+use crate::error::AppResult;
+use crate::models::{AssetIssue, AssetVerifyReport, VerifyAssetsInput};
+
+const ASSET_VERIFY_REPORT_KEY: &str = "asset_verify_report";
+/// Entry prefix `export_backup_zip` stores cached files under.
+pub(super) const BACKUP_ASSETS_PREFIX: &str = "assets/";
+/// Appended to a damaged file moved aside while its attachment is downloaded
+/// again.
+pub(super) const DAMAGED_SUFFIX: &str = ".damaged";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Problem {
+    Missing,
+    /// Content no longer hashes to the recorded `sha256`.
+    Corrupt,
+    /// An HTML page cached in place of the attachment it was fetched for.
+    Html,
+}
+
+impl Problem {
+    fn name(self) -> &'static str {
+        match self {
+            Problem::Missing => "missing",
+            Problem::Corrupt => "corrupt",
+            Problem::Html => "html",
+        }
+    }
+}
+
+struct CachedRow {
+    attachment_id: String,
+    conversation_id: String,
+    original_url: String,
+    sha256: Option<String>,
+    kind: String,
+    mime: Option<String>,
+}
+
+impl CachedRow {
+    /// Whether the attachment was recorded as something an HTML page can only
+    /// stand in for, rather than a document that may legitimately be markup.
+    fn expects_binary(&self) -> bool {
+        let mime = self.mime.as_deref().unwrap_or_default().to_lowercase();
+        matches!(self.kind.as_str(), "image" | "pdf")
+            || mime.starts_with("image/")
+            || mime == "application/pdf"
+    }
+}
+
+impl Database {
+    /// Re-hashes every cached file. With `repair`, bad files are restored from
+    /// a backup archive when one holds the recorded content, otherwise their
+    /// attachments go back on the download queue; ones that cannot be fetched
+    /// again are marked failed and keep whatever file is left. The report is
+    /// also kept for `last_asset_verify_report`.
+    pub fn verify_assets(&self, input: VerifyAssetsInput) -> AppResult<AssetVerifyReport> {
+        let mut by_path: BTreeMap<String, Vec<CachedRow>> = BTreeMap::new();
+        {
+            let conn = self.open()?;
+            let mut stmt = conn.prepare(
+                r#"
+                SELECT id, conversation_id, original_url, local_path, sha256, kind, mime
+                FROM attachments
+                WHERE local_path IS NOT NULL
+                "#,
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(3)?,
+                    CachedRow {
+                        attachment_id: row.get(0)?,
+                        conversation_id: row.get(1)?,
+                        original_url: row.get(2)?,
+                        sha256: row.get(4)?,
+                        kind: row.get(5)?,
+                        mime: row.get(6)?,
+                    },
+                ))
+            })?;
+            for row in rows {
+                let (local_path, cached) = row?;
+                by_path.entry(local_path).or_default().push(cached);
+            }
+        }
+
+        let mut backups = if input.repair {
+            BackupAssets::open(self.backup_candidates(input.backup_path.as_deref())?)
+        } else {
+            BackupAssets::default()
+        };
+
+        let mut report = AssetVerifyReport {
+            checked_at: now_iso(),
+            repair: input.repair,
+            checked: 0,
+            healthy: 0,
+            missing: 0,
+            corrupt: 0,
+            restored: 0,
+            requeued: 0,
+            unrecoverable: 0,
+            issues: Vec::new(),
+        };
+        for (local_path, rows) in by_path {
+            report.checked += rows.len() as i64;
+            let path = self.resolve_asset_path(&local_path);
+            // Rows sharing a file share its content, so any recorded hash will do.
+            let expected = rows.iter().find_map(|row| row.sha256.clone());
+            let expects_binary = rows.iter().any(CachedRow::expects_binary);
+            let Some(problem) = check_file(&path, expected.as_deref(), expects_binary)? else {
+                report.healthy += rows.len() as i64;
+                continue;
+            };
+            match problem {
+                Problem::Missing => report.missing += rows.len() as i64,
+                Problem::Corrupt | Problem::Html => report.corrupt += rows.len() as i64,
+            }
+
+            let resolutions = if input.repair {
+                self.repair_asset(
+                    &local_path,
+                    &rows,
+                    problem,
+                    expected.as_deref(),
+                    &mut backups,
+                )?
+            } else {
+                vec![None; rows.len()]
+            };
+            for (row, resolution) in rows.into_iter().zip(resolutions) {
+                match resolution {
+                    Some("restored") => report.restored += 1,
+                    Some("requeued") => report.requeued += 1,
+                    Some(_) => report.unrecoverable += 1,
+                    None => {}
+                }
+                report.issues.push(AssetIssue {
+                    attachment_id: row.attachment_id,
+                    conversation_id: row.conversation_id,
+                    original_url: row.original_url,
+                    local_path: path.to_string_lossy().to_string(),
+                    problem: problem.name().to_string(),
+                    resolution: resolution.map(str::to_string),
+                });
+            }
+        }
+
+        if report.requeued > 0 {
+            self.queue_attachment_downloads(None)?;
+        }
+        let conn = self.open()?;
+        conn.execute(
+            r#"
+            INSERT INTO app_settings (key, value_json, updated_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(key) DO UPDATE SET value_json = excluded.value_json, updated_at = excluded.updated_at
+            "#,
+            params![
+                ASSET_VERIFY_REPORT_KEY,
+                serde_json::to_string(&report)?,
+                now_iso()
+            ],
+        )?;
+        Ok(report)
+    }
+
+    pub fn last_asset_verify_report(&self) -> AppResult<Option<AssetVerifyReport>> {
+        let conn = self.open()?;
+        let raw: Option<String> = conn
+            .query_row(
+                "SELECT value_json FROM app_settings WHERE key = ?1",
+                params![ASSET_VERIFY_REPORT_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(raw.and_then(|raw| serde_json::from_str(&raw).ok()))
+    }
+
+    /// Returns one resolution per row: `restored`, `requeued` or
+    /// `unrecoverable`.
+    fn repair_asset(
+        &self,
+        local_path: &str,
+        rows: &[CachedRow],
+        problem: Problem,
+        expected: Option<&str>,
+        backups: &mut BackupAssets,
+    ) -> AppResult<Vec<Option<&'static str>>> {
+        let path = self.resolve_asset_path(local_path);
+        // A backup holds whatever was stored, HTML pages included.
+        let restored = match (problem, expected) {
+            (Problem::Missing | Problem::Corrupt, Some(sha)) => backups.find(sha)?,
+            _ => None,
+        };
+        if let Some(bytes) = restored {
+            // The bytes hash to the recorded sha, so they belong under the
+            // same content-addressed name; the rename replaces the damaged
+            // file only once they are fully written.
+            let dir = path.parent().unwrap_or(Path::new("."));
+            fs::create_dir_all(dir)?;
+            let partial_path = dir.join(format!(".{}.partial", Uuid::new_v4()));
+            fs::write(&partial_path, &bytes)?;
+            if let Err(err) = fs::rename(&partial_path, &path) {
+                let _ = fs::remove_file(&partial_path);
+                return Err(err.into());
+            }
+            let conn = self.open()?;
+            conn.execute(
+                r#"
+                UPDATE attachments SET status = 'cached', size_bytes = ?1, error = NULL
+                WHERE local_path = ?2
+                "#,
+                params![bytes.len() as i64, local_path],
+            )?;
+            return Ok(vec![Some("restored"); rows.len()]);
+        }
+
+        let downloadable = |row: &CachedRow| {
+            is_http_or_https_url(&row.original_url) || is_data_url(&row.original_url)
+        };
+        // With nothing to replace it, a damaged file is still the only copy.
+        // Once anything is requeued it moves aside, or the fresh download
+        // would find it under the same name and keep it; `finish_download`
+        // deletes it when a good copy is stored.
+        let keep_file = problem != Problem::Missing && !rows.iter().any(downloadable);
+        let error = format!("asset_{}", problem.name());
+        let conn = self.open()?;
+        let mut resolutions = Vec::with_capacity(rows.len());
+        for row in rows {
+            if keep_file {
+                conn.execute(
+                    "UPDATE attachments SET status = 'failed', error = ?1 WHERE id = ?2",
+                    params![error, row.attachment_id],
+                )?;
+                resolutions.push(Some("unrecoverable"));
+                continue;
+            }
+            let (status, resolution) = if downloadable(row) {
+                ("remote_only", "requeued")
+            } else {
+                ("failed", "unrecoverable")
+            };
+            conn.execute(
+                r#"
+                UPDATE attachments
+                SET status = ?1, local_path = NULL, sha256 = NULL, size_bytes = NULL, error = ?2
+                WHERE id = ?3
+                "#,
+                params![status, error, row.attachment_id],
+            )?;
+            resolutions.push(Some(resolution));
+        }
+        if !keep_file {
+            match fs::rename(&path, damaged_path(&path)) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(resolutions)
+    }
+
+    /// The named archive, or every backup in the data dir, newest first.
+    fn backup_candidates(&self, backup_path: Option<&str>) -> AppResult<Vec<PathBuf>> {
+        if let Some(path) = backup_path.map(str::trim).filter(|path| !path.is_empty()) {
+            return Ok(vec![PathBuf::from(path)]);
+        }
+        let backup_dir = self.backups_dir();
+        let entries = match fs::read_dir(&backup_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "zip") {
+                paths.push(path);
+            }
+        }
+        // Names carry a sortable timestamp.
+        paths.sort_by(|a, b| b.cmp(a));
+        Ok(paths)
+    }
+}
+
+/// `None` when the file is present and intact. An HTML page only counts as a
+/// problem when `expects_binary` says the attachment should be something else.
+fn check_file(
+    path: &Path,
+    expected_sha: Option<&str>,
+    expects_binary: bool,
+) -> AppResult<Option<Problem>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Some(Problem::Missing)),
+        Err(err) => return Err(err.into()),
+    };
+    let mut hasher = Sha256::new();
+    let mut head: Vec<u8> = Vec::new();
+    let mut buf = vec![0_u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        if head.is_empty() {
+            head.extend_from_slice(&buf[..read.min(1024)]);
+        }
+        hasher.update(&buf[..read]);
+    }
+    let actual = format!("{:x}", hasher.finalize());
+    if expected_sha.is_some_and(|expected| !expected.eq_ignore_ascii_case(&actual)) {
+        return Ok(Some(Problem::Corrupt));
+    }
+    if expects_binary && looks_like_html(&head) {
+        return Ok(Some(Problem::Html));
+    }
+    Ok(None)
+}
+
+/// Where `repair_asset` moves the damaged file at `path` aside.
+pub(super) fn damaged_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(DAMAGED_SUFFIX);
+    path.with_file_name(name)
+}
+
+/// Backup archives opened lazily, with an index of the asset entries in each.
+#[derive(Default)]
+struct BackupAssets {
+    pending: Vec<PathBuf>,
+    opened: Vec<(ZipArchive<File>, HashMap<String, String>)>,
+}
+
+impl BackupAssets {
+    fn open(paths: Vec<PathBuf>) -> Self {
+        let mut pending = paths;
+        pending.reverse();
+        Self {
+            pending,
+            opened: Vec::new(),
+        }
+    }
+
+    /// Bytes of the first asset entry hashing to `sha`. Unreadable archives
+    /// are skipped.
+    fn find(&mut self, sha: &str) -> AppResult<Option<Vec<u8>>> {
+        let sha = sha.to_lowercase();
+        let mut index = 0;
+        loop {
+            if index == self.opened.len() {
+                let Some(path) = self.pending.pop() else {
+                    return Ok(None);
+                };
+                let Ok(archive) = File::open(&path)
+                    .map_err(zip::result::ZipError::from)
+                    .and_then(ZipArchive::new)
+                else {
+                    continue;
+                };
+                let entries = archive
+                    .file_names()
+                    .filter_map(|name| {
+                        let file_name = name.strip_prefix(BACKUP_ASSETS_PREFIX)?;
+                        let hash = file_name.split('.').next()?;
+                        Some((hash.to_lowercase(), name.to_string()))
+                    })
+                    .collect();
+                self.opened.push((archive, entries));
+            }
+
+            let (archive, entries) = &mut self.opened[index];
+            if let Some(name) = entries.get(&sha) {
+                let mut bytes = Vec::new();
+                archive.by_name(name)?.read_to_end(&mut bytes)?;
+                if format!("{:x}", Sha256::digest(&bytes)) == sha {
+                    return Ok(Some(bytes));
+                }
+            }
+            index += 1;
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as CURSOR_ENCODING;
+use base64::Engine as _;
+use rusqlite::{params_from_iter, types::Value};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::search::MAX_PAGE_SIZE;
+use super::{
+    row_to_conversation, smart_folders, tags, Database, CONVERSATION_COLUMNS,
+    CONVERSATION_COLUMN_COUNT, UNCATEGORIZED_FOLDER_ID,
+};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ConversationCounts, ConversationPage, ConversationSummary, ListConversationsInput,
+};
+
+/// Counted per listed row off the `(conversation_id, seq)` index instead of
+/// joining every message of every conversation.
+const MESSAGE_COUNT_SQL: &str = "(SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id)";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SortKey {
+    Created,
+    Updated,
+    Title,
+    MessageCount,
+    Captured,
+}
+
+impl SortKey {
+    fn parse(raw: Option<&str>) -> AppResult<Self> {
+        Ok(match raw.unwrap_or("created") {
+            "created" => SortKey::Created,
+            "updated" => SortKey::Updated,
+            "title" => SortKey::Title,
+            "messageCount" => SortKey::MessageCount,
+            "captured" => SortKey::Captured,
+            other => {
+                return Err(AppError::InvalidInput(format!(
+                    "不支持的排序方式：{other}，可用的有 created、updated、title、messageCount、captured"
+                )))
+            }
+        })
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            SortKey::Created => "created",
+            SortKey::Updated => "updated",
+            SortKey::Title => "title",
+            SortKey::MessageCount => "messageCount",
+            SortKey::Captured => "captured",
+        }
+    }
+
+    /// Titles read A to Z by default; everything else newest or largest first.
+    fn default_descending(self) -> bool {
+        self != SortKey::Title
+    }
+
+    /// Never NULL, so it compares in a row value: conversations that were
+    /// never captured sort as the empty string.
+    fn sql(self) -> &'static str {
+        match self {
+            SortKey::Created => "c.created_at",
+            SortKey::Updated => "c.updated_at",
+            SortKey::Title => "c.title COLLATE NOCASE",
+            SortKey::MessageCount => MESSAGE_COUNT_SQL,
+            SortKey::Captured => "COALESCE(c.captured_at, '')",
+        }
+    }
+}
+
+/// Position after the last row of a page. Opaque to callers; it records the
+/// ordering it was made for so it cannot be replayed against another one.
+#[derive(Serialize, Deserialize)]
+struct Cursor {
+    sort: String,
+    desc: bool,
+    key: serde_json::Value,
+    id: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> AppResult<String> {
+        Ok(CURSOR_ENCODING.encode(serde_json::to_vec(self)?))
+    }
+
+    fn decode(raw: &str) -> AppResult<Self> {
+        CURSOR_ENCODING
+            .decode(raw)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| AppError::InvalidInput("分页游标无效".to_string()))
+    }
+
+    fn key_value(&self) -> AppResult<Value> {
+        match &self.key {
+            serde_json::Value::String(text) => Ok(Value::Text(text.clone())),
+            serde_json::Value::Number(number) => number
+                .as_i64()
+                .map(Value::Integer)
+                .ok_or_else(|| AppError::InvalidInput("分页游标无效".to_string())),
+            _ => Err(AppError::InvalidInput("分页游标无效".to_string())),
+        }
+    }
+}
+
+fn key_to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Integer(number) => number.into(),
+        Value::Text(text) => text.into(),
+        _ => serde_json::Value::Null,
+    }
+}
+
+impl Database {
+    /// One page of live conversations matching the filters, in the requested
+    /// order, plus how many match in total. Without a `limit` every match is
+    /// returned at once.
+    pub fn list_conversations(
+        &self,
+        input: Option<ListConversationsInput>,
+    ) -> AppResult<ConversationPage> {
+        let input = input.unwrap_or_default();
+        let sort = SortKey::parse(input.sort.as_deref())?;
+        let descending = match input.direction.as_deref() {
+            None => sort.default_descending(),
+            Some("desc") => true,
+            Some("asc") => false,
+            Some(other) => {
+                return Err(AppError::InvalidInput(format!("不支持的排序方向：{other}")))
+            }
+        };
+        let cursor = input.cursor.as_deref().map(Cursor::decode).transpose()?;
+        if let Some(cursor) = cursor.as_ref() {
+            if cursor.sort != sort.name() || cursor.desc != descending {
+                return Err(AppError::InvalidInput(
+                    "分页游标与当前排序方式不匹配".to_string(),
+                ));
+            }
+        }
+        let limit = input.limit.map(|limit| limit.clamp(1, MAX_PAGE_SIZE));
+
+        let conn = self.open()?;
+        self.ensure_system_folders(&conn)?;
+
+        let mut filters = String::new();
+        let mut values: Vec<Value> = Vec::new();
+        if let Some(folder_id) = input.folder_id {
+            if let Some(filter) = smart_folders::load_filter(&conn, &folder_id)? {
+                let (predicate, params) = smart_folders::filter_sql(&conn, &filter)?;
+                filters.push_str(&format!(" AND {predicate} "));
+                values.extend(params);
+            } else if folder_id == UNCATEGORIZED_FOLDER_ID {
+                filters.push_str(" AND (c.folder_id = ? OR c.folder_id IS NULL) ");
+                values.push(Value::Text(folder_id));
+            } else {
+                filters.push_str(" AND c.folder_id = ? ");
+                values.push(Value::Text(folder_id));
+            }
+        }
+
+        if let Some(source) = input.source {
+            if source != "all" {
+                filters.push_str(" AND c.source = ? ");
+                values.push(Value::Text(source));
+            }
+        }
+
+        let tag_ids = input.tag_ids.unwrap_or_default();
+        if let Some((predicate, params)) =
+            tags::tag_filter_sql(&tag_ids, input.tag_match.as_deref())?
+        {
+            filters.push_str(&format!(" AND {predicate} "));
+            values.extend(params);
+        }
+
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM conversations c WHERE c.deleted_at IS NULL {filters}"),
+            params_from_iter(values.iter()),
+            |row| row.get(0),
+        )?;
+
+        let key_sql = sort.sql();
+        let (direction, comparison) = if descending {
+            ("DESC", "<")
+        } else {
+            ("ASC", ">")
+        };
+        let mut page_values = values;
+        let mut seek = String::new();
+        if let Some(cursor) = cursor.as_ref() {
+            seek = format!(" AND ({key_sql}, c.id) {comparison} (?, ?) ");
+            page_values.push(cursor.key_value()?);
+            page_values.push(Value::Text(cursor.id.clone()));
+        }
+        // One extra row tells whether another page follows.
+        page_values.push(Value::Integer(limit.map_or(-1, |limit| limit + 1)));
+
+        let mut stmt = conn.prepare(&format!(
+            r#"
+            SELECT {CONVERSATION_COLUMNS}, {MESSAGE_COUNT_SQL}, {key_sql}
+            FROM conversations c
+            WHERE c.deleted_at IS NULL {filters} {seek}
+            ORDER BY {key_sql} {direction}, c.id {direction}
+            LIMIT ?
+            "#
+        ))?;
+        let rows = stmt.query_map(params_from_iter(page_values.iter()), |row| {
+            let conversation = row_to_conversation(row)?;
+            let key: Value = row.get(CONVERSATION_COLUMN_COUNT + 1)?;
+            Ok((
+                ConversationSummary {
+                    conversation,
+                    message_count: row.get(CONVERSATION_COLUMN_COUNT)?,
+                },
+                key,
+            ))
+        })?;
+        let mut rows = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut next_cursor = None;
+        if let Some(limit) = limit {
+            if rows.len() as i64 > limit {
+                rows.truncate(limit as usize);
+                if let Some((last, key)) = rows.last() {
+                    next_cursor = Some(
+                        Cursor {
+                            sort: sort.name().to_string(),
+                            desc: descending,
+                            key: key_to_json(key.clone()),
+                            id: last.conversation.id.clone(),
+                        }
+                        .encode()?,
+                    );
+                }
+            }
+        }
+        let items = rows.into_iter().map(|(summary, _)| summary).collect();
+
+        Ok(ConversationPage {
+            items,
+            total,
+            next_cursor,
+        })
+    }
+
+    /// Live conversation counts per folder and per source, for badges.
+    /// Conversations without a folder count towards the uncategorized one.
+    pub fn conversation_counts(&self) -> AppResult<ConversationCounts> {
+        let conn = self.open()?;
+        let grouped = |sql: &str, values: &[&str]| -> AppResult<BTreeMap<String, i64>> {
+            let mut stmt = conn.prepare(sql)?;
+            let rows = stmt.query_map(params_from_iter(values), |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        };
+        let by_folder = grouped(
+            r#"
+            SELECT COALESCE(folder_id, ?1), COUNT(*) FROM conversations
+            WHERE deleted_at IS NULL GROUP BY 1
+            "#,
+            &[UNCATEGORIZED_FOLDER_ID],
+        )?;
+        let by_source = grouped(
+            "SELECT source, COUNT(*) FROM conversations WHERE deleted_at IS NULL GROUP BY source",
+            &[],
+        )?;
+        Ok(ConversationCounts {
+            total: by_source.values().sum(),
+            by_folder,
+            by_source,
+        })
+    }
+}
@@ -0,0 +1,610 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use crate::error::{AppError, AppResult};
+
+/// Ordered schema steps. `version` must increase by one per entry and a step must
+/// never be edited once released; append a new migration instead.
+struct Migration {
+    version: i64,
+    description: &'static str,
+    apply: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial schema",
+        apply: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "messages.thought_markdown",
+        apply: add_thought_markdown,
+    },
+    Migration {
+        version: 3,
+        description: "conversation trash and app settings",
+        apply: add_trash_and_settings,
+    },
+    Migration {
+        version: 4,
+        description: "conversation imported_at/captured_at",
+        apply: add_import_timestamps,
+    },
+    Migration {
+        version: 5,
+        description: "conversation revisions",
+        apply: add_conversation_revisions,
+    },
+    Migration {
+        version: 6,
+        description: "message tree",
+        apply: add_message_tree,
+    },
+    Migration {
+        version: 7,
+        description: "CJK-aware messages_fts",
+        apply: add_cjk_search_tokens,
+    },
+    Migration {
+        version: 8,
+        description: "multi-column messages_fts kept by triggers",
+        apply: add_multi_column_search,
+    },
+    Migration {
+        version: 9,
+        description: "message_embeddings",
+        apply: add_message_embeddings,
+    },
+    Migration {
+        version: 10,
+        description: "tag colors",
+        apply: add_tag_colors,
+    },
+    Migration {
+        version: 11,
+        description: "import_rules",
+        apply: add_import_rules,
+    },
+    Migration {
+        version: 12,
+        description: "smart_folders",
+        apply: add_smart_folders,
+    },
+    Migration {
+        version: 13,
+        description: "conversation listing indexes",
+        apply: add_listing_indexes,
+    },
+    Migration {
+        version: 14,
+        description: "attachment download queue",
+        apply: add_attachment_downloads,
+    },
+    Migration {
+        version: 15,
+        description: "attachment local_path relative to the data dir",
+        apply: relativize_asset_paths,
+    },
+];
+
+pub(super) fn latest_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Brings the database up to `latest_version()`. Each step runs in its own
+/// immediate transaction together with the `user_version` bump, so a failure
+/// leaves the file at the last fully applied version.
+pub(super) fn run(conn: &mut Connection) -> AppResult<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+          version INTEGER PRIMARY KEY,
+          description TEXT NOT NULL,
+          applied_at TEXT NOT NULL
+        );
+        "#,
+    )
+    .map_err(migrate_error)?;
+
+    let latest = latest_version();
+    let current = current_version(conn)?;
+    if current > latest {
+        return Err(AppError::Schema(format!(
+            "数据库版本 {current} 高于当前应用支持的版本 {latest}，请升级应用后再打开"
+        )));
+    }
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current) {
+        let tx = conn
+            .transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(migrate_error)?;
+
+        // Another process may have migrated while we waited for the write lock.
+        if current_version(&tx)? >= migration.version {
+            continue;
+        }
+
+        (migration.apply)(&tx).map_err(|e| {
+            AppError::Schema(format!(
+                "migration {} ({}) failed: {e}",
+                migration.version, migration.description
+            ))
+        })?;
+
+        tx.execute(
+            "INSERT OR REPLACE INTO schema_migrations (version, description, applied_at) VALUES (?1, ?2, ?3)",
+            params![migration.version, migration.description, Utc::now().to_rfc3339()],
+        )
+        .map_err(migrate_error)?;
+        tx.pragma_update(None, "user_version", migration.version)
+            .map_err(migrate_error)?;
+        tx.commit().map_err(migrate_error)?;
+    }
+
+    Ok(())
+}
+
+// Keep lock contention distinguishable so callers can retry instead of
+// treating a busy file as a broken schema.
+fn migrate_error(err: rusqlite::Error) -> AppError {
+    match AppError::from(err) {
+        AppError::Locked(message) => AppError::Locked(message),
+        other => AppError::Schema(format!("migrate failed: {}", other.message())),
+    }
+}
+
+fn current_version(conn: &Connection) -> AppResult<i64> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+        .map_err(migrate_error)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let found: Option<String> = conn
+        .query_row(
+            "SELECT name FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, column],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(found.is_some())
+}
+
+fn initial_schema(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS folders (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL,
+          parent_id TEXT,
+          sort_order INTEGER NOT NULL DEFAULT 0,
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL,
+          FOREIGN KEY(parent_id) REFERENCES folders(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS conversations (
+          id TEXT PRIMARY KEY,
+          source TEXT NOT NULL,
+          source_conversation_id TEXT,
+          folder_id TEXT,
+          title TEXT NOT NULL,
+          summary TEXT,
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL,
+          fingerprint TEXT NOT NULL,
+          meta_json TEXT NOT NULL,
+          FOREIGN KEY(folder_id) REFERENCES folders(id)
+        );
+
+        CREATE TABLE IF NOT EXISTS messages (
+          id TEXT PRIMARY KEY,
+          conversation_id TEXT NOT NULL,
+          seq INTEGER NOT NULL,
+          role TEXT NOT NULL,
+          content_markdown TEXT NOT NULL,
+          thought_markdown TEXT,
+          model TEXT,
+          timestamp TEXT,
+          token_count INTEGER,
+          FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS tags (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL UNIQUE
+        );
+
+        CREATE TABLE IF NOT EXISTS conversation_tags (
+          conversation_id TEXT NOT NULL,
+          tag_id TEXT NOT NULL,
+          PRIMARY KEY (conversation_id, tag_id),
+          FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE,
+          FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
+        );
+
+        CREATE TABLE IF NOT EXISTS imports (
+          id TEXT PRIMARY KEY,
+          source TEXT NOT NULL,
+          imported_count INTEGER NOT NULL,
+          skipped_count INTEGER NOT NULL,
+          conflict_count INTEGER NOT NULL,
+          created_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS source_profiles (
+          id TEXT PRIMARY KEY,
+          source TEXT NOT NULL,
+          profile_name TEXT NOT NULL,
+          profile_json TEXT NOT NULL,
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS attachments (
+          id TEXT PRIMARY KEY,
+          message_id TEXT NOT NULL,
+          conversation_id TEXT NOT NULL,
+          kind TEXT NOT NULL,
+          original_url TEXT NOT NULL,
+          local_path TEXT,
+          mime TEXT,
+          size_bytes INTEGER,
+          sha256 TEXT,
+          status TEXT NOT NULL,
+          error TEXT,
+          created_at TEXT NOT NULL,
+          FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE,
+          FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_conversations_updated_at ON conversations(updated_at);
+        CREATE INDEX IF NOT EXISTS idx_messages_conv_seq ON messages(conversation_id, seq);
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_conversations_fingerprint ON conversations(fingerprint);
+        CREATE INDEX IF NOT EXISTS idx_attachments_message_id ON attachments(message_id);
+        CREATE INDEX IF NOT EXISTS idx_attachments_conv_id ON attachments(conversation_id);
+        CREATE INDEX IF NOT EXISTS idx_attachments_sha256 ON attachments(sha256);
+
+        CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+          message_id UNINDEXED,
+          conversation_id UNINDEXED,
+          content_markdown
+        );
+        "#,
+    )
+}
+
+// Installs created before thought capture was added have `messages` without
+// this column; `CREATE TABLE IF NOT EXISTS` in step 1 does not touch them.
+fn add_thought_markdown(conn: &Connection) -> rusqlite::Result<()> {
+    if has_column(conn, "messages", "thought_markdown")? {
+        return Ok(());
+    }
+    conn.execute("ALTER TABLE messages ADD COLUMN thought_markdown TEXT", [])?;
+    Ok(())
+}
+
+fn add_trash_and_settings(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        ALTER TABLE conversations ADD COLUMN deleted_at TEXT;
+        CREATE INDEX IF NOT EXISTS idx_conversations_deleted_at ON conversations(deleted_at);
+
+        CREATE TABLE IF NOT EXISTS app_settings (
+          key TEXT PRIMARY KEY,
+          value_json TEXT NOT NULL,
+          updated_at TEXT NOT NULL
+        );
+        "#,
+    )
+}
+
+// Before this step `created_at`/`updated_at` held the import time, so that is
+// the best available `imported_at`; extension captures also get `captured_at`.
+fn add_import_timestamps(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        ALTER TABLE conversations ADD COLUMN imported_at TEXT;
+        ALTER TABLE conversations ADD COLUMN captured_at TEXT;
+
+        UPDATE conversations SET imported_at = created_at;
+        UPDATE conversations SET captured_at = updated_at
+        WHERE json_valid(meta_json) AND json_extract(meta_json, '$.capturedBy') = 'extension';
+
+        CREATE INDEX IF NOT EXISTS idx_conversations_created_at ON conversations(created_at);
+        "#,
+    )
+}
+
+// No foreign key on `conversation_id`: an overwrite import deletes and re-inserts
+// the conversation row under the same id, and its history has to survive that.
+fn add_conversation_revisions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS conversation_revisions (
+          id TEXT PRIMARY KEY,
+          conversation_id TEXT NOT NULL,
+          revision INTEGER NOT NULL,
+          title TEXT NOT NULL,
+          message_count INTEGER NOT NULL,
+          messages_json TEXT NOT NULL,
+          source_updated_at TEXT,
+          created_at TEXT NOT NULL,
+          UNIQUE(conversation_id, revision)
+        );
+        "#,
+    )
+}
+
+// Existing transcripts are linear: each message hangs off the one before it.
+fn add_message_tree(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        ALTER TABLE messages ADD COLUMN parent_id TEXT;
+        ALTER TABLE messages ADD COLUMN branch_index INTEGER NOT NULL DEFAULT 0;
+        ALTER TABLE messages ADD COLUMN on_active_path INTEGER NOT NULL DEFAULT 1;
+
+        UPDATE messages SET parent_id = (
+          SELECT prev.id FROM messages prev
+          WHERE prev.conversation_id = messages.conversation_id AND prev.seq < messages.seq
+          ORDER BY prev.seq DESC
+          LIMIT 1
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_messages_parent_id ON messages(parent_id);
+        "#,
+    )
+}
+
+/// `app_settings` key a step sets when `messages_fts` has to be filled from
+/// the tokenizer of the running build; `Database::migrate` does that and
+/// removes the key. Steps cannot fill it themselves without tying what they
+/// write to code that keeps changing after release.
+pub(super) const SEARCH_REBUILD_PENDING_KEY: &str = "search_index_rebuild_pending";
+
+fn queue_search_rebuild(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO app_settings (key, value_json, updated_at) VALUES (?1, 'true', ?2)",
+        params![SEARCH_REBUILD_PENDING_KEY, Utc::now().to_rfc3339()],
+    )?;
+    Ok(())
+}
+
+// The original index used plain `unicode61`, which keeps each unbroken CJK
+// run as one token. Its replacement indexes `search_tokens` output, so it is
+// filled by the rebuild this queues.
+fn add_cjk_search_tokens(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        DROP TABLE IF EXISTS messages_fts;
+        CREATE VIRTUAL TABLE messages_fts USING fts5(
+          message_id UNINDEXED,
+          conversation_id UNINDEXED,
+          content_markdown,
+          tokenize = 'unicode61 remove_diacritics 2'
+        );
+        "#,
+    )?;
+    queue_search_rebuild(conn)
+}
+
+// Titles, summaries, thoughts and attachment names join message content in
+// the index, and triggers take over keeping it current from the write paths.
+// Spelled out as of this version; `search::rebuild_search_index` may move on,
+// and is what fills the new table.
+fn add_multi_column_search(conn: &Connection) -> rusqlite::Result<()> {
+    let attachment_names = |message_id: &str| {
+        format!(
+            "search_tokens((SELECT group_concat(attachment_file_name(a.original_url), ' ') \
+             FROM attachments a WHERE a.message_id = {message_id}))"
+        )
+    };
+    let refresh_attachments = |message_id: &str| {
+        format!(
+            "UPDATE messages_fts SET attachments = {} \
+             WHERE message_id = {message_id};",
+            attachment_names(message_id)
+        )
+    };
+    let new_attachments = attachment_names("NEW.id");
+    let refresh_new = refresh_attachments("NEW.message_id");
+    let refresh_old = refresh_attachments("OLD.message_id");
+    conn.execute_batch(&format!(
+        r#"
+        DROP TABLE IF EXISTS messages_fts;
+
+        CREATE VIRTUAL TABLE messages_fts USING fts5(
+          message_id UNINDEXED,
+          conversation_id UNINDEXED,
+          title,
+          summary,
+          content,
+          thoughts,
+          attachments,
+          tokenize = 'unicode61 remove_diacritics 2'
+        );
+        INSERT INTO messages_fts (messages_fts, rank)
+        VALUES ('rank', 'bm25(0.0, 0.0, 8.0, 4.0, 1.0, 0.5, 2.0)');
+
+        CREATE TRIGGER conversations_fts_insert AFTER INSERT ON conversations BEGIN
+          INSERT INTO messages_fts (conversation_id, title, summary)
+          VALUES (NEW.id, search_tokens(NEW.title), search_tokens(NEW.summary));
+        END;
+        CREATE TRIGGER conversations_fts_update AFTER UPDATE OF title, summary ON conversations BEGIN
+          UPDATE messages_fts
+          SET title = search_tokens(NEW.title), summary = search_tokens(NEW.summary)
+          WHERE conversation_id = NEW.id AND message_id IS NULL;
+        END;
+        CREATE TRIGGER conversations_fts_delete AFTER DELETE ON conversations BEGIN
+          DELETE FROM messages_fts WHERE conversation_id = OLD.id AND message_id IS NULL;
+        END;
+
+        CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+          INSERT INTO messages_fts (message_id, conversation_id, content, thoughts, attachments)
+          VALUES (NEW.id, NEW.conversation_id, search_tokens(NEW.content_markdown),
+            search_tokens(NEW.thought_markdown), {new_attachments});
+        END;
+        CREATE TRIGGER messages_fts_update AFTER UPDATE OF content_markdown, thought_markdown ON messages BEGIN
+          UPDATE messages_fts
+          SET content = search_tokens(NEW.content_markdown),
+              thoughts = search_tokens(NEW.thought_markdown)
+          WHERE message_id = NEW.id;
+        END;
+        CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+          DELETE FROM messages_fts WHERE message_id = OLD.id;
+        END;
+
+        CREATE TRIGGER attachments_fts_insert AFTER INSERT ON attachments BEGIN
+          {refresh_new}
+        END;
+        CREATE TRIGGER attachments_fts_update AFTER UPDATE OF original_url, message_id ON attachments BEGIN
+          {refresh_old}
+          {refresh_new}
+        END;
+        CREATE TRIGGER attachments_fts_delete AFTER DELETE ON attachments BEGIN
+          {refresh_old}
+        END;
+        "#
+    ))?;
+    queue_search_rebuild(conn)
+}
+
+fn add_message_embeddings(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS message_embeddings (
+          message_id TEXT NOT NULL,
+          chunk_index INTEGER NOT NULL,
+          conversation_id TEXT NOT NULL,
+          model TEXT NOT NULL,
+          content_hash TEXT NOT NULL,
+          char_start INTEGER NOT NULL,
+          char_end INTEGER NOT NULL,
+          vector BLOB NOT NULL,
+          created_at TEXT NOT NULL,
+          PRIMARY KEY (message_id, chunk_index),
+          FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE,
+          FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_message_embeddings_model ON message_embeddings(model);
+        "#,
+    )
+}
+
+// Tag counts and tag filters look links up by tag; the primary key only
+// covers lookups by conversation.
+fn add_tag_colors(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        ALTER TABLE tags ADD COLUMN color TEXT;
+
+        CREATE INDEX IF NOT EXISTS idx_conversation_tags_tag_id ON conversation_tags(tag_id);
+        "#,
+    )
+}
+
+fn add_import_rules(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS import_rules (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL,
+          enabled INTEGER NOT NULL DEFAULT 1,
+          position INTEGER NOT NULL,
+          conditions_json TEXT NOT NULL,
+          folder_id TEXT,
+          tags_json TEXT NOT NULL DEFAULT '[]',
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL,
+          FOREIGN KEY(folder_id) REFERENCES folders(id) ON DELETE SET NULL
+        );
+        "#,
+    )
+}
+
+// Smart folders sit in the folder tree but hold no conversations, so they get
+// their own table rather than a flag on `folders`.
+fn add_smart_folders(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS smart_folders (
+          id TEXT PRIMARY KEY,
+          name TEXT NOT NULL,
+          parent_id TEXT,
+          sort_order INTEGER NOT NULL DEFAULT 0,
+          filter_json TEXT NOT NULL,
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL,
+          FOREIGN KEY(parent_id) REFERENCES folders(id) ON DELETE SET NULL
+        );
+        "#,
+    )
+}
+
+// Backs the sort keys and per-folder/per-source counts of the conversation list.
+fn add_listing_indexes(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_conversations_title ON conversations(title COLLATE NOCASE);
+        CREATE INDEX IF NOT EXISTS idx_conversations_captured_at ON conversations(captured_at);
+        CREATE INDEX IF NOT EXISTS idx_conversations_folder_id ON conversations(folder_id);
+        CREATE INDEX IF NOT EXISTS idx_conversations_source ON conversations(source);
+        "#,
+    )
+}
+
+// One row per attachment waiting to be cached; `url` is the normalized URL so
+// attachments sharing it are fetched once.
+fn add_attachment_downloads(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS attachment_downloads (
+          attachment_id TEXT PRIMARY KEY,
+          url TEXT NOT NULL,
+          host TEXT NOT NULL,
+          status TEXT NOT NULL DEFAULT 'queued',
+          attempts INTEGER NOT NULL DEFAULT 0,
+          next_attempt_at TEXT NOT NULL,
+          last_error TEXT,
+          created_at TEXT NOT NULL,
+          updated_at TEXT NOT NULL,
+          FOREIGN KEY(attachment_id) REFERENCES attachments(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS idx_attachment_downloads_due ON attachment_downloads(status, next_attempt_at);
+        CREATE INDEX IF NOT EXISTS idx_attachment_downloads_url ON attachment_downloads(url);
+        "#,
+    )
+}
+
+// Cached files were recorded by absolute path, which breaks once the data dir
+// moves. Every file `store_asset` wrote sits directly in `assets/`, so those
+// become `assets/<file name>` wherever that dir was; other paths are left as
+// they are. Both separators are split on, since the database may have been
+// written on another OS.
+fn relativize_asset_paths(conn: &Connection) -> rusqlite::Result<()> {
+    let paths = {
+        let mut stmt = conn
+            .prepare("SELECT DISTINCT local_path FROM attachments WHERE local_path IS NOT NULL")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    for path in paths {
+        let mut parts = path.rsplit(['/', '\\']);
+        let (Some(file_name), Some(dir)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if file_name.is_empty() || dir != "assets" {
+            continue;
+        }
+        let relative = format!("assets/{file_name}");
+        if relative != path {
+            conn.execute(
+                "UPDATE attachments SET local_path = ?1 WHERE local_path = ?2",
+                params![relative, path],
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -1,168 +1,153 @@
+mod bundled_model;
+mod downloads;
+mod integrity;
+mod listing;
+mod migrations;
+mod query;
+mod recapture;
+mod revisions;
+mod rules;
+mod search;
+mod semantic;
+mod settings;
+mod smart_folders;
+mod sniff;
+mod storage;
+mod tags;
+mod trash;
+mod tree;
+
 use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
 use base64::Engine as _;
 use chrono::Utc;
-use reqwest::blocking::Client as BlockingHttpClient;
-use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
 use sha2::{Digest, Sha256};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use uuid::Uuid;
 
+use crate::error::{AppError, AppResult};
 use crate::models::{
-    Attachment, Conversation, ConversationDetail, ConversationSummary, Folder, ImportBatch,
-    ImportResult, ListConversationsInput, LiveCaptureRequest, Message, NormalizedConversation,
-    NormalizedTurn,
-    SearchResult,
+    Attachment, Conversation, ConversationDetail, Folder, ImportBatch, ImportResult,
+    LiveCaptureRequest, Message, NormalizedConversation, NormalizedTurn,
 };
 
+type DbConnection = PooledConnection<SqliteConnectionManager>;
+
 #[derive(Clone)]
 pub struct Database {
     db_path: PathBuf,
+    pool: Pool<SqliteConnectionManager>,
+    downloads: Arc<downloads::DownloadQueue>,
+    /// Overrides where the bundled embedding model is read from; see
+    /// `with_embedding_model_dir`.
+    embedding_model_dir: Option<PathBuf>,
+    embedding_model: Arc<Mutex<Option<Arc<bundled_model::BundledModel>>>>,
 }
 
 const UNCATEGORIZED_FOLDER_ID: &str = "uncategorized";
 const UNCATEGORIZED_FOLDER_NAME: &str = "未分类";
 
+const POOL_MAX_SIZE: u32 = 8;
+const POOL_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+/// Cached files live here, inside the data dir; stored `local_path`s are
+/// `assets/<file name>`.
+const ASSETS_DIR_NAME: &str = "assets";
+
 impl Database {
-    pub fn new(db_path: PathBuf) -> Result<Self, String> {
-        let db = Self { db_path };
+    pub fn new(db_path: PathBuf) -> AppResult<Self> {
+        let manager = SqliteConnectionManager::file(&db_path).with_init(configure_connection);
+        let pool = Pool::builder()
+            .max_size(POOL_MAX_SIZE)
+            .connection_timeout(POOL_CONNECTION_TIMEOUT)
+            .build(manager)
+            .map_err(|e| AppError::Database(format!("open db pool failed: {e}")))?;
+
+        let db = Self {
+            db_path,
+            pool,
+            downloads: Arc::default(),
+            embedding_model_dir: None,
+            embedding_model: Arc::default(),
+        };
         db.migrate()?;
         Ok(db)
     }
 
+    /// Reads the bundled embedding model from `dir` (the app's resource dir)
+    /// instead of the data dir.
+    pub fn with_embedding_model_dir(mut self, dir: PathBuf) -> Self {
+        self.embedding_model_dir = Some(dir);
+        self
+    }
+
     pub fn path(&self) -> &Path {
         &self.db_path
     }
 
-    fn open(&self) -> Result<Connection, String> {
-        Connection::open(&self.db_path).map_err(|e| format!("open db failed: {e}"))
+    fn data_dir(&self) -> &Path {
+        self.db_path.parent().unwrap_or_else(|| Path::new("."))
     }
 
-    fn migrate(&self) -> Result<(), String> {
-        let conn = self.open()?;
-        conn.execute_batch(
-            r#"
-            PRAGMA foreign_keys = ON;
-
-            CREATE TABLE IF NOT EXISTS folders (
-              id TEXT PRIMARY KEY,
-              name TEXT NOT NULL,
-              parent_id TEXT,
-              sort_order INTEGER NOT NULL DEFAULT 0,
-              created_at TEXT NOT NULL,
-              updated_at TEXT NOT NULL,
-              FOREIGN KEY(parent_id) REFERENCES folders(id)
-            );
-
-            CREATE TABLE IF NOT EXISTS conversations (
-              id TEXT PRIMARY KEY,
-              source TEXT NOT NULL,
-              source_conversation_id TEXT,
-              folder_id TEXT,
-              title TEXT NOT NULL,
-              summary TEXT,
-              created_at TEXT NOT NULL,
-              updated_at TEXT NOT NULL,
-              fingerprint TEXT NOT NULL,
-              meta_json TEXT NOT NULL,
-              FOREIGN KEY(folder_id) REFERENCES folders(id)
-            );
-
-            CREATE TABLE IF NOT EXISTS messages (
-              id TEXT PRIMARY KEY,
-              conversation_id TEXT NOT NULL,
-              seq INTEGER NOT NULL,
-              role TEXT NOT NULL,
-              content_markdown TEXT NOT NULL,
-              thought_markdown TEXT,
-              model TEXT,
-              timestamp TEXT,
-              token_count INTEGER,
-              FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
-            );
-
-            CREATE TABLE IF NOT EXISTS tags (
-              id TEXT PRIMARY KEY,
-              name TEXT NOT NULL UNIQUE
-            );
-
-            CREATE TABLE IF NOT EXISTS conversation_tags (
-              conversation_id TEXT NOT NULL,
-              tag_id TEXT NOT NULL,
-              PRIMARY KEY (conversation_id, tag_id),
-              FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE,
-              FOREIGN KEY(tag_id) REFERENCES tags(id) ON DELETE CASCADE
-            );
+    fn assets_dir(&self) -> PathBuf {
+        self.data_dir().join(ASSETS_DIR_NAME)
+    }
 
-            CREATE TABLE IF NOT EXISTS imports (
-              id TEXT PRIMARY KEY,
-              source TEXT NOT NULL,
-              imported_count INTEGER NOT NULL,
-              skipped_count INTEGER NOT NULL,
-              conflict_count INTEGER NOT NULL,
-              created_at TEXT NOT NULL
-            );
+    fn backups_dir(&self) -> PathBuf {
+        self.data_dir().join("backups")
+    }
 
-            CREATE TABLE IF NOT EXISTS source_profiles (
-              id TEXT PRIMARY KEY,
-              source TEXT NOT NULL,
-              profile_name TEXT NOT NULL,
-              profile_json TEXT NOT NULL,
-              created_at TEXT NOT NULL,
-              updated_at TEXT NOT NULL
-            );
+    /// Where a stored `local_path` points on disk. Paths are kept relative to
+    /// the data dir so it can be moved or restored elsewhere; absolute ones
+    /// are files outside it.
+    fn resolve_asset_path(&self, local_path: &str) -> PathBuf {
+        let path = Path::new(local_path);
+        if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.data_dir().join(path)
+        }
+    }
 
-            CREATE TABLE IF NOT EXISTS attachments (
-              id TEXT PRIMARY KEY,
-              message_id TEXT NOT NULL,
-              conversation_id TEXT NOT NULL,
-              kind TEXT NOT NULL,
-              original_url TEXT NOT NULL,
-              local_path TEXT,
-              mime TEXT,
-              size_bytes INTEGER,
-              sha256 TEXT,
-              status TEXT NOT NULL,
-              error TEXT,
-              created_at TEXT NOT NULL,
-              FOREIGN KEY(message_id) REFERENCES messages(id) ON DELETE CASCADE,
-              FOREIGN KEY(conversation_id) REFERENCES conversations(id) ON DELETE CASCADE
-            );
+    /// `local_path` as handed to the UI, which needs an absolute path.
+    fn resolved_local_path(&self, local_path: Option<String>) -> Option<String> {
+        local_path.map(|path| {
+            self.resolve_asset_path(&path)
+                .to_string_lossy()
+                .to_string()
+        })
+    }
 
-            CREATE INDEX IF NOT EXISTS idx_conversations_updated_at ON conversations(updated_at);
-            CREATE INDEX IF NOT EXISTS idx_messages_conv_seq ON messages(conversation_id, seq);
-            CREATE UNIQUE INDEX IF NOT EXISTS idx_conversations_fingerprint ON conversations(fingerprint);
-            CREATE INDEX IF NOT EXISTS idx_attachments_message_id ON attachments(message_id);
-            CREATE INDEX IF NOT EXISTS idx_attachments_conv_id ON attachments(conversation_id);
-            CREATE INDEX IF NOT EXISTS idx_attachments_sha256 ON attachments(sha256);
-
-            CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
-              message_id UNINDEXED,
-              conversation_id UNINDEXED,
-              content_markdown
-            );
-            "#,
-        )
-        .map_err(|e| format!("migrate failed: {e}"))?;
+    fn open(&self) -> AppResult<DbConnection> {
+        Ok(self.pool.get()?)
+    }
 
-        // Backward-compatible column migration for existing installs.
-        let _ = conn.execute("ALTER TABLE messages ADD COLUMN thought_markdown TEXT", []);
+    fn migrate(&self) -> AppResult<()> {
+        let mut conn = self.open()?;
+        migrations::run(&mut conn)?;
+        search::run_queued_rebuild(&conn)?;
         self.ensure_system_folders(&conn)?;
 
         Ok(())
     }
 
-    fn ensure_system_folders(&self, conn: &Connection) -> Result<(), String> {
+    fn ensure_system_folders(&self, conn: &Connection) -> AppResult<()> {
         let existing: Option<String> = conn
             .query_row(
                 "SELECT id FROM folders WHERE id = ?1 LIMIT 1",
                 params![UNCATEGORIZED_FOLDER_ID],
                 |row| row.get(0),
             )
-            .optional()
-            .map_err(|e| e.to_string())?;
+            .optional()?;
 
         if existing.is_some() {
             return Ok(());
@@ -173,8 +158,7 @@ impl Database {
                 "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM folders WHERE parent_id IS NULL",
                 [],
                 |row| row.get(0),
-            )
-            .map_err(|e| e.to_string())?;
+            )?;
 
         let now = now_iso();
         conn.execute(
@@ -189,65 +173,41 @@ impl Database {
                 now.clone(),
                 now
             ],
-        )
-        .map_err(|e| e.to_string())?;
+        )?;
 
         Ok(())
     }
 
-    pub fn list_folders(&self) -> Result<Vec<Folder>, String> {
+    /// Folders and smart folders together, each with its live conversation
+    /// count, in sibling order.
+    pub fn list_folders(&self) -> AppResult<Vec<Folder>> {
         let conn = self.open()?;
-        let mut stmt = conn
-            .prepare(
-                r#"
-                SELECT id, name, parent_id, sort_order, created_at, updated_at
-                FROM folders
-                ORDER BY sort_order ASC, created_at ASC
-                "#,
-            )
-            .map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT {FOLDER_COLUMNS} FROM folders f ORDER BY f.sort_order ASC, f.created_at ASC"
+        ))?;
 
-        let rows = stmt
-            .query_map([], |row| {
-                Ok(Folder {
-                    id: row.get(0)?,
-                    name: row.get(1)?,
-                    parent_id: row.get(2)?,
-                    sort_order: row.get(3)?,
-                    created_at: row.get(4)?,
-                    updated_at: row.get(5)?,
-                })
-            })
-            .map_err(|e| e.to_string())?;
+        let rows = stmt.query_map(params![UNCATEGORIZED_FOLDER_ID], row_to_folder)?;
 
         let mut folders = Vec::new();
         for row in rows {
-            folders.push(row.map_err(|e| e.to_string())?);
+            folders.push(row?);
         }
+        folders.extend(smart_folders::list_smart_folders(&conn)?);
+        folders.sort_by(|a, b| {
+            a.sort_order
+                .cmp(&b.sort_order)
+                .then_with(|| a.created_at.cmp(&b.created_at))
+        });
         Ok(folders)
     }
 
-    pub fn create_folder(&self, name: String, parent_id: Option<String>) -> Result<Folder, String> {
+    pub fn create_folder(&self, name: String, parent_id: Option<String>) -> AppResult<Folder> {
         let conn = self.open()?;
         self.ensure_system_folders(&conn)?;
         let now = now_iso();
         let id = Uuid::new_v4().to_string();
 
-        let sort_order: i64 = if parent_id.is_some() {
-            conn.query_row(
-                "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM folders WHERE parent_id = ?1",
-                params![parent_id.clone()],
-                |row| row.get(0),
-            )
-            .map_err(|e| e.to_string())?
-        } else {
-            conn.query_row(
-                "SELECT COALESCE(MAX(sort_order), -1) + 1 FROM folders WHERE parent_id IS NULL",
-                [],
-                |row| row.get(0),
-            )
-            .map_err(|e| e.to_string())?
-        };
+        let sort_order = next_sort_order(&conn, parent_id.as_deref())?;
 
         conn.execute(
             r#"
@@ -255,8 +215,7 @@ impl Database {
             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
             "#,
             params![id, name, parent_id, sort_order, now, now],
-        )
-        .map_err(|e| e.to_string())?;
+        )?;
 
         Ok(Folder {
             id,
@@ -265,195 +224,276 @@ impl Database {
             sort_order,
             created_at: now.clone(),
             updated_at: now,
+            conversation_count: 0,
+            filter: None,
         })
     }
 
-    pub fn move_folder(&self, id: String, parent_id: Option<String>) -> Result<(), String> {
+    pub fn rename_folder(&self, id: String, name: String) -> AppResult<Folder> {
         if id == UNCATEGORIZED_FOLDER_ID {
-            return Err("未分类文件夹不可移动".to_string());
+            return Err(AppError::InvalidInput("未分类文件夹不可重命名".to_string()));
+        }
+        let name = name.trim().to_string();
+        if name.is_empty() {
+            return Err(AppError::InvalidInput("文件夹名称不能为空".to_string()));
         }
         let conn = self.open()?;
-        conn.execute(
-            "UPDATE folders SET parent_id = ?1, updated_at = ?2 WHERE id = ?3",
-            params![parent_id, now_iso(), id],
-        )
-        .map_err(|e| e.to_string())?;
-        Ok(())
+        let updated = conn.execute(
+            "UPDATE folders SET name = ?1, updated_at = ?2 WHERE id = ?3",
+            params![name, now_iso(), id],
+        )?;
+        if updated == 0 {
+            return Err(AppError::NotFound(format!("folder {id} not found")));
+        }
+        load_folder(&conn, &id)
+    }
+
+    /// Sets the order of the folders and smart folders directly under
+    /// `parent_id`; `ids` must list every one of them exactly once.
+    pub fn reorder_folders(
+        &self,
+        parent_id: Option<String>,
+        ids: Vec<String>,
+    ) -> AppResult<Vec<Folder>> {
+        let conn = self.open()?;
+        let tx = conn.unchecked_transaction()?;
+        let siblings: HashSet<String> = {
+            let mut stmt = tx.prepare(
+                r#"
+                SELECT id FROM folders WHERE parent_id IS ?1
+                UNION ALL
+                SELECT id FROM smart_folders WHERE parent_id IS ?1
+                "#,
+            )?;
+            let rows = stmt.query_map(params![parent_id], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let requested: HashSet<String> = ids.iter().cloned().collect();
+        if requested.len() != ids.len() || requested != siblings {
+            return Err(AppError::InvalidInput(
+                "排序列表必须包含该层级的全部文件夹且不能重复".to_string(),
+            ));
+        }
+        let now = now_iso();
+        for (sort_order, id) in ids.iter().enumerate() {
+            for table in ["folders", "smart_folders"] {
+                tx.execute(
+                    &format!("UPDATE {table} SET sort_order = ?1, updated_at = ?2 WHERE id = ?3"),
+                    params![sort_order as i64, now, id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        drop(conn);
+        self.list_folders()
     }
 
-    pub fn delete_folder(&self, id: String) -> Result<(), String> {
+    /// Re-parents a folder, appending it after its new siblings. Rejects
+    /// unknown parents and moves that would make a folder its own ancestor.
+    pub fn move_folder(&self, id: String, parent_id: Option<String>) -> AppResult<()> {
         if id == UNCATEGORIZED_FOLDER_ID {
-            return Err("未分类文件夹不可删除".to_string());
+            return Err(AppError::InvalidInput("未分类文件夹不可移动".to_string()));
         }
         let conn = self.open()?;
-        self.ensure_system_folders(&conn)?;
-        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
-
-        tx.execute(
-            "UPDATE folders SET parent_id = NULL, updated_at = ?1 WHERE parent_id = ?2",
-            params![now_iso(), id.clone()],
-        )
-        .map_err(|e| e.to_string())?;
-
+        let tx = conn.unchecked_transaction()?;
+        let current_parent: Option<String> = tx
+            .query_row(
+                "SELECT parent_id FROM folders WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("folder {id} not found")))?;
+        if current_parent == parent_id {
+            return Ok(());
+        }
+        if let Some(parent_id) = parent_id.as_deref() {
+            let parent_exists: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM folders WHERE id = ?1)",
+                params![parent_id],
+                |row| row.get(0),
+            )?;
+            if !parent_exists {
+                return Err(AppError::NotFound(format!("folder {parent_id} not found")));
+            }
+            // UNION (not UNION ALL) also stops on a cycle left by older builds.
+            let would_cycle: bool = tx.query_row(
+                r#"
+                WITH RECURSIVE ancestors(id) AS (
+                  SELECT ?1
+                  UNION
+                  SELECT f.parent_id FROM folders f JOIN ancestors a ON f.id = a.id
+                  WHERE f.parent_id IS NOT NULL
+                )
+                SELECT EXISTS (SELECT 1 FROM ancestors WHERE id = ?2)
+                "#,
+                params![parent_id, id],
+                |row| row.get(0),
+            )?;
+            if would_cycle {
+                return Err(AppError::InvalidInput(
+                    "不能将文件夹移动到其自身或子文件夹中".to_string(),
+                ));
+            }
+        }
+        let sort_order = next_sort_order(&tx, parent_id.as_deref())?;
         tx.execute(
-            "UPDATE conversations SET folder_id = ?1, updated_at = ?2 WHERE folder_id = ?3",
-            params![UNCATEGORIZED_FOLDER_ID, now_iso(), id.clone()],
-        )
-        .map_err(|e| e.to_string())?;
-
-        tx.execute("DELETE FROM folders WHERE id = ?1", params![id])
-            .map_err(|e| e.to_string())?;
-
-        tx.commit().map_err(|e| e.to_string())?;
+            "UPDATE folders SET parent_id = ?1, sort_order = ?2, updated_at = ?3 WHERE id = ?4",
+            params![parent_id, sort_order, now_iso(), id],
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn move_conversation(&self, id: String, folder_id: Option<String>) -> Result<(), String> {
+    /// Deletes a folder; its conversations go to the uncategorized folder. With
+    /// `recursive` every subfolder and smart folder below it is deleted too,
+    /// otherwise they move up to the deleted folder's parent.
+    pub fn delete_folder(&self, id: String, recursive: bool) -> AppResult<()> {
+        if id == UNCATEGORIZED_FOLDER_ID {
+            return Err(AppError::InvalidInput("未分类文件夹不可删除".to_string()));
+        }
         let conn = self.open()?;
         self.ensure_system_folders(&conn)?;
-        let normalized_folder = folder_id.or_else(|| Some(UNCATEGORIZED_FOLDER_ID.to_string()));
-        conn.execute(
-            "UPDATE conversations SET folder_id = ?1, updated_at = ?2 WHERE id = ?3",
-            params![normalized_folder, now_iso(), id],
-        )
-        .map_err(|e| e.to_string())?;
+        let tx = conn.unchecked_transaction()?;
+
+        let parent_id: Option<String> = tx
+            .query_row(
+                "SELECT parent_id FROM folders WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("folder {id} not found")))?;
+        let now = now_iso();
+
+        let doomed: Vec<String> = if recursive {
+            let mut stmt = tx.prepare(
+                r#"
+                WITH RECURSIVE subtree(id) AS (
+                  SELECT ?1
+                  UNION
+                  SELECT f.id FROM folders f JOIN subtree s ON f.parent_id = s.id
+                )
+                SELECT id FROM subtree WHERE id != ?2
+                "#,
+            )?;
+            let rows = stmt.query_map(params![id, UNCATEGORIZED_FOLDER_ID], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        } else {
+            let children: Vec<(String, &str)> = {
+                let mut stmt = tx.prepare(
+                    r#"
+                    SELECT id, 'folders', sort_order, created_at FROM folders WHERE parent_id = ?1
+                    UNION ALL
+                    SELECT id, 'smart_folders', sort_order, created_at FROM smart_folders
+                    WHERE parent_id = ?1
+                    ORDER BY 3 ASC, 4 ASC
+                    "#,
+                )?;
+                let rows = stmt.query_map(params![id], |row| {
+                    let table: String = row.get(1)?;
+                    let table = if table == "folders" { "folders" } else { "smart_folders" };
+                    Ok((row.get(0)?, table))
+                })?;
+                rows.collect::<rusqlite::Result<_>>()?
+            };
+            let first_order = next_sort_order(&tx, parent_id.as_deref())?;
+            for (offset, (child, table)) in children.iter().enumerate() {
+                tx.execute(
+                    &format!(
+                        "UPDATE {table} SET parent_id = ?1, sort_order = ?2, updated_at = ?3 \
+                         WHERE id = ?4"
+                    ),
+                    params![parent_id, first_order + offset as i64, now, child],
+                )?;
+            }
+            vec![id]
+        };
+
+        let marks = search::placeholders(doomed.len());
+        let reassigned = std::iter::once(UNCATEGORIZED_FOLDER_ID)
+            .chain(doomed.iter().map(String::as_str));
+        tx.execute(
+            &format!("UPDATE conversations SET folder_id = ? WHERE folder_id IN ({marks})"),
+            params_from_iter(reassigned),
+        )?;
+        tx.execute(
+            &format!("DELETE FROM smart_folders WHERE parent_id IN ({marks})"),
+            params_from_iter(doomed.iter()),
+        )?;
+        tx.execute(
+            &format!("DELETE FROM folders WHERE id IN ({marks})"),
+            params_from_iter(doomed.iter()),
+        )?;
+
+        tx.commit()?;
         Ok(())
     }
 
-    pub fn list_conversations(
-        &self,
-        input: Option<ListConversationsInput>,
-    ) -> Result<Vec<ConversationSummary>, String> {
+    pub fn move_conversation(&self, id: String, folder_id: Option<String>) -> AppResult<()> {
         let conn = self.open()?;
         self.ensure_system_folders(&conn)?;
-        let mut sql = String::from(
-            r#"
-            SELECT
-              c.id,
-              c.source,
-              c.source_conversation_id,
-              c.folder_id,
-              c.title,
-              c.summary,
-              c.created_at,
-              c.updated_at,
-              c.fingerprint,
-              c.meta_json,
-              COUNT(m.id) as message_count
-            FROM conversations c
-            LEFT JOIN messages m ON m.conversation_id = c.id
-            WHERE 1 = 1
-            "#,
-        );
-
-        let mut values: Vec<Value> = Vec::new();
-        if let Some(i) = input {
-            if let Some(folder_id) = i.folder_id {
-                if folder_id == UNCATEGORIZED_FOLDER_ID {
-                    sql.push_str(" AND (c.folder_id = ? OR c.folder_id IS NULL) ");
-                    values.push(Value::Text(folder_id));
-                } else {
-                    sql.push_str(" AND c.folder_id = ? ");
-                    values.push(Value::Text(folder_id));
-                }
-            }
-
-            if let Some(source) = i.source {
-                if source != "all" {
-                    sql.push_str(" AND c.source = ? ");
-                    values.push(Value::Text(source));
-                }
+        if let Some(folder_id) = folder_id.as_deref() {
+            if smart_folders::load_filter(&conn, folder_id)?.is_some() {
+                return Err(AppError::InvalidInput(
+                    "智能文件夹按条件自动收录会话，不能手动移入".to_string(),
+                ));
             }
         }
-
-        sql.push_str(" GROUP BY c.id ORDER BY c.created_at DESC ");
-
-        let mut stmt = conn.prepare(&sql).map_err(|e| e.to_string())?;
-        let rows = stmt
-            .query_map(params_from_iter(values.iter()), |row| {
-                let conv = row_to_conversation(row)?;
-                Ok(ConversationSummary {
-                    conversation: conv,
-                    message_count: row.get(10)?,
-                })
-            })
-            .map_err(|e| e.to_string())?;
-
-        let mut list = Vec::new();
-        for row in rows {
-            list.push(row.map_err(|e| e.to_string())?);
+        let normalized_folder = folder_id.or_else(|| Some(UNCATEGORIZED_FOLDER_ID.to_string()));
+        let updated = conn.execute(
+            "UPDATE conversations SET folder_id = ?1 WHERE id = ?2",
+            params![normalized_folder, id],
+        )?;
+        if updated == 0 {
+            return Err(AppError::NotFound(format!("conversation {id} not found")));
         }
-
-        Ok(list)
+        Ok(())
     }
 
-    pub fn open_conversation(&self, id: String) -> Result<Option<ConversationDetail>, String> {
+    pub fn open_conversation(&self, id: String) -> AppResult<Option<ConversationDetail>> {
         let conn = self.open()?;
 
-        let mut stmt = conn
-            .prepare(
-                r#"
-                SELECT id, source, source_conversation_id, folder_id, title, summary, created_at, updated_at, fingerprint, meta_json
-                FROM conversations
-                WHERE id = ?1
-                LIMIT 1
-                "#,
-            )
-            .map_err(|e| e.to_string())?;
+        let mut stmt = conn.prepare_cached(&format!(
+            r#"
+            SELECT {CONVERSATION_COLUMNS}
+            FROM conversations c
+            WHERE c.id = ?1
+            LIMIT 1
+            "#
+        ))?;
 
-        let mut rows = stmt.query(params![id]).map_err(|e| e.to_string())?;
-        let Some(row) = rows.next().map_err(|e| e.to_string())? else {
+        let mut rows = stmt.query(params![id])?;
+        let Some(row) = rows.next()? else {
             return Ok(None);
         };
 
-        let conversation = Conversation {
-            id: row.get(0).map_err(|e| e.to_string())?,
-            source: row.get(1).map_err(|e| e.to_string())?,
-            source_conversation_id: row.get(2).map_err(|e| e.to_string())?,
-            folder_id: row.get(3).map_err(|e| e.to_string())?,
-            title: row.get(4).map_err(|e| e.to_string())?,
-            summary: row.get(5).map_err(|e| e.to_string())?,
-            created_at: row.get(6).map_err(|e| e.to_string())?,
-            updated_at: row.get(7).map_err(|e| e.to_string())?,
-            fingerprint: row.get(8).map_err(|e| e.to_string())?,
-            meta_json: row.get(9).map_err(|e| e.to_string())?,
-        };
-        self.promote_attachment_kinds(&conversation.id)?;
+        let conversation = row_to_conversation(row)?;
+        self.promote_attachment_kinds(&conn, &conversation.id)?;
 
-        let mut message_stmt = conn
-            .prepare(
-                r#"
-                SELECT id, conversation_id, seq, role, content_markdown, thought_markdown, model, timestamp, token_count
-                FROM messages
-                WHERE conversation_id = ?1
-                ORDER BY seq ASC
-                "#,
-            )
-            .map_err(|e| e.to_string())?;
+        let mut message_stmt = conn.prepare_cached(&format!(
+            r#"
+            SELECT {MESSAGE_COLUMNS}
+            FROM messages
+            WHERE conversation_id = ?1
+            ORDER BY seq ASC
+            "#
+        ))?;
 
-        let message_rows = message_stmt
-            .query_map(params![conversation.id.clone()], |row| {
-                Ok(Message {
-                    id: row.get(0)?,
-                    conversation_id: row.get(1)?,
-                    seq: row.get(2)?,
-                    role: row.get(3)?,
-                    content_markdown: row.get(4)?,
-                    thought_markdown: row.get(5)?,
-                    model: row.get(6)?,
-                    timestamp: row.get(7)?,
-                    token_count: row.get(8)?,
-                })
-            })
-            .map_err(|e| e.to_string())?;
+        let message_rows = message_stmt.query_map(params![conversation.id.clone()], row_to_message)?;
 
-        let mut messages = Vec::new();
+        let mut tree = Vec::new();
         for row in message_rows {
-            messages.push(row.map_err(|e| e.to_string())?);
+            tree.push(row?);
         }
+        let messages: Vec<Message> = tree
+            .iter()
+            .filter(|message| message.on_active_path)
+            .cloned()
+            .collect();
 
         let mut tag_stmt = conn
-            .prepare(
+            .prepare_cached(
                 r#"
                 SELECT t.name
                 FROM tags t
@@ -461,28 +501,25 @@ impl Database {
                 WHERE ct.conversation_id = ?1
                 ORDER BY t.name ASC
                 "#,
-            )
-            .map_err(|e| e.to_string())?;
+            )?;
 
         let tag_rows = tag_stmt
-            .query_map(params![conversation.id.clone()], |row| row.get::<_, String>(0))
-            .map_err(|e| e.to_string())?;
+            .query_map(params![conversation.id.clone()], |row| row.get::<_, String>(0))?;
 
         let mut tags = Vec::new();
         for tag in tag_rows {
-            tags.push(tag.map_err(|e| e.to_string())?);
+            tags.push(tag?);
         }
 
         let mut attachment_stmt = conn
-            .prepare(
+            .prepare_cached(
                 r#"
                 SELECT id, message_id, conversation_id, kind, original_url, local_path, mime, size_bytes, sha256, status, error, created_at
                 FROM attachments
                 WHERE conversation_id = ?1
                 ORDER BY created_at ASC
                 "#,
-            )
-            .map_err(|e| e.to_string())?;
+            )?;
 
         let attachment_rows = attachment_stmt
             .query_map(params![conversation.id.clone()], |row| {
@@ -492,7 +529,7 @@ impl Database {
                     conversation_id: row.get(2)?,
                     kind: row.get(3)?,
                     original_url: row.get(4)?,
-                    local_path: row.get(5)?,
+                    local_path: self.resolved_local_path(row.get(5)?),
                     mime: row.get(6)?,
                     size_bytes: row.get(7)?,
                     sha256: row.get(8)?,
@@ -500,17 +537,16 @@ impl Database {
                     error: row.get(10)?,
                     created_at: row.get(11)?,
                 })
-            })
-            .map_err(|e| e.to_string())?;
+            })?;
 
         let mut attachments = Vec::new();
         for row in attachment_rows {
-            attachments.push(row.map_err(|e| e.to_string())?);
+            attachments.push(row?);
         }
         append_virtual_named_attachments(
             &conversation.id,
             &conversation.updated_at,
-            &messages,
+            &tree,
             &mut attachments,
         );
 
@@ -525,28 +561,37 @@ impl Database {
                         && is_data_url(&attachment.original_url))
             });
         if needs_attachment_cache {
+            // Queueing checks out its own connection, so release this one first.
+            drop(rows);
+            drop((stmt, message_stmt, tag_stmt, attachment_stmt));
+            drop(conn);
             self.schedule_attachment_cache(conversation.id.clone());
         }
 
         Ok(Some(ConversationDetail {
             conversation,
             messages,
+            tree,
             tags,
             attachments,
         }))
     }
 
-    fn promote_attachment_kinds(&self, conversation_id: &str) -> Result<(), String> {
-        let conn = self.open()?;
+    /// Runs on the caller's connection: a second checkout while it is held
+    /// can starve the pool.
+    fn promote_attachment_kinds(
+        &self,
+        conn: &Connection,
+        conversation_id: &str,
+    ) -> AppResult<()> {
         let mut stmt = conn
-            .prepare(
+            .prepare_cached(
                 r#"
                 SELECT id, original_url, mime
                 FROM attachments
                 WHERE conversation_id = ?1 AND kind = 'file'
                 "#,
-            )
-            .map_err(|e| e.to_string())?;
+            )?;
 
         let rows = stmt
             .query_map(params![conversation_id], |row| {
@@ -555,12 +600,11 @@ impl Database {
                     row.get::<_, String>(1)?,
                     row.get::<_, Option<String>>(2)?,
                 ))
-            })
-            .map_err(|e| e.to_string())?;
+            })?;
 
         let mut updates: Vec<(String, String, Option<String>)> = Vec::new();
         for row in rows {
-            let (id, original_url, mime) = row.map_err(|e| e.to_string())?;
+            let (id, original_url, mime) = row?;
             let normalized_url = normalize_attachment_url(&original_url);
             if normalized_url.is_empty() {
                 continue;
@@ -581,153 +625,33 @@ impl Database {
             conn.execute(
                 "UPDATE attachments SET kind = ?1, mime = COALESCE(?2, mime) WHERE id = ?3",
                 params![kind, mime, id],
-            )
-            .map_err(|e| e.to_string())?;
+            )?;
         }
 
         Ok(())
     }
 
-    pub fn search_conversations(&self, query: String) -> Result<Vec<SearchResult>, String> {
-        let conn = self.open()?;
-        let trimmed = query.trim().to_string();
-        if trimmed.is_empty() {
-            return Ok(vec![]);
-        }
-
-        let escaped = trimmed.replace('"', " ").replace('\'', " ");
-        let fts_query = format!("\"{}\"", escaped);
-
-        let mut results_map: HashMap<String, SearchResult> = HashMap::new();
-
-        let mut fts_stmt = conn
-            .prepare(
-                r#"
-                SELECT
-                  c.id,
-                  c.source,
-                  c.source_conversation_id,
-                  c.folder_id,
-                  c.title,
-                  c.summary,
-                  c.created_at,
-                  c.updated_at,
-                  c.fingerprint,
-                  c.meta_json,
-                  (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id) AS message_count,
-                  snippet(messages_fts, 2, '[', ']', '…', 12) as snippet
-                FROM messages_fts
-                JOIN conversations c ON c.id = messages_fts.conversation_id
-                WHERE messages_fts MATCH ?1
-                GROUP BY c.id
-                ORDER BY c.updated_at DESC
-                LIMIT 100
-                "#,
-            )
-            .map_err(|e| e.to_string())?;
-
-        let fts_rows = fts_stmt
-            .query_map(params![fts_query], |row| {
-                let summary = ConversationSummary {
-                    conversation: Conversation {
-                        id: row.get(0)?,
-                        source: row.get(1)?,
-                        source_conversation_id: row.get(2)?,
-                        folder_id: row.get(3)?,
-                        title: row.get(4)?,
-                        summary: row.get(5)?,
-                        created_at: row.get(6)?,
-                        updated_at: row.get(7)?,
-                        fingerprint: row.get(8)?,
-                        meta_json: row.get(9)?,
-                    },
-                    message_count: row.get(10)?,
-                };
-
-                Ok(SearchResult {
-                    conversation: summary,
-                    snippet: row.get(11)?,
-                })
-            })
-            .map_err(|e| e.to_string())?;
-
-        for row in fts_rows {
-            let result = row.map_err(|e| e.to_string())?;
-            results_map.insert(result.conversation.conversation.id.clone(), result);
-        }
-
-        let like_query = format!("%{}%", trimmed);
-        let mut title_stmt = conn
-            .prepare(
-                r#"
-                SELECT
-                  c.id,
-                  c.source,
-                  c.source_conversation_id,
-                  c.folder_id,
-                  c.title,
-                  c.summary,
-                  c.created_at,
-                  c.updated_at,
-                  c.fingerprint,
-                  c.meta_json,
-                  (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id) AS message_count
-                FROM conversations c
-                WHERE c.title LIKE ?1
-                ORDER BY c.updated_at DESC
-                LIMIT 50
-                "#,
-            )
-            .map_err(|e| e.to_string())?;
-
-        let title_rows = title_stmt
-            .query_map(params![like_query], |row| {
-                Ok(ConversationSummary {
-                    conversation: Conversation {
-                        id: row.get(0)?,
-                        source: row.get(1)?,
-                        source_conversation_id: row.get(2)?,
-                        folder_id: row.get(3)?,
-                        title: row.get(4)?,
-                        summary: row.get(5)?,
-                        created_at: row.get(6)?,
-                        updated_at: row.get(7)?,
-                        fingerprint: row.get(8)?,
-                        meta_json: row.get(9)?,
-                    },
-                    message_count: row.get(10)?,
-                })
-            })
-            .map_err(|e| e.to_string())?;
-
-        for row in title_rows {
-            let summary = row.map_err(|e| e.to_string())?;
-            results_map.entry(summary.conversation.id.clone()).or_insert(SearchResult {
-                snippet: summary.conversation.title.clone(),
-                conversation: summary,
-            });
-        }
-
-        let mut results: Vec<SearchResult> = results_map.into_values().collect();
-        results.sort_by(|a, b| b.conversation.conversation.updated_at.cmp(&a.conversation.conversation.updated_at));
-        results.truncate(100);
-
-        Ok(results)
-    }
-
-    pub fn import_files(&self, batch: ImportBatch) -> Result<ImportResult, String> {
+    pub fn import_files(&self, batch: ImportBatch) -> AppResult<ImportResult> {
         let conn = self.open()?;
         self.ensure_system_folders(&conn)?;
-        let tx = conn.unchecked_transaction().map_err(|e| e.to_string())?;
+        let tx = conn.unchecked_transaction()?;
 
         let mut imported = 0_i64;
         let mut skipped = 0_i64;
         let mut conflicts = 0_i64;
         let mut imported_conversation_ids: Vec<String> = Vec::new();
+        let mut purged_asset_paths: Vec<String> = Vec::new();
         let target_folder_id = batch
             .folder_id
             .clone()
             .or_else(|| Some(UNCATEGORIZED_FOLDER_ID.to_string()));
+        // Rules only file conversations the caller did not put in a folder.
+        let rules = rules::load_enabled_rules(&tx)?;
+        let rule_moves = if batch.folder_id.is_none() {
+            rules::MovePolicy::FromUncategorized
+        } else {
+            rules::MovePolicy::Never
+        };
 
         for conv in &batch.conversations {
             if conv.turns.is_empty() {
@@ -736,58 +660,87 @@ impl Database {
 
             let mut source_conversation_id = conv.source_conversation_id.clone();
             let mut fingerprint = compute_fingerprint(conv);
-            let existing_by_source = if let Some(source_id) = source_conversation_id.as_ref() {
-                find_existing_by_source_ref(&tx, &conv.source, source_id)?
-            } else {
-                None
+            // Overwrites keep the conversation id so its revision history stays attached.
+            let mut reused_conversation_id: Option<String> = None;
+            let mut overwritten: Option<OverwrittenRow> = None;
+            let mut kept_tag_ids: Vec<String> = Vec::new();
+            let live_match = match source_conversation_id.as_deref() {
+                Some(source_id) => find_existing_by_source_ref(&tx, &conv.source, source_id)?
+                    .map(|(id, _title)| (id, true)),
+                None => None,
+            };
+            let live_match = match live_match {
+                Some(found) => Some(found),
+                None => {
+                    find_existing_by_fingerprint(&tx, &fingerprint)?.map(|(id, _title)| (id, false))
+                }
             };
 
-            if let Some((existing_id, _existing_title)) = existing_by_source {
+            let mut replaced_id: Option<String> = None;
+            if let Some((existing_id, by_source)) = live_match {
                 conflicts += 1;
                 match batch.strategy.as_str() {
-                    "skip" => {
-                        skipped += 1;
-                        continue;
-                    }
-                    "overwrite" => {
-                        delete_conversation_for_overwrite(&tx, &existing_id)?;
-                    }
+                    "overwrite" => replaced_id = Some(existing_id),
                     "duplicate" => {
-                        source_conversation_id = source_conversation_id
-                            .map(|id| format!("{}#dup-{}", id, Uuid::new_v4()));
+                        if by_source {
+                            source_conversation_id = source_conversation_id
+                                .map(|id| format!("{}#dup-{}", id, Uuid::new_v4()));
+                        }
                         fingerprint = format!("{}-dup-{}", fingerprint, Uuid::new_v4());
                     }
+                    // "skip" and unknown strategies.
                     _ => {
                         skipped += 1;
                         continue;
                     }
                 }
-            } else {
-                let existing_by_fingerprint = find_existing_by_fingerprint(&tx, &fingerprint)?;
-                if let Some((existing_id, _existing_title)) = existing_by_fingerprint {
-                    conflicts += 1;
-                    match batch.strategy.as_str() {
-                        "skip" => {
-                            skipped += 1;
-                            continue;
-                        }
-                        "overwrite" => {
-                            delete_conversation_for_overwrite(&tx, &existing_id)?;
-                        }
-                        "duplicate" => {
-                            fingerprint = format!("{}-dup-{}", fingerprint, Uuid::new_v4());
-                        }
-                        _ => {
-                            skipped += 1;
-                            continue;
-                        }
-                    }
-                }
+            } else if batch.strategy == "overwrite" {
+                // Trashed conversations are only brought back by an import
+                // that replaces them; re-inserting the row clears `deleted_at`.
+                replaced_id = trash::find_trashed_match(
+                    &tx,
+                    &conv.source,
+                    source_conversation_id.as_deref(),
+                    &fingerprint,
+                )?;
+            }
+
+            if let Some(existing_id) = replaced_id {
+                revisions::snapshot_before_overwrite(
+                    &tx,
+                    &existing_id,
+                    &conv.source,
+                    &conv.turns,
+                    conv.current_turn_id.as_deref(),
+                )?;
+                kept_tag_ids = tags::conversation_tag_ids(&tx, &existing_id)?;
+                overwritten = Some(load_overwritten_row(&tx, &existing_id)?);
+                purged_asset_paths.extend(delete_conversation_for_overwrite(&tx, &existing_id)?);
+                reused_conversation_id = Some(existing_id);
+            }
+            // Anything still holding the fingerprint is in the trash and stays
+            // there, so the new row needs its own.
+            if fingerprint_taken(&tx, &fingerprint)? {
+                fingerprint = format!("{}-dup-{}", fingerprint, Uuid::new_v4());
             }
 
-            let conversation_id = Uuid::new_v4().to_string();
-            let created_at = now_iso();
-            let updated_at = created_at.clone();
+            let conversation_id =
+                reused_conversation_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+            let mut imported_at = now_iso();
+            let (mut created_at, updated_at) = resolve_conversation_times(conv, &imported_at);
+            // An overwrite stays where it was filed, unless the caller picked a
+            // folder, and keeps when it was first created and imported.
+            let mut folder_id = target_folder_id.clone();
+            if let Some(row) = overwritten {
+                if batch.folder_id.is_none() {
+                    folder_id = row.folder_id;
+                }
+                created_at = created_at.min(row.created_at);
+                if let Some(first_imported_at) = row.imported_at {
+                    imported_at = first_imported_at;
+                }
+            }
+            let captured_at = conv.captured_at.as_deref().and_then(normalize_timestamp);
             let meta_json = conv
                 .meta
                 .clone()
@@ -798,173 +751,42 @@ impl Database {
                 r#"
                 INSERT INTO conversations (
                     id, source, source_conversation_id, folder_id, title, summary,
-                    created_at, updated_at, fingerprint, meta_json
-                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                    created_at, updated_at, fingerprint, meta_json, imported_at, captured_at
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
                 "#,
                 params![
                     conversation_id.clone(),
                     conv.source.clone(),
                     source_conversation_id,
-                    target_folder_id.clone(),
+                    folder_id,
                     conv.title.clone(),
                     conv.summary.clone(),
                     created_at,
                     updated_at,
                     fingerprint,
                     meta_json,
+                    imported_at,
+                    captured_at,
                 ],
-            )
-            .map_err(|e| e.to_string())?;
-
-            for (idx, turn) in conv.turns.iter().enumerate() {
-                let msg_id = Uuid::new_v4().to_string();
-                let seq = idx as i64;
-                let thought_markdown = if conv.source == "gemini" {
-                    None
-                } else {
-                    turn.thought_markdown.clone()
-                };
-                tx.execute(
-                    r#"
-                    INSERT INTO messages (
-                      id, conversation_id, seq, role, content_markdown, thought_markdown, model, timestamp, token_count
-                    ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
-                    "#,
-                    params![
-                        msg_id,
-                        conversation_id.clone(),
-                        seq,
-                        turn.role.clone(),
-                        turn.content_markdown.clone(),
-                        thought_markdown,
-                        turn.model.clone(),
-                        turn.timestamp.clone(),
-                        turn.token_count,
-                    ],
-                )
-                .map_err(|e| e.to_string())?;
-
-                tx.execute(
-                    "INSERT INTO messages_fts (message_id, conversation_id, content_markdown) VALUES (?1, ?2, ?3)",
-                    params![msg_id.clone(), conversation_id.clone(), turn.content_markdown.clone()],
-                )
-                .map_err(|e| e.to_string())?;
-
-                let mut seen_attachment_urls: HashSet<String> = HashSet::new();
-                let mut has_non_virtual_attachment = false;
-                if let Some(attachments) = turn.attachments.as_ref() {
-                    for attachment in attachments {
-                        let normalized_url = normalize_attachment_url(&attachment.original_url);
-                        if normalized_url.is_empty() {
-                            continue;
-                        }
-                        if is_navigation_url(&normalized_url) {
-                            continue;
-                        }
-                        if !seen_attachment_urls.insert(normalized_url.clone()) {
-                            continue;
-                        }
-                        if !is_virtual_attachment_url(&normalized_url) {
-                            has_non_virtual_attachment = true;
-                        }
-                        let normalized_kind = classify_attachment_kind(
-                            &attachment.kind,
-                            &normalized_url,
-                            attachment.mime.as_deref(),
-                        );
-                        if normalized_kind == "file" && !looks_like_file_url(&normalized_url) {
-                            continue;
-                        }
-
-                        let status = attachment
-                            .status
-                            .as_ref()
-                            .map(|s| s.as_str())
-                            .unwrap_or("remote_only");
-
-                        tx.execute(
-                            r#"
-                            INSERT INTO attachments (
-                              id, message_id, conversation_id, kind, original_url, local_path,
-                              mime, size_bytes, sha256, status, error, created_at
-                            ) VALUES (?1, ?2, ?3, ?4, ?5, NULL, ?6, NULL, NULL, ?7, NULL, ?8)
-                            "#,
-                            params![
-                                Uuid::new_v4().to_string(),
-                                msg_id.clone(),
-                                conversation_id.clone(),
-                                normalized_kind,
-                                normalized_url,
-                                attachment
-                                    .mime
-                                    .clone()
-                                    .or_else(|| infer_attachment_mime(&normalized_url)),
-                                status,
-                                now_iso(),
-                            ],
-                        )
-                        .map_err(|e| e.to_string())?;
-                    }
-                }
+            )?;
 
-                for (kind, url, mime) in extract_inline_attachments(&turn.content_markdown) {
-                    if kind != "image" && kind != "pdf" && kind != "file" {
-                        continue;
-                    }
-                    if !seen_attachment_urls.insert(url.clone()) {
-                        continue;
-                    }
-                    if !is_virtual_attachment_url(&url) {
-                        has_non_virtual_attachment = true;
-                    }
-                    tx.execute(
-                        r#"
-                        INSERT INTO attachments (
-                          id, message_id, conversation_id, kind, original_url, local_path,
-                          mime, size_bytes, sha256, status, error, created_at
-                        ) VALUES (?1, ?2, ?3, ?4, ?5, NULL, ?6, NULL, NULL, 'remote_only', NULL, ?7)
-                        "#,
-                        params![
-                            Uuid::new_v4().to_string(),
-                            msg_id.clone(),
-                            conversation_id.clone(),
-                            kind,
-                            url,
-                            mime,
-                            now_iso(),
-                        ],
-                    )
-                    .map_err(|e| e.to_string())?;
-                }
+            let placements = tree::plan_message_tree(&conv.turns, conv.current_turn_id.as_deref());
+            let mut message_ids: Vec<String> = Vec::with_capacity(conv.turns.len());
+            for (idx, (turn, placement)) in conv.turns.iter().zip(&placements).enumerate() {
+                let link = MessageLink {
+                    parent_id: placement.parent.map(|parent| message_ids[parent].as_str()),
+                    branch_index: placement.branch_index,
+                    on_active_path: placement.on_active_path,
+                };
+                let message_id =
+                    insert_message(&tx, &conversation_id, &conv.source, idx as i64, turn, &link)?;
+                message_ids.push(message_id);
+            }
 
-                if turn.role.eq_ignore_ascii_case("user") && !has_non_virtual_attachment {
-                    for (kind, url, mime) in extract_named_file_attachments(&turn.content_markdown) {
-                        if kind != "image" && kind != "pdf" && kind != "file" {
-                            continue;
-                        }
-                        if !seen_attachment_urls.insert(url.clone()) {
-                            continue;
-                        }
-                        tx.execute(
-                            r#"
-                            INSERT INTO attachments (
-                              id, message_id, conversation_id, kind, original_url, local_path,
-                              mime, size_bytes, sha256, status, error, created_at
-                            ) VALUES (?1, ?2, ?3, ?4, ?5, NULL, ?6, NULL, NULL, 'remote_only', NULL, ?7)
-                            "#,
-                            params![
-                                Uuid::new_v4().to_string(),
-                                msg_id.clone(),
-                                conversation_id.clone(),
-                                kind,
-                                url,
-                                mime,
-                                now_iso(),
-                            ],
-                        )
-                        .map_err(|e| e.to_string())?;
-                    }
-                }
+            tags::restore_tag_ids(&tx, &conversation_id, &kept_tag_ids)?;
+            tags::apply_tag_names(&tx, &conversation_id, &conv.tags)?;
+            if let Some(change) = rules::plan_change(&tx, &rules, &conversation_id, rule_moves)? {
+                rules::apply_change(&tx, &change)?;
             }
 
             imported += 1;
@@ -981,10 +803,10 @@ impl Database {
                 conflicts,
                 now_iso(),
             ],
-        )
-        .map_err(|e| e.to_string())?;
+        )?;
 
-        tx.commit().map_err(|e| e.to_string())?;
+        tx.commit()?;
+        self.remove_unreferenced_assets(&conn, &purged_asset_paths)?;
         for conversation_id in imported_conversation_ids {
             self.schedule_attachment_cache(conversation_id);
         }
@@ -996,7 +818,7 @@ impl Database {
         })
     }
 
-    pub fn import_live_capture(&self, request: LiveCaptureRequest) -> Result<ImportResult, String> {
+    pub fn import_live_capture(&self, request: LiveCaptureRequest) -> AppResult<ImportResult> {
         let LiveCaptureRequest {
             source,
             page_url,
@@ -1004,12 +826,13 @@ impl Database {
             turns,
             captured_at,
             version,
+            tags,
         } = request;
 
         let canonical_page_url = canonicalize_source_url(&page_url);
         let sanitized_turns = sanitize_live_capture_turns(&source, turns);
         if sanitized_turns.is_empty() {
-            return Err("未提取到有效会话内容".to_string());
+            return Err(AppError::InvalidInput("未提取到有效会话内容".to_string()));
         }
 
         let conv = NormalizedConversation {
@@ -1018,14 +841,21 @@ impl Database {
             title,
             summary: None,
             created_at: Some(captured_at.clone()),
-            updated_at: Some(captured_at),
+            updated_at: Some(captured_at.clone()),
+            captured_at: Some(captured_at),
+            current_turn_id: None,
             turns: sanitized_turns,
             meta: Some(serde_json::json!({
                 "capturedBy": "extension",
                 "version": version
             })),
+            tags,
         };
 
+        if let Some(result) = self.recapture_in_place(&conv)? {
+            return Ok(result);
+        }
+
         self.import_files(ImportBatch {
             conversations: vec![conv],
             strategy: "overwrite".to_string(),
@@ -1036,18 +866,17 @@ impl Database {
     pub fn list_conversation_attachments(
         &self,
         conversation_id: String,
-    ) -> Result<Vec<Attachment>, String> {
+    ) -> AppResult<Vec<Attachment>> {
         let conn = self.open()?;
         let mut stmt = conn
-            .prepare(
+            .prepare_cached(
                 r#"
                 SELECT id, message_id, conversation_id, kind, original_url, local_path, mime, size_bytes, sha256, status, error, created_at
                 FROM attachments
                 WHERE conversation_id = ?1
                 ORDER BY created_at ASC
                 "#,
-            )
-            .map_err(|e| e.to_string())?;
+            )?;
 
         let rows = stmt
             .query_map(params![conversation_id], |row| {
@@ -1057,7 +886,7 @@ impl Database {
                     conversation_id: row.get(2)?,
                     kind: row.get(3)?,
                     original_url: row.get(4)?,
-                    local_path: row.get(5)?,
+                    local_path: self.resolved_local_path(row.get(5)?),
                     mime: row.get(6)?,
                     size_bytes: row.get(7)?,
                     sha256: row.get(8)?,
@@ -1065,257 +894,74 @@ impl Database {
                     error: row.get(10)?,
                     created_at: row.get(11)?,
                 })
-            })
-            .map_err(|e| e.to_string())?;
+            })?;
 
         let mut items = Vec::new();
         for row in rows {
-            items.push(row.map_err(|e| e.to_string())?);
+            items.push(row?);
         }
 
-        let mut message_stmt = conn
-            .prepare(
-                r#"
-                SELECT id, conversation_id, seq, role, content_markdown, thought_markdown, model, timestamp, token_count
-                FROM messages
-                WHERE conversation_id = ?1
-                ORDER BY seq ASC
-                "#,
-            )
-            .map_err(|e| e.to_string())?;
-        let message_rows = message_stmt
-            .query_map(params![conversation_id.clone()], |row| {
-                Ok(Message {
-                    id: row.get(0)?,
-                    conversation_id: row.get(1)?,
-                    seq: row.get(2)?,
-                    role: row.get(3)?,
-                    content_markdown: row.get(4)?,
-                    thought_markdown: row.get(5)?,
-                    model: row.get(6)?,
-                    timestamp: row.get(7)?,
-                    token_count: row.get(8)?,
-                })
-            })
-            .map_err(|e| e.to_string())?;
+        let mut message_stmt = conn.prepare_cached(&format!(
+            r#"
+            SELECT {MESSAGE_COLUMNS}
+            FROM messages
+            WHERE conversation_id = ?1
+            ORDER BY seq ASC
+            "#
+        ))?;
+        let message_rows = message_stmt.query_map(params![conversation_id.clone()], row_to_message)?;
 
         let mut messages = Vec::new();
         for row in message_rows {
-            messages.push(row.map_err(|e| e.to_string())?);
+            messages.push(row?);
         }
         append_virtual_named_attachments(&conversation_id, &now_iso(), &messages, &mut items);
 
         Ok(items)
     }
 
-    fn schedule_attachment_cache(&self, conversation_id: String) {
-        let db = self.clone();
-        tauri::async_runtime::spawn_blocking(move || {
-            if let Err(err) = db.cache_attachments_for_conversation(&conversation_id) {
-                eprintln!(
-                    "attachment cache background task failed: conversation_id={}, error={}",
-                    conversation_id, err
-                );
-            }
-        });
-    }
+    /// Writes `bytes` into the assets dir under their content hash; identical
+    /// content shares one file. The extension and returned MIME come from the
+    /// content when it is recognised, falling back to `mime` and the URL. The
+    /// returned `local_path` is relative to the data dir.
+    pub(crate) fn store_asset(
+        &self,
+        bytes: &[u8],
+        url: &str,
+        mime: Option<&str>,
+    ) -> AppResult<StoredAsset> {
+        let assets_dir = self.assets_dir();
+        fs::create_dir_all(&assets_dir)?;
 
-    fn cache_attachments_for_conversation(&self, conversation_id: &str) -> Result<(), String> {
-        let pending = {
-            let conn = self.open()?;
-            let mut stmt = conn
-                .prepare(
-                    r#"
-                    SELECT a.id, a.kind, a.original_url, a.mime, c.source
-                    FROM attachments a
-                    JOIN conversations c ON c.id = a.conversation_id
-                    WHERE a.conversation_id = ?1
-                      AND (
-                        a.status = 'remote_only'
-                        OR a.status = 'failed'
-                        OR (a.status = 'cached' AND a.local_path IS NULL AND a.original_url LIKE 'data:%')
-                      )
-                    ORDER BY a.created_at ASC
-                    "#,
-                )
-                .map_err(|e| e.to_string())?;
-
-            let rows = stmt
-                .query_map(params![conversation_id], |row| {
-                    Ok((
-                        row.get::<_, String>(0)?,
-                        row.get::<_, String>(1)?,
-                        row.get::<_, String>(2)?,
-                        row.get::<_, Option<String>>(3)?,
-                        row.get::<_, String>(4)?,
-                    ))
-                })
-                .map_err(|e| e.to_string())?;
+        let mut hasher = Sha256::new();
+        hasher.update(bytes);
+        let sha = format!("{:x}", hasher.finalize());
 
-            let mut items = Vec::new();
-            for row in rows {
-                items.push(row.map_err(|e| e.to_string())?);
-            }
-            items
+        let mime = sniff::sniff_mime(bytes, mime).or_else(|| mime.map(str::to_string));
+        let ext = infer_file_extension(url, mime.as_deref());
+        let file_name = if ext.is_empty() {
+            sha.clone()
+        } else {
+            format!("{sha}.{ext}")
         };
-
-        let assets_dir = self
-            .db_path
-            .parent()
-            .unwrap_or_else(|| Path::new("."))
-            .join("assets");
-        fs::create_dir_all(&assets_dir).map_err(|e| e.to_string())?;
-
-        let client = BlockingHttpClient::builder()
-            .timeout(std::time::Duration::from_secs(15))
-            .redirect(reqwest::redirect::Policy::limited(5))
-            .build()
-            .map_err(|e| e.to_string())?;
-
-        for (attachment_id, kind, original_url, mime_hint, source) in pending {
-            let normalized_url = normalize_attachment_url(&original_url);
-            if normalized_url.is_empty() {
-                self.mark_attachment_failed(&attachment_id, "invalid_url".to_string())?;
-                continue;
-            }
-            if is_virtual_attachment_url(&normalized_url) {
-                continue;
-            }
-            let current_kind = normalize_attachment_kind(&kind);
-            if is_data_url(&normalized_url) {
-                let Some((data_mime, bytes)) = decode_data_url(&normalized_url) else {
-                    self.mark_attachment_failed(&attachment_id, "invalid_data_url".to_string())?;
-                    continue;
-                };
-                if bytes.is_empty() {
-                    self.mark_attachment_failed(&attachment_id, "empty_data_url".to_string())?;
-                    continue;
-                }
-
-                let mut hasher = Sha256::new();
-                hasher.update(&bytes);
-                let sha = format!("{:x}", hasher.finalize());
-
-                let mime = mime_hint.or(data_mime);
-                let final_kind =
-                    classify_attachment_kind(&current_kind, &normalized_url, mime.as_deref());
-                let ext = infer_file_extension(&normalized_url, mime.as_deref());
-                let file_name = if ext.is_empty() {
-                    sha.clone()
-                } else {
-                    format!("{sha}.{ext}")
-                };
-                let file_path = assets_dir.join(file_name);
-                if !file_path.exists() {
-                    fs::write(&file_path, &bytes).map_err(|e| e.to_string())?;
-                }
-
-                let local_path = file_path.to_string_lossy().to_string();
-                self.mark_attachment_cached(
-                    &attachment_id,
-                    local_path,
-                    mime,
-                    bytes.len() as i64,
-                    sha,
-                )?;
-                if final_kind != current_kind {
-                    self.update_attachment_kind(&attachment_id, final_kind)?;
-                }
-                continue;
-            }
-            let inferred_kind =
-                classify_attachment_kind(&current_kind, &normalized_url, mime_hint.as_deref());
-            let should_attempt_download = is_http_or_https_url(&normalized_url)
-                && (inferred_kind != "file" || looks_like_file_url(&normalized_url));
-            if (source == "gemini" || source == "ai_studio")
-                && looks_like_cloud_drive_file_url(&normalized_url)
-            {
-                continue;
-            }
-            if !should_attempt_download {
-                continue;
-            }
-
-            let response = match client
-                .get(normalized_url.clone())
-                .header(
-                    reqwest::header::USER_AGENT,
-                    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/122.0.0.0 Safari/537.36",
-                )
-                .header(reqwest::header::ACCEPT, "*/*")
-                .send()
-            {
-                Ok(resp) => resp,
-                Err(err) => {
-                    self.mark_attachment_failed(&attachment_id, err.to_string())?;
-                    continue;
-                }
-            };
-
-            if !response.status().is_success() {
-                self.mark_attachment_failed(
-                    &attachment_id,
-                    format!("http_status_{}", response.status().as_u16()),
-                )?;
-                continue;
-            }
-
-            let header_mime = response
-                .headers()
-                .get(reqwest::header::CONTENT_TYPE)
-                .and_then(|v| v.to_str().ok())
-                .map(|v| v.to_string());
-
-            let bytes = match response.bytes() {
-                Ok(bytes) => bytes,
-                Err(err) => {
-                    self.mark_attachment_failed(&attachment_id, err.to_string())?;
-                    continue;
-                }
-            };
-
-            let mut hasher = Sha256::new();
-            hasher.update(&bytes);
-            let sha = format!("{:x}", hasher.finalize());
-
-            let mime = header_mime.or(mime_hint);
-            let final_kind =
-                classify_attachment_kind(&current_kind, &normalized_url, mime.as_deref());
-            let ext = infer_file_extension(&normalized_url, mime.as_deref());
-            let file_name = if ext.is_empty() {
-                sha.clone()
-            } else {
-                format!("{sha}.{ext}")
-            };
-            let file_path = assets_dir.join(file_name);
-            if !file_path.exists() {
-                fs::write(&file_path, &bytes).map_err(|e| e.to_string())?;
-            }
-
-            let local_path = file_path.to_string_lossy().to_string();
-            self.mark_attachment_cached(
-                &attachment_id,
-                local_path,
-                mime,
-                bytes.len() as i64,
-                sha,
-            )?;
-            if final_kind != current_kind {
-                self.update_attachment_kind(&attachment_id, final_kind)?;
+        let file_path = assets_dir.join(&file_name);
+        if !file_path.exists() {
+            // Written aside and renamed, so an interrupted write never leaves a
+            // truncated file under the final name.
+            let partial_path = assets_dir.join(format!(".{sha}.{}.partial", Uuid::new_v4()));
+            fs::write(&partial_path, bytes)?;
+            if let Err(err) = fs::rename(&partial_path, &file_path) {
+                let _ = fs::remove_file(&partial_path);
+                return Err(err.into());
             }
         }
 
-        Ok(())
-    }
-
-    fn mark_attachment_failed(&self, attachment_id: &str, error: String) -> Result<(), String> {
-        let conn = self.open()?;
-        conn.execute(
-            "UPDATE attachments SET status = 'failed', error = ?1 WHERE id = ?2",
-            params![truncate_error(&error), attachment_id],
-        )
-        .map_err(|e| e.to_string())?;
-        Ok(())
+        Ok(StoredAsset {
+            local_path: format!("{ASSETS_DIR_NAME}/{file_name}"),
+            sha256: sha,
+            size_bytes: bytes.len() as i64,
+            mime,
+        })
     }
 
     fn mark_attachment_cached(
@@ -1325,40 +971,41 @@ impl Database {
         mime: Option<String>,
         size_bytes: i64,
         sha256: String,
-    ) -> Result<(), String> {
+    ) -> AppResult<()> {
         let conn = self.open()?;
         conn.execute(
             "UPDATE attachments SET status = 'cached', local_path = ?1, mime = ?2, size_bytes = ?3, sha256 = ?4, error = NULL WHERE id = ?5",
             params![local_path, mime, size_bytes, sha256, attachment_id],
-        )
-        .map_err(|e| e.to_string())?;
+        )?;
         Ok(())
     }
 
-    fn update_attachment_kind(&self, attachment_id: &str, kind: String) -> Result<(), String> {
+    fn update_attachment_kind(&self, attachment_id: &str, kind: String) -> AppResult<()> {
         let conn = self.open()?;
         conn.execute(
             "UPDATE attachments SET kind = ?1 WHERE id = ?2",
             params![kind, attachment_id],
-        )
-        .map_err(|e| e.to_string())?;
+        )?;
         Ok(())
     }
 
-    pub fn export_backup_zip(&self) -> Result<String, String> {
-        let conn = self.open()?;
-        let mut ids_stmt = conn
-            .prepare("SELECT id FROM conversations ORDER BY updated_at DESC")
-            .map_err(|e| e.to_string())?;
+    pub fn export_backup_zip(&self) -> AppResult<String> {
+        let conversation_ids = {
+            let conn = self.open()?;
+            let mut ids_stmt = conn
+                .prepare_cached(
+                    "SELECT id FROM conversations WHERE deleted_at IS NULL ORDER BY updated_at DESC",
+                )?;
 
-        let id_rows = ids_stmt
-            .query_map([], |row| row.get::<_, String>(0))
-            .map_err(|e| e.to_string())?;
+            let id_rows = ids_stmt
+                .query_map([], |row| row.get::<_, String>(0))?;
 
-        let mut conversation_ids = Vec::new();
-        for row in id_rows {
-            conversation_ids.push(row.map_err(|e| e.to_string())?);
-        }
+            let mut ids = Vec::new();
+            for row in id_rows {
+                ids.push(row?);
+            }
+            ids
+        };
 
         let mut conversations = Vec::new();
         for id in conversation_ids {
@@ -1373,12 +1020,8 @@ impl Database {
             "conversations": conversations,
         });
 
-        let backup_dir = self
-            .db_path
-            .parent()
-            .unwrap_or_else(|| Path::new("."))
-            .join("backups");
-        fs::create_dir_all(&backup_dir).map_err(|e| e.to_string())?;
+        let backup_dir = self.backups_dir();
+        fs::create_dir_all(&backup_dir)?;
 
         let file_name = format!(
             "ai-history-backup-{}.zip",
@@ -1386,13 +1029,12 @@ impl Database {
         );
         let backup_path = backup_dir.join(file_name);
 
-        let file = File::create(&backup_path).map_err(|e| e.to_string())?;
+        let file = File::create(&backup_path)?;
         let mut zip = zip::ZipWriter::new(file);
         let options = zip::write::SimpleFileOptions::default()
             .compression_method(zip::CompressionMethod::Deflated);
 
-        zip.start_file("backup.jsonl", options)
-            .map_err(|e| e.to_string())?;
+        zip.start_file("backup.jsonl", options)?;
 
         let mut lines = Vec::new();
         lines.push(
@@ -1416,14 +1058,118 @@ impl Database {
             }
         }
 
-        zip.write_all(lines.join("\n").as_bytes())
-            .map_err(|e| e.to_string())?;
-        zip.finish().map_err(|e| e.to_string())?;
+        zip.write_all(lines.join("\n").as_bytes())?;
+
+        // Cached files go in as they are, so `verify_assets` can restore them.
+        let asset_paths = {
+            let conn = self.open()?;
+            let mut stmt = conn.prepare(
+                r#"
+                SELECT DISTINCT a.local_path
+                FROM attachments a
+                JOIN conversations c ON c.id = a.conversation_id
+                WHERE a.local_path IS NOT NULL AND c.deleted_at IS NULL
+                ORDER BY a.local_path
+                "#,
+            )?;
+            let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let asset_options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for local_path in asset_paths {
+            let path = self.resolve_asset_path(&local_path);
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let mut asset = match File::open(&path) {
+                Ok(asset) => asset,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+            zip.start_file(
+                format!("{}{file_name}", integrity::BACKUP_ASSETS_PREFIX),
+                asset_options,
+            )?;
+            std::io::copy(&mut asset, &mut zip)?;
+        }
+        zip.finish()?;
 
         Ok(backup_path.to_string_lossy().to_string())
     }
 }
 
+/// Runs once for every pooled connection. SQLite scopes these settings to the
+/// connection, so issuing them from `migrate` alone leaves other connections
+/// without foreign keys (and the `ON DELETE CASCADE` clauses never fire).
+fn configure_connection(conn: &mut Connection) -> rusqlite::Result<()> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+    conn.execute_batch(
+        r#"
+        PRAGMA journal_mode = WAL;
+        PRAGMA synchronous = NORMAL;
+        PRAGMA foreign_keys = ON;
+        "#,
+    )?;
+    search::register_functions(conn)?;
+    semantic::register_functions(conn)
+}
+
+/// Column list decoded by `row_to_conversation`, always aliased as `c`. Extra
+/// per-query columns start at index `CONVERSATION_COLUMN_COUNT`.
+const CONVERSATION_COLUMNS: &str = "c.id, c.source, c.source_conversation_id, c.folder_id, c.title, \
+     c.summary, c.created_at, c.updated_at, c.fingerprint, c.meta_json, c.deleted_at, \
+     c.imported_at, c.captured_at";
+const CONVERSATION_COLUMN_COUNT: usize = 13;
+
+/// Columns decoded by `row_to_folder`, aliased `f`; `?1` must bind the
+/// uncategorized folder id, which also holds conversations without a folder.
+const FOLDER_COLUMNS: &str = r#"
+    f.id, f.name, f.parent_id, f.sort_order, f.created_at, f.updated_at,
+    (SELECT COUNT(*) FROM conversations c
+     WHERE c.deleted_at IS NULL AND (c.folder_id = f.id OR (f.id = ?1 AND c.folder_id IS NULL)))
+"#;
+
+fn row_to_folder(row: &rusqlite::Row<'_>) -> rusqlite::Result<Folder> {
+    Ok(Folder {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        parent_id: row.get(2)?,
+        sort_order: row.get(3)?,
+        created_at: row.get(4)?,
+        updated_at: row.get(5)?,
+        conversation_count: row.get(6)?,
+        filter: None,
+    })
+}
+
+fn load_folder(conn: &Connection, id: &str) -> AppResult<Folder> {
+    conn.query_row(
+        &format!("SELECT {FOLDER_COLUMNS} FROM folders f WHERE f.id = ?2"),
+        params![UNCATEGORIZED_FOLDER_ID, id],
+        row_to_folder,
+    )
+    .optional()?
+    .ok_or_else(|| AppError::NotFound(format!("folder {id} not found")))
+}
+
+/// Sort order that puts a new child of `parent_id` after its existing
+/// folders and smart folders.
+fn next_sort_order(conn: &Connection, parent_id: Option<&str>) -> AppResult<i64> {
+    Ok(conn.query_row(
+        r#"
+        SELECT COALESCE(MAX(sort_order), -1) + 1 FROM (
+          SELECT sort_order FROM folders WHERE parent_id IS ?1
+          UNION ALL
+          SELECT sort_order FROM smart_folders WHERE parent_id IS ?1
+        )
+        "#,
+        params![parent_id],
+        |row| row.get(0),
+    )?)
+}
+
 fn row_to_conversation(row: &rusqlite::Row<'_>) -> rusqlite::Result<Conversation> {
     Ok(Conversation {
         id: row.get(0)?,
@@ -1436,69 +1182,348 @@ fn row_to_conversation(row: &rusqlite::Row<'_>) -> rusqlite::Result<Conversation
         updated_at: row.get(7)?,
         fingerprint: row.get(8)?,
         meta_json: row.get(9)?,
+        deleted_at: row.get(10)?,
+        imported_at: row.get(11)?,
+        captured_at: row.get(12)?,
+    })
+}
+
+const MESSAGE_COLUMNS: &str = "id, conversation_id, seq, role, content_markdown, thought_markdown, \
+     model, timestamp, token_count, parent_id, branch_index, on_active_path";
+
+fn row_to_message(row: &rusqlite::Row<'_>) -> rusqlite::Result<Message> {
+    Ok(Message {
+        id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        seq: row.get(2)?,
+        role: row.get(3)?,
+        content_markdown: row.get(4)?,
+        thought_markdown: row.get(5)?,
+        model: row.get(6)?,
+        timestamp: row.get(7)?,
+        token_count: row.get(8)?,
+        parent_id: row.get(9)?,
+        branch_index: row.get(10)?,
+        on_active_path: row.get(11)?,
     })
 }
 
 fn find_existing_by_fingerprint(
     conn: &rusqlite::Transaction<'_>,
     fingerprint: &str,
-) -> Result<Option<(String, String)>, String> {
+) -> AppResult<Option<(String, String)>> {
     let mut stmt = conn
-        .prepare("SELECT id, title FROM conversations WHERE fingerprint = ?1 LIMIT 1")
-        .map_err(|e| e.to_string())?;
+        .prepare_cached(
+            "SELECT id, title FROM conversations WHERE fingerprint = ?1 AND deleted_at IS NULL LIMIT 1",
+        )?;
 
-    let mut rows = stmt.query(params![fingerprint]).map_err(|e| e.to_string())?;
-    let Some(row) = rows.next().map_err(|e| e.to_string())? else {
+    let mut rows = stmt.query(params![fingerprint])?;
+    let Some(row) = rows.next()? else {
         return Ok(None);
     };
 
-    let id: String = row.get(0).map_err(|e| e.to_string())?;
-    let title: String = row.get(1).map_err(|e| e.to_string())?;
+    let id: String = row.get(0)?;
+    let title: String = row.get(1)?;
     Ok(Some((id, title)))
 }
 
+fn fingerprint_taken(conn: &rusqlite::Transaction<'_>, fingerprint: &str) -> AppResult<bool> {
+    Ok(conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM conversations WHERE fingerprint = ?1)",
+        params![fingerprint],
+        |row| row.get(0),
+    )?)
+}
+
 fn find_existing_by_source_ref(
     conn: &rusqlite::Transaction<'_>,
     source: &str,
     source_conversation_id: &str,
-) -> Result<Option<(String, String)>, String> {
+) -> AppResult<Option<(String, String)>> {
     let mut stmt = conn
-        .prepare(
-            "SELECT id, title FROM conversations WHERE source = ?1 AND source_conversation_id = ?2 ORDER BY updated_at DESC LIMIT 1",
-        )
-        .map_err(|e| e.to_string())?;
+        .prepare_cached(
+            "SELECT id, title FROM conversations WHERE source = ?1 AND source_conversation_id = ?2 AND deleted_at IS NULL ORDER BY updated_at DESC LIMIT 1",
+        )?;
 
     let mut rows = stmt
-        .query(params![source, source_conversation_id])
-        .map_err(|e| e.to_string())?;
-    let Some(row) = rows.next().map_err(|e| e.to_string())? else {
+        .query(params![source, source_conversation_id])?;
+    let Some(row) = rows.next()? else {
         return Ok(None);
     };
 
-    let id: String = row.get(0).map_err(|e| e.to_string())?;
-    let title: String = row.get(1).map_err(|e| e.to_string())?;
+    let id: String = row.get(0)?;
+    let title: String = row.get(1)?;
     Ok(Some((id, title)))
 }
 
+/// What an overwrite keeps from the row it replaces.
+struct OverwrittenRow {
+    folder_id: Option<String>,
+    created_at: String,
+    imported_at: Option<String>,
+}
+
+fn load_overwritten_row(
+    tx: &rusqlite::Transaction<'_>,
+    conversation_id: &str,
+) -> AppResult<OverwrittenRow> {
+    Ok(tx.query_row(
+        "SELECT folder_id, created_at, imported_at FROM conversations WHERE id = ?1",
+        params![conversation_id],
+        |row| {
+            Ok(OverwrittenRow {
+                folder_id: row.get(0)?,
+                created_at: row.get(1)?,
+                imported_at: row.get(2)?,
+            })
+        },
+    )?)
+}
+
+/// Clears a conversation ahead of re-inserting it. Returns the cached file
+/// paths it referenced, for removal once the import commits and if nothing
+/// re-imported points at them again.
 fn delete_conversation_for_overwrite(
     tx: &rusqlite::Transaction<'_>,
     conversation_id: &str,
-) -> Result<(), String> {
+) -> AppResult<Vec<String>> {
+    let local_paths = {
+        let mut stmt = tx.prepare_cached(
+            "SELECT DISTINCT local_path FROM attachments WHERE conversation_id = ?1 AND local_path IS NOT NULL",
+        )?;
+        let rows = stmt.query_map(params![conversation_id], |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
     tx.execute(
-        "DELETE FROM messages_fts WHERE conversation_id = ?1",
+        "DELETE FROM attachments WHERE conversation_id = ?1",
         params![conversation_id],
-    )
-    .map_err(|e| e.to_string())?;
+    )?;
     tx.execute(
         "DELETE FROM messages WHERE conversation_id = ?1",
         params![conversation_id],
-    )
-    .map_err(|e| e.to_string())?;
-    tx.execute("DELETE FROM conversations WHERE id = ?1", params![conversation_id])
-        .map_err(|e| e.to_string())?;
+    )?;
+    tx.execute("DELETE FROM conversations WHERE id = ?1", params![conversation_id])?;
+    Ok(local_paths)
+}
+
+pub(crate) struct StoredAsset {
+    pub local_path: String,
+    pub sha256: String,
+    pub size_bytes: i64,
+    /// Detected from the content, else the MIME the caller passed in.
+    pub mime: Option<String>,
+}
+
+struct PendingAttachment {
+    kind: String,
+    url: String,
+    mime: Option<String>,
+    status: String,
+    stored: Option<StoredAsset>,
+}
+
+/// Attachments implied by a turn: explicit refs first, then images and file links
+/// in the markdown, and finally bare file names a user mentioned when nothing
+/// downloadable was attached (recorded as virtual `aihistory://upload/` refs).
+fn collect_turn_attachments(turn: &NormalizedTurn) -> Vec<PendingAttachment> {
+    let mut out = Vec::new();
+    let mut seen_attachment_urls: HashSet<String> = HashSet::new();
+    let mut has_non_virtual_attachment = false;
+
+    if let Some(attachments) = turn.attachments.as_ref() {
+        for attachment in attachments {
+            // Files an in-process importer already wrote to the assets dir.
+            if let Some(local_path) = attachment.local_path.clone() {
+                if !seen_attachment_urls.insert(attachment.original_url.clone()) {
+                    continue;
+                }
+                out.push(PendingAttachment {
+                    kind: classify_attachment_kind(
+                        &attachment.kind,
+                        &attachment.original_url,
+                        attachment.mime.as_deref(),
+                    ),
+                    url: attachment.original_url.clone(),
+                    mime: attachment
+                        .mime
+                        .clone()
+                        .or_else(|| infer_attachment_mime(&attachment.original_url)),
+                    status: "cached".to_string(),
+                    stored: Some(StoredAsset {
+                        local_path,
+                        sha256: attachment.sha256.clone().unwrap_or_default(),
+                        size_bytes: attachment.size_bytes.unwrap_or(0),
+                        mime: attachment.mime.clone(),
+                    }),
+                });
+                has_non_virtual_attachment = true;
+                continue;
+            }
+
+            let normalized_url = normalize_attachment_url(&attachment.original_url);
+            if normalized_url.is_empty() {
+                continue;
+            }
+            if is_navigation_url(&normalized_url) {
+                continue;
+            }
+            if !seen_attachment_urls.insert(normalized_url.clone()) {
+                continue;
+            }
+            if !is_virtual_attachment_url(&normalized_url) {
+                has_non_virtual_attachment = true;
+            }
+            let normalized_kind = classify_attachment_kind(
+                &attachment.kind,
+                &normalized_url,
+                attachment.mime.as_deref(),
+            );
+            if normalized_kind == "file" && !looks_like_file_url(&normalized_url) {
+                continue;
+            }
+
+            out.push(PendingAttachment {
+                kind: normalized_kind,
+                mime: attachment
+                    .mime
+                    .clone()
+                    .or_else(|| infer_attachment_mime(&normalized_url)),
+                url: normalized_url,
+                status: attachment
+                    .status
+                    .as_deref()
+                    .unwrap_or("remote_only")
+                    .to_string(),
+                stored: None,
+            });
+        }
+    }
+
+    for (kind, url, mime) in extract_inline_attachments(&turn.content_markdown) {
+        if kind != "image" && kind != "pdf" && kind != "file" {
+            continue;
+        }
+        if !seen_attachment_urls.insert(url.clone()) {
+            continue;
+        }
+        if !is_virtual_attachment_url(&url) {
+            has_non_virtual_attachment = true;
+        }
+        out.push(PendingAttachment {
+            kind,
+            url,
+            mime,
+            status: "remote_only".to_string(),
+            stored: None,
+        });
+    }
+
+    if turn.role.eq_ignore_ascii_case("user") && !has_non_virtual_attachment {
+        for (kind, url, mime) in extract_named_file_attachments(&turn.content_markdown) {
+            if kind != "image" && kind != "pdf" && kind != "file" {
+                continue;
+            }
+            if !seen_attachment_urls.insert(url.clone()) {
+                continue;
+            }
+            out.push(PendingAttachment {
+                kind,
+                url,
+                mime,
+                status: "remote_only".to_string(),
+                stored: None,
+            });
+        }
+    }
+
+    out
+}
+
+fn insert_attachment(
+    tx: &rusqlite::Transaction<'_>,
+    conversation_id: &str,
+    message_id: &str,
+    attachment: &PendingAttachment,
+) -> AppResult<()> {
+    tx.execute(
+        r#"
+        INSERT INTO attachments (
+          id, message_id, conversation_id, kind, original_url, local_path,
+          mime, size_bytes, sha256, status, error, created_at
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, NULL, ?11)
+        "#,
+        params![
+            Uuid::new_v4().to_string(),
+            message_id,
+            conversation_id,
+            attachment.kind,
+            attachment.url,
+            attachment.stored.as_ref().map(|stored| stored.local_path.as_str()),
+            attachment.mime,
+            attachment.stored.as_ref().map(|stored| stored.size_bytes),
+            attachment.stored.as_ref().map(|stored| stored.sha256.as_str()),
+            attachment.status,
+            now_iso(),
+        ],
+    )?;
     Ok(())
 }
 
+fn turn_thought_markdown(source: &str, turn: &NormalizedTurn) -> Option<String> {
+    if source == "gemini" {
+        None
+    } else {
+        turn.thought_markdown.clone()
+    }
+}
+
+/// Where a new message hangs in the conversation tree.
+struct MessageLink<'a> {
+    parent_id: Option<&'a str>,
+    branch_index: i64,
+    on_active_path: bool,
+}
+
+/// Inserts one turn with its search row and attachments; returns the message id.
+fn insert_message(
+    tx: &rusqlite::Transaction<'_>,
+    conversation_id: &str,
+    source: &str,
+    seq: i64,
+    turn: &NormalizedTurn,
+    link: &MessageLink<'_>,
+) -> AppResult<String> {
+    let msg_id = Uuid::new_v4().to_string();
+    tx.execute(
+        r#"
+        INSERT INTO messages (
+          id, conversation_id, seq, role, content_markdown, thought_markdown, model, timestamp, token_count,
+          parent_id, branch_index, on_active_path
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+        "#,
+        params![
+            msg_id,
+            conversation_id,
+            seq,
+            turn.role,
+            turn.content_markdown,
+            turn_thought_markdown(source, turn),
+            turn.model,
+            turn.timestamp,
+            turn.token_count,
+            link.parent_id,
+            link.branch_index,
+            link.on_active_path,
+        ],
+    )?;
+
+    for attachment in collect_turn_attachments(turn) {
+        insert_attachment(tx, conversation_id, &msg_id, &attachment)?;
+    }
+
+    Ok(msg_id)
+}
+
 fn compute_fingerprint(conv: &NormalizedConversation) -> String {
     let mut message_hashes: HashSet<String> = HashSet::new();
     for turn in &conv.turns {
@@ -1526,6 +1551,61 @@ fn now_iso() -> String {
     Utc::now().to_rfc3339()
 }
 
+/// Parses the timestamp shapes importers hand us (RFC 3339, `YYYY-MM-DD HH:MM:SS`
+/// taken as UTC, or unix seconds/milliseconds) into the RFC 3339 form stored
+/// everywhere else, so string ordering in SQL stays chronological.
+fn normalize_timestamp(raw: &str) -> Option<String> {
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+
+    if let Ok(parsed) = chrono::DateTime::parse_from_rfc3339(trimmed) {
+        return Some(parsed.with_timezone(&Utc).to_rfc3339());
+    }
+
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S%.f"] {
+        if let Ok(parsed) = chrono::NaiveDateTime::parse_from_str(trimmed, format) {
+            return Some(parsed.and_utc().to_rfc3339());
+        }
+    }
+
+    if let Ok(number) = trimmed.parse::<f64>() {
+        if !number.is_finite() || number <= 0.0 {
+            return None;
+        }
+        let millis = if number > 10_000_000_000.0 {
+            number
+        } else {
+            number * 1000.0
+        };
+        return chrono::DateTime::<Utc>::from_timestamp_millis(millis as i64)
+            .map(|parsed| parsed.to_rfc3339());
+    }
+
+    None
+}
+
+/// Source-side `(created_at, updated_at)`: each falls back to the other, then to
+/// the capture time, then to `fallback`; `updated_at` never precedes `created_at`.
+fn resolve_conversation_times(conv: &NormalizedConversation, fallback: &str) -> (String, String) {
+    let created = conv.created_at.as_deref().and_then(normalize_timestamp);
+    let updated = conv.updated_at.as_deref().and_then(normalize_timestamp);
+    let captured = conv.captured_at.as_deref().and_then(normalize_timestamp);
+
+    let created_at = created
+        .clone()
+        .or_else(|| updated.clone())
+        .or_else(|| captured.clone())
+        .unwrap_or_else(|| fallback.to_string());
+    let updated_at = updated
+        .or(captured)
+        .unwrap_or_else(|| created_at.clone())
+        .max(created_at.clone());
+
+    (created_at, updated_at)
+}
+
 fn normalize_attachment_kind(raw: &str) -> String {
     let lower = raw.to_lowercase();
     if lower.contains("pdf") {
@@ -2494,6 +2574,30 @@ fn infer_file_extension(url: &str, mime: Option<&str>) -> String {
         if lower.contains("application/json") {
             return "json".to_string();
         }
+        if lower.contains("svg") {
+            return "svg".to_string();
+        }
+        if lower.contains("avif") {
+            return "avif".to_string();
+        }
+        if lower.contains("heic") {
+            return "heic".to_string();
+        }
+        if lower.contains("image/bmp") {
+            return "bmp".to_string();
+        }
+        if lower.contains("image/x-icon") {
+            return "ico".to_string();
+        }
+        if lower.contains("image/tiff") {
+            return "tiff".to_string();
+        }
+        if lower.contains("text/html") {
+            return "html".to_string();
+        }
+        if lower.contains("application/zip") {
+            return "zip".to_string();
+        }
     }
 
     if let Some(file_name) = extract_filename_from_url(url) {
@@ -2523,3 +2627,87 @@ fn truncate_error(error: &str) -> String {
     }
     error[..MAX].to_string()
 }
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+
+    use super::Database;
+    use crate::models::{ImportBatch, NormalizedConversation};
+
+    /// A database in its own temporary data dir, removed again on drop even
+    /// when the test panics.
+    pub(crate) struct TempDatabase {
+        db: Option<Database>,
+        dir: PathBuf,
+    }
+
+    impl TempDatabase {
+        pub(crate) fn new() -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("ai-history-test-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let db = Database::new(dir.join("ai-history.sqlite")).unwrap();
+            Self { db: Some(db), dir }
+        }
+
+        /// The data dir, for files a test puts next to the database.
+        pub(crate) fn dir(&self) -> &Path {
+            &self.dir
+        }
+
+        pub(crate) fn import(&self, conv: NormalizedConversation, strategy: &str) {
+            self.import_into(conv, strategy, None);
+        }
+
+        pub(crate) fn import_into(
+            &self,
+            conv: NormalizedConversation,
+            strategy: &str,
+            folder_id: Option<String>,
+        ) {
+            self.import_files(ImportBatch {
+                conversations: vec![conv],
+                strategy: strategy.to_string(),
+                folder_id,
+            })
+            .unwrap();
+        }
+    }
+
+    impl Deref for TempDatabase {
+        type Target = Database;
+
+        fn deref(&self) -> &Database {
+            self.db.as_ref().expect("database is open until drop")
+        }
+    }
+
+    impl Drop for TempDatabase {
+        fn drop(&mut self) {
+            // Close the pool first so no connection holds the file open.
+            self.db.take();
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// A linear conversation of `(role, text)` turns.
+    pub(crate) fn conversation(
+        source_conversation_id: &str,
+        title: &str,
+        turns: &[(&str, &str)],
+    ) -> NormalizedConversation {
+        let turns: Vec<serde_json::Value> = turns
+            .iter()
+            .map(|(role, text)| serde_json::json!({ "role": role, "contentMarkdown": text }))
+            .collect();
+        serde_json::from_value(serde_json::json!({
+            "source": "chatgpt",
+            "sourceConversationId": source_conversation_id,
+            "title": title,
+            "turns": turns,
+        }))
+        .unwrap()
+    }
+}
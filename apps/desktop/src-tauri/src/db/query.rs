@@ -0,0 +1,1018 @@
+//! Search query language.
+//!
+//! ```text
+//! query   := or
+//! or      := and ("OR" and)*
+//! and     := unary (["AND"] unary)*
+//! unary   := ("NOT" | "-") unary | primary
+//! primary := "(" or ")" | "\"phrase\""["*"] | word["*"] | key ":" value
+//! ```
+//!
+//! Words and phrases match a conversation's title and summary, or the text,
+//! thoughts and attachment names of any of its messages. Each top-level word
+//! or phrase only has to occur somewhere in the conversation, so `rust retry`
+//! finds a conversation titled rust with a message about retries; a negated
+//! word excludes every conversation that mentions it anywhere. `title:`,
+//! `summary:`, `content:`, `thought:` and `file:` restrict a word or phrase to
+//! one of those fields. `key:value` filters are `source`, `folder`, `tag`,
+//! `role`, `model`, `before`, `after` and `has`; any other `key:value` is
+//! plain text.
+//! Operators must be upper case so "and"/"or" stay searchable words.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::types::Value;
+
+use super::search::query_phrase;
+use crate::error::{AppError, AppResult};
+
+const FILTER_KEYS: &[&str] = &[
+    "source", "folder", "tag", "role", "model", "before", "after", "has",
+];
+/// Field prefixes and the `messages_fts` column each one searches.
+const FIELD_KEYS: &[(&str, &str)] = &[
+    ("title", "title"),
+    ("summary", "summary"),
+    ("content", "content"),
+    ("thought", "thoughts"),
+    ("file", "attachments"),
+];
+/// Columns of the per-conversation index row; the rest belong to messages.
+const CONVERSATION_FIELDS: &[&str] = &["title", "summary"];
+const ROLES: &[&str] = &["user", "assistant", "system"];
+const ATTACHMENT_KINDS: &[&str] = &["image", "pdf", "file"];
+
+/// A parsed query compiled for the two places search looks: message rows
+/// (`m` joined to `c`) and the title/summary row of each conversation.
+pub(super) struct CompiledQuery {
+    /// FTS5 expression matching messages with any of the top-level text
+    /// they can contain; when present the message search is driven by
+    /// `messages_fts MATCH` and can be ranked.
+    pub fts: Option<String>,
+    /// Remaining conditions over `m` and `c`, AND-ed with the match.
+    pub predicate: String,
+    pub params: Vec<Value>,
+    /// False when the only top-level text is `title:`/`summary:` scoped, so
+    /// no message can match.
+    pub match_messages: bool,
+    /// FTS5 expression picking the messages that are hits: `fts`, or else
+    /// any positive message text nested under filters, as in
+    /// `rust OR source:gemini`.
+    pub hit_fts: Option<String>,
+    /// Whether matching messages are hits: only when the query has message
+    /// text or a `role:`/`model:` filter. Conversation-level filters alone
+    /// say nothing about which messages matter.
+    pub message_hits: bool,
+    /// FTS5 expression matching conversation rows with any of the top-level
+    /// text.
+    pub conversation_fts: Option<String>,
+    /// Remaining conditions over `c`, when the query has any text.
+    pub conversation_predicate: Option<String>,
+    pub conversation_params: Vec<Value>,
+    /// Positive words and phrases that can occur in messages, for
+    /// highlighting.
+    pub terms: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Text {
+        text: String,
+        prefix: bool,
+        /// `messages_fts` column the text is restricted to.
+        field: Option<&'static str>,
+    },
+    Filter(Filter),
+    And(Vec<Node>),
+    Or(Vec<Node>),
+    Not(Box<Node>),
+}
+
+#[derive(Debug, Clone)]
+enum Filter {
+    Source(String),
+    Folder(String),
+    Tag(String),
+    Role(String),
+    Model { name: String, prefix: bool },
+    Before(String),
+    After(String),
+    Has(String),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scope {
+    Message,
+    Conversation,
+}
+
+/// Parses and compiles `query`; `Ok(None)` when it has nothing to search for.
+pub(super) fn compile(query: &str) -> AppResult<Option<CompiledQuery>> {
+    let tokens = lex(query)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        end: query.chars().count() + 1,
+    };
+    let root = parser.parse_or()?;
+    if let Some((_, at)) = parser.tokens.get(parser.position) {
+        return Err(syntax_error("多余的右括号", *at));
+    }
+
+    let mut texts = Vec::new();
+    collect_texts(&root, &mut texts);
+    let terms = texts
+        .iter()
+        .filter(|(_, field)| !field.is_some_and(|field| CONVERSATION_FIELDS.contains(&field)))
+        .map(|(text, _)| text.clone())
+        .collect();
+
+    let conjuncts = match &root {
+        Node::And(items) => items.clone(),
+        other => vec![other.clone()],
+    };
+    // Top-level text is AND-ed per conversation, not per index row: an index
+    // row only has to hold one of the terms, and every term must occur
+    // somewhere in the conversation. Conversation-only text stays out of the
+    // message match: "title:rust tokio" means a message about tokio in a
+    // conversation titled rust.
+    let mut message_text = Vec::new();
+    let mut text_terms = Vec::new();
+    let mut rest = Vec::new();
+    for node in conjuncts {
+        match fts_expr(&node) {
+            Some(expr) => {
+                let conversation_only = conversation_text_only(&node);
+                if !conversation_only {
+                    message_text.push(expr.clone());
+                }
+                text_terms.push((expr, conversation_only));
+            }
+            None => rest.push(node),
+        }
+    }
+    let fts = (!message_text.is_empty()).then(|| message_text.join(" OR "));
+    let match_messages = message_text.len() == text_terms.len();
+    let hit_fts = fts.clone().or_else(|| {
+        let mut nested = Vec::new();
+        for node in &rest {
+            collect_message_exprs(node, &mut nested);
+        }
+        (!nested.is_empty()).then(|| nested.join(" OR "))
+    });
+    let message_hits = hit_fts.is_some() || rest.iter().any(has_message_filter);
+    let conversation_fts = (!text_terms.is_empty()).then(|| {
+        text_terms
+            .iter()
+            .map(|(expr, _)| expr.as_str())
+            .collect::<Vec<_>>()
+            .join(" OR ")
+    });
+
+    // A lone term is already enforced by the match itself.
+    let message_terms: Vec<&(String, bool)> = text_terms
+        .iter()
+        .filter(|(_, conversation_only)| *conversation_only || message_text.len() > 1)
+        .collect();
+    let conversation_terms: Vec<&(String, bool)> = if text_terms.len() > 1 {
+        text_terms.iter().collect()
+    } else {
+        Vec::new()
+    };
+    let mut params = Vec::new();
+    let predicate = conjunction_sql(&message_terms, &rest, Scope::Message, &mut params);
+    let mut conversation_params = Vec::new();
+    let conversation_predicate = (!texts.is_empty()).then(|| {
+        conjunction_sql(
+            &conversation_terms,
+            &rest,
+            Scope::Conversation,
+            &mut conversation_params,
+        )
+    });
+
+    Ok(Some(CompiledQuery {
+        fts,
+        predicate,
+        params,
+        match_messages,
+        hit_fts,
+        message_hits,
+        conversation_fts,
+        conversation_predicate,
+        conversation_params,
+        terms,
+    }))
+}
+
+/// `terms` are FTS5 expressions the conversation must match somewhere,
+/// flagged when only its title/summary row counts.
+fn conjunction_sql(
+    terms: &[&(String, bool)],
+    nodes: &[Node],
+    scope: Scope,
+    params: &mut Vec<Value>,
+) -> String {
+    let mut parts = Vec::with_capacity(terms.len() + nodes.len());
+    for (expr, conversation_only) in terms {
+        params.push(Value::Text(expr.clone()));
+        parts.push(conversation_match_sql(*conversation_only).to_string());
+    }
+    parts.extend(nodes.iter().map(|node| to_sql(node, scope, params)));
+    if parts.is_empty() {
+        return "1".to_string();
+    }
+    parts.join(" AND ")
+}
+
+/// Conversations with an index row matching the bound FTS5 expression;
+/// with `conversation_only`, only their title/summary row counts.
+fn conversation_match_sql(conversation_only: bool) -> &'static str {
+    if conversation_only {
+        "c.id IN (SELECT conversation_id FROM messages_fts \
+         WHERE messages_fts MATCH ? AND message_id IS NULL)"
+    } else {
+        "c.id IN (SELECT conversation_id FROM messages_fts WHERE messages_fts MATCH ?)"
+    }
+}
+
+fn syntax_error(detail: &str, at: usize) -> AppError {
+    AppError::InvalidInput(format!("搜索语法错误：{detail}（第 {at} 个字符）"))
+}
+
+#[derive(Debug, Clone)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Text {
+        text: String,
+        prefix: bool,
+        field: Option<&'static str>,
+    },
+    Filter(Filter),
+}
+
+/// Splits `query` into tokens; positions are 1-based character offsets.
+fn lex(query: &str) -> AppResult<Vec<(Token, usize)>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let at = i + 1;
+        if ch.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        match ch {
+            '(' => {
+                tokens.push((Token::LParen, at));
+                i += 1;
+            }
+            ')' => {
+                tokens.push((Token::RParen, at));
+                i += 1;
+            }
+            '"' => {
+                let (text, next) = read_quoted(&chars, i)?;
+                let prefix = chars.get(next) == Some(&'*');
+                if text.trim().is_empty() {
+                    return Err(syntax_error("引号内没有内容", at));
+                }
+                tokens.push((
+                    Token::Text {
+                        text,
+                        prefix,
+                        field: None,
+                    },
+                    at,
+                ));
+                i = if prefix { next + 1 } else { next };
+            }
+            '-' if chars
+                .get(i + 1)
+                .is_some_and(|next| !next.is_whitespace() && *next != ')') =>
+            {
+                tokens.push((Token::Not, at));
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !matches!(chars[i], '(' | ')' | '"')
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.as_str() {
+                    "AND" => tokens.push((Token::And, at)),
+                    "OR" => tokens.push((Token::Or, at)),
+                    "NOT" => tokens.push((Token::Not, at)),
+                    _ => {
+                        if let Some((key, value)) = word.split_once(':') {
+                            let key = key.to_lowercase();
+                            if let Some(&(_, column)) =
+                                FIELD_KEYS.iter().find(|(name, _)| *name == key)
+                            {
+                                let (text, prefix) =
+                                    if value.is_empty() && chars.get(i) == Some(&'"') {
+                                        let (quoted, next) = read_quoted(&chars, i)?;
+                                        let prefix = chars.get(next) == Some(&'*');
+                                        i = if prefix { next + 1 } else { next };
+                                        (quoted, prefix)
+                                    } else {
+                                        match value.strip_suffix('*') {
+                                            Some(stripped) => (stripped.to_string(), true),
+                                            None => (value.to_string(), false),
+                                        }
+                                    };
+                                if query_phrase(&text, prefix).is_none() {
+                                    return Err(syntax_error(&format!("{key}: 缺少值"), at));
+                                }
+                                tokens.push((
+                                    Token::Text {
+                                        text,
+                                        prefix,
+                                        field: Some(column),
+                                    },
+                                    at,
+                                ));
+                                continue;
+                            }
+                            if FILTER_KEYS.contains(&key.as_str()) {
+                                let value = if value.is_empty() && chars.get(i) == Some(&'"') {
+                                    let (quoted, next) = read_quoted(&chars, i)?;
+                                    i = next;
+                                    quoted
+                                } else {
+                                    value.to_string()
+                                };
+                                tokens.push((Token::Filter(parse_filter(&key, &value, at)?), at));
+                                continue;
+                            }
+                            // Any other `key:value`, such as `TODO:fix`, is
+                            // searched as plain text.
+                        }
+                        let (text, prefix) = match word.strip_suffix('*') {
+                            Some(stripped) => (stripped.to_string(), true),
+                            None => (word, false),
+                        };
+                        // Bare punctuation has nothing to match; drop it like
+                        // the tokenizer would.
+                        if query_phrase(&text, prefix).is_some() {
+                            tokens.push((
+                                Token::Text {
+                                    text,
+                                    prefix,
+                                    field: None,
+                                },
+                                at,
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Reads a `"…"` string starting at `chars[start]`; `""` inside is a literal
+/// quote. Returns the text and the index just past the closing quote.
+fn read_quoted(chars: &[char], start: usize) -> AppResult<(String, usize)> {
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '"' {
+            if chars.get(i + 1) == Some(&'"') {
+                text.push('"');
+                i += 2;
+                continue;
+            }
+            return Ok((text, i + 1));
+        }
+        text.push(chars[i]);
+        i += 1;
+    }
+    Err(syntax_error("引号没有闭合", start + 1))
+}
+
+fn parse_filter(key: &str, value: &str, at: usize) -> AppResult<Filter> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(syntax_error(&format!("{key}: 缺少值"), at));
+    }
+    let lower = value.to_lowercase();
+    Ok(match key {
+        "source" => Filter::Source(lower),
+        "folder" => Filter::Folder(value.to_string()),
+        "tag" => Filter::Tag(value.to_string()),
+        "role" => {
+            if !ROLES.contains(&lower.as_str()) {
+                return Err(syntax_error(
+                    &format!("role: 只支持 {}", ROLES.join("、")),
+                    at,
+                ));
+            }
+            Filter::Role(lower)
+        }
+        "model" => match value.strip_suffix('*') {
+            Some(stripped) => Filter::Model {
+                name: stripped.to_string(),
+                prefix: true,
+            },
+            None => Filter::Model {
+                name: value.to_string(),
+                prefix: false,
+            },
+        },
+        "before" | "after" => {
+            let Some(bound) = parse_date_bound(value) else {
+                return Err(syntax_error(&format!("{key}: 日期格式应为 YYYY-MM-DD"), at));
+            };
+            if key == "before" {
+                Filter::Before(bound)
+            } else {
+                Filter::After(bound)
+            }
+        }
+        "has" => {
+            if !ATTACHMENT_KINDS.contains(&lower.as_str()) {
+                return Err(syntax_error(
+                    &format!("has: 只支持 {}", ATTACHMENT_KINDS.join("、")),
+                    at,
+                ));
+            }
+            Filter::Has(lower)
+        }
+        _ => unreachable!("key checked against FILTER_KEYS"),
+    })
+}
+
+/// A calendar date means the start of that day in UTC; full timestamps are
+/// used as given. Formatted like stored timestamps so they compare as text.
+pub(super) fn parse_date_bound(value: &str) -> Option<String> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Some(timestamp.with_timezone(&Utc).to_rfc3339());
+    }
+    ["%Y-%m-%d", "%Y/%m/%d"]
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc().to_rfc3339())
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    /// Position reported for errors at the end of the input.
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn at(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map(|(_, at)| *at)
+            .unwrap_or(self.end)
+    }
+
+    fn starts_operand(&self) -> bool {
+        matches!(
+            self.peek(),
+            Some(Token::LParen | Token::Not | Token::Text { .. } | Token::Filter(_))
+        )
+    }
+
+    fn parse_or(&mut self) -> AppResult<Node> {
+        let mut items = vec![self.parse_and(false)?];
+        while matches!(self.peek(), Some(Token::Or)) {
+            self.position += 1;
+            items.push(self.parse_and(true)?);
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            Node::Or(items)
+        })
+    }
+
+    fn parse_and(&mut self, after_or: bool) -> AppResult<Node> {
+        if !self.starts_operand() {
+            let detail = match self.peek() {
+                Some(Token::And) => "AND 前面缺少搜索条件",
+                Some(Token::Or) => "OR 前面缺少搜索条件",
+                Some(Token::RParen) if !after_or => "多余的右括号",
+                _ => "OR 后面缺少搜索条件",
+            };
+            return Err(syntax_error(detail, self.at()));
+        }
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.position += 1;
+                    if !self.starts_operand() {
+                        return Err(syntax_error("AND 后面缺少搜索条件", self.at()));
+                    }
+                }
+                Some(_) if self.starts_operand() => items.push(self.parse_unary()?),
+                _ => break,
+            }
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            Node::And(items)
+        })
+    }
+
+    fn parse_unary(&mut self) -> AppResult<Node> {
+        if matches!(self.peek(), Some(Token::Not)) {
+            self.position += 1;
+            if !self.starts_operand() {
+                return Err(syntax_error("NOT 后面缺少搜索条件", self.at()));
+            }
+            return Ok(Node::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> AppResult<Node> {
+        let at = self.at();
+        let Some((token, _)) = self.tokens.get(self.position).cloned() else {
+            return Err(syntax_error("缺少搜索条件", at));
+        };
+        self.position += 1;
+        match token {
+            Token::LParen => {
+                if matches!(self.peek(), Some(Token::RParen)) {
+                    return Err(syntax_error("括号内没有内容", at));
+                }
+                let inner = self.parse_or()?;
+                if !matches!(self.peek(), Some(Token::RParen)) {
+                    return Err(syntax_error("括号没有闭合", at));
+                }
+                self.position += 1;
+                Ok(inner)
+            }
+            Token::Text {
+                text,
+                prefix,
+                field,
+            } => Ok(Node::Text {
+                text,
+                prefix,
+                field,
+            }),
+            Token::Filter(filter) => Ok(Node::Filter(filter)),
+            Token::RParen => Err(syntax_error("多余的右括号", at)),
+            Token::And | Token::Or | Token::Not => Err(syntax_error("缺少搜索条件", at)),
+        }
+    }
+}
+
+/// Positive words and phrases with the field they are restricted to.
+fn collect_texts(node: &Node, texts: &mut Vec<(String, Option<&'static str>)>) {
+    match node {
+        Node::Text { text, field, .. } => texts.push((text.clone(), *field)),
+        Node::And(items) | Node::Or(items) => {
+            for item in items {
+                collect_texts(item, texts);
+            }
+        }
+        Node::Not(_) | Node::Filter(_) => {}
+    }
+}
+
+/// Whether every piece of text in the node is `title:`/`summary:` scoped.
+fn conversation_text_only(node: &Node) -> bool {
+    match node {
+        Node::Text { field, .. } => field.is_some_and(|field| CONVERSATION_FIELDS.contains(&field)),
+        Node::And(items) | Node::Or(items) => items.iter().all(conversation_text_only),
+        Node::Not(inner) => conversation_text_only(inner),
+        Node::Filter(_) => false,
+    }
+}
+
+/// FTS5 expressions for the positive text in the node that messages can
+/// contain.
+fn collect_message_exprs(node: &Node, exprs: &mut Vec<String>) {
+    match node {
+        Node::Text { .. } if !conversation_text_only(node) => exprs.extend(fts_expr(node)),
+        Node::And(items) | Node::Or(items) => {
+            for item in items {
+                collect_message_exprs(item, exprs);
+            }
+        }
+        Node::Text { .. } | Node::Not(_) | Node::Filter(_) => {}
+    }
+}
+
+/// Whether the node has a filter on individual messages.
+fn has_message_filter(node: &Node) -> bool {
+    match node {
+        Node::Filter(filter) => matches!(filter, Filter::Role(_) | Filter::Model { .. }),
+        Node::And(items) | Node::Or(items) => items.iter().any(has_message_filter),
+        Node::Not(inner) => has_message_filter(inner),
+        Node::Text { .. } => false,
+    }
+}
+
+/// The node as a standalone FTS5 expression, if it is pure positive text.
+fn fts_expr(node: &Node) -> Option<String> {
+    match node {
+        Node::Text {
+            text,
+            prefix,
+            field,
+        } => {
+            let phrase = query_phrase(text, *prefix)?;
+            Some(match field {
+                Some(column) => format!("{column} : {phrase}"),
+                None => phrase,
+            })
+        }
+        Node::And(items) => {
+            let parts = items.iter().map(fts_expr).collect::<Option<Vec<_>>>()?;
+            Some(format!("({})", parts.join(" AND ")))
+        }
+        Node::Or(items) => {
+            let parts = items.iter().map(fts_expr).collect::<Option<Vec<_>>>()?;
+            Some(format!("({})", parts.join(" OR ")))
+        }
+        Node::Not(_) | Node::Filter(_) => None,
+    }
+}
+
+fn to_sql(node: &Node, scope: Scope, params: &mut Vec<Value>) -> String {
+    if let Some(expr) = fts_expr(node) {
+        params.push(Value::Text(expr));
+        return if scope == Scope::Message && !conversation_text_only(node) {
+            "m.id IN (SELECT message_id FROM messages_fts WHERE messages_fts MATCH ?)"
+        } else {
+            conversation_match_sql(conversation_text_only(node))
+        }
+        .to_string();
+    }
+    match node {
+        // The lexer drops text without searchable tokens, so every `Text`
+        // has an FTS expression.
+        Node::Text { .. } => "0".to_string(),
+        Node::Filter(filter) => filter_sql(filter, scope, params),
+        Node::And(items) => join_sql(items, " AND ", scope, params),
+        Node::Or(items) => join_sql(items, " OR ", scope, params),
+        // Excluded text rules out the whole conversation, not just the
+        // messages that contain it.
+        Node::Not(inner) => match fts_expr(inner) {
+            Some(expr) => {
+                params.push(Value::Text(expr));
+                format!("NOT ({})", conversation_match_sql(false))
+            }
+            None => format!("NOT ({})", to_sql(inner, scope, params)),
+        },
+    }
+}
+
+fn join_sql(items: &[Node], separator: &str, scope: Scope, params: &mut Vec<Value>) -> String {
+    let parts: Vec<String> = items
+        .iter()
+        .map(|item| to_sql(item, scope, params))
+        .collect();
+    format!("({})", parts.join(separator))
+}
+
+/// Message-level condition; in conversation scope it asks whether any
+/// message of the conversation satisfies it.
+fn message_sql(condition: &str, scope: Scope) -> String {
+    match scope {
+        Scope::Message => condition.replace("{m}", "m"),
+        Scope::Conversation => format!(
+            "EXISTS (SELECT 1 FROM messages fm WHERE fm.conversation_id = c.id AND {})",
+            condition.replace("{m}", "fm")
+        ),
+    }
+}
+
+fn filter_sql(filter: &Filter, scope: Scope, params: &mut Vec<Value>) -> String {
+    match filter {
+        Filter::Source(source) => {
+            params.push(Value::Text(source.clone()));
+            "c.source = ?".to_string()
+        }
+        // A folder matches by id or name and includes its subfolders.
+        Filter::Folder(folder) => {
+            params.push(Value::Text(folder.clone()));
+            params.push(Value::Text(folder.clone()));
+            r#"c.folder_id IN (
+              WITH RECURSIVE scope_folders(id) AS (
+                SELECT id FROM folders WHERE id = ? OR name = ? COLLATE NOCASE
+                UNION
+                SELECT f.id FROM folders f JOIN scope_folders s ON f.parent_id = s.id
+              )
+              SELECT id FROM scope_folders
+            )"#
+            .to_string()
+        }
+        Filter::Tag(tag) => {
+            params.push(Value::Text(tag.clone()));
+            "EXISTS (SELECT 1 FROM conversation_tags ct JOIN tags t ON t.id = ct.tag_id \
+             WHERE ct.conversation_id = c.id AND t.name = ? COLLATE NOCASE)"
+                .to_string()
+        }
+        Filter::Role(role) => {
+            params.push(Value::Text(role.clone()));
+            message_sql("{m}.role = ?", scope)
+        }
+        Filter::Model { name, prefix } => {
+            if *prefix {
+                params.push(Value::Text(format!("{}%", escape_like(name))));
+                message_sql("{m}.model LIKE ? ESCAPE '\\'", scope)
+            } else {
+                params.push(Value::Text(name.clone()));
+                message_sql("{m}.model = ? COLLATE NOCASE", scope)
+            }
+        }
+        // Dates compare against the conversation's last activity.
+        Filter::Before(bound) => {
+            params.push(Value::Text(bound.clone()));
+            "c.updated_at < ?".to_string()
+        }
+        Filter::After(bound) => {
+            params.push(Value::Text(bound.clone()));
+            "c.updated_at >= ?".to_string()
+        }
+        Filter::Has(kind) => {
+            params.push(Value::Text(kind.clone()));
+            "EXISTS (SELECT 1 FROM attachments a WHERE a.conversation_id = c.id AND a.kind = ?)"
+                .to_string()
+        }
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiled(query: &str) -> CompiledQuery {
+        compile(query)
+            .unwrap()
+            .expect("query has something to search for")
+    }
+
+    fn error(query: &str) -> String {
+        match compile(query) {
+            Err(AppError::InvalidInput(message)) => message,
+            Err(other) => panic!("unexpected error for {query:?}: {other}"),
+            Ok(_) => panic!("{query:?} should not parse"),
+        }
+    }
+
+    fn texts(params: &[Value]) -> Vec<&str> {
+        params
+            .iter()
+            .map(|value| match value {
+                Value::Text(text) => text.as_str(),
+                other => panic!("unexpected parameter {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_queries_search_nothing() {
+        assert!(compile("").unwrap().is_none());
+        assert!(compile("   ").unwrap().is_none());
+    }
+
+    #[test]
+    fn words_and_phrases_drive_the_full_text_match() {
+        let query = compiled("rust");
+        assert_eq!(query.fts.as_deref(), Some("\"rust\""));
+        assert_eq!(query.conversation_fts, query.fts);
+        assert_eq!(query.predicate, "1");
+        assert!(query.params.is_empty());
+        assert!(query.match_messages);
+        assert_eq!(query.terms, ["rust"]);
+    }
+
+    #[test]
+    fn each_top_level_term_may_match_a_different_row() {
+        let query = compiled("rust \"full text\"");
+        assert_eq!(query.fts.as_deref(), Some("\"rust\" OR \"full text\""));
+        assert_eq!(query.conversation_fts, query.fts);
+        let each = "c.id IN (SELECT conversation_id FROM messages_fts WHERE messages_fts MATCH ?)";
+        assert_eq!(query.predicate, format!("{each} AND {each}"));
+        assert_eq!(texts(&query.params), ["\"rust\"", "\"full text\""]);
+        assert_eq!(
+            query.conversation_predicate.as_deref(),
+            Some(query.predicate.as_str())
+        );
+        assert_eq!(texts(&query.conversation_params), texts(&query.params));
+        assert_eq!(query.terms, ["rust", "full text"]);
+    }
+
+    #[test]
+    fn a_trailing_star_makes_a_prefix() {
+        assert_eq!(compiled("rust*").fts.as_deref(), Some("\"rust\" *"));
+        assert_eq!(
+            compiled("\"full te\"*").fts.as_deref(),
+            Some("\"full te\" *")
+        );
+        assert_eq!(
+            compiled("content:\"a b\"*").fts.as_deref(),
+            Some("content : \"a b\" *")
+        );
+    }
+
+    #[test]
+    fn doubled_quotes_inside_a_phrase_are_literal() {
+        assert_eq!(compiled("\"say \"\"hi\"\"\"").terms, ["say \"hi\""]);
+    }
+
+    #[test]
+    fn operators_group_as_written() {
+        assert_eq!(compiled("a OR b").fts.as_deref(), Some("(\"a\" OR \"b\")"));
+        assert_eq!(
+            compiled("a AND (b OR c)").fts.as_deref(),
+            Some("\"a\" OR (\"b\" OR \"c\")")
+        );
+        // Nested AND still needs one row with both.
+        assert_eq!(
+            compiled("(a b) OR c").fts.as_deref(),
+            Some("((\"a\" AND \"b\") OR \"c\")")
+        );
+        // Lower-case operators are ordinary words.
+        assert_eq!(compiled("a or b").terms, ["a", "or", "b"]);
+    }
+
+    #[test]
+    fn negation_excludes_whole_conversations() {
+        for query in ["NOT x", "-x"] {
+            let query = compiled(query);
+            assert!(query.fts.is_none());
+            assert_eq!(
+                query.predicate,
+                "NOT (c.id IN (SELECT conversation_id FROM messages_fts WHERE messages_fts MATCH ?))"
+            );
+            assert_eq!(texts(&query.params), ["\"x\""]);
+            assert!(query.terms.is_empty());
+        }
+        // A lone dash is punctuation, not an operator.
+        assert_eq!(compiled("a - b").terms, ["a", "b"]);
+    }
+
+    #[test]
+    fn conversation_fields_stay_out_of_the_message_match() {
+        let query = compiled("title:rust tokio");
+        assert_eq!(query.fts.as_deref(), Some("\"tokio\""));
+        assert_eq!(texts(&query.params), ["title : \"rust\""]);
+        assert_eq!(
+            query.conversation_fts.as_deref(),
+            Some("title : \"rust\" OR \"tokio\"")
+        );
+        assert_eq!(
+            texts(&query.conversation_params),
+            ["title : \"rust\"", "\"tokio\""]
+        );
+        assert_eq!(query.terms, ["tokio"]);
+
+        let query = compiled("summary:rust");
+        assert!(query.fts.is_none());
+        assert!(!query.match_messages);
+        assert_eq!(
+            query.conversation_fts.as_deref(),
+            Some("summary : \"rust\"")
+        );
+    }
+
+    #[test]
+    fn message_fields_map_to_their_columns() {
+        for (query, expected) in [
+            ("content:rust", "content : \"rust\""),
+            ("thought:rust", "thoughts : \"rust\""),
+            ("file:report", "attachments : \"report\""),
+            ("Content:rust", "content : \"rust\""),
+        ] {
+            assert_eq!(compiled(query).fts.as_deref(), Some(expected), "{query}");
+        }
+    }
+
+    #[test]
+    fn each_filter_compiles_to_its_condition() {
+        for (query, predicate, params) in [
+            ("source:ChatGPT", "c.source = ?", vec!["chatgpt"]),
+            ("tag:Work", "EXISTS (SELECT 1 FROM conversation_tags ct JOIN tags t ON t.id = ct.tag_id \
+              WHERE ct.conversation_id = c.id AND t.name = ? COLLATE NOCASE)", vec!["Work"]),
+            ("role:User", "m.role = ?", vec!["user"]),
+            ("model:gpt-4o", "m.model = ? COLLATE NOCASE", vec!["gpt-4o"]),
+            ("model:gpt_4*", "m.model LIKE ? ESCAPE '\\'", vec!["gpt\\_4%"]),
+            ("before:2024-01-02", "c.updated_at < ?", vec!["2024-01-02T00:00:00+00:00"]),
+            ("after:2024/01/02", "c.updated_at >= ?", vec!["2024-01-02T00:00:00+00:00"]),
+            ("has:PDF", "EXISTS (SELECT 1 FROM attachments a WHERE a.conversation_id = c.id AND a.kind = ?)", vec!["pdf"]),
+        ] {
+            let query = compiled(query);
+            assert_eq!(query.predicate, predicate);
+            assert_eq!(texts(&query.params), params);
+            assert!(query.fts.is_none());
+            assert!(query.conversation_predicate.is_none());
+        }
+
+        let query = compiled("folder:\"My Notes\"");
+        assert!(query.predicate.starts_with("c.folder_id IN ("));
+        assert_eq!(texts(&query.params), ["My Notes", "My Notes"]);
+    }
+
+    #[test]
+    fn message_filters_ask_about_any_message_for_conversation_rows() {
+        let query = compiled("rust role:assistant");
+        assert_eq!(query.predicate, "m.role = ?");
+        assert_eq!(
+            query.conversation_predicate.as_deref(),
+            Some("EXISTS (SELECT 1 FROM messages fm WHERE fm.conversation_id = c.id AND fm.role = ?)")
+        );
+        assert_eq!(texts(&query.conversation_params), ["assistant"]);
+    }
+
+    #[test]
+    fn nested_text_matches_any_row_and_picks_the_hits() {
+        let query = compiled("rust OR source:gemini");
+        assert!(query.fts.is_none());
+        assert_eq!(query.hit_fts.as_deref(), Some("\"rust\""));
+        assert!(query.message_hits);
+        assert_eq!(
+            query.conversation_predicate.as_deref(),
+            Some(
+                "(c.id IN (SELECT conversation_id FROM messages_fts WHERE messages_fts MATCH ?) \
+                 OR c.source = ?)"
+            )
+        );
+
+        let query = compiled("(title:rust OR tag:x) model:gpt");
+        assert!(query.hit_fts.is_none());
+        assert!(query.message_hits);
+        assert!(query
+            .conversation_predicate
+            .unwrap()
+            .contains("MATCH ? AND message_id IS NULL"));
+    }
+
+    #[test]
+    fn unknown_keys_are_plain_text() {
+        for (query, fts) in [
+            ("error:timeout", "\"error timeout\""),
+            ("TODO:fix", "\"TODO fix\""),
+            ("note:foo", "\"note foo\""),
+            ("https://example.com", "\"https example com\""),
+        ] {
+            let compiled = compiled(query);
+            assert_eq!(compiled.fts.as_deref(), Some(fts), "{query}");
+            assert_eq!(compiled.terms, [query]);
+        }
+    }
+
+    #[test]
+    fn malformed_queries_report_where() {
+        for (query, expected) in [
+            ("(a", "括号没有闭合（第 1 个字符）"),
+            ("a)", "多余的右括号（第 2 个字符）"),
+            ("()", "括号内没有内容（第 1 个字符）"),
+            ("a AND", "AND 后面缺少搜索条件（第 6 个字符）"),
+            ("AND a", "AND 前面缺少搜索条件（第 1 个字符）"),
+            ("OR a", "OR 前面缺少搜索条件（第 1 个字符）"),
+            ("a OR", "OR 后面缺少搜索条件（第 5 个字符）"),
+            ("a NOT", "NOT 后面缺少搜索条件（第 6 个字符）"),
+            ("x \"abc", "引号没有闭合（第 3 个字符）"),
+            ("\"\"", "引号内没有内容（第 1 个字符）"),
+            ("a title:", "title: 缺少值（第 3 个字符）"),
+            ("tag:", "tag: 缺少值（第 1 个字符）"),
+            (
+                "role:bot",
+                "role: 只支持 user、assistant、system（第 1 个字符）",
+            ),
+            ("has:video", "has: 只支持 image、pdf、file（第 1 个字符）"),
+            (
+                "中文 before:昨天",
+                "before: 日期格式应为 YYYY-MM-DD（第 4 个字符）",
+            ),
+        ] {
+            assert_eq!(error(query), format!("搜索语法错误：{expected}"), "{query}");
+        }
+    }
+}
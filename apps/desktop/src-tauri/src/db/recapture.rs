@@ -0,0 +1,285 @@
+use rusqlite::{params, OptionalExtension, Transaction};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::{
+    collect_turn_attachments, compute_fingerprint, insert_attachment, insert_message,
+    normalize_timestamp, now_iso, revisions, rules, tags, tree, turn_thought_markdown, Database,
+    MessageLink,
+};
+use crate::error::AppResult;
+use crate::models::{ImportResult, NormalizedConversation, NormalizedTurn};
+
+struct StoredMessage {
+    id: String,
+    role: String,
+    content_markdown: String,
+    thought_markdown: Option<String>,
+}
+
+impl Database {
+    /// Updates the earlier capture of the same page in place; edited turns
+    /// become branches. `None` means there is no earlier capture to update.
+    pub(super) fn recapture_in_place(
+        &self,
+        conv: &NormalizedConversation,
+    ) -> AppResult<Option<ImportResult>> {
+        let Some(source_conversation_id) = conv.source_conversation_id.as_deref() else {
+            return Ok(None);
+        };
+
+        let conn = self.open()?;
+        let tx = conn.unchecked_transaction()?;
+        let fingerprint = compute_fingerprint(conv);
+
+        // A trashed earlier capture counts too: capturing the page again takes
+        // it back out of the trash.
+        let existing: Option<(String, String, bool)> = tx
+            .query_row(
+                r#"
+                SELECT id, meta_json, deleted_at IS NOT NULL FROM conversations
+                WHERE source = ?1 AND source_conversation_id = ?2
+                ORDER BY deleted_at IS NOT NULL, updated_at DESC
+                LIMIT 1
+                "#,
+                params![conv.source, source_conversation_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        let Some((conversation_id, existing_meta_json, trashed)) = existing else {
+            return Ok(None);
+        };
+        if trashed {
+            tx.execute(
+                "UPDATE conversations SET deleted_at = NULL WHERE id = ?1",
+                params![conversation_id],
+            )?;
+        }
+
+        revisions::snapshot_before_recapture(&tx, &conversation_id, &conv.source, &conv.turns)?;
+
+        // A capture is one path through the tree. Each turn reuses the child of
+        // the previous one with the same content, whichever branch it is on;
+        // an edited or inserted turn starts a new branch there instead, so
+        // stored messages are never rewritten. New turns go after every stored
+        // message to keep `seq` unique.
+        let mut next_seq: i64 = tx.query_row(
+            "SELECT COALESCE(MAX(seq), -1) + 1 FROM messages WHERE conversation_id = ?1",
+            params![conversation_id],
+            |row| row.get(0),
+        )?;
+        let mut path: Vec<String> = Vec::with_capacity(conv.turns.len());
+        for turn in &conv.turns {
+            let parent_id = path.last().map(String::as_str);
+            let existing = matching_child(&tx, &conversation_id, parent_id, &conv.source, turn)?;
+            let message_id = match existing {
+                Some(message_id) => {
+                    tx.execute(
+                        r#"
+                        UPDATE messages
+                        SET model = COALESCE(?1, model),
+                            timestamp = COALESCE(?2, timestamp),
+                            token_count = COALESCE(?3, token_count)
+                        WHERE id = ?4
+                        "#,
+                        params![turn.model, turn.timestamp, turn.token_count, message_id],
+                    )?;
+                    add_new_attachments(&tx, &conversation_id, &message_id, turn)?;
+                    message_id
+                }
+                None => {
+                    let link = MessageLink {
+                        parent_id,
+                        branch_index: tree::next_branch_index(&tx, &conversation_id, parent_id)?,
+                        on_active_path: true,
+                    };
+                    let message_id =
+                        insert_message(&tx, &conversation_id, &conv.source, next_seq, turn, &link)?;
+                    next_seq += 1;
+                    message_id
+                }
+            };
+            path.push(message_id);
+        }
+
+        // Turns the capture no longer shows stay in the tree, off the path.
+        tx.execute(
+            "UPDATE messages SET on_active_path = 0 WHERE conversation_id = ?1",
+            params![conversation_id],
+        )?;
+        for message_id in &path {
+            tx.execute(
+                "UPDATE messages SET on_active_path = 1 WHERE id = ?1",
+                params![message_id],
+            )?;
+        }
+        reuse_cached_files(&tx, &conversation_id)?;
+
+        let fingerprint_taken: Option<String> = tx
+            .query_row(
+                "SELECT id FROM conversations WHERE fingerprint = ?1 AND id <> ?2 LIMIT 1",
+                params![fingerprint, conversation_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let meta_json = merge_meta(&existing_meta_json, conv.meta.as_ref());
+        let captured_at = conv.captured_at.as_deref().and_then(normalize_timestamp);
+        let updated_at = conv
+            .updated_at
+            .as_deref()
+            .and_then(normalize_timestamp)
+            .or_else(|| captured_at.clone())
+            .unwrap_or_else(now_iso);
+
+        tx.execute(
+            r#"
+            UPDATE conversations
+            SET title = ?1,
+                summary = COALESCE(summary, ?2),
+                fingerprint = CASE WHEN ?3 THEN fingerprint ELSE ?4 END,
+                meta_json = ?5,
+                updated_at = MAX(created_at, ?6),
+                captured_at = COALESCE(?7, captured_at)
+            WHERE id = ?8
+            "#,
+            params![
+                conv.title,
+                conv.summary,
+                fingerprint_taken.is_some(),
+                fingerprint,
+                meta_json,
+                updated_at,
+                captured_at,
+                conversation_id,
+            ],
+        )?;
+        tags::apply_tag_names(&tx, &conversation_id, &conv.tags)?;
+        let rules = rules::load_enabled_rules(&tx)?;
+        let moves = rules::MovePolicy::FromUncategorized;
+        if let Some(change) = rules::plan_change(&tx, &rules, &conversation_id, moves)? {
+            rules::apply_change(&tx, &change)?;
+        }
+
+        tx.execute(
+            "INSERT INTO imports (id, source, imported_count, skipped_count, conflict_count, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![Uuid::new_v4().to_string(), "live_capture", 1, 0, 1, now_iso()],
+        )?;
+
+        tx.commit()?;
+        self.schedule_attachment_cache(conversation_id);
+
+        Ok(Some(ImportResult {
+            imported: 1,
+            skipped: 0,
+            conflicts: 1,
+        }))
+    }
+}
+
+/// The child of `parent_id` holding the same turn, preferring the one on the
+/// active path.
+fn matching_child(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    parent_id: Option<&str>,
+    source: &str,
+    turn: &NormalizedTurn,
+) -> AppResult<Option<String>> {
+    let mut stmt = tx.prepare_cached(
+        r#"
+        SELECT id, role, content_markdown, thought_markdown
+        FROM messages
+        WHERE conversation_id = ?1 AND parent_id IS ?2
+        ORDER BY on_active_path DESC, branch_index ASC
+        "#,
+    )?;
+    let rows = stmt.query_map(params![conversation_id, parent_id], |row| {
+        Ok(StoredMessage {
+            id: row.get(0)?,
+            role: row.get(1)?,
+            content_markdown: row.get(2)?,
+            thought_markdown: row.get(3)?,
+        })
+    })?;
+
+    let thought_markdown = turn_thought_markdown(source, turn);
+    for row in rows {
+        let message = row?;
+        if message.role == turn.role
+            && message.content_markdown == turn.content_markdown
+            && message.thought_markdown == thought_markdown
+        {
+            return Ok(Some(message.id));
+        }
+    }
+    Ok(None)
+}
+
+/// Adds attachments the capture now references; existing rows are kept.
+fn add_new_attachments(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    message_id: &str,
+    turn: &NormalizedTurn,
+) -> AppResult<()> {
+    let mut existing_urls: HashSet<String> = HashSet::new();
+    {
+        let mut stmt =
+            tx.prepare_cached("SELECT original_url FROM attachments WHERE message_id = ?1")?;
+        let rows = stmt.query_map(params![message_id], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            existing_urls.insert(row?);
+        }
+    }
+
+    for attachment in collect_turn_attachments(turn) {
+        if existing_urls.contains(&attachment.url) {
+            continue;
+        }
+        insert_attachment(tx, conversation_id, message_id, &attachment)?;
+    }
+    Ok(())
+}
+
+/// Points uncached attachments at a file another row of the conversation
+/// already cached from the same URL, e.g. on a new branch of an edited turn,
+/// so it is not downloaded again.
+fn reuse_cached_files(tx: &Transaction<'_>, conversation_id: &str) -> AppResult<()> {
+    tx.execute(
+        r#"
+        UPDATE attachments
+        SET status = 'cached', local_path = cached.local_path, mime = COALESCE(cached.mime, attachments.mime),
+            size_bytes = cached.size_bytes, sha256 = cached.sha256, error = NULL
+        FROM (
+          SELECT original_url, local_path, mime, size_bytes, sha256
+          FROM attachments
+          WHERE conversation_id = ?1 AND status = 'cached' AND local_path IS NOT NULL
+          GROUP BY original_url
+        ) AS cached
+        WHERE attachments.conversation_id = ?1
+          AND attachments.local_path IS NULL
+          AND attachments.original_url = cached.original_url
+        "#,
+        params![conversation_id],
+    )?;
+    Ok(())
+}
+
+/// Overlays the capture's meta keys onto the stored object so keys written by
+/// the app or other importers survive a re-capture.
+fn merge_meta(existing_json: &str, incoming: Option<&serde_json::Value>) -> String {
+    let mut merged = serde_json::from_str::<serde_json::Value>(existing_json)
+        .ok()
+        .filter(|value| value.is_object())
+        .unwrap_or_else(|| serde_json::json!({}));
+
+    if let (Some(target), Some(serde_json::Value::Object(fields))) =
+        (merged.as_object_mut(), incoming)
+    {
+        for (key, value) in fields {
+            target.insert(key.clone(), value.clone());
+        }
+    }
+
+    merged.to_string()
+}
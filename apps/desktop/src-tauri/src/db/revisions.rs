@@ -0,0 +1,486 @@
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::HashMap;
+
+use super::{now_iso, row_to_message, tree, turn_thought_markdown, Database, MESSAGE_COLUMNS};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ConversationRevision, ConversationRevisionDetail, Message, MessageChange, NormalizedTurn,
+    RevisionDiff,
+};
+
+impl Database {
+    pub fn list_conversation_revisions(
+        &self,
+        conversation_id: String,
+    ) -> AppResult<Vec<ConversationRevision>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare_cached(
+            r#"
+            SELECT id, conversation_id, revision, title, message_count, source_updated_at, created_at
+            FROM conversation_revisions
+            WHERE conversation_id = ?1
+            ORDER BY revision DESC
+            "#,
+        )?;
+        let rows = stmt.query_map(params![conversation_id], row_to_revision)?;
+
+        let mut revisions = Vec::new();
+        for row in rows {
+            revisions.push(row?);
+        }
+        Ok(revisions)
+    }
+
+    pub fn open_conversation_revision(
+        &self,
+        conversation_id: String,
+        revision: i64,
+    ) -> AppResult<ConversationRevisionDetail> {
+        let conn = self.open()?;
+        load_revision(&conn, &conversation_id, revision)
+    }
+
+    /// Diffs revision `from_revision` against `to_revision`, or against the
+    /// live conversation when `to_revision` is `None`.
+    pub fn diff_conversation_revisions(
+        &self,
+        conversation_id: String,
+        from_revision: i64,
+        to_revision: Option<i64>,
+    ) -> AppResult<RevisionDiff> {
+        let conn = self.open()?;
+        let from_messages = active_path(load_revision(&conn, &conversation_id, from_revision)?);
+        let to_messages = match to_revision {
+            Some(revision) => active_path(load_revision(&conn, &conversation_id, revision)?),
+            None => load_current_messages(&conn, &conversation_id)?,
+        };
+
+        let changes = diff_messages(&from_messages, &to_messages);
+        let count = |kind: &str| changes.iter().filter(|c| c.kind == kind).count() as i64;
+
+        Ok(RevisionDiff {
+            conversation_id,
+            from_revision,
+            to_revision,
+            added: count("added"),
+            removed: count("removed"),
+            edited: count("edited"),
+            changes,
+        })
+    }
+}
+
+/// The snapshot's active path, root first: a message gets a higher `seq`
+/// than its parent, and snapshots taken before they kept the whole tree
+/// hold only the path, in order.
+fn active_path(revision: ConversationRevisionDetail) -> Vec<Message> {
+    revision
+        .messages
+        .into_iter()
+        .filter(|message| message.on_active_path)
+        .collect()
+}
+
+fn row_to_revision(row: &rusqlite::Row<'_>) -> rusqlite::Result<ConversationRevision> {
+    Ok(ConversationRevision {
+        id: row.get(0)?,
+        conversation_id: row.get(1)?,
+        revision: row.get(2)?,
+        title: row.get(3)?,
+        message_count: row.get(4)?,
+        source_updated_at: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+fn load_revision(
+    conn: &Connection,
+    conversation_id: &str,
+    revision: i64,
+) -> AppResult<ConversationRevisionDetail> {
+    let found = conn
+        .query_row(
+            r#"
+            SELECT id, conversation_id, revision, title, message_count, source_updated_at, created_at,
+                   messages_json
+            FROM conversation_revisions
+            WHERE conversation_id = ?1 AND revision = ?2
+            "#,
+            params![conversation_id, revision],
+            |row| Ok((row_to_revision(row)?, row.get::<_, String>(7)?)),
+        )
+        .optional()?;
+    let Some((revision_row, messages_json)) = found else {
+        return Err(AppError::NotFound(format!(
+            "revision {revision} of conversation {conversation_id} not found"
+        )));
+    };
+
+    let messages: Vec<Message> = serde_json::from_str(&messages_json)
+        .map_err(|e| AppError::Database(format!("corrupt revision snapshot: {e}")))?;
+    Ok(ConversationRevisionDetail {
+        revision: revision_row,
+        messages,
+    })
+}
+
+/// The active path, root first. Revisions keep the whole tree, but this is
+/// the transcript that gets diffed.
+fn load_current_messages(conn: &Connection, conversation_id: &str) -> AppResult<Vec<Message>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        r#"
+        WITH RECURSIVE active_path(message_id, depth) AS (
+          SELECT id, 0 FROM messages
+          WHERE conversation_id = ?1 AND parent_id IS NULL AND on_active_path = 1
+          UNION
+          SELECT m.id, p.depth + 1
+          FROM messages m
+          JOIN active_path p ON m.parent_id = p.message_id
+          WHERE m.on_active_path = 1
+        )
+        SELECT {MESSAGE_COLUMNS}
+        FROM messages
+        JOIN active_path ON active_path.message_id = messages.id
+        ORDER BY active_path.depth ASC, messages.seq ASC
+        "#
+    ))?;
+    let rows = stmt.query_map(params![conversation_id], row_to_message)?;
+
+    let mut messages = Vec::new();
+    for row in rows {
+        messages.push(row?);
+    }
+    Ok(messages)
+}
+
+/// Every message of the conversation, branches included, in `seq` order.
+fn load_tree_messages(conn: &Connection, conversation_id: &str) -> AppResult<Vec<Message>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {MESSAGE_COLUMNS} FROM messages WHERE conversation_id = ?1 ORDER BY seq ASC"
+    ))?;
+    let rows = stmt.query_map(params![conversation_id], row_to_message)?;
+
+    let mut messages = Vec::new();
+    for row in rows {
+        messages.push(row?);
+    }
+    Ok(messages)
+}
+
+/// Snapshots the conversation before an overwrite replaces its whole tree.
+/// Nothing is stored when `incoming` would rebuild the same tree, so repeated
+/// imports of an unchanged export do not pile up revisions; a changed branch
+/// off the active path still counts, since the overwrite drops it.
+pub(super) fn snapshot_before_overwrite(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    source: &str,
+    incoming: &[NormalizedTurn],
+    current_turn_id: Option<&str>,
+) -> AppResult<()> {
+    let messages = load_tree_messages(tx, conversation_id)?;
+    let placements = tree::plan_message_tree(incoming, current_turn_id);
+    let position: HashMap<&str, usize> = messages
+        .iter()
+        .enumerate()
+        .map(|(index, message)| (message.id.as_str(), index))
+        .collect();
+    let parent_position = |message: &Message| {
+        message
+            .parent_id
+            .as_deref()
+            .and_then(|id| position.get(id).copied())
+    };
+    let unchanged = messages.len() == incoming.len()
+        && messages.iter().zip(incoming.iter().zip(&placements)).all(
+            |(message, (turn, placement))| {
+                same_content(message, source, turn)
+                    && message.on_active_path == placement.on_active_path
+                    && parent_position(message) == placement.parent
+            },
+        );
+    if unchanged {
+        return Ok(());
+    }
+    store_revision(tx, conversation_id, &messages)
+}
+
+/// Snapshots the conversation before a capture moves its active path. A
+/// capture only adds to the tree, so nothing is stored when the path it
+/// shows has the same turns as the stored one.
+pub(super) fn snapshot_before_recapture(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    source: &str,
+    incoming: &[NormalizedTurn],
+) -> AppResult<()> {
+    let path = load_current_messages(tx, conversation_id)?;
+    let unchanged = path.len() == incoming.len()
+        && path
+            .iter()
+            .zip(incoming)
+            .all(|(message, turn)| same_content(message, source, turn));
+    if unchanged {
+        return Ok(());
+    }
+    store_revision(
+        tx,
+        conversation_id,
+        &load_tree_messages(tx, conversation_id)?,
+    )
+}
+
+fn same_content(message: &Message, source: &str, turn: &NormalizedTurn) -> bool {
+    message.role == turn.role
+        && message.content_markdown == turn.content_markdown
+        && message.thought_markdown == turn_thought_markdown(source, turn)
+}
+
+/// Stores `messages`, the whole tree, as the conversation's next revision.
+fn store_revision(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    messages: &[Message],
+) -> AppResult<()> {
+    let (title, updated_at): (String, String) = tx.query_row(
+        "SELECT title, updated_at FROM conversations WHERE id = ?1",
+        params![conversation_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let next_revision: i64 = tx.query_row(
+        "SELECT COALESCE(MAX(revision), 0) + 1 FROM conversation_revisions WHERE conversation_id = ?1",
+        params![conversation_id],
+        |row| row.get(0),
+    )?;
+
+    tx.execute(
+        r#"
+        INSERT INTO conversation_revisions (
+          id, conversation_id, revision, title, message_count, messages_json,
+          source_updated_at, created_at
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        "#,
+        params![
+            uuid::Uuid::new_v4().to_string(),
+            conversation_id,
+            next_revision,
+            title,
+            messages.len() as i64,
+            serde_json::to_string(messages)?,
+            updated_at,
+            now_iso(),
+        ],
+    )?;
+    Ok(())
+}
+
+fn same_turn(a: &Message, b: &Message) -> bool {
+    a.role == b.role
+        && a.content_markdown == b.content_markdown
+        && a.thought_markdown == b.thought_markdown
+}
+
+/// Per-message diff from an LCS alignment of the two transcripts. Within each
+/// gap between matched turns, removed and added turns with the same role are
+/// paired up as edits (e.g. a regenerated answer); the rest stay added/removed.
+fn diff_messages(old: &[Message], new: &[Message]) -> Vec<MessageChange> {
+    let mut lcs = vec![vec![0_usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if same_turn(&old[i], &new[j]) {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let mut removed: Vec<&Message> = Vec::new();
+    let mut added: Vec<&Message> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && same_turn(&old[i], &new[j]) {
+            flush_gap(&mut changes, &mut removed, &mut added);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            added.push(&new[j]);
+            j += 1;
+        } else {
+            removed.push(&old[i]);
+            i += 1;
+        }
+    }
+    flush_gap(&mut changes, &mut removed, &mut added);
+    changes
+}
+
+fn flush_gap(
+    changes: &mut Vec<MessageChange>,
+    removed: &mut Vec<&Message>,
+    added: &mut Vec<&Message>,
+) {
+    let mut unmatched_added: Vec<&Message> = Vec::new();
+    for new_message in added.drain(..) {
+        match removed.iter().position(|old| old.role == new_message.role) {
+            Some(pos) => {
+                let old_message = removed.remove(pos);
+                changes.push(MessageChange {
+                    kind: "edited".to_string(),
+                    old: Some(old_message.clone()),
+                    new: Some(new_message.clone()),
+                });
+            }
+            None => unmatched_added.push(new_message),
+        }
+    }
+    for old_message in removed.drain(..) {
+        changes.push(MessageChange {
+            kind: "removed".to_string(),
+            old: Some(old_message.clone()),
+            new: None,
+        });
+    }
+    for new_message in unmatched_added {
+        changes.push(MessageChange {
+            kind: "added".to_string(),
+            old: None,
+            new: Some(new_message.clone()),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_support::{conversation, TempDatabase};
+    use crate::models::{Conversation, NormalizedConversation};
+
+    /// The prompt was edited after a first answer; the edit's answer is the
+    /// current turn.
+    fn branched_conversation(side_answer: &str) -> NormalizedConversation {
+        let turn = |id: &str, parent: Option<&str>, role: &str, text: &str| {
+            serde_json::json!({
+                "id": id, "parentId": parent, "role": role, "contentMarkdown": text,
+            })
+        };
+        serde_json::from_value(serde_json::json!({
+            "source": "chatgpt",
+            "sourceConversationId": "chatgpt-branched",
+            "title": "编辑过的提问",
+            "currentTurnId": "a2",
+            "turns": [
+                turn("u1", None, "user", "解释一下 RAG"),
+                turn("a1", Some("u1"), "assistant", side_answer),
+                turn("u2", None, "user", "用一句话解释 RAG"),
+                turn("a2", Some("u2"), "assistant", "先检索，再生成。"),
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn filed_conversation(created_at: Option<&str>, answer: &str) -> NormalizedConversation {
+        let mut conv = conversation(
+            "chatgpt-filed",
+            "归档的会话",
+            &[("user", "问题"), ("assistant", answer)],
+        );
+        conv.created_at = created_at.map(str::to_string);
+        conv
+    }
+
+    #[test]
+    fn overwrite_keeps_the_folder_and_first_timestamps() {
+        let db = TempDatabase::new();
+        let stored = |db: &TempDatabase| -> Conversation {
+            db.list_conversations(None).unwrap().items[0]
+                .conversation
+                .clone()
+        };
+
+        db.import(
+            filed_conversation(Some("2024-01-01T00:00:00Z"), "第一版"),
+            "overwrite",
+        );
+        let first = stored(&db);
+        let folder = db.create_folder("项目".to_string(), None).unwrap();
+        db.move_conversation(first.id.clone(), Some(folder.id.clone()))
+            .unwrap();
+
+        db.import(filed_conversation(None, "第二版"), "overwrite");
+        let second = stored(&db);
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.folder_id.as_deref(), Some(folder.id.as_str()));
+        assert_eq!(second.created_at, first.created_at);
+        assert_eq!(second.imported_at, first.imported_at);
+        assert_eq!(
+            db.list_conversation_revisions(first.id.clone())
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // A folder passed with the import still wins.
+        let other = db.create_folder("其他".to_string(), None).unwrap();
+        db.import_into(
+            filed_conversation(None, "第三版"),
+            "overwrite",
+            Some(other.id.clone()),
+        );
+        assert_eq!(stored(&db).folder_id.as_deref(), Some(other.id.as_str()));
+    }
+
+    #[test]
+    fn overwrites_snapshot_the_whole_tree() {
+        let db = TempDatabase::new();
+        db.import(branched_conversation("RAG 是检索增强生成。"), "overwrite");
+        let id = db.list_conversations(None).unwrap().items[0]
+            .conversation
+            .id
+            .clone();
+        db.import(branched_conversation("RAG 是检索增强生成。"), "overwrite");
+        assert!(db
+            .list_conversation_revisions(id.clone())
+            .unwrap()
+            .is_empty());
+
+        // Only the branch off the active path changed, and the overwrite
+        // drops the old one, so it still gets a revision.
+        db.import(branched_conversation("RAG 先检索再生成。"), "overwrite");
+        let revision = db.open_conversation_revision(id.clone(), 1).unwrap();
+        let snapshot: Vec<(&str, Option<&str>, bool)> = revision
+            .messages
+            .iter()
+            .map(|message| {
+                let parent = message.parent_id.as_deref().map(|parent_id| {
+                    revision
+                        .messages
+                        .iter()
+                        .find(|m| m.id == parent_id)
+                        .unwrap()
+                        .content_markdown
+                        .as_str()
+                });
+                (
+                    message.content_markdown.as_str(),
+                    parent,
+                    message.on_active_path,
+                )
+            })
+            .collect();
+        assert_eq!(
+            snapshot,
+            vec![
+                ("解释一下 RAG", None, false),
+                ("RAG 是检索增强生成。", Some("解释一下 RAG"), false),
+                ("用一句话解释 RAG", None, true),
+                ("先检索，再生成。", Some("用一句话解释 RAG"), true),
+            ]
+        );
+        assert_eq!(revision.revision.message_count, 4);
+
+        // Diffs compare the active paths, which did not change.
+        let diff = db.diff_conversation_revisions(id, 1, None).unwrap();
+        assert!(diff.changes.is_empty());
+    }
+}
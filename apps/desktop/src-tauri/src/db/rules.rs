@@ -0,0 +1,569 @@
+use regex::{Regex, RegexBuilder};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::{now_iso, tags, Database, UNCATEGORIZED_FOLDER_ID};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ApplyImportRulesInput, ApplyImportRulesReport, ImportRule, ImportRuleChange,
+    ImportRuleConditions, ImportRuleInput,
+};
+
+const ATTACHMENT_KINDS: &[&str] = &["image", "pdf", "file"];
+const MAX_PATTERN_BYTES: usize = 1 << 20;
+
+impl Database {
+    pub fn list_import_rules(&self) -> AppResult<Vec<ImportRule>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {RULE_COLUMNS} FROM import_rules ORDER BY position ASC, created_at ASC"
+        ))?;
+        let rows = stmt.query_map([], row_to_rule)?;
+        let mut rules = Vec::new();
+        for row in rows {
+            rules.push(row?);
+        }
+        Ok(rules)
+    }
+
+    /// Adds a rule after the existing ones.
+    pub fn create_import_rule(&self, input: ImportRuleInput) -> AppResult<ImportRule> {
+        let conn = self.open()?;
+        let input = validate_rule(&conn, input)?;
+        let id = Uuid::new_v4().to_string();
+        let now = now_iso();
+        conn.execute(
+            r#"
+            INSERT INTO import_rules (
+              id, name, enabled, position, conditions_json, folder_id, tags_json,
+              created_at, updated_at
+            )
+            VALUES (
+              ?1, ?2, ?3, (SELECT COALESCE(MAX(position), -1) + 1 FROM import_rules),
+              ?4, ?5, ?6, ?7, ?7
+            )
+            "#,
+            params![
+                id,
+                input.name,
+                input.enabled,
+                serde_json::to_string(&input.conditions)?,
+                input.folder_id,
+                serde_json::to_string(&input.tags)?,
+                now,
+            ],
+        )?;
+        load_rule(&conn, &id)
+    }
+
+    pub fn update_import_rule(&self, id: String, input: ImportRuleInput) -> AppResult<ImportRule> {
+        let conn = self.open()?;
+        let input = validate_rule(&conn, input)?;
+        let updated = conn.execute(
+            r#"
+            UPDATE import_rules
+            SET name = ?1, enabled = ?2, conditions_json = ?3, folder_id = ?4, tags_json = ?5,
+                updated_at = ?6
+            WHERE id = ?7
+            "#,
+            params![
+                input.name,
+                input.enabled,
+                serde_json::to_string(&input.conditions)?,
+                input.folder_id,
+                serde_json::to_string(&input.tags)?,
+                now_iso(),
+                id,
+            ],
+        )?;
+        if updated == 0 {
+            return Err(AppError::NotFound(format!("import rule {id} not found")));
+        }
+        load_rule(&conn, &id)
+    }
+
+    pub fn delete_import_rule(&self, id: String) -> AppResult<()> {
+        let conn = self.open()?;
+        let deleted = conn.execute("DELETE FROM import_rules WHERE id = ?1", params![id])?;
+        if deleted == 0 {
+            return Err(AppError::NotFound(format!("import rule {id} not found")));
+        }
+        Ok(())
+    }
+
+    /// Sets the evaluation order; `ids` must list every rule exactly once.
+    pub fn reorder_import_rules(&self, ids: Vec<String>) -> AppResult<Vec<ImportRule>> {
+        let conn = self.open()?;
+        let tx = conn.unchecked_transaction()?;
+        let total: i64 = tx.query_row("SELECT COUNT(*) FROM import_rules", [], |row| row.get(0))?;
+        let unique: HashSet<&String> = ids.iter().collect();
+        if unique.len() != ids.len() || ids.len() as i64 != total {
+            return Err(AppError::InvalidInput(
+                "规则排序必须包含全部规则且不能重复".to_string(),
+            ));
+        }
+        for (position, id) in ids.iter().enumerate() {
+            let updated = tx.execute(
+                "UPDATE import_rules SET position = ?1 WHERE id = ?2",
+                params![position as i64, id],
+            )?;
+            if updated == 0 {
+                return Err(AppError::NotFound(format!("import rule {id} not found")));
+            }
+        }
+        tx.commit()?;
+        drop(conn);
+        self.list_import_rules()
+    }
+
+    /// Re-runs the enabled rules over every live conversation. Conversations
+    /// already filed elsewhere only move when `include_filed` is set; tags are
+    /// only ever added.
+    pub fn apply_import_rules(
+        &self,
+        input: ApplyImportRulesInput,
+    ) -> AppResult<ApplyImportRulesReport> {
+        let conn = self.open()?;
+        self.ensure_system_folders(&conn)?;
+        let rules = load_enabled_rules(&conn)?;
+        let ids: Vec<String> = {
+            let mut stmt = conn.prepare(
+                "SELECT id FROM conversations WHERE deleted_at IS NULL ORDER BY created_at ASC",
+            )?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let moves = if input.include_filed {
+            MovePolicy::Always
+        } else {
+            MovePolicy::FromUncategorized
+        };
+        let mut changes = Vec::new();
+        for id in &ids {
+            if let Some(change) = plan_change(&conn, &rules, id, moves)? {
+                changes.push(change);
+            }
+        }
+
+        if !input.dry_run && !changes.is_empty() {
+            let tx = conn.unchecked_transaction()?;
+            for change in &changes {
+                apply_change(&tx, change)?;
+            }
+            tx.commit()?;
+        }
+
+        Ok(ApplyImportRulesReport {
+            dry_run: input.dry_run,
+            scanned: ids.len() as i64,
+            moved: changes.iter().filter(|c| c.to_folder_id.is_some()).count() as i64,
+            tagged: changes.iter().filter(|c| !c.added_tags.is_empty()).count() as i64,
+            changes,
+        })
+    }
+}
+
+const RULE_COLUMNS: &str = "id, name, enabled, position, conditions_json, folder_id, tags_json, \
+     created_at, updated_at";
+
+fn row_to_rule(row: &rusqlite::Row<'_>) -> rusqlite::Result<ImportRule> {
+    let conditions_json: String = row.get(4)?;
+    let tags_json: String = row.get(6)?;
+    Ok(ImportRule {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        enabled: row.get(2)?,
+        position: row.get(3)?,
+        conditions: serde_json::from_str(&conditions_json).unwrap_or_default(),
+        folder_id: row.get(5)?,
+        tags: serde_json::from_str(&tags_json).unwrap_or_default(),
+        created_at: row.get(7)?,
+        updated_at: row.get(8)?,
+    })
+}
+
+fn load_rule(conn: &Connection, id: &str) -> AppResult<ImportRule> {
+    conn.query_row(
+        &format!("SELECT {RULE_COLUMNS} FROM import_rules WHERE id = ?1"),
+        params![id],
+        row_to_rule,
+    )
+    .optional()?
+    .ok_or_else(|| AppError::NotFound(format!("import rule {id} not found")))
+}
+
+fn validate_rule(conn: &Connection, input: ImportRuleInput) -> AppResult<ImportRuleInput> {
+    let name = input.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::InvalidInput("规则名称不能为空".to_string()));
+    }
+
+    let raw = input.conditions;
+    let conditions = ImportRuleConditions {
+        sources: clean_list(raw.sources, |value| Ok(value.to_lowercase()))?,
+        title_pattern: raw
+            .title_pattern
+            .map(|pattern| pattern.trim().to_string())
+            .filter(|pattern| !pattern.is_empty()),
+        content_keywords: clean_list(raw.content_keywords, |value| Ok(value.to_string()))?,
+        models: clean_list(raw.models, |value| Ok(value.to_string()))?,
+        url_hosts: clean_list(raw.url_hosts, normalize_host)?,
+        attachment_kinds: clean_list(raw.attachment_kinds, |value| {
+            let kind = value.to_lowercase();
+            if ATTACHMENT_KINDS.contains(&kind.as_str()) {
+                Ok(kind)
+            } else {
+                Err(AppError::InvalidInput(format!(
+                    "未知的附件类型：{value}，可用的有 {}",
+                    ATTACHMENT_KINDS.join("、")
+                )))
+            }
+        })?,
+    };
+    if let Some(pattern) = conditions.title_pattern.as_deref() {
+        compile_pattern(pattern)?;
+    }
+    let has_condition = !conditions.sources.is_empty()
+        || conditions.title_pattern.is_some()
+        || !conditions.content_keywords.is_empty()
+        || !conditions.models.is_empty()
+        || !conditions.url_hosts.is_empty()
+        || !conditions.attachment_kinds.is_empty();
+    if !has_condition {
+        return Err(AppError::InvalidInput(
+            "规则至少需要一个匹配条件".to_string(),
+        ));
+    }
+
+    let mut seen = HashSet::new();
+    let mut tag_names = Vec::new();
+    for tag in input.tags {
+        let tag = tags::normalize_tag_name(&tag)?;
+        if seen.insert(tag.to_lowercase()) {
+            tag_names.push(tag);
+        }
+    }
+    let folder_id = input.folder_id.filter(|id| !id.is_empty());
+    if let Some(folder_id) = folder_id.as_deref() {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM folders WHERE id = ?1)",
+            params![folder_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(AppError::NotFound(format!("folder {folder_id} not found")));
+        }
+    }
+    if folder_id.is_none() && tag_names.is_empty() {
+        return Err(AppError::InvalidInput(
+            "规则需要指定目标文件夹或标签".to_string(),
+        ));
+    }
+
+    Ok(ImportRuleInput {
+        name,
+        enabled: input.enabled,
+        conditions,
+        folder_id,
+        tags: tag_names,
+    })
+}
+
+/// Trims entries, drops empty ones and duplicates.
+fn clean_list(
+    values: Vec<String>,
+    normalize: impl Fn(&str) -> AppResult<String>,
+) -> AppResult<Vec<String>> {
+    let mut out: Vec<String> = Vec::new();
+    for value in values {
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        let value = normalize(value)?;
+        if !out.contains(&value) {
+            out.push(value);
+        }
+    }
+    Ok(out)
+}
+
+/// Accepts a bare host (`chatgpt.com`, `*.openai.com`) or a pasted URL.
+fn normalize_host(value: &str) -> AppResult<String> {
+    let host = if value.contains("://") {
+        reqwest::Url::parse(value)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .unwrap_or_default()
+    } else {
+        value.trim_start_matches("*.").to_string()
+    };
+    let host = host.trim_end_matches('.').to_lowercase();
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|ch| ch.is_alphanumeric() || matches!(ch, '.' | '-' | '_'));
+    if !valid {
+        return Err(AppError::InvalidInput(format!(
+            "网址域名格式不正确：{value}"
+        )));
+    }
+    Ok(host)
+}
+
+fn compile_pattern(pattern: &str) -> AppResult<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .size_limit(MAX_PATTERN_BYTES)
+        .build()
+        .map_err(|err| AppError::InvalidInput(format!("标题正则表达式无效：{err}")))
+}
+
+/// An enabled rule ready to be matched.
+pub(super) struct CompiledRule {
+    id: String,
+    conditions: ImportRuleConditions,
+    title_pattern: Option<Regex>,
+    /// Lower-cased, so matching never allocates per rule.
+    content_keywords: Vec<String>,
+    models: Vec<String>,
+    folder_id: Option<String>,
+    tags: Vec<String>,
+}
+
+pub(super) fn load_enabled_rules(conn: &Connection) -> AppResult<Vec<CompiledRule>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {RULE_COLUMNS} FROM import_rules WHERE enabled = 1 \
+         ORDER BY position ASC, created_at ASC"
+    ))?;
+    let rows = stmt.query_map([], row_to_rule)?;
+    let mut rules = Vec::new();
+    for row in rows {
+        let rule = row?;
+        let title_pattern = match rule.conditions.title_pattern.as_deref() {
+            Some(pattern) => Some(compile_pattern(pattern)?),
+            None => None,
+        };
+        let lower = |values: &[String]| values.iter().map(|v| v.to_lowercase()).collect();
+        rules.push(CompiledRule {
+            content_keywords: lower(&rule.conditions.content_keywords),
+            models: lower(&rule.conditions.models),
+            id: rule.id,
+            title_pattern,
+            conditions: rule.conditions,
+            folder_id: rule.folder_id,
+            tags: rule.tags,
+        });
+    }
+    Ok(rules)
+}
+
+/// What a stored conversation looks like to the rules.
+struct Subject {
+    source: String,
+    title: String,
+    folder_id: Option<String>,
+    host: Option<String>,
+    /// Lower-cased message contents.
+    contents: Vec<String>,
+    models: Vec<String>,
+    attachment_kinds: HashSet<String>,
+    tags: HashSet<String>,
+}
+
+fn load_subject(conn: &Connection, conversation_id: &str) -> AppResult<Subject> {
+    let (source, title, folder_id, source_conversation_id): (
+        String,
+        String,
+        Option<String>,
+        Option<String>,
+    ) = conn
+        .query_row(
+            "SELECT source, title, folder_id, source_conversation_id FROM conversations WHERE id = ?1",
+            params![conversation_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("conversation {conversation_id} not found")))?;
+
+    // Live captures are keyed by their page URL; other imports by a source id.
+    let host = source_conversation_id
+        .as_deref()
+        .filter(|value| value.starts_with("http://") || value.starts_with("https://"))
+        .and_then(|value| reqwest::Url::parse(value).ok())
+        .and_then(|url| url.host_str().map(str::to_lowercase));
+
+    let mut contents = Vec::new();
+    let mut models = Vec::new();
+    let mut stmt = conn.prepare_cached(
+        "SELECT content_markdown, model FROM messages WHERE conversation_id = ?1",
+    )?;
+    let rows = stmt.query_map(params![conversation_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?))
+    })?;
+    for row in rows {
+        let (content, model) = row?;
+        contents.push(content.to_lowercase());
+        if let Some(model) = model {
+            models.push(model.to_lowercase());
+        }
+    }
+
+    let mut stmt =
+        conn.prepare_cached("SELECT DISTINCT kind FROM attachments WHERE conversation_id = ?1")?;
+    let attachment_kinds = stmt
+        .query_map(params![conversation_id], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stmt = conn.prepare_cached(
+        r#"
+        SELECT t.name FROM tags t
+        JOIN conversation_tags ct ON ct.tag_id = t.id
+        WHERE ct.conversation_id = ?1
+        "#,
+    )?;
+    let tags = stmt
+        .query_map(params![conversation_id], |row| row.get::<_, String>(0))?
+        .map(|name| name.map(|name| name.to_lowercase()))
+        .collect::<rusqlite::Result<_>>()?;
+
+    Ok(Subject {
+        source,
+        title,
+        folder_id,
+        host,
+        contents,
+        models,
+        attachment_kinds,
+        tags,
+    })
+}
+
+fn rule_matches(rule: &CompiledRule, subject: &Subject) -> bool {
+    let conditions = &rule.conditions;
+    if !conditions.sources.is_empty() && !conditions.sources.contains(&subject.source) {
+        return false;
+    }
+    if let Some(pattern) = rule.title_pattern.as_ref() {
+        if !pattern.is_match(&subject.title) {
+            return false;
+        }
+    }
+    if !rule.content_keywords.is_empty()
+        && !rule.content_keywords.iter().any(|keyword| {
+            subject
+                .contents
+                .iter()
+                .any(|content| content.contains(keyword.as_str()))
+        })
+    {
+        return false;
+    }
+    if !rule.models.is_empty()
+        && !rule.models.iter().any(|name| {
+            subject
+                .models
+                .iter()
+                .any(|model| model.contains(name.as_str()))
+        })
+    {
+        return false;
+    }
+    if !conditions.url_hosts.is_empty() {
+        let Some(host) = subject.host.as_deref() else {
+            return false;
+        };
+        let matched = conditions.url_hosts.iter().any(|wanted| {
+            host == wanted
+                || host
+                    .strip_suffix(wanted.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        });
+        if !matched {
+            return false;
+        }
+    }
+    if !conditions.attachment_kinds.is_empty()
+        && !conditions
+            .attachment_kinds
+            .iter()
+            .any(|kind| subject.attachment_kinds.contains(kind))
+    {
+        return false;
+    }
+    true
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(super) enum MovePolicy {
+    Never,
+    /// Only conversations still in the uncategorized folder.
+    FromUncategorized,
+    Always,
+}
+
+/// Matches `rules` against a stored conversation; `None` when nothing would
+/// change.
+pub(super) fn plan_change(
+    conn: &Connection,
+    rules: &[CompiledRule],
+    conversation_id: &str,
+    moves: MovePolicy,
+) -> AppResult<Option<ImportRuleChange>> {
+    if rules.is_empty() {
+        return Ok(None);
+    }
+    let subject = load_subject(conn, conversation_id)?;
+    let mut rule_ids = Vec::new();
+    let mut folder_id: Option<&str> = None;
+    let mut added_tags: Vec<String> = Vec::new();
+    let mut seen_tags = subject.tags.clone();
+    for rule in rules.iter().filter(|rule| rule_matches(rule, &subject)) {
+        rule_ids.push(rule.id.clone());
+        if folder_id.is_none() {
+            folder_id = rule.folder_id.as_deref();
+        }
+        for tag in &rule.tags {
+            if seen_tags.insert(tag.to_lowercase()) {
+                added_tags.push(tag.clone());
+            }
+        }
+    }
+
+    let uncategorized = subject
+        .folder_id
+        .as_deref()
+        .is_none_or(|id| id == UNCATEGORIZED_FOLDER_ID);
+    let may_move = match moves {
+        MovePolicy::Never => false,
+        MovePolicy::FromUncategorized => uncategorized,
+        MovePolicy::Always => true,
+    };
+    let to_folder_id = folder_id
+        .filter(|target| may_move && subject.folder_id.as_deref() != Some(*target))
+        .map(str::to_string);
+
+    if to_folder_id.is_none() && added_tags.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(ImportRuleChange {
+        conversation_id: conversation_id.to_string(),
+        title: subject.title,
+        from_folder_id: subject.folder_id,
+        to_folder_id,
+        added_tags,
+        rule_ids,
+    }))
+}
+
+pub(super) fn apply_change(tx: &Transaction<'_>, change: &ImportRuleChange) -> AppResult<()> {
+    if let Some(folder_id) = change.to_folder_id.as_deref() {
+        tx.execute(
+            "UPDATE conversations SET folder_id = ?1 WHERE id = ?2",
+            params![folder_id, change.conversation_id],
+        )?;
+    }
+    tags::apply_tag_names(tx, &change.conversation_id, &change.added_tags)
+}
@@ -0,0 +1,1039 @@
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use super::migrations::SEARCH_REBUILD_PENDING_KEY;
+use super::query::{self, CompiledQuery};
+use super::{
+    extract_filename_from_url, row_to_conversation, Database, CONVERSATION_COLUMNS,
+    CONVERSATION_COLUMN_COUNT,
+};
+use crate::error::AppResult;
+use crate::models::{
+    ConversationSummary, SearchHit, SearchInput, SearchPage, SearchResult, TextRange,
+};
+
+/// SQL function that turns text into the token stream stored in
+/// `messages_fts`. Registered on every connection so SQL (the rebuild below,
+/// triggers) and Rust index text the same way.
+pub(super) const SEARCH_TOKENS_FN: &str = "search_tokens";
+
+const SNIPPET_CONTEXT_CHARS: usize = 24;
+pub(super) const SNIPPET_MAX_CHARS: usize = 96;
+
+/// SQL function giving the file name an attachment URL refers to, or an
+/// empty string when the URL does not name one.
+const ATTACHMENT_FILE_NAME_FN: &str = "attachment_file_name";
+
+/// bm25 weights for the `messages_fts` columns, in declaration order. The id
+/// columns are unindexed; a hit in a title outweighs one in a long message.
+const RANK_WEIGHTS: &str = "bm25(0.0, 0.0, 8.0, 4.0, 1.0, 0.5, 2.0)";
+
+pub(super) const DEFAULT_PAGE_SIZE: i64 = 50;
+pub(super) const MAX_PAGE_SIZE: i64 = 200;
+
+pub(super) struct RankedConversation {
+    pub id: String,
+    pub score: f64,
+    pub title_match: bool,
+    pub updated_at: String,
+}
+
+impl Database {
+    /// Runs a query in the syntax described in `query.rs`. Malformed queries
+    /// are rejected with `InvalidInput` rather than searched literally.
+    ///
+    /// Conversations are ranked by their best message's bm25 score plus the
+    /// score of their title and summary (weighted by `RANK_WEIGHTS`);
+    /// filter-only queries have no score and fall back to most recently
+    /// updated first.
+    pub fn search_conversations(&self, input: SearchInput) -> AppResult<SearchPage> {
+        let offset = input.offset.max(0);
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let empty_page = SearchPage {
+            results: Vec::new(),
+            total: 0,
+            offset,
+            next_offset: None,
+        };
+        let Some(compiled) = query::compile(&input.query)? else {
+            return Ok(empty_page);
+        };
+        let conn = self.open()?;
+
+        let ranked = rank_conversations(&conn, &compiled)?;
+        let total = ranked.len() as i64;
+        let page: Vec<&RankedConversation> = ranked
+            .iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        if page.is_empty() {
+            return Ok(SearchPage {
+                total,
+                ..empty_page
+            });
+        }
+
+        let ids: Vec<String> = page.iter().map(|entry| entry.id.clone()).collect();
+        let mut summaries = load_summaries(&conn, &ids)?;
+        let mut hits = load_hits(&conn, &compiled, &ids)?;
+
+        let mut results = Vec::with_capacity(page.len());
+        for entry in page {
+            let Some(summary) = summaries.remove(&entry.id) else {
+                continue;
+            };
+            let hits = hits.remove(&entry.id).unwrap_or_default();
+            let best_hit = hits.iter().max_by(|a, b| {
+                a.score
+                    .partial_cmp(&b.score)
+                    .unwrap_or(Ordering::Equal)
+                    .then(b.seq.cmp(&a.seq))
+            });
+            let snippet = match best_hit {
+                Some(hit) if !entry.title_match || !hit.highlights.is_empty() => {
+                    hit.snippet.clone()
+                }
+                _ => summary.conversation.title.clone(),
+            };
+            results.push(SearchResult {
+                conversation: summary,
+                snippet,
+                score: entry.score,
+                title_match: entry.title_match,
+                hits,
+            });
+        }
+
+        let next = offset + limit;
+        Ok(SearchPage {
+            results,
+            total,
+            offset,
+            next_offset: (next < total).then_some(next),
+        })
+    }
+
+    /// Re-tokenizes every conversation and message into a fresh
+    /// `messages_fts`; returns the number of messages indexed.
+    pub fn rebuild_search_index(&self) -> AppResult<i64> {
+        let conn = self.open()?;
+        let tx = conn.unchecked_transaction()?;
+        let indexed = rebuild_search_index(&tx)?;
+        tx.commit()?;
+        Ok(indexed)
+    }
+}
+
+/// FROM/WHERE shared by the ranking and hit queries: messages `m` of live
+/// conversations `c` that satisfy the query and match `fts` when given, plus
+/// the per-message score. The FTS5 `rank` column is bm25 and, unlike
+/// `bm25()`, may be aggregated.
+fn message_scope(
+    compiled: &CompiledQuery,
+    fts: Option<&String>,
+) -> (String, &'static str, Vec<Value>) {
+    let mut params = Vec::new();
+    let (from, score) = match fts {
+        Some(fts) => {
+            params.push(Value::Text(fts.clone()));
+            (
+                "FROM messages_fts \
+                 JOIN messages m ON m.id = messages_fts.message_id \
+                 JOIN conversations c ON c.id = m.conversation_id \
+                 WHERE messages_fts MATCH ? AND",
+                "-messages_fts.rank",
+            )
+        }
+        None => (
+            "FROM messages m JOIN conversations c ON c.id = m.conversation_id WHERE",
+            "0.0",
+        ),
+    };
+    params.extend(compiled.params.iter().cloned());
+    (
+        format!("{from} c.deleted_at IS NULL AND ({})", compiled.predicate),
+        score,
+        params,
+    )
+}
+
+pub(super) fn rank_conversations(
+    conn: &Connection,
+    compiled: &CompiledQuery,
+) -> AppResult<Vec<RankedConversation>> {
+    let mut by_id: HashMap<String, RankedConversation> = HashMap::new();
+
+    if compiled.match_messages {
+        let (scope, score, params) = message_scope(compiled, compiled.fts.as_ref());
+        let mut stmt = conn.prepare(&format!(
+            "SELECT c.id, MAX({score}), c.updated_at {scope} GROUP BY c.id"
+        ))?;
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            Ok(RankedConversation {
+                id: row.get(0)?,
+                score: row.get(1)?,
+                title_match: false,
+                updated_at: row.get(2)?,
+            })
+        })?;
+        for row in rows {
+            let entry = row?;
+            by_id.insert(entry.id.clone(), entry);
+        }
+    }
+
+    // Title and summary live in one index row per conversation, the one
+    // without a message_id.
+    if let Some(predicate) = &compiled.conversation_predicate {
+        let mut params = Vec::new();
+        let (from, score) = match &compiled.conversation_fts {
+            Some(fts) => {
+                params.push(Value::Text(fts.clone()));
+                (
+                    "FROM messages_fts \
+                     JOIN conversations c ON c.id = messages_fts.conversation_id \
+                     WHERE messages_fts MATCH ? AND messages_fts.message_id IS NULL AND",
+                    "-messages_fts.rank",
+                )
+            }
+            None => ("FROM conversations c WHERE", "0.0"),
+        };
+        params.extend(compiled.conversation_params.iter().cloned());
+        let mut stmt = conn.prepare(&format!(
+            "SELECT c.id, {score}, c.updated_at {from} c.deleted_at IS NULL AND ({predicate})"
+        ))?;
+        let rows = stmt.query_map(params_from_iter(params), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, f64>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (id, score, updated_at) = row?;
+            by_id
+                .entry(id.clone())
+                .and_modify(|entry| {
+                    entry.score += score;
+                    entry.title_match = true;
+                })
+                .or_insert(RankedConversation {
+                    id,
+                    score,
+                    title_match: true,
+                    updated_at,
+                });
+        }
+    }
+
+    let mut ranked: Vec<RankedConversation> = by_id.into_values().collect();
+    ranked.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| b.updated_at.cmp(&a.updated_at))
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    Ok(ranked)
+}
+
+pub(super) fn placeholders(count: usize) -> String {
+    vec!["?"; count].join(", ")
+}
+
+pub(super) fn load_summaries(
+    conn: &Connection,
+    ids: &[String],
+) -> AppResult<HashMap<String, ConversationSummary>> {
+    let mut stmt = conn.prepare(&format!(
+        r#"
+        SELECT
+          {CONVERSATION_COLUMNS},
+          (SELECT COUNT(*) FROM messages mc WHERE mc.conversation_id = c.id) AS message_count
+        FROM conversations c
+        WHERE c.id IN ({})
+        "#,
+        placeholders(ids.len())
+    ))?;
+    let rows = stmt.query_map(params_from_iter(ids.iter()), |row| {
+        Ok(ConversationSummary {
+            conversation: row_to_conversation(row)?,
+            message_count: row.get(CONVERSATION_COLUMN_COUNT)?,
+        })
+    })?;
+
+    let mut summaries = HashMap::new();
+    for row in rows {
+        let summary = row?;
+        summaries.insert(summary.conversation.id.clone(), summary);
+    }
+    Ok(summaries)
+}
+
+/// Message fields a hit can come from, in the order they are tried for the
+/// snippet; the names match the `messages_fts` columns.
+const HIT_FIELDS: [&str; 3] = ["content", "thoughts", "attachments"];
+
+pub(super) fn load_hits(
+    conn: &Connection,
+    compiled: &CompiledQuery,
+    ids: &[String],
+) -> AppResult<HashMap<String, Vec<SearchHit>>> {
+    let mut hits: HashMap<String, Vec<SearchHit>> = HashMap::new();
+    if !compiled.message_hits {
+        return Ok(hits);
+    }
+    let (scope, score, mut params) = message_scope(compiled, compiled.hit_fts.as_ref());
+    params.extend(ids.iter().cloned().map(Value::Text));
+    let mut stmt = conn.prepare(&format!(
+        r#"
+        SELECT m.conversation_id, m.id, m.seq, m.role, {score}, m.on_active_path,
+          m.content_markdown,
+          COALESCE(m.thought_markdown, ''),
+          COALESCE((
+            SELECT group_concat({ATTACHMENT_FILE_NAME_FN}(a.original_url), ' ')
+            FROM attachments a WHERE a.message_id = m.id
+          ), '')
+        {scope} AND c.id IN ({})
+        ORDER BY m.conversation_id, m.seq
+        "#,
+        placeholders(ids.len())
+    ))?;
+    let rows = stmt.query_map(params_from_iter(params), |row| {
+        let mut fields = Vec::with_capacity(HIT_FIELDS.len());
+        for (offset, field) in HIT_FIELDS.iter().enumerate() {
+            let text: String = row.get(6 + offset)?;
+            let chars: Vec<char> = text.chars().collect();
+            let ranges = highlight_ranges(&chars, &compiled.terms);
+            fields.push((*field, chars, ranges));
+        }
+        let matched = fields
+            .iter()
+            .position(|(_, _, ranges)| !ranges.is_empty())
+            .unwrap_or(0);
+        let (field, chars, ranges) = fields.swap_remove(matched);
+        Ok((
+            row.get::<_, String>(0)?,
+            SearchHit {
+                message_id: row.get(1)?,
+                seq: row.get(2)?,
+                role: row.get(3)?,
+                field: field.to_string(),
+                snippet: snippet_around(&chars, &ranges),
+                highlights: utf16_ranges(&chars, &ranges),
+                score: row.get(4)?,
+                on_active_path: row.get(5)?,
+            },
+        ))
+    })?;
+
+    for row in rows {
+        let (conversation_id, hit) = row?;
+        hits.entry(conversation_id).or_default().push(hit);
+    }
+    Ok(hits)
+}
+
+pub(super) fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8
+        | FunctionFlags::SQLITE_DETERMINISTIC
+        | FunctionFlags::SQLITE_INNOCUOUS;
+    conn.create_scalar_function(SEARCH_TOKENS_FN, 1, flags, |ctx| {
+        let text = ctx.get::<Option<String>>(0)?;
+        Ok(text.map(|text| search_tokens(&text)))
+    })?;
+    conn.create_scalar_function(ATTACHMENT_FILE_NAME_FN, 1, flags, |ctx| {
+        let url = ctx.get::<Option<String>>(0)?;
+        Ok(url
+            .as_deref()
+            .and_then(extract_filename_from_url)
+            .unwrap_or_default())
+    })
+}
+
+/// Indexed attachment names of the message whose id is `message_id`, for the
+/// triggers and the rebuild below.
+fn attachment_names_sql(message_id: &str) -> String {
+    format!(
+        "{SEARCH_TOKENS_FN}((SELECT group_concat({ATTACHMENT_FILE_NAME_FN}(a.original_url), ' ') \
+         FROM attachments a WHERE a.message_id = {message_id}))"
+    )
+}
+
+/// Re-reads the attachment names of message `message_id` into its index row.
+fn refresh_attachments_sql(message_id: &str) -> String {
+    format!(
+        "UPDATE messages_fts SET attachments = {} \
+         WHERE message_id = {message_id};",
+        attachment_names_sql(message_id)
+    )
+}
+
+/// Runs the rebuild a migration queued, if any, and takes it off the queue.
+pub(super) fn run_queued_rebuild(conn: &Connection) -> AppResult<()> {
+    let queued: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM app_settings WHERE key = ?1)",
+        params![SEARCH_REBUILD_PENDING_KEY],
+        |row| row.get(0),
+    )?;
+    if !queued {
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    rebuild_search_index(&tx)?;
+    tx.execute(
+        "DELETE FROM app_settings WHERE key = ?1",
+        params![SEARCH_REBUILD_PENDING_KEY],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Drops and recreates `messages_fts` and the triggers that keep it in step
+/// with `conversations`, `messages` and `attachments`, then indexes
+/// everything; returns the number of messages indexed.
+///
+/// Each message has a row keyed by its `message_id` with `content`,
+/// `thoughts` and `attachments`; each conversation has a row keyed by its
+/// `conversation_id`, with no `message_id`, holding `title` and `summary`.
+/// Rows are never addressed by rowid, which `VACUUM` may renumber.
+pub(super) fn rebuild_search_index(conn: &Connection) -> rusqlite::Result<i64> {
+    let new_attachments = attachment_names_sql("NEW.id");
+    let all_attachments = attachment_names_sql("m.id");
+    let refresh_new = refresh_attachments_sql("NEW.message_id");
+    let refresh_old = refresh_attachments_sql("OLD.message_id");
+    conn.execute_batch(&format!(
+        r#"
+        DROP TRIGGER IF EXISTS conversations_fts_insert;
+        DROP TRIGGER IF EXISTS conversations_fts_update;
+        DROP TRIGGER IF EXISTS conversations_fts_delete;
+        DROP TRIGGER IF EXISTS messages_fts_insert;
+        DROP TRIGGER IF EXISTS messages_fts_update;
+        DROP TRIGGER IF EXISTS messages_fts_delete;
+        DROP TRIGGER IF EXISTS attachments_fts_insert;
+        DROP TRIGGER IF EXISTS attachments_fts_update;
+        DROP TRIGGER IF EXISTS attachments_fts_delete;
+        DROP TABLE IF EXISTS messages_fts;
+
+        CREATE VIRTUAL TABLE messages_fts USING fts5(
+          message_id UNINDEXED,
+          conversation_id UNINDEXED,
+          title,
+          summary,
+          content,
+          thoughts,
+          attachments,
+          tokenize = 'unicode61 remove_diacritics 2'
+        );
+        INSERT INTO messages_fts (messages_fts, rank) VALUES ('rank', '{RANK_WEIGHTS}');
+
+        INSERT INTO messages_fts (conversation_id, title, summary)
+        SELECT id, {SEARCH_TOKENS_FN}(title), {SEARCH_TOKENS_FN}(summary)
+        FROM conversations;
+        INSERT INTO messages_fts (message_id, conversation_id, content, thoughts, attachments)
+        SELECT m.id, m.conversation_id, {SEARCH_TOKENS_FN}(m.content_markdown),
+          {SEARCH_TOKENS_FN}(m.thought_markdown), {all_attachments}
+        FROM messages m;
+
+        CREATE TRIGGER conversations_fts_insert AFTER INSERT ON conversations BEGIN
+          INSERT INTO messages_fts (conversation_id, title, summary)
+          VALUES (NEW.id, {SEARCH_TOKENS_FN}(NEW.title), {SEARCH_TOKENS_FN}(NEW.summary));
+        END;
+        CREATE TRIGGER conversations_fts_update AFTER UPDATE OF title, summary ON conversations BEGIN
+          UPDATE messages_fts
+          SET title = {SEARCH_TOKENS_FN}(NEW.title), summary = {SEARCH_TOKENS_FN}(NEW.summary)
+          WHERE conversation_id = NEW.id AND message_id IS NULL;
+        END;
+        CREATE TRIGGER conversations_fts_delete AFTER DELETE ON conversations BEGIN
+          DELETE FROM messages_fts WHERE conversation_id = OLD.id AND message_id IS NULL;
+        END;
+
+        CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+          INSERT INTO messages_fts (message_id, conversation_id, content, thoughts, attachments)
+          VALUES (NEW.id, NEW.conversation_id, {SEARCH_TOKENS_FN}(NEW.content_markdown),
+            {SEARCH_TOKENS_FN}(NEW.thought_markdown), {new_attachments});
+        END;
+        CREATE TRIGGER messages_fts_update AFTER UPDATE OF content_markdown, thought_markdown ON messages BEGIN
+          UPDATE messages_fts
+          SET content = {SEARCH_TOKENS_FN}(NEW.content_markdown),
+              thoughts = {SEARCH_TOKENS_FN}(NEW.thought_markdown)
+          WHERE message_id = NEW.id;
+        END;
+        CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+          DELETE FROM messages_fts WHERE message_id = OLD.id;
+        END;
+
+        CREATE TRIGGER attachments_fts_insert AFTER INSERT ON attachments BEGIN
+          {refresh_new}
+        END;
+        CREATE TRIGGER attachments_fts_update AFTER UPDATE OF original_url, message_id ON attachments BEGIN
+          {refresh_old}
+          {refresh_new}
+        END;
+        CREATE TRIGGER attachments_fts_delete AFTER DELETE ON attachments BEGIN
+          {refresh_old}
+        END;
+        "#
+    ))?;
+    conn.query_row(
+        "SELECT COUNT(*) FROM messages_fts WHERE message_id IS NOT NULL",
+        [],
+        |row| row.get(0),
+    )
+}
+
+/// Han, kana and Hangul: scripts written without spaces between words, which
+/// `unicode61` would otherwise keep as one token per unbroken run.
+fn is_cjk(ch: char) -> bool {
+    matches!(ch,
+        '\u{3005}'
+        | '\u{3040}'..='\u{309F}'
+        | '\u{30A0}'..='\u{30FA}'
+        | '\u{30FC}'..='\u{30FF}'
+        | '\u{3130}'..='\u{318F}'
+        | '\u{31F0}'..='\u{31FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{AC00}'..='\u{D7AF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FF66}'..='\u{FF9F}'
+        | '\u{20000}'..='\u{2FFFF}'
+    )
+}
+
+enum Segment<'a> {
+    Cjk(Vec<char>),
+    Other(&'a str),
+}
+
+fn segments(text: &str) -> Vec<Segment<'_>> {
+    let mut out = Vec::new();
+    let mut other_start: Option<usize> = None;
+    let mut run: Vec<char> = Vec::new();
+    for (offset, ch) in text.char_indices() {
+        if is_cjk(ch) {
+            if let Some(start) = other_start.take() {
+                out.push(Segment::Other(&text[start..offset]));
+            }
+            run.push(ch);
+        } else {
+            if !run.is_empty() {
+                out.push(Segment::Cjk(std::mem::take(&mut run)));
+            }
+            other_start.get_or_insert(offset);
+        }
+    }
+    if let Some(start) = other_start {
+        out.push(Segment::Other(&text[start..]));
+    }
+    if !run.is_empty() {
+        out.push(Segment::Cjk(run));
+    }
+    out
+}
+
+fn bigrams(run: &[char]) -> impl Iterator<Item = String> + '_ {
+    run.windows(2).map(|pair| pair.iter().collect())
+}
+
+/// Index-side tokens: every CJK run becomes its overlapping bigrams plus its
+/// last character on its own, so two-character words match exactly, longer
+/// words match as a phrase of bigrams, and a single character matches as a
+/// prefix. Other text is passed through for `unicode61` to split on spaces and
+/// punctuation.
+pub(super) fn search_tokens(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() * 2);
+    for segment in segments(text) {
+        match segment {
+            Segment::Other(other) => out.push_str(other),
+            Segment::Cjk(run) => {
+                out.push(' ');
+                for bigram in bigrams(&run) {
+                    out.push_str(&bigram);
+                    out.push(' ');
+                }
+                if let Some(last) = run.last() {
+                    out.push(*last);
+                }
+                out.push(' ');
+            }
+        }
+    }
+    out
+}
+
+/// Query-side counterpart of `search_tokens`: an FTS5 phrase that matches
+/// `term` anywhere in indexed text. A CJK run only keeps its trailing single
+/// character when more text follows in the term, because that is where the
+/// index has it too; a lone trailing character becomes a prefix query.
+/// `prefix` asks for the last word to match as a prefix (`word*`).
+pub(super) fn query_phrase(term: &str, prefix: bool) -> Option<String> {
+    let mut parts: Vec<(Vec<String>, Option<String>)> = Vec::new();
+    for segment in segments(term) {
+        match segment {
+            Segment::Other(other) => {
+                let words: Vec<String> = other
+                    .split(|ch: char| !ch.is_alphanumeric())
+                    .filter(|word| !word.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                if !words.is_empty() {
+                    parts.push((words, None));
+                }
+            }
+            Segment::Cjk(run) if run.len() == 1 => parts.push((vec![run[0].to_string()], None)),
+            Segment::Cjk(run) => {
+                parts.push((bigrams(&run).collect(), run.last().map(char::to_string)));
+            }
+        }
+    }
+    let last_index = parts.len().checked_sub(1)?;
+
+    let mut tokens: Vec<String> = Vec::new();
+    let mut star = false;
+    for (index, (words, trailing)) in parts.into_iter().enumerate() {
+        let is_last = index == last_index;
+        star = is_last && trailing.is_none() && {
+            let mut chars = words[0].chars();
+            let single_cjk =
+                words.len() == 1 && chars.next().is_some_and(is_cjk) && chars.next().is_none();
+            let latin = !words[0].chars().any(is_cjk);
+            single_cjk || (prefix && latin)
+        };
+        tokens.extend(words);
+        if !is_last {
+            tokens.extend(trailing);
+        }
+    }
+
+    let phrase = tokens.join(" ").replace('"', "\"\"");
+    Some(if star {
+        format!("\"{phrase}\" *")
+    } else {
+        format!("\"{phrase}\"")
+    })
+}
+
+/// Case-insensitive occurrences of `terms` in `chars` as sorted, merged
+/// `[start, end)` character ranges.
+fn highlight_ranges(chars: &[char], terms: &[String]) -> Vec<(usize, usize)> {
+    let mut ranges = Vec::new();
+    for term in terms {
+        let needle: Vec<char> = term.trim().chars().collect();
+        if needle.is_empty() || needle.len() > chars.len() {
+            continue;
+        }
+        let mut start = 0;
+        while start + needle.len() <= chars.len() {
+            let matched = chars[start..start + needle.len()]
+                .iter()
+                .zip(&needle)
+                .all(|(a, b)| a.to_lowercase().eq(b.to_lowercase()));
+            if matched {
+                ranges.push((start, start + needle.len()));
+                start += needle.len();
+            } else {
+                start += 1;
+            }
+        }
+    }
+    ranges.sort_unstable();
+
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// A short excerpt around the first range, with every range inside it
+/// wrapped in `[` `]`.
+fn snippet_around(chars: &[char], ranges: &[(usize, usize)]) -> String {
+    let Some(&(first_start, first_end)) = ranges.first() else {
+        return truncate_chars(chars, 0, SNIPPET_MAX_CHARS);
+    };
+    let from = first_start.saturating_sub(SNIPPET_CONTEXT_CHARS);
+    let to = (first_end + SNIPPET_MAX_CHARS.saturating_sub(first_end - from)).min(chars.len());
+
+    let mut out = String::new();
+    if from > 0 {
+        out.push('…');
+    }
+    let mut cursor = from;
+    for &(start, end) in ranges.iter().filter(|(start, _)| *start < to) {
+        let end = end.min(to);
+        out.extend(&chars[cursor..start]);
+        out.push('[');
+        out.extend(&chars[start..end]);
+        out.push(']');
+        cursor = end;
+    }
+    out.extend(&chars[cursor..to.max(cursor)]);
+    if to < chars.len() {
+        out.push('…');
+    }
+    collapse_whitespace(&out)
+}
+
+fn utf16_ranges(chars: &[char], ranges: &[(usize, usize)]) -> Vec<TextRange> {
+    let mut offsets = Vec::with_capacity(chars.len() + 1);
+    let mut offset = 0_i64;
+    offsets.push(0);
+    for ch in chars {
+        offset += ch.len_utf16() as i64;
+        offsets.push(offset);
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| TextRange {
+            start: offsets[start],
+            end: offsets[end],
+        })
+        .collect()
+}
+
+pub(super) fn truncate_chars(chars: &[char], from: usize, max: usize) -> String {
+    let to = (from + max).min(chars.len());
+    let mut out: String = chars[from..to].iter().collect();
+    if to < chars.len() {
+        out.push('…');
+    }
+    collapse_whitespace(&out)
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::{conversation, TempDatabase};
+    use rusqlite::params;
+
+    const CORPUS: &[(&str, &str)] = &[
+        ("zh-1", "我们正在讨论全文搜索的实现方式。"),
+        ("zh-2", "中文分词一直是个难题，尤其是在没有空格的文本里。"),
+        ("zh-3", "这个模型的上下文窗口很大"),
+        ("ja-1", "東京タワーに行きました。とても綺麗でした。"),
+        ("ja-2", "ひらがなとカタカナの混在したテキストを検索する"),
+        ("mixed-1", "用GPT模型写了一段Rust代码，然后用SQLite存储。"),
+        ("mixed-2", "Gemini 1.5 Pro の長いコンテキストは便利です"),
+        ("en-1", "Full-text search with SQLite FTS5 and bm25 ranking"),
+        ("ko-1", "한국어 검색도 지원해야 합니다"),
+    ];
+
+    fn corpus_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_functions(&conn).unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE conversations (id TEXT PRIMARY KEY, title TEXT, summary TEXT);
+            CREATE TABLE messages (
+              id TEXT PRIMARY KEY, conversation_id TEXT, content_markdown TEXT, thought_markdown TEXT
+            );
+            CREATE TABLE attachments (id TEXT PRIMARY KEY, message_id TEXT, original_url TEXT);
+            INSERT INTO conversations (id, title) VALUES ('c', 'corpus');
+            "#,
+        )
+        .unwrap();
+        for (id, text) in CORPUS {
+            conn.execute(
+                "INSERT INTO messages (id, conversation_id, content_markdown) VALUES (?1, 'c', ?2)",
+                params![id, text],
+            )
+            .unwrap();
+        }
+        assert_eq!(rebuild_search_index(&conn).unwrap(), CORPUS.len() as i64);
+        conn
+    }
+
+    fn search(conn: &Connection, query: &str) -> Vec<String> {
+        let Some(phrase) = query_phrase(query, false) else {
+            return Vec::new();
+        };
+        let mut stmt = conn
+            .prepare(
+                "SELECT message_id FROM messages_fts \
+                 WHERE messages_fts MATCH ?1 AND message_id IS NOT NULL ORDER BY message_id",
+            )
+            .unwrap();
+        let rows = stmt
+            .query_map(params![phrase], |row| row.get::<_, String>(0))
+            .unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn chinese_words_match_inside_unbroken_text() {
+        let conn = corpus_db();
+        assert_eq!(search(&conn, "搜索"), vec!["zh-1"]);
+        assert_eq!(search(&conn, "分词"), vec!["zh-2"]);
+        assert_eq!(search(&conn, "全文搜索"), vec!["zh-1"]);
+        assert_eq!(search(&conn, "上下文窗口"), vec!["zh-3"]);
+        assert_eq!(search(&conn, "模型"), vec!["mixed-1", "zh-3"]);
+    }
+
+    #[test]
+    fn chinese_single_characters_match() {
+        let conn = corpus_db();
+        assert_eq!(search(&conn, "窗"), vec!["zh-3"]);
+        // Last character of a run is indexed on its own.
+        assert_eq!(search(&conn, "大"), vec!["zh-3"]);
+    }
+
+    #[test]
+    fn non_adjacent_characters_do_not_match() {
+        let conn = corpus_db();
+        assert!(search(&conn, "全搜").is_empty());
+        assert!(search(&conn, "中分").is_empty());
+    }
+
+    #[test]
+    fn japanese_kanji_and_kana_match() {
+        let conn = corpus_db();
+        assert_eq!(search(&conn, "東京"), vec!["ja-1"]);
+        assert_eq!(search(&conn, "タワー"), vec!["ja-1"]);
+        assert_eq!(search(&conn, "カタカナ"), vec!["ja-2"]);
+        assert_eq!(search(&conn, "検索"), vec!["ja-2"]);
+        assert_eq!(search(&conn, "コンテキスト"), vec!["mixed-2"]);
+    }
+
+    #[test]
+    fn mixed_language_queries_match() {
+        let conn = corpus_db();
+        assert_eq!(search(&conn, "GPT模型"), vec!["mixed-1"]);
+        assert_eq!(search(&conn, "型写"), vec!["mixed-1"]);
+        assert_eq!(search(&conn, "Rust代码"), vec!["mixed-1"]);
+        assert_eq!(search(&conn, "用SQLite"), vec!["mixed-1"]);
+        assert_eq!(search(&conn, "Pro の"), vec!["mixed-2"]);
+        assert_eq!(search(&conn, "sqlite"), vec!["en-1", "mixed-1"]);
+    }
+
+    #[test]
+    fn latin_and_hangul_match() {
+        let conn = corpus_db();
+        assert_eq!(search(&conn, "full-text search"), vec!["en-1"]);
+        assert_eq!(search(&conn, "FTS5"), vec!["en-1"]);
+        assert_eq!(search(&conn, "검색"), vec!["ko-1"]);
+        assert!(search(&conn, "postgres").is_empty());
+    }
+
+    #[test]
+    fn quotes_and_punctuation_are_not_query_syntax() {
+        let conn = corpus_db();
+        assert_eq!(search(&conn, "\"搜索\""), vec!["zh-1"]);
+        assert!(query_phrase("，。！", false).is_none());
+        assert!(query_phrase("\"", false).is_none());
+    }
+
+    #[test]
+    fn triggers_follow_messages_by_id() {
+        let conn = corpus_db();
+        conn.execute_batch(
+            r#"
+            DELETE FROM messages WHERE id = 'zh-1';
+            UPDATE messages SET content_markdown = '换成别的内容' WHERE id = 'zh-2';
+            INSERT INTO attachments (id, message_id, original_url)
+            VALUES ('a', 'en-1', 'https://example.com/report.pdf');
+            UPDATE conversations SET title = '新的标题' WHERE id = 'c';
+            "#,
+        )
+        .unwrap();
+        assert!(search(&conn, "搜索").is_empty());
+        assert!(search(&conn, "分词").is_empty());
+        assert_eq!(search(&conn, "别的内容"), vec!["zh-2"]);
+        assert_eq!(search(&conn, "report"), vec!["en-1"]);
+        let titles: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM messages_fts WHERE messages_fts MATCH ?1 AND conversation_id = 'c'",
+                params![query_phrase("新的标题", false).unwrap()],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(titles, 1);
+    }
+
+    fn snippet(content: &str, terms: &[&str]) -> String {
+        let chars: Vec<char> = content.chars().collect();
+        let terms: Vec<String> = terms.iter().map(|term| term.to_string()).collect();
+        snippet_around(&chars, &highlight_ranges(&chars, &terms))
+    }
+
+    #[test]
+    fn snippet_marks_the_hits() {
+        assert_eq!(
+            snippet("我们正在讨论全文搜索的实现方式。", &["搜索"]),
+            "我们正在讨论全文[搜索]的实现方式。"
+        );
+        assert_eq!(
+            snippet("Using SQLite here, sqlite too", &["here", "sqlite"]),
+            "Using [SQLite] [here], [sqlite] too"
+        );
+    }
+
+    #[test]
+    fn highlights_are_utf16_offsets() {
+        let chars: Vec<char> = "😀 搜索 and 搜索".chars().collect();
+        let ranges = highlight_ranges(&chars, &["搜索".to_string()]);
+        let offsets: Vec<(i64, i64)> = utf16_ranges(&chars, &ranges)
+            .iter()
+            .map(|range| (range.start, range.end))
+            .collect();
+        assert_eq!(offsets, vec![(3, 5), (10, 12)]);
+    }
+
+    fn search_results(db: &Database, query: &str) -> Vec<SearchResult> {
+        db.search_conversations(SearchInput {
+            query: query.to_string(),
+            offset: 0,
+            limit: None,
+        })
+        .unwrap()
+        .results
+    }
+
+    fn hits(db: &Database, query: &str) -> Vec<(String, bool)> {
+        let results = search_results(db, query);
+        assert_eq!(results.len(), 1, "{query}");
+        results[0]
+            .hits
+            .iter()
+            .map(|hit| (hit.role.clone(), hit.on_active_path))
+            .collect()
+    }
+
+    #[test]
+    fn conversation_filters_alone_return_no_hits() {
+        let db = TempDatabase::new();
+        let mut conv = conversation(
+            "gemini-filters",
+            "过滤条件",
+            &[
+                ("user", "第一个问题"),
+                ("assistant", "第一个回答"),
+                ("user", "第二个问题"),
+            ],
+        );
+        conv.source = "gemini".to_string();
+        db.import(conv, "skip");
+
+        assert!(hits(&db, "source:gemini").is_empty());
+        assert!(hits(&db, "source:gemini after:2000-01-01").is_empty());
+        assert_eq!(
+            hits(&db, "source:gemini role:user"),
+            [("user".to_string(), true), ("user".to_string(), true)]
+        );
+        assert_eq!(hits(&db, "回答"), [("assistant".to_string(), true)]);
+    }
+
+    #[test]
+    fn plain_terms_may_match_different_rows_of_a_conversation() {
+        let db = TempDatabase::new();
+        db.import(
+            conversation("title", "Rust notes", &[("user", "how to retry a request")]),
+            "skip",
+        );
+        db.import(
+            conversation(
+                "turns",
+                "notes",
+                &[("user", "rust question"), ("user", "then retry")],
+            ),
+            "skip",
+        );
+        db.import(
+            conversation("one", "notes", &[("user", "only rust here")]),
+            "skip",
+        );
+
+        let found = |query: &str| {
+            let mut found: Vec<(String, usize)> = search_results(&db, query)
+                .into_iter()
+                .map(|result| {
+                    (
+                        result
+                            .conversation
+                            .conversation
+                            .source_conversation_id
+                            .unwrap(),
+                        result.hits.len(),
+                    )
+                })
+                .collect();
+            found.sort();
+            found
+        };
+        assert_eq!(
+            found("rust retry"),
+            [("title".to_string(), 1), ("turns".to_string(), 2)]
+        );
+        assert_eq!(found("title:rust retry"), [("title".to_string(), 1)]);
+        assert!(found("\"rust retry\"").is_empty());
+    }
+
+    #[test]
+    fn text_nested_under_filters_matches_and_highlights_messages() {
+        let db = TempDatabase::new();
+        db.import(
+            conversation(
+                "nested",
+                "notes",
+                &[("user", "a rust question"), ("assistant", "an answer")],
+            ),
+            "skip",
+        );
+
+        for query in ["rust OR source:gemini", "(rust OR tag:x) role:user"] {
+            let results = search_results(&db, query);
+            assert_eq!(results.len(), 1, "{query}");
+            let hits = &results[0].hits;
+            assert_eq!(hits.len(), 1, "{query}");
+            assert_eq!(hits[0].role, "user");
+            assert_eq!(hits[0].highlights.len(), 1);
+            assert_eq!(results[0].snippet, "a [rust] question");
+        }
+        assert!(search_results(&db, "notes (retry OR source:gemini)").is_empty());
+    }
+
+    #[test]
+    fn a_queued_rebuild_runs_when_the_database_opens() {
+        let db = TempDatabase::new();
+        db.import(
+            conversation("chatgpt-queued", "排队重建", &[("user", "全文搜索")]),
+            "skip",
+        );
+        let conn = db.open().unwrap();
+        conn.execute_batch("DELETE FROM messages_fts").unwrap();
+        conn.execute(
+            "INSERT INTO app_settings (key, value_json, updated_at) VALUES (?1, 'true', '')",
+            params![SEARCH_REBUILD_PENDING_KEY],
+        )
+        .unwrap();
+        drop(conn);
+        assert!(search_results(&db, "搜索").is_empty());
+
+        let reopened = Database::new(db.path().to_path_buf()).unwrap();
+        assert_eq!(search_results(&reopened, "搜索").len(), 1);
+        let queued: bool = reopened
+            .open()
+            .unwrap()
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM app_settings WHERE key = ?1)",
+                params![SEARCH_REBUILD_PENDING_KEY],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!queued);
+    }
+}
@@ -0,0 +1,797 @@
+//! Optional embedding index over message chunks, behind `semantic_search`.
+//!
+//! Vectors are L2-normalised `f32`s stored little-endian in
+//! `message_embeddings.vector`, so cosine similarity is the dot product the
+//! `vector_dot` SQL function computes. Lookup scans the current model's rows,
+//! which is fast enough for a personal archive and keeps everything in the
+//! one SQLite file. Embeddings come from the model bundled with the app (see
+//! `bundled_model`) or from an OpenAI-compatible `/embeddings` endpoint,
+//! which can be a local server such as Ollama.
+
+use reqwest::blocking::Client as BlockingHttpClient;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Deserialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError};
+use std::time::Duration;
+
+use super::bundled_model::{BundledModel, BUNDLED_MODEL_DIR_NAME, BUNDLED_MODEL_ID};
+use super::search::{
+    load_hits, load_summaries, rank_conversations, truncate_chars, DEFAULT_PAGE_SIZE,
+    MAX_PAGE_SIZE, SNIPPET_MAX_CHARS,
+};
+use super::{now_iso, query, Database};
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    SearchHit, SearchPage, SearchResult, SemanticIndexProgress, SemanticIndexReport,
+    SemanticSearchInput, SemanticSearchSettings,
+};
+
+/// Values accepted for `SemanticSearchSettings::provider`.
+pub(super) const EMBEDDING_PROVIDERS: &[&str] = &["bundled", "openai"];
+
+const VECTOR_DOT_FN: &str = "vector_dot";
+
+const CHUNK_CHARS: usize = 1000;
+const CHUNK_OVERLAP_CHARS: usize = 200;
+/// Inputs per embedding request, and roughly the number of chunks written
+/// per transaction.
+const EMBED_BATCH_CHUNKS: usize = 32;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Nearest chunks considered per query, before grouping by conversation.
+const CANDIDATE_CHUNKS: i64 = 200;
+const MIN_SIMILARITY: f64 = 0.1;
+/// Reciprocal rank fusion constant; 60 is the usual choice and keeps one
+/// list's top result from drowning out the other's.
+const RRF_K: f64 = 60.0;
+
+struct Chunk {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+struct PendingMessage {
+    id: String,
+    conversation_id: String,
+    hash: String,
+    chunks: Vec<Chunk>,
+}
+
+#[derive(Default)]
+struct Fused<'a> {
+    score: f64,
+    updated_at: &'a str,
+    title_match: bool,
+}
+
+struct SemanticMatch {
+    conversation_id: String,
+    similarity: f64,
+    updated_at: String,
+    hits: Vec<SearchHit>,
+}
+
+impl Database {
+    /// Embeds every message that is new, was edited since it was embedded, or
+    /// was embedded with a different model; rows from other models are
+    /// dropped first. Reports progress after each batch.
+    pub fn update_semantic_index(
+        &self,
+        mut on_progress: impl FnMut(&SemanticIndexProgress),
+    ) -> AppResult<SemanticIndexReport> {
+        let embedder = self.embedder()?;
+        let model = embedder.model_id();
+        let conn = self.open()?;
+        let removed_chunks = conn.execute(
+            "DELETE FROM message_embeddings WHERE model <> ?1",
+            params![model],
+        )? as i64;
+
+        let mut stale = Vec::new();
+        let mut unchanged_messages = 0;
+        {
+            let mut stmt = conn.prepare(
+                r#"
+                SELECT m.id, m.content_markdown,
+                  (SELECT e.content_hash FROM message_embeddings e WHERE e.message_id = m.id LIMIT 1)
+                FROM messages m
+                ORDER BY m.conversation_id, m.seq
+                "#,
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                ))
+            })?;
+            for row in rows {
+                let (id, content, stored_hash) = row?;
+                let hash = content_hash(&content);
+                // Blank messages have nothing to embed and never get rows.
+                let current = match &stored_hash {
+                    Some(stored) => *stored == hash,
+                    None => content.trim().is_empty(),
+                };
+                if current {
+                    unchanged_messages += 1;
+                } else {
+                    stale.push(id);
+                }
+            }
+        }
+
+        let total = stale.len() as i64;
+        let mut report = SemanticIndexReport {
+            model: model.clone(),
+            embedded_messages: 0,
+            embedded_chunks: 0,
+            unchanged_messages,
+            removed_chunks,
+        };
+        let mut batch: Vec<PendingMessage> = Vec::new();
+        let mut batch_chunks = 0;
+        for (index, message_id) in stale.iter().enumerate() {
+            // Content is read per batch rather than up front so a first
+            // index of a large archive does not hold all of it in memory.
+            let message = conn
+                .query_row(
+                    "SELECT conversation_id, content_markdown FROM messages WHERE id = ?1",
+                    params![message_id],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+                )
+                .optional()?;
+            if let Some((conversation_id, content)) = message {
+                let chunks = chunk_text(&content);
+                batch_chunks += chunks.len();
+                batch.push(PendingMessage {
+                    id: message_id.clone(),
+                    conversation_id,
+                    hash: content_hash(&content),
+                    chunks,
+                });
+            }
+            if batch_chunks >= EMBED_BATCH_CHUNKS || index + 1 == stale.len() {
+                write_embeddings(&conn, &embedder, &model, &batch, &mut report)?;
+                batch.clear();
+                batch_chunks = 0;
+                on_progress(&SemanticIndexProgress {
+                    processed: index as i64 + 1,
+                    total,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Drops the whole embedding index; returns the number of chunks removed.
+    pub fn clear_semantic_index(&self) -> AppResult<i64> {
+        let conn = self.open()?;
+        Ok(conn.execute("DELETE FROM message_embeddings", [])? as i64)
+    }
+
+    /// Ranks conversations by how close their message chunks are to `query`
+    /// in embedding space, fused with the keyword ranking of
+    /// `search_conversations` unless `semantic_only` is set.
+    pub fn semantic_search(&self, input: SemanticSearchInput) -> AppResult<SearchPage> {
+        let offset = input.offset.max(0);
+        let limit = input
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let mut page = SearchPage {
+            results: Vec::new(),
+            total: 0,
+            offset,
+            next_offset: None,
+        };
+        let text = input.query.trim();
+        if text.is_empty() {
+            return Ok(page);
+        }
+
+        let embedder = self.embedder()?;
+        let model = embedder.model_id();
+        let conn = self.open()?;
+        let indexed: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM message_embeddings WHERE model = ?1)",
+            params![model],
+            |row| row.get(0),
+        )?;
+        if !indexed {
+            return Err(AppError::InvalidInput(
+                "语义索引尚未建立，请先更新索引".to_string(),
+            ));
+        }
+        let vector = embedder
+            .embed(&[text.to_string()])?
+            .pop()
+            .unwrap_or_default();
+        let semantic = nearest_conversations(&conn, &model, &vector)?;
+
+        // Questions in plain language are often not valid query syntax
+        // (stray quotes, "key:" words); keyword search just sits those out.
+        let compiled = if input.semantic_only {
+            None
+        } else {
+            query::compile(text).ok().flatten()
+        };
+        let keyword = match &compiled {
+            Some(compiled) => rank_conversations(&conn, compiled)?,
+            None => Vec::new(),
+        };
+
+        let mut fused: HashMap<&str, Fused> = HashMap::new();
+        for (rank, entry) in semantic.iter().enumerate() {
+            let slot = fused.entry(&entry.conversation_id).or_default();
+            slot.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+            slot.updated_at = &entry.updated_at;
+        }
+        for (rank, entry) in keyword.iter().enumerate() {
+            let slot = fused.entry(&entry.id).or_default();
+            slot.score += 1.0 / (RRF_K + rank as f64 + 1.0);
+            slot.updated_at = &entry.updated_at;
+            slot.title_match = entry.title_match;
+        }
+        let mut ranked: Vec<(&str, Fused)> = fused.into_iter().collect();
+        ranked.sort_by(|(a_id, a), (b_id, b)| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(Ordering::Equal)
+                .then_with(|| b.updated_at.cmp(a.updated_at))
+                .then_with(|| a_id.cmp(b_id))
+        });
+
+        page.total = ranked.len() as i64;
+        let ranked: Vec<_> = ranked
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .collect();
+        if ranked.is_empty() {
+            return Ok(page);
+        }
+        let ids: Vec<String> = ranked.iter().map(|(id, _)| id.to_string()).collect();
+        let mut summaries = load_summaries(&conn, &ids)?;
+        let mut keyword_hits = match &compiled {
+            Some(compiled) => load_hits(&conn, compiled, &ids)?,
+            None => HashMap::new(),
+        };
+        let mut semantic_hits: HashMap<&str, &Vec<SearchHit>> = semantic
+            .iter()
+            .map(|entry| (entry.conversation_id.as_str(), &entry.hits))
+            .collect();
+
+        for (id, entry) in ranked {
+            let Some(summary) = summaries.remove(id) else {
+                continue;
+            };
+            let hits = match semantic_hits.remove(id) {
+                Some(hits) => hits.clone(),
+                None => keyword_hits.remove(id).unwrap_or_default(),
+            };
+            let snippet = hits
+                .iter()
+                .max_by(|a, b| a.score.partial_cmp(&b.score).unwrap_or(Ordering::Equal))
+                .map(|hit| hit.snippet.clone())
+                .unwrap_or_else(|| summary.conversation.title.clone());
+            page.results.push(SearchResult {
+                conversation: summary,
+                snippet,
+                score: entry.score,
+                title_match: entry.title_match,
+                hits,
+            });
+        }
+        let next = offset + limit;
+        page.next_offset = (next < page.total).then_some(next);
+        Ok(page)
+    }
+}
+
+/// Embeds `batch` and replaces each message's rows in one transaction. The
+/// embedding happens before the transaction so writers are not blocked on
+/// the model or the embedding service.
+fn write_embeddings(
+    conn: &Connection,
+    embedder: &Embedder,
+    model: &str,
+    batch: &[PendingMessage],
+    report: &mut SemanticIndexReport,
+) -> AppResult<()> {
+    let texts: Vec<String> = batch
+        .iter()
+        .flat_map(|message| message.chunks.iter().map(|chunk| chunk.text.clone()))
+        .collect();
+    let mut vectors = embedder.embed(&texts)?.into_iter();
+
+    let tx = conn.unchecked_transaction()?;
+    let created_at = now_iso();
+    for message in batch {
+        let vectors: Vec<Vec<f32>> = vectors.by_ref().take(message.chunks.len()).collect();
+        tx.execute(
+            "DELETE FROM message_embeddings WHERE message_id = ?1",
+            params![message.id],
+        )?;
+        // The message may have been deleted while its batch was embedded.
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM messages WHERE id = ?1)",
+            params![message.id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            continue;
+        }
+        for (index, (chunk, vector)) in message.chunks.iter().zip(&vectors).enumerate() {
+            tx.execute(
+                r#"
+                INSERT INTO message_embeddings (
+                  message_id, chunk_index, conversation_id, model, content_hash,
+                  char_start, char_end, vector, created_at
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                "#,
+                params![
+                    message.id,
+                    index as i64,
+                    message.conversation_id,
+                    model,
+                    message.hash,
+                    chunk.start as i64,
+                    chunk.end as i64,
+                    vector_to_blob(vector),
+                    created_at,
+                ],
+            )?;
+        }
+        report.embedded_messages += 1;
+        report.embedded_chunks += message.chunks.len() as i64;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Closest chunks of live conversations, grouped by conversation with the
+/// best chunk of each message as a hit; most similar conversation first.
+fn nearest_conversations(
+    conn: &Connection,
+    model: &str,
+    vector: &[f32],
+) -> AppResult<Vec<SemanticMatch>> {
+    let mut stmt = conn.prepare(&format!(
+        r#"
+        SELECT e.conversation_id, e.message_id, m.seq, m.role, m.content_markdown,
+          e.char_start, {VECTOR_DOT_FN}(e.vector, ?2) AS similarity, c.updated_at,
+          m.on_active_path
+        FROM message_embeddings e
+        JOIN messages m ON m.id = e.message_id
+        JOIN conversations c ON c.id = e.conversation_id
+        WHERE e.model = ?1 AND c.deleted_at IS NULL
+        ORDER BY similarity DESC
+        LIMIT ?3
+        "#
+    ))?;
+    let rows = stmt.query_map(
+        params![model, vector_to_blob(vector), CANDIDATE_CHUNKS],
+        |row| {
+            let content: String = row.get(4)?;
+            let chars: Vec<char> = content.chars().collect();
+            let start = (row.get::<_, i64>(5)?.max(0) as usize).min(chars.len());
+            let mut snippet = truncate_chars(&chars, start, SNIPPET_MAX_CHARS);
+            if start > 0 {
+                snippet.insert(0, '…');
+            }
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(7)?,
+                SearchHit {
+                    message_id: row.get(1)?,
+                    seq: row.get(2)?,
+                    role: row.get(3)?,
+                    field: "content".to_string(),
+                    snippet,
+                    highlights: Vec::new(),
+                    score: row.get::<_, Option<f64>>(6)?.unwrap_or(0.0),
+                    on_active_path: row.get(8)?,
+                },
+            ))
+        },
+    )?;
+
+    let mut matches: Vec<SemanticMatch> = Vec::new();
+    let mut positions: HashMap<String, usize> = HashMap::new();
+    for row in rows {
+        let (conversation_id, updated_at, hit) = row?;
+        if hit.score < MIN_SIMILARITY {
+            break;
+        }
+        let position = *positions.entry(conversation_id.clone()).or_insert_with(|| {
+            matches.push(SemanticMatch {
+                conversation_id,
+                similarity: hit.score,
+                updated_at,
+                hits: Vec::new(),
+            });
+            matches.len() - 1
+        });
+        let entry = &mut matches[position];
+        // Rows arrive best first, so a message's first chunk is its best.
+        if !entry
+            .hits
+            .iter()
+            .any(|seen| seen.message_id == hit.message_id)
+        {
+            entry.hits.push(hit);
+        }
+    }
+    for entry in &mut matches {
+        entry.hits.sort_by_key(|hit| hit.seq);
+    }
+    matches.sort_by(|a, b| {
+        b.similarity
+            .partial_cmp(&a.similarity)
+            .unwrap_or(Ordering::Equal)
+    });
+    Ok(matches)
+}
+
+pub(super) fn register_functions(conn: &Connection) -> rusqlite::Result<()> {
+    conn.create_scalar_function(
+        VECTOR_DOT_FN,
+        2,
+        FunctionFlags::SQLITE_UTF8
+            | FunctionFlags::SQLITE_DETERMINISTIC
+            | FunctionFlags::SQLITE_INNOCUOUS,
+        |ctx| {
+            let a = ctx.get::<Option<Vec<u8>>>(0)?;
+            let b = ctx.get::<Option<Vec<u8>>>(1)?;
+            Ok(match (a, b) {
+                (Some(a), Some(b)) if a.len() == b.len() && a.len() % 4 == 0 => {
+                    Some(blob_dot(&a, &b))
+                }
+                _ => None,
+            })
+        },
+    )
+}
+
+fn blob_dot(a: &[u8], b: &[u8]) -> f64 {
+    a.chunks_exact(4)
+        .zip(b.chunks_exact(4))
+        .map(|(x, y)| {
+            let x = f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+            let y = f32::from_le_bytes([y[0], y[1], y[2], y[3]]);
+            f64::from(x) * f64::from(y)
+        })
+        .sum()
+}
+
+fn vector_to_blob(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn normalize(mut vector: Vec<f32>) -> Vec<f32> {
+    let norm = vector.iter().map(|value| value * value).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        for value in &mut vector {
+            *value /= norm;
+        }
+    }
+    vector
+}
+
+fn content_hash(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Splits `content` into overlapping windows of about `CHUNK_CHARS`
+/// characters, preferring to end each one at a line or sentence break.
+/// Offsets are in characters.
+fn chunk_text(content: &str) -> Vec<Chunk> {
+    let chars: Vec<char> = content.chars().collect();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let mut end = (start + CHUNK_CHARS).min(chars.len());
+        if end < chars.len() {
+            if let Some(last_break) = (start + CHUNK_CHARS / 2..end)
+                .rev()
+                .find(|&index| matches!(chars[index], '\n' | '.' | '!' | '?' | '。' | '！' | '？'))
+            {
+                end = last_break + 1;
+            }
+        }
+        let text: String = chars[start..end].iter().collect();
+        if !text.trim().is_empty() {
+            chunks.push(Chunk { start, end, text });
+        }
+        if end == chars.len() {
+            break;
+        }
+        start = end.saturating_sub(CHUNK_OVERLAP_CHARS).max(start + 1);
+    }
+    chunks
+}
+
+enum Embedder {
+    Bundled(Arc<BundledModel>),
+    OpenAi {
+        client: BlockingHttpClient,
+        url: String,
+        model: String,
+        api_key: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl Database {
+    fn embedder(&self) -> AppResult<Embedder> {
+        let settings = self.stored_settings()?.semantic_search;
+        if !settings.enabled {
+            return Err(AppError::InvalidInput(
+                "语义搜索未开启，请先在设置中启用".to_string(),
+            ));
+        }
+        match settings.provider.as_str() {
+            "bundled" => return Ok(Embedder::Bundled(self.bundled_model()?)),
+            "openai" => {}
+            other => return Err(AppError::InvalidInput(format!("未知的嵌入提供方：{other}"))),
+        }
+        let (endpoint, model) = check_settings(&settings)?;
+        Ok(Embedder::OpenAi {
+            client: BlockingHttpClient::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()?,
+            url: format!("{endpoint}/embeddings"),
+            model: model.to_string(),
+            api_key: settings
+                .api_key
+                .as_deref()
+                .map(str::trim)
+                .filter(|key| !key.is_empty())
+                .map(str::to_string),
+        })
+    }
+
+    /// Loaded on first use and kept for the life of the app; a failed load
+    /// is retried next time.
+    fn bundled_model(&self) -> AppResult<Arc<BundledModel>> {
+        let mut slot = self
+            .embedding_model
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(model) = slot.as_ref() {
+            return Ok(model.clone());
+        }
+        let dir = match &self.embedding_model_dir {
+            Some(dir) => dir.clone(),
+            None => self.data_dir().join(BUNDLED_MODEL_DIR_NAME),
+        };
+        let model = Arc::new(BundledModel::load(&dir)?);
+        *slot = Some(model.clone());
+        Ok(model)
+    }
+}
+
+impl Embedder {
+    /// Stored with every vector; vectors from different models are never
+    /// compared.
+    fn model_id(&self) -> String {
+        match self {
+            Embedder::Bundled(_) => BUNDLED_MODEL_ID.to_string(),
+            Embedder::OpenAi { url, model, .. } => format!("openai:{model}@{url}"),
+        }
+    }
+
+    fn embed(&self, texts: &[String]) -> AppResult<Vec<Vec<f32>>> {
+        let (client, url, model, api_key) = match self {
+            Embedder::Bundled(bundled) => {
+                let mut vectors = Vec::with_capacity(texts.len());
+                for batch in texts.chunks(EMBED_BATCH_CHUNKS) {
+                    vectors.extend(bundled.embed(batch)?.into_iter().map(normalize));
+                }
+                return Ok(vectors);
+            }
+            Embedder::OpenAi {
+                client,
+                url,
+                model,
+                api_key,
+            } => (client, url, model, api_key),
+        };
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(EMBED_BATCH_CHUNKS) {
+            let mut request = client
+                .post(url)
+                .json(&json!({ "model": model, "input": batch }));
+            if let Some(key) = api_key {
+                request = request.bearer_auth(key);
+            }
+            let response = request.send()?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().unwrap_or_default();
+                let body: String = body.chars().take(200).collect();
+                return Err(AppError::Network(format!("嵌入服务返回 {status}：{body}")));
+            }
+            let mut parsed: EmbeddingResponse = response.json()?;
+            if parsed.data.len() != batch.len() {
+                return Err(AppError::Network(format!(
+                    "嵌入服务返回了 {} 个向量，应为 {} 个",
+                    parsed.data.len(),
+                    batch.len()
+                )));
+            }
+            parsed.data.sort_by_key(|item| item.index);
+            vectors.extend(
+                parsed
+                    .data
+                    .into_iter()
+                    .map(|item| normalize(item.embedding)),
+            );
+        }
+        Ok(vectors)
+    }
+}
+
+/// The endpoint without a trailing slash and the model name, once both are
+/// usable.
+pub(super) fn check_settings(settings: &SemanticSearchSettings) -> AppResult<(&str, &str)> {
+    let endpoint = settings.endpoint.trim().trim_end_matches('/');
+    if !(endpoint.starts_with("http://") || endpoint.starts_with("https://")) {
+        return Err(AppError::InvalidInput(
+            "嵌入服务地址必须以 http:// 或 https:// 开头".to_string(),
+        ));
+    }
+    let model = settings.model.trim();
+    if model.is_empty() {
+        return Err(AppError::InvalidInput("请填写嵌入模型名称".to_string()));
+    }
+    Ok((endpoint, model))
+}
+
+#[cfg(test)]
+mod tests {
+    use candle_core::{DType, Device};
+    use candle_nn::{VarBuilder, VarMap};
+    use candle_transformers::models::bert::{BertModel, Config};
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    use super::{BUNDLED_MODEL_DIR_NAME, BUNDLED_MODEL_ID};
+    use crate::db::test_support::{conversation, TempDatabase};
+    use crate::error::AppError;
+    use crate::models::{AppSettings, SemanticSearchInput};
+
+    /// A randomly initialised one-layer BERT with a word-level vocabulary, in
+    /// the layout the bundled model ships in.
+    fn write_tiny_model(dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        let config = json!({
+            "vocab_size": 8,
+            "hidden_size": 16,
+            "num_hidden_layers": 1,
+            "num_attention_heads": 2,
+            "intermediate_size": 32,
+            "hidden_act": "gelu",
+            "hidden_dropout_prob": 0.0,
+            "max_position_embeddings": 64,
+            "type_vocab_size": 2,
+            "initializer_range": 0.02,
+            "layer_norm_eps": 1e-12,
+            "pad_token_id": 0,
+        });
+        fs::write(dir.join("config.json"), config.to_string()).unwrap();
+        let config: Config = serde_json::from_value(config).unwrap();
+        let weights = VarMap::new();
+        BertModel::load(
+            VarBuilder::from_varmap(&weights, DType::F32, &Device::Cpu),
+            &config,
+        )
+        .unwrap();
+        weights.save(dir.join("model.safetensors")).unwrap();
+        let tokenizer = json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": { "type": "Whitespace" },
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": {
+                    "[PAD]": 0, "[UNK]": 1, "rust": 2, "borrow": 3, "checker": 4,
+                    "soup": 5, "recipe": 6,
+                },
+                "unk_token": "[UNK]",
+            },
+        });
+        fs::write(dir.join("tokenizer.json"), tokenizer.to_string()).unwrap();
+    }
+
+    fn enable(db: &TempDatabase, provider: &str) -> Result<AppSettings, AppError> {
+        let mut settings = db.get_settings().unwrap();
+        settings.semantic_search.enabled = true;
+        settings.semantic_search.provider = provider.to_string();
+        db.update_settings(settings)
+    }
+
+    #[test]
+    fn the_bundled_model_is_the_default_and_reports_missing_files() {
+        let db = TempDatabase::new();
+        assert_eq!(
+            db.get_settings().unwrap().semantic_search.provider,
+            "bundled"
+        );
+        enable(&db, "bundled").unwrap();
+
+        let err = db.update_semantic_index(|_| {}).unwrap_err();
+        assert!(
+            matches!(&err, AppError::NotFound(message) if message.contains("config.json")),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn the_bundled_model_indexes_and_finds_messages() {
+        let db = TempDatabase::new();
+        write_tiny_model(&db.dir().join(BUNDLED_MODEL_DIR_NAME));
+        db.import(
+            conversation("chatgpt-rust", "Rust", &[("user", "rust borrow checker")]),
+            "skip",
+        );
+        db.import(
+            conversation("chatgpt-soup", "Soup", &[("user", "soup recipe soup")]),
+            "skip",
+        );
+        enable(&db, "bundled").unwrap();
+
+        let report = db.update_semantic_index(|_| {}).unwrap();
+        assert_eq!(report.model, BUNDLED_MODEL_ID);
+        assert_eq!(report.embedded_messages, 2);
+        let page = db
+            .semantic_search(SemanticSearchInput {
+                query: "rust borrow checker".to_string(),
+                offset: 0,
+                limit: None,
+                semantic_only: true,
+            })
+            .unwrap();
+        assert_eq!(page.results[0].conversation.conversation.title, "Rust");
+        assert!((page.results[0].hits[0].score - 1.0).abs() < 1e-4);
+
+        let again = db.update_semantic_index(|_| {}).unwrap();
+        assert_eq!(again.unchanged_messages, 2);
+    }
+
+    #[test]
+    fn unknown_providers_are_rejected() {
+        let db = TempDatabase::new();
+        let err = enable(&db, "hashed").unwrap_err();
+        assert!(matches!(err, AppError::InvalidInput(_)), "{err}");
+    }
+}
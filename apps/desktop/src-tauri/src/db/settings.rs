@@ -0,0 +1,81 @@
+use rusqlite::{params, OptionalExtension};
+
+use super::semantic;
+use super::{now_iso, Database};
+use crate::error::{AppError, AppResult};
+use crate::models::AppSettings;
+
+const APP_SETTINGS_KEY: &str = "app";
+/// Sent to the webview in place of a stored API key; saving it back keeps the
+/// stored key.
+const REDACTED_API_KEY: &str = "********";
+
+impl Database {
+    /// Settings for the webview, with secrets redacted.
+    pub fn get_settings(&self) -> AppResult<AppSettings> {
+        Ok(redact(self.stored_settings()?))
+    }
+
+    pub(super) fn stored_settings(&self) -> AppResult<AppSettings> {
+        let conn = self.open()?;
+        let raw: Option<String> = conn
+            .query_row(
+                "SELECT value_json FROM app_settings WHERE key = ?1",
+                params![APP_SETTINGS_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        // Unknown or missing fields fall back to their defaults, so settings written
+        // by an older build keep working after new options are added.
+        Ok(raw
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .unwrap_or_default())
+    }
+
+    pub fn update_settings(&self, mut settings: AppSettings) -> AppResult<AppSettings> {
+        if settings.trash_retention_days < 0 {
+            return Err(AppError::InvalidInput(
+                "回收站保留天数不能为负数".to_string(),
+            ));
+        }
+        if settings.attachments.max_file_size_mb < 0 || settings.attachments.max_total_size_mb < 0
+        {
+            return Err(AppError::InvalidInput(
+                "附件大小上限不能为负数".to_string(),
+            ));
+        }
+        let provider = settings.semantic_search.provider.as_str();
+        if !semantic::EMBEDDING_PROVIDERS.contains(&provider) {
+            return Err(AppError::InvalidInput(format!(
+                "未知的嵌入提供方：{provider}，可用的有 {}",
+                semantic::EMBEDDING_PROVIDERS.join("、")
+            )));
+        }
+        if settings.semantic_search.enabled && provider == "openai" {
+            semantic::check_settings(&settings.semantic_search)?;
+        }
+        if settings.semantic_search.api_key.as_deref() == Some(REDACTED_API_KEY) {
+            settings.semantic_search.api_key = self.stored_settings()?.semantic_search.api_key;
+        }
+
+        let conn = self.open()?;
+        conn.execute(
+            r#"
+            INSERT INTO app_settings (key, value_json, updated_at)
+            VALUES (?1, ?2, ?3)
+            ON CONFLICT(key) DO UPDATE SET value_json = excluded.value_json, updated_at = excluded.updated_at
+            "#,
+            params![APP_SETTINGS_KEY, serde_json::to_string(&settings)?, now_iso()],
+        )?;
+        Ok(redact(settings))
+    }
+}
+
+fn redact(mut settings: AppSettings) -> AppSettings {
+    let api_key = &mut settings.semantic_search.api_key;
+    if api_key.as_deref().is_some_and(|key| !key.trim().is_empty()) {
+        *api_key = Some(REDACTED_API_KEY.to_string());
+    }
+    settings
+}
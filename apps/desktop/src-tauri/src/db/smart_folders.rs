@@ -0,0 +1,308 @@
+use chrono::{Duration, Utc};
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+
+use super::search::{placeholders, rank_conversations};
+use super::{next_sort_order, now_iso, query, tags, Database};
+use crate::error::{AppError, AppResult};
+use crate::models::{Folder, SmartFolderFilter, SmartFolderInput};
+
+const MAX_WITHIN_DAYS: i64 = 36_500;
+
+impl Database {
+    /// Saves a filter as a virtual folder, placed after its siblings.
+    pub fn create_smart_folder(&self, input: SmartFolderInput) -> AppResult<Folder> {
+        let conn = self.open()?;
+        let (name, filter) = validate_input(&conn, &input)?;
+        let id = uuid::Uuid::new_v4().to_string();
+        let now = now_iso();
+        let sort_order = next_sort_order(&conn, input.parent_id.as_deref())?;
+        conn.execute(
+            r#"
+            INSERT INTO smart_folders (id, name, parent_id, sort_order, filter_json, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6)
+            "#,
+            params![
+                id,
+                name,
+                input.parent_id,
+                sort_order,
+                serde_json::to_string(&filter)?,
+                now
+            ],
+        )?;
+        load_smart_folder(&conn, &id)
+    }
+
+    /// Replaces a smart folder's name, parent and filter.
+    pub fn update_smart_folder(&self, id: String, input: SmartFolderInput) -> AppResult<Folder> {
+        let conn = self.open()?;
+        let (name, filter) = validate_input(&conn, &input)?;
+        let current_parent: Option<String> = conn
+            .query_row(
+                "SELECT parent_id FROM smart_folders WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| AppError::NotFound(format!("smart folder {id} not found")))?;
+        if current_parent != input.parent_id {
+            let sort_order = next_sort_order(&conn, input.parent_id.as_deref())?;
+            conn.execute(
+                "UPDATE smart_folders SET parent_id = ?1, sort_order = ?2 WHERE id = ?3",
+                params![input.parent_id, sort_order, id],
+            )?;
+        }
+        conn.execute(
+            "UPDATE smart_folders SET name = ?1, filter_json = ?2, updated_at = ?3 WHERE id = ?4",
+            params![name, serde_json::to_string(&filter)?, now_iso(), id],
+        )?;
+        load_smart_folder(&conn, &id)
+    }
+
+    /// Deletes the saved filter; the conversations it showed are untouched.
+    pub fn delete_smart_folder(&self, id: String) -> AppResult<()> {
+        let conn = self.open()?;
+        let deleted = conn.execute("DELETE FROM smart_folders WHERE id = ?1", params![id])?;
+        if deleted == 0 {
+            return Err(AppError::NotFound(format!("smart folder {id} not found")));
+        }
+        Ok(())
+    }
+}
+
+fn validate_input(
+    conn: &Connection,
+    input: &SmartFolderInput,
+) -> AppResult<(String, SmartFolderFilter)> {
+    let name = input.name.trim().to_string();
+    if name.is_empty() {
+        return Err(AppError::InvalidInput("文件夹名称不能为空".to_string()));
+    }
+    if let Some(parent_id) = input.parent_id.as_deref() {
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM folders WHERE id = ?1)",
+            params![parent_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(AppError::NotFound(format!("folder {parent_id} not found")));
+        }
+    }
+
+    let raw = &input.filter;
+    let mut sources: Vec<String> = Vec::new();
+    for source in &raw.sources {
+        let source = source.trim().to_lowercase();
+        if !source.is_empty() && source != "all" && !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+    let mut tag_ids: Vec<String> = Vec::new();
+    for tag_id in &raw.tag_ids {
+        if tag_ids.contains(tag_id) {
+            continue;
+        }
+        let exists: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM tags WHERE id = ?1)",
+            params![tag_id],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(AppError::NotFound(format!("tag {tag_id} not found")));
+        }
+        tag_ids.push(tag_id.clone());
+    }
+    let tag_match = raw.tag_match.clone().filter(|_| !tag_ids.is_empty());
+    // Rejects an unknown match mode up front.
+    tags::tag_filter_sql(&tag_ids, tag_match.as_deref())?;
+
+    let date_bound = |value: &Option<String>, key: &str| -> AppResult<Option<String>> {
+        match value.as_deref().map(str::trim).filter(|v| !v.is_empty()) {
+            Some(value) => query::parse_date_bound(value)
+                .map(Some)
+                .ok_or_else(|| AppError::InvalidInput(format!("{key} 日期格式应为 YYYY-MM-DD"))),
+            None => Ok(None),
+        }
+    };
+    let after = date_bound(&raw.after, "after")?;
+    let before = date_bound(&raw.before, "before")?;
+    if let Some(days) = raw.within_days {
+        if !(1..=MAX_WITHIN_DAYS).contains(&days) {
+            return Err(AppError::InvalidInput(format!(
+                "最近天数应在 1 到 {MAX_WITHIN_DAYS} 之间"
+            )));
+        }
+    }
+
+    let query = raw
+        .query
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(str::to_string);
+    if let Some(text) = query.as_deref() {
+        if query::compile(text)?.is_none() {
+            return Err(AppError::InvalidInput(
+                "搜索条件中没有可搜索的内容".to_string(),
+            ));
+        }
+    }
+
+    let filter = SmartFolderFilter {
+        sources,
+        tag_ids,
+        tag_match,
+        after,
+        before,
+        within_days: raw.within_days,
+        query,
+        has_attachments: raw.has_attachments,
+    };
+    let has_criterion = !filter.sources.is_empty()
+        || !filter.tag_ids.is_empty()
+        || filter.after.is_some()
+        || filter.before.is_some()
+        || filter.within_days.is_some()
+        || filter.query.is_some()
+        || filter.has_attachments.is_some();
+    if !has_criterion {
+        return Err(AppError::InvalidInput(
+            "智能文件夹至少需要一个筛选条件".to_string(),
+        ));
+    }
+    Ok((name, filter))
+}
+
+/// Filter of the smart folder `id`, or `None` when `id` is not one.
+pub(super) fn load_filter(conn: &Connection, id: &str) -> AppResult<Option<SmartFolderFilter>> {
+    let raw: Option<String> = conn
+        .query_row(
+            "SELECT filter_json FROM smart_folders WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(raw.map(|raw| serde_json::from_str(&raw).unwrap_or_default()))
+}
+
+/// Condition over `c` selecting the conversations a filter matches. A text
+/// query is searched up front and its matches passed in as one JSON array.
+pub(super) fn filter_sql(
+    conn: &Connection,
+    filter: &SmartFolderFilter,
+) -> AppResult<(String, Vec<Value>)> {
+    let mut conditions: Vec<String> = vec!["1".to_string()];
+    let mut params: Vec<Value> = Vec::new();
+
+    if !filter.sources.is_empty() {
+        conditions.push(format!(
+            "c.source IN ({})",
+            placeholders(filter.sources.len())
+        ));
+        params.extend(filter.sources.iter().cloned().map(Value::Text));
+    }
+    if let Some((predicate, tag_params)) =
+        tags::tag_filter_sql(&filter.tag_ids, filter.tag_match.as_deref())?
+    {
+        conditions.push(predicate);
+        params.extend(tag_params);
+    }
+    if let Some(after) = filter.after.as_ref() {
+        conditions.push("c.updated_at >= ?".to_string());
+        params.push(Value::Text(after.clone()));
+    }
+    if let Some(before) = filter.before.as_ref() {
+        conditions.push("c.updated_at < ?".to_string());
+        params.push(Value::Text(before.clone()));
+    }
+    if let Some(days) = filter.within_days {
+        conditions.push("c.updated_at >= ?".to_string());
+        params.push(Value::Text(
+            (Utc::now() - Duration::days(days)).to_rfc3339(),
+        ));
+    }
+    match filter.has_attachments {
+        Some(true) => conditions.push(
+            "EXISTS (SELECT 1 FROM attachments a WHERE a.conversation_id = c.id)".to_string(),
+        ),
+        Some(false) => conditions.push(
+            "NOT EXISTS (SELECT 1 FROM attachments a WHERE a.conversation_id = c.id)".to_string(),
+        ),
+        None => {}
+    }
+    if let Some(text) = filter.query.as_deref() {
+        let ids: Vec<String> = match query::compile(text)? {
+            Some(compiled) => rank_conversations(conn, &compiled)?
+                .into_iter()
+                .map(|entry| entry.id)
+                .collect(),
+            None => Vec::new(),
+        };
+        conditions.push("c.id IN (SELECT value FROM json_each(?))".to_string());
+        params.push(Value::Text(serde_json::to_string(&ids)?));
+    }
+
+    Ok((format!("({})", conditions.join(" AND ")), params))
+}
+
+fn count_matches(conn: &Connection, filter: &SmartFolderFilter) -> AppResult<i64> {
+    let (predicate, params) = filter_sql(conn, filter)?;
+    Ok(conn.query_row(
+        &format!("SELECT COUNT(*) FROM conversations c WHERE c.deleted_at IS NULL AND {predicate}"),
+        rusqlite::params_from_iter(params),
+        |row| row.get(0),
+    )?)
+}
+
+/// Every smart folder with its current match count.
+pub(super) fn list_smart_folders(conn: &Connection) -> AppResult<Vec<Folder>> {
+    let rows: Vec<(Folder, String)> = {
+        let mut stmt =
+            conn.prepare(&format!("SELECT {SMART_FOLDER_COLUMNS} FROM smart_folders"))?;
+        let rows = stmt.query_map([], row_to_smart_folder)?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    let mut folders = Vec::with_capacity(rows.len());
+    for (folder, filter_json) in rows {
+        folders.push(with_filter(conn, folder, &filter_json)?);
+    }
+    Ok(folders)
+}
+
+fn load_smart_folder(conn: &Connection, id: &str) -> AppResult<Folder> {
+    let (folder, filter_json) = conn
+        .query_row(
+            &format!("SELECT {SMART_FOLDER_COLUMNS} FROM smart_folders WHERE id = ?1"),
+            params![id],
+            row_to_smart_folder,
+        )
+        .optional()?
+        .ok_or_else(|| AppError::NotFound(format!("smart folder {id} not found")))?;
+    with_filter(conn, folder, &filter_json)
+}
+
+const SMART_FOLDER_COLUMNS: &str =
+    "id, name, parent_id, sort_order, created_at, updated_at, filter_json";
+
+fn row_to_smart_folder(row: &rusqlite::Row<'_>) -> rusqlite::Result<(Folder, String)> {
+    Ok((
+        Folder {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            parent_id: row.get(2)?,
+            sort_order: row.get(3)?,
+            created_at: row.get(4)?,
+            updated_at: row.get(5)?,
+            conversation_count: 0,
+            filter: None,
+        },
+        row.get(6)?,
+    ))
+}
+
+fn with_filter(conn: &Connection, mut folder: Folder, filter_json: &str) -> AppResult<Folder> {
+    let filter: SmartFolderFilter = serde_json::from_str(filter_json).unwrap_or_default();
+    folder.conversation_count = count_matches(conn, &filter)?;
+    folder.filter = Some(filter);
+    Ok(folder)
+}
@@ -0,0 +1,227 @@
+//! File type detection from leading bytes, so a cached attachment is stored
+//! and classified by what it is rather than by what the server or URL claims.
+
+const HTML_PREFIXES: &[&str] = &[
+    "<!doctype html",
+    "<html",
+    "<head",
+    "<body",
+    "<script",
+    "<meta",
+    "<title",
+];
+/// Sizes of the known BMP info headers, which follow the 14-byte file header.
+/// "BM" alone also starts plenty of text.
+const BMP_INFO_HEADER_SIZES: &[u32] = &[12, 40, 52, 56, 108, 124];
+
+/// MIME type recognised from the content, or `None` when the bytes carry no
+/// known signature. Containers shared by several formats (ZIP for Office
+/// documents, CFB for legacy Office) keep the claimed type when it fits.
+pub(super) fn sniff_mime(bytes: &[u8], claimed: Option<&str>) -> Option<String> {
+    let claimed_lower = claimed.map(str::to_lowercase).unwrap_or_default();
+    let detected = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        "image/webp"
+    } else if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+        "audio/wav"
+    } else if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        match &bytes[8..12] {
+            b"avif" | b"avis" => "image/avif",
+            b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        }
+    } else if looks_like_bmp(bytes) {
+        "image/bmp"
+    } else if bytes.starts_with(b"\x00\x00\x01\x00") {
+        "image/x-icon"
+    } else if bytes.starts_with(b"II*\x00") || bytes.starts_with(b"MM\x00*") {
+        "image/tiff"
+    } else if bytes.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if bytes.starts_with(b"PK\x03\x04") {
+        if claimed_lower.contains("openxmlformats") || claimed_lower.contains("epub") {
+            return claimed.map(str::to_string);
+        }
+        "application/zip"
+    } else if bytes.starts_with(b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") {
+        if claimed_lower.contains("ms") {
+            return claimed.map(str::to_string);
+        }
+        "application/x-cfb"
+    } else if bytes.starts_with(b"\x1f\x8b") {
+        "application/gzip"
+    } else if bytes.starts_with(b"OggS") {
+        "audio/ogg"
+    } else if bytes.starts_with(b"ID3") || bytes.starts_with(b"\xff\xfb") {
+        "audio/mpeg"
+    } else if bytes.starts_with(b"\x1a\x45\xdf\xa3") {
+        "video/webm"
+    } else if looks_like_html(bytes) {
+        "text/html"
+    } else if looks_like_svg(bytes) {
+        "image/svg+xml"
+    } else {
+        return None;
+    };
+    Some(detected.to_string())
+}
+
+/// True for markup documents, e.g. the login or error page some hosts serve
+/// with a 200 in place of an image.
+pub(super) fn looks_like_html(bytes: &[u8]) -> bool {
+    let head = leading_text(bytes);
+    if HTML_PREFIXES.iter().any(|prefix| head.starts_with(prefix)) {
+        return true;
+    }
+    head.starts_with("<!--") && head.contains("<html")
+}
+
+fn looks_like_bmp(bytes: &[u8]) -> bool {
+    bytes.len() >= 18
+        && bytes.starts_with(b"BM")
+        && BMP_INFO_HEADER_SIZES.contains(&u32::from_le_bytes([
+            bytes[14], bytes[15], bytes[16], bytes[17],
+        ]))
+}
+
+fn looks_like_svg(bytes: &[u8]) -> bool {
+    let head = leading_text(bytes);
+    head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg"))
+}
+
+/// First 1 KiB, lowercased, after a UTF-8 BOM and leading whitespace.
+fn leading_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xef\xbb\xbf").unwrap_or(bytes);
+    let start = bytes
+        .iter()
+        .position(|byte| !byte.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    let end = bytes.len().min(start + 1024);
+    String::from_utf8_lossy(&bytes[start..end]).to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sniff(bytes: &[u8]) -> Option<String> {
+        sniff_mime(bytes, None)
+    }
+
+    fn bmp(info_header_size: u32) -> Vec<u8> {
+        let mut bytes = b"BM".to_vec();
+        bytes.extend_from_slice(&[0; 12]);
+        bytes.extend_from_slice(&info_header_size.to_le_bytes());
+        bytes.extend_from_slice(&[0; 8]);
+        bytes
+    }
+
+    #[test]
+    fn recognises_each_signature() {
+        let cases: &[(&[u8], &str)] = &[
+            (b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR", "image/png"),
+            (b"\xff\xd8\xff\xe0\0\x10JFIF", "image/jpeg"),
+            (b"GIF87a\x01\0", "image/gif"),
+            (b"GIF89a\x01\0", "image/gif"),
+            (b"RIFF\0\0\0\0WEBPVP8 ", "image/webp"),
+            (b"RIFF\0\0\0\0WAVEfmt ", "audio/wav"),
+            (b"\0\0\0\x1cftypavif\0\0", "image/avif"),
+            (b"\0\0\0\x18ftypheic\0\0", "image/heic"),
+            (b"\0\0\0\x20ftypM4A \0\0", "audio/mp4"),
+            (b"\0\0\0\x20ftypisom\0\0", "video/mp4"),
+            (b"\0\0\x01\0\x01\0\x10\x10", "image/x-icon"),
+            (b"II*\0\x08\0\0\0", "image/tiff"),
+            (b"MM\0*\0\0\0\x08", "image/tiff"),
+            (b"%PDF-1.7\n", "application/pdf"),
+            (b"PK\x03\x04\x14\0", "application/zip"),
+            (b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1\0", "application/x-cfb"),
+            (b"\x1f\x8b\x08\0", "application/gzip"),
+            (b"OggS\0\x02", "audio/ogg"),
+            (b"ID3\x04\0", "audio/mpeg"),
+            (b"\xff\xfb\x90\0", "audio/mpeg"),
+            (b"\x1a\x45\xdf\xa3\x01", "video/webm"),
+            (
+                b"<svg xmlns=\"http://www.w3.org/2000/svg\"/>",
+                "image/svg+xml",
+            ),
+            (b"<?xml version=\"1.0\"?>\n<svg/>", "image/svg+xml"),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(sniff(bytes).as_deref(), Some(*expected), "{bytes:?}");
+        }
+        for size in BMP_INFO_HEADER_SIZES {
+            assert_eq!(sniff(&bmp(*size)).as_deref(), Some("image/bmp"));
+        }
+    }
+
+    #[test]
+    fn text_starting_with_bm_is_not_a_bitmap() {
+        assert_eq!(sniff(&bmp(64)), None);
+        assert_eq!(sniff(b"BMW owners manual, chapter 1"), None);
+        assert_eq!(sniff(b"BM"), None);
+    }
+
+    #[test]
+    fn recognises_html_pages_served_as_attachments() {
+        let login = b"\xef\xbb\xbf\n  <!DOCTYPE html>\n<html><head><title>Sign in</title></head>\
+                      <body><form action=\"/login\"></form></body></html>";
+        assert_eq!(
+            sniff_mime(login, Some("image/png")).as_deref(),
+            Some("text/html")
+        );
+        assert!(looks_like_html(login));
+        for page in [
+            &b"<html lang=\"en\">"[..],
+            b"<HEAD><meta charset=utf-8>",
+            b"<script>location.href='/login'</script>",
+            b"<!-- served by proxy -->\n<html>",
+        ] {
+            assert!(looks_like_html(page), "{page:?}");
+        }
+        assert!(!looks_like_html(b"<!-- a comment, no page -->"));
+        assert!(!looks_like_html(b"plain text <html> later"));
+        assert!(!looks_like_html(b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn shared_containers_keep_a_fitting_claimed_type() {
+        let zip = b"PK\x03\x04\x14\0";
+        let docx = "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+        assert_eq!(sniff_mime(zip, Some(docx)).as_deref(), Some(docx));
+        assert_eq!(
+            sniff_mime(zip, Some("application/epub+zip")).as_deref(),
+            Some("application/epub+zip")
+        );
+        assert_eq!(
+            sniff_mime(zip, Some("image/png")).as_deref(),
+            Some("application/zip")
+        );
+
+        let cfb = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1\0";
+        assert_eq!(
+            sniff_mime(cfb, Some("application/msword")).as_deref(),
+            Some("application/msword")
+        );
+        assert_eq!(
+            sniff_mime(cfb, Some("application/vnd.ms-excel")).as_deref(),
+            Some("application/vnd.ms-excel")
+        );
+        assert_eq!(
+            sniff_mime(cfb, Some("application/pdf")).as_deref(),
+            Some("application/x-cfb")
+        );
+    }
+
+    #[test]
+    fn unknown_bytes_have_no_type() {
+        assert_eq!(sniff(b""), None);
+        assert_eq!(sniff(b"just some text"), None);
+        assert_eq!(sniff(b"\0\x01\x02\x03"), None);
+    }
+}
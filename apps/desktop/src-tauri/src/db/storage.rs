@@ -0,0 +1,219 @@
+use rusqlite::{params_from_iter, Connection};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use super::integrity::DAMAGED_SUFFIX;
+use super::{Database, UNCATEGORIZED_FOLDER_ID};
+use crate::error::AppResult;
+use crate::models::{AssetGcInput, AssetGcReport, OrphanAsset, StorageBucket, StorageReport};
+
+/// Files this recent are left alone: a download may have written one and not
+/// yet recorded it on its attachment row.
+const ORPHAN_GRACE: Duration = Duration::from_secs(10 * 60);
+
+/// Attachment rows whose message or conversation is gone. Foreign keys cascade
+/// today, but databases written before they were enforced can still hold some.
+const ORPHAN_ROW_CONDITION: &str = r#"
+    NOT EXISTS (SELECT 1 FROM messages m WHERE m.id = attachments.message_id)
+    OR NOT EXISTS (SELECT 1 FROM conversations c WHERE c.id = attachments.conversation_id)
+"#;
+
+impl Database {
+    /// Removes orphaned attachment rows, then files in `assets/` no row points
+    /// at. With `dry_run` nothing is touched and the report lists what would go.
+    pub fn collect_asset_garbage(&self, input: AssetGcInput) -> AppResult<AssetGcReport> {
+        let conn = self.open()?;
+        let orphan_rows: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM attachments WHERE {ORPHAN_ROW_CONDITION}"),
+            [],
+            |row| row.get(0),
+        )?;
+        if !input.dry_run && orphan_rows > 0 {
+            conn.execute(
+                &format!("DELETE FROM attachments WHERE {ORPHAN_ROW_CONDITION}"),
+                [],
+            )?;
+        }
+
+        // Rows just found orphaned no longer keep their files, dry run or not.
+        let referenced = referenced_paths(&conn)?;
+        let orphan_files = self.scan_orphan_files(&referenced)?;
+        let freed_bytes = orphan_files.iter().map(|file| file.size_bytes).sum();
+
+        let mut removed_files = 0_i64;
+        if !input.dry_run {
+            for file in &orphan_files {
+                match fs::remove_file(&file.path) {
+                    Ok(()) => removed_files += 1,
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+        }
+
+        Ok(AssetGcReport {
+            dry_run: input.dry_run,
+            orphan_rows,
+            orphan_files,
+            freed_bytes,
+            removed_rows: if input.dry_run { 0 } else { orphan_rows },
+            removed_files,
+        })
+    }
+
+    /// Cached attachment bytes by conversation source, folder and kind. A file
+    /// shared by several groups counts once in each of them; `total_bytes`
+    /// counts it once overall.
+    pub fn storage_report(&self) -> AppResult<StorageReport> {
+        let conn = self.open()?;
+        let (file_count, total_bytes) = conn.query_row(
+            &format!(
+                r#"
+                SELECT COUNT(*), COALESCE(SUM(size_bytes), 0) FROM (
+                  SELECT MAX(COALESCE(size_bytes, 0)) AS size_bytes FROM attachments
+                  WHERE local_path IS NOT NULL AND NOT ({ORPHAN_ROW_CONDITION})
+                  GROUP BY local_path
+                )
+                "#
+            ),
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+
+        let by_source = storage_buckets(&conn, "c.source", &[])?;
+        // Trashed conversations still hold their files until purged.
+        let by_folder = storage_buckets(
+            &conn,
+            "CASE WHEN c.deleted_at IS NOT NULL THEN 'trash' ELSE COALESCE(c.folder_id, ?1) END",
+            &[UNCATEGORIZED_FOLDER_ID],
+        )?;
+        let by_kind = storage_buckets(&conn, "a.kind", &[])?;
+
+        let referenced = referenced_paths(&conn)?;
+        let orphans = self.scan_orphan_files(&referenced)?;
+        Ok(StorageReport {
+            file_count,
+            total_bytes,
+            by_source,
+            by_folder,
+            by_kind,
+            orphan_files: orphans.len() as i64,
+            orphan_bytes: orphans.iter().map(|file| file.size_bytes).sum(),
+        })
+    }
+
+    /// Files in `assets/` outside `referenced`, oldest writes only. Leftovers
+    /// of interrupted writes count too; damaged files `repair_asset` moved
+    /// aside do not, since they are kept for the user to look at.
+    fn scan_orphan_files(&self, referenced: &HashSet<String>) -> AppResult<Vec<OrphanAsset>> {
+        let assets_dir = self.assets_dir();
+        let entries = match fs::read_dir(&assets_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let referenced: HashSet<PathBuf> = referenced
+            .iter()
+            .map(|local_path| self.resolve_asset_path(local_path))
+            .collect();
+        let now = SystemTime::now();
+        let mut orphans = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            if referenced.contains(&path) || path.to_string_lossy().ends_with(DAMAGED_SUFFIX) {
+                continue;
+            }
+            let recent = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .is_none_or(|age| age < ORPHAN_GRACE);
+            if recent {
+                continue;
+            }
+            orphans.push(OrphanAsset {
+                path: path.to_string_lossy().to_string(),
+                size_bytes: metadata.len() as i64,
+            });
+        }
+        orphans.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(orphans)
+    }
+}
+
+fn referenced_paths(conn: &Connection) -> AppResult<HashSet<String>> {
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT DISTINCT local_path FROM attachments WHERE local_path IS NOT NULL AND NOT ({ORPHAN_ROW_CONDITION})"
+    ))?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+    let mut paths = HashSet::new();
+    for row in rows {
+        paths.insert(row?);
+    }
+    Ok(paths)
+}
+
+fn storage_buckets(
+    conn: &Connection,
+    key_sql: &str,
+    values: &[&str],
+) -> AppResult<Vec<StorageBucket>> {
+    let mut stmt = conn.prepare(&format!(
+        r#"
+        SELECT key, COUNT(*), SUM(size_bytes), SUM(attachments) FROM (
+          SELECT {key_sql} AS key, MAX(COALESCE(a.size_bytes, 0)) AS size_bytes, COUNT(*) AS attachments
+          FROM attachments a
+          JOIN conversations c ON c.id = a.conversation_id
+          WHERE a.local_path IS NOT NULL
+          GROUP BY key, a.local_path
+        )
+        GROUP BY key
+        ORDER BY SUM(size_bytes) DESC, key ASC
+        "#
+    ))?;
+    let rows = stmt.query_map(params_from_iter(values), |row| {
+        Ok(StorageBucket {
+            key: row.get(0)?,
+            files: row.get(1)?,
+            bytes: row.get(2)?,
+            attachments: row.get(3)?,
+        })
+    })?;
+    Ok(rows.collect::<rusqlite::Result<_>>()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    use crate::db::test_support::TempDatabase;
+    use crate::models::AssetGcInput;
+
+    #[test]
+    fn damaged_files_are_not_collected() {
+        let db = TempDatabase::new();
+        let assets_dir = db.dir().join("assets");
+        fs::create_dir_all(&assets_dir).unwrap();
+        let long_ago = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        for name in ["stray.png", "photo.png.damaged"] {
+            let file = File::create(assets_dir.join(name)).unwrap();
+            file.set_modified(long_ago).unwrap();
+        }
+
+        let report = db
+            .collect_asset_garbage(AssetGcInput { dry_run: false })
+            .unwrap();
+        assert_eq!(report.removed_files, 1);
+        assert!(!assets_dir.join("stray.png").exists());
+        assert!(assets_dir.join("photo.png.damaged").exists());
+    }
+}
@@ -0,0 +1,347 @@
+use rusqlite::{
+    params, params_from_iter, types::Value, Connection, OptionalExtension, Transaction,
+};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use super::search::placeholders;
+use super::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::Tag;
+
+const MAX_TAG_NAME_CHARS: usize = 64;
+
+impl Database {
+    /// Every tag with the number of live (not trashed) conversations using it,
+    /// by name.
+    pub fn list_tags(&self) -> AppResult<Vec<Tag>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {TAG_COLUMNS} FROM tags t ORDER BY t.name COLLATE NOCASE ASC"
+        ))?;
+        let rows = stmt.query_map([], row_to_tag)?;
+        let mut tags = Vec::new();
+        for row in rows {
+            tags.push(row?);
+        }
+        Ok(tags)
+    }
+
+    pub fn create_tag(&self, name: String, color: Option<String>) -> AppResult<Tag> {
+        let name = normalize_tag_name(&name)?;
+        let color = normalize_color(color)?;
+        let conn = self.open()?;
+        if let Some(existing) = find_tag_id(&conn, &name)? {
+            let existing = load_tag(&conn, &existing)?;
+            return Err(AppError::Conflict(format!(
+                "标签「{}」已存在",
+                existing.name
+            )));
+        }
+        let id = Uuid::new_v4().to_string();
+        conn.execute(
+            "INSERT INTO tags (id, name, color) VALUES (?1, ?2, ?3)",
+            params![id, name, color],
+        )?;
+        load_tag(&conn, &id)
+    }
+
+    /// Renames a tag. Changing only the case of its own name is allowed;
+    /// taking another tag's name is a conflict, since that is a merge.
+    pub fn rename_tag(&self, id: String, name: String) -> AppResult<Tag> {
+        let name = normalize_tag_name(&name)?;
+        let conn = self.open()?;
+        load_tag(&conn, &id)?;
+        if let Some(other) = find_tag_id(&conn, &name)?.filter(|other| *other != id) {
+            let other = load_tag(&conn, &other)?;
+            return Err(AppError::Conflict(format!(
+                "标签「{}」已存在，如需合并请使用合并标签",
+                other.name
+            )));
+        }
+        conn.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![name, id])?;
+        load_tag(&conn, &id)
+    }
+
+    pub fn set_tag_color(&self, id: String, color: Option<String>) -> AppResult<Tag> {
+        let color = normalize_color(color)?;
+        let conn = self.open()?;
+        let updated = conn.execute(
+            "UPDATE tags SET color = ?1 WHERE id = ?2",
+            params![color, id],
+        )?;
+        if updated == 0 {
+            return Err(AppError::NotFound(format!("tag {id} not found")));
+        }
+        load_tag(&conn, &id)
+    }
+
+    /// Moves every conversation tagged with one of `source_ids` onto
+    /// `target_id`, then deletes the source tags.
+    pub fn merge_tags(&self, source_ids: Vec<String>, target_id: String) -> AppResult<Tag> {
+        let conn = self.open()?;
+        load_tag(&conn, &target_id)?;
+        let tx = conn.unchecked_transaction()?;
+        for source_id in source_ids.iter().filter(|id| **id != target_id) {
+            load_tag(&tx, source_id)?;
+            tx.execute(
+                r#"
+                INSERT OR IGNORE INTO conversation_tags (conversation_id, tag_id)
+                SELECT conversation_id, ?1 FROM conversation_tags WHERE tag_id = ?2
+                "#,
+                params![target_id, source_id],
+            )?;
+            tx.execute(
+                "DELETE FROM conversation_tags WHERE tag_id = ?1",
+                params![source_id],
+            )?;
+            tx.execute("DELETE FROM tags WHERE id = ?1", params![source_id])?;
+        }
+        tx.commit()?;
+        load_tag(&conn, &target_id)
+    }
+
+    /// Deletes a tag and untags every conversation that had it.
+    pub fn delete_tag(&self, id: String) -> AppResult<()> {
+        let conn = self.open()?;
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM conversation_tags WHERE tag_id = ?1",
+            params![id],
+        )?;
+        let deleted = tx.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
+        if deleted == 0 {
+            return Err(AppError::NotFound(format!("tag {id} not found")));
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Adds every tag to every conversation; returns the number of new
+    /// conversation/tag pairs.
+    pub fn tag_conversations(
+        &self,
+        conversation_ids: Vec<String>,
+        tag_ids: Vec<String>,
+    ) -> AppResult<i64> {
+        let conn = self.open()?;
+        let tx = conn.unchecked_transaction()?;
+        for tag_id in &tag_ids {
+            load_tag(&tx, tag_id)?;
+        }
+        let mut added = 0_i64;
+        for conversation_id in &conversation_ids {
+            let exists: bool = tx.query_row(
+                "SELECT EXISTS (SELECT 1 FROM conversations WHERE id = ?1)",
+                params![conversation_id],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                return Err(AppError::NotFound(format!(
+                    "conversation {conversation_id} not found"
+                )));
+            }
+            for tag_id in &tag_ids {
+                added += tx.execute(
+                    "INSERT OR IGNORE INTO conversation_tags (conversation_id, tag_id) VALUES (?1, ?2)",
+                    params![conversation_id, tag_id],
+                )? as i64;
+            }
+        }
+        tx.commit()?;
+        Ok(added)
+    }
+
+    /// Removes every tag from every conversation; returns the number of
+    /// conversation/tag pairs removed.
+    pub fn untag_conversations(
+        &self,
+        conversation_ids: Vec<String>,
+        tag_ids: Vec<String>,
+    ) -> AppResult<i64> {
+        if conversation_ids.is_empty() || tag_ids.is_empty() {
+            return Ok(0);
+        }
+        let conn = self.open()?;
+        let removed = conn.execute(
+            &format!(
+                "DELETE FROM conversation_tags WHERE conversation_id IN ({}) AND tag_id IN ({})",
+                placeholders(conversation_ids.len()),
+                placeholders(tag_ids.len())
+            ),
+            params_from_iter(conversation_ids.iter().chain(tag_ids.iter())),
+        )?;
+        Ok(removed as i64)
+    }
+}
+
+/// Condition over `c` for conversations carrying `any` (the default) or
+/// `all` of `tag_ids`; `None` when there are no tags to filter by.
+pub(super) fn tag_filter_sql(
+    tag_ids: &[String],
+    tag_match: Option<&str>,
+) -> AppResult<Option<(String, Vec<Value>)>> {
+    let match_all = match tag_match {
+        None | Some("any") => false,
+        Some("all") => true,
+        Some(other) => {
+            return Err(AppError::InvalidInput(format!(
+                "不支持的标签匹配方式：{other}"
+            )))
+        }
+    };
+    let tag_ids: Vec<&String> = tag_ids.iter().collect::<HashSet<_>>().into_iter().collect();
+    if tag_ids.is_empty() {
+        return Ok(None);
+    }
+    let marks = placeholders(tag_ids.len());
+    let mut params: Vec<Value> = tag_ids
+        .iter()
+        .map(|id| Value::Text((*id).clone()))
+        .collect();
+    let predicate = if match_all {
+        params.push(Value::Integer(tag_ids.len() as i64));
+        format!(
+            "(SELECT COUNT(*) FROM conversation_tags ct \
+             WHERE ct.conversation_id = c.id AND ct.tag_id IN ({marks})) = ?"
+        )
+    } else {
+        format!(
+            "EXISTS (SELECT 1 FROM conversation_tags ct \
+             WHERE ct.conversation_id = c.id AND ct.tag_id IN ({marks}))"
+        )
+    };
+    Ok(Some((predicate, params)))
+}
+
+/// Column list decoded by `row_to_tag`, always aliased as `t`.
+const TAG_COLUMNS: &str = "t.id, t.name, t.color, \
+     (SELECT COUNT(*) FROM conversation_tags ct JOIN conversations c ON c.id = ct.conversation_id \
+      WHERE ct.tag_id = t.id AND c.deleted_at IS NULL)";
+
+fn row_to_tag(row: &rusqlite::Row<'_>) -> rusqlite::Result<Tag> {
+    Ok(Tag {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        color: row.get(2)?,
+        conversation_count: row.get(3)?,
+    })
+}
+
+fn load_tag(conn: &Connection, id: &str) -> AppResult<Tag> {
+    conn.query_row(
+        &format!("SELECT {TAG_COLUMNS} FROM tags t WHERE t.id = ?1"),
+        params![id],
+        row_to_tag,
+    )
+    .optional()?
+    .ok_or_else(|| AppError::NotFound(format!("tag {id} not found")))
+}
+
+/// Tag names are unique regardless of case, matching the `tag:` search filter.
+fn find_tag_id(conn: &Connection, name: &str) -> AppResult<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT id FROM tags WHERE name = ?1 COLLATE NOCASE",
+            params![name],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+pub(super) fn normalize_tag_name(name: &str) -> AppResult<String> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() {
+        return Err(AppError::InvalidInput("标签名不能为空".to_string()));
+    }
+    if name.chars().count() > MAX_TAG_NAME_CHARS {
+        return Err(AppError::InvalidInput(format!(
+            "标签名不能超过 {MAX_TAG_NAME_CHARS} 个字符"
+        )));
+    }
+    Ok(name)
+}
+
+/// `#rgb` or `#rrggbb`, stored lower case; an empty string clears the color.
+fn normalize_color(color: Option<String>) -> AppResult<Option<String>> {
+    let Some(color) = color.map(|value| value.trim().to_lowercase()) else {
+        return Ok(None);
+    };
+    if color.is_empty() {
+        return Ok(None);
+    }
+    let valid = color.strip_prefix('#').is_some_and(|hex| {
+        matches!(hex.len(), 3 | 6) && hex.chars().all(|ch| ch.is_ascii_hexdigit())
+    });
+    if !valid {
+        return Err(AppError::InvalidInput(format!(
+            "标签颜色格式不正确：{color}，应为 #rrggbb"
+        )));
+    }
+    Ok(Some(color))
+}
+
+/// Tags `conversation_id` with `names`, creating tags that do not exist yet.
+/// Used by imports, where tags arrive by name; invalid names are skipped
+/// rather than failing the import.
+pub(super) fn apply_tag_names(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    names: &[String],
+) -> AppResult<()> {
+    let mut seen = HashSet::new();
+    for name in names {
+        let Ok(name) = normalize_tag_name(name) else {
+            continue;
+        };
+        if !seen.insert(name.to_lowercase()) {
+            continue;
+        }
+        let tag_id = match find_tag_id(tx, &name)? {
+            Some(id) => id,
+            None => {
+                let id = Uuid::new_v4().to_string();
+                tx.execute(
+                    "INSERT INTO tags (id, name) VALUES (?1, ?2)",
+                    params![id, name],
+                )?;
+                id
+            }
+        };
+        tx.execute(
+            "INSERT OR IGNORE INTO conversation_tags (conversation_id, tag_id) VALUES (?1, ?2)",
+            params![conversation_id, tag_id],
+        )?;
+    }
+    Ok(())
+}
+
+/// Tag ids of a conversation, so an overwrite that deletes and re-inserts it
+/// can put them back.
+pub(super) fn conversation_tag_ids(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+) -> AppResult<Vec<String>> {
+    let mut stmt =
+        tx.prepare_cached("SELECT tag_id FROM conversation_tags WHERE conversation_id = ?1")?;
+    let rows = stmt.query_map(params![conversation_id], |row| row.get::<_, String>(0))?;
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+pub(super) fn restore_tag_ids(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    tag_ids: &[String],
+) -> AppResult<()> {
+    for tag_id in tag_ids {
+        tx.execute(
+            "INSERT OR IGNORE INTO conversation_tags (conversation_id, tag_id) VALUES (?1, ?2)",
+            params![conversation_id, tag_id],
+        )?;
+    }
+    Ok(())
+}
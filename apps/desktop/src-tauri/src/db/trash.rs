@@ -0,0 +1,308 @@
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::collections::HashSet;
+use std::fs;
+
+use super::{
+    now_iso, row_to_conversation, Database, CONVERSATION_COLUMNS, CONVERSATION_COLUMN_COUNT,
+};
+use crate::error::{AppError, AppResult};
+use crate::models::{ConversationSummary, PurgeResult};
+
+impl Database {
+    pub fn trash_conversation(&self, id: String) -> AppResult<()> {
+        let conn = self.open()?;
+        let updated = conn.execute(
+            "UPDATE conversations SET deleted_at = ?1 WHERE id = ?2 AND deleted_at IS NULL",
+            params![now_iso(), id],
+        )?;
+        if updated == 0 {
+            ensure_conversation_exists(&conn, &id)?;
+        }
+        Ok(())
+    }
+
+    pub fn restore_conversation(&self, id: String) -> AppResult<()> {
+        let conn = self.open()?;
+        let updated = conn.execute(
+            "UPDATE conversations SET deleted_at = NULL WHERE id = ?1 AND deleted_at IS NOT NULL",
+            params![id],
+        )?;
+        if updated == 0 {
+            ensure_conversation_exists(&conn, &id)?;
+        }
+        Ok(())
+    }
+
+    pub fn list_trash(&self) -> AppResult<Vec<ConversationSummary>> {
+        let conn = self.open()?;
+        let mut stmt = conn.prepare_cached(&format!(
+            r#"
+            SELECT
+              {CONVERSATION_COLUMNS},
+              (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id) AS message_count
+            FROM conversations c
+            WHERE c.deleted_at IS NOT NULL
+            ORDER BY c.deleted_at DESC
+            "#
+        ))?;
+
+        let rows = stmt.query_map([], |row| {
+            Ok(ConversationSummary {
+                conversation: row_to_conversation(row)?,
+                message_count: row.get(CONVERSATION_COLUMN_COUNT)?,
+            })
+        })?;
+
+        let mut list = Vec::new();
+        for row in rows {
+            list.push(row?);
+        }
+        Ok(list)
+    }
+
+    /// Permanently removes trashed conversations: all of them when `ids` is `None`,
+    /// otherwise only the listed ones that are actually in the trash.
+    pub fn purge_trash(&self, ids: Option<Vec<String>>) -> AppResult<PurgeResult> {
+        let conn = self.open()?;
+        let trashed = select_trashed_ids(&conn, None)?;
+        let targets: Vec<String> = match ids {
+            Some(ids) => {
+                let wanted: HashSet<String> = ids.into_iter().collect();
+                trashed
+                    .into_iter()
+                    .filter(|id| wanted.contains(id))
+                    .collect()
+            }
+            None => trashed,
+        };
+        self.purge_conversations(&conn, &targets)
+    }
+
+    /// Purges conversations that have been in the trash longer than the configured
+    /// retention. Called once at startup.
+    pub fn purge_expired_trash(&self) -> AppResult<PurgeResult> {
+        let retention_days = self.stored_settings()?.trash_retention_days;
+        if retention_days <= 0 {
+            return Ok(PurgeResult {
+                purged: 0,
+                removed_files: 0,
+            });
+        }
+
+        let cutoff = (Utc::now() - chrono::Duration::days(retention_days)).to_rfc3339();
+        let conn = self.open()?;
+        let targets = select_trashed_ids(&conn, Some(&cutoff))?;
+        self.purge_conversations(&conn, &targets)
+    }
+
+    fn purge_conversations(&self, conn: &Connection, ids: &[String]) -> AppResult<PurgeResult> {
+        if ids.is_empty() {
+            return Ok(PurgeResult {
+                purged: 0,
+                removed_files: 0,
+            });
+        }
+
+        let tx = conn.unchecked_transaction()?;
+        let mut asset_paths = Vec::new();
+        for id in ids {
+            asset_paths.extend(purge_conversation(&tx, id)?);
+        }
+        tx.commit()?;
+
+        let removed_files = self.remove_unreferenced_assets(conn, &asset_paths)?;
+        Ok(PurgeResult {
+            purged: ids.len() as i64,
+            removed_files,
+        })
+    }
+
+    /// Deletes cached files from `assets/` once no attachment row points at them.
+    /// Files are content-addressed, so another conversation may still share one.
+    pub(super) fn remove_unreferenced_assets(
+        &self,
+        conn: &Connection,
+        local_paths: &[String],
+    ) -> AppResult<i64> {
+        let assets_dir = self.assets_dir();
+        let mut removed = 0_i64;
+        let mut seen = HashSet::new();
+        for local_path in local_paths {
+            if !seen.insert(local_path.as_str()) {
+                continue;
+            }
+
+            let still_referenced: Option<i64> = conn
+                .query_row(
+                    "SELECT 1 FROM attachments WHERE local_path = ?1 LIMIT 1",
+                    params![local_path],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if still_referenced.is_some() {
+                continue;
+            }
+
+            let path = self.resolve_asset_path(local_path);
+            if path.parent() != Some(assets_dir.as_path()) || !path.is_file() {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            removed += 1;
+        }
+        Ok(removed)
+    }
+}
+
+fn ensure_conversation_exists(conn: &Connection, id: &str) -> AppResult<()> {
+    let found: Option<String> = conn
+        .query_row(
+            "SELECT id FROM conversations WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if found.is_none() {
+        return Err(AppError::NotFound(format!("conversation {id} not found")));
+    }
+    Ok(())
+}
+
+fn select_trashed_ids(conn: &Connection, deleted_before: Option<&str>) -> AppResult<Vec<String>> {
+    let mut stmt = conn.prepare_cached(
+        r#"
+        SELECT id FROM conversations
+        WHERE deleted_at IS NOT NULL AND (?1 IS NULL OR deleted_at < ?1)
+        ORDER BY deleted_at ASC
+        "#,
+    )?;
+    let rows = stmt.query_map(params![deleted_before], |row| row.get::<_, String>(0))?;
+
+    let mut ids = Vec::new();
+    for row in rows {
+        ids.push(row?);
+    }
+    Ok(ids)
+}
+
+/// Removes a conversation and everything hanging off it. Returns the cached
+/// file paths it referenced so the caller can drop the ones left orphaned
+/// after the transaction commits.
+fn purge_conversation(tx: &Transaction<'_>, conversation_id: &str) -> AppResult<Vec<String>> {
+    let mut stmt = tx.prepare_cached(
+        "SELECT DISTINCT local_path FROM attachments WHERE conversation_id = ?1 AND local_path IS NOT NULL",
+    )?;
+    let rows = stmt.query_map(params![conversation_id], |row| row.get::<_, String>(0))?;
+    let mut local_paths = Vec::new();
+    for row in rows {
+        local_paths.push(row?);
+    }
+    drop(stmt);
+
+    tx.execute(
+        "DELETE FROM attachments WHERE conversation_id = ?1",
+        params![conversation_id],
+    )?;
+    tx.execute(
+        "DELETE FROM conversation_tags WHERE conversation_id = ?1",
+        params![conversation_id],
+    )?;
+    tx.execute(
+        "DELETE FROM messages WHERE conversation_id = ?1",
+        params![conversation_id],
+    )?;
+    tx.execute(
+        "DELETE FROM conversation_revisions WHERE conversation_id = ?1",
+        params![conversation_id],
+    )?;
+    tx.execute(
+        "DELETE FROM conversations WHERE id = ?1",
+        params![conversation_id],
+    )?;
+    Ok(local_paths)
+}
+
+/// The trashed conversation an overwrite import would replace, preferring a
+/// match on the source id over one on the fingerprint.
+pub(super) fn find_trashed_match(
+    tx: &Transaction<'_>,
+    source: &str,
+    source_conversation_id: Option<&str>,
+    fingerprint: &str,
+) -> AppResult<Option<String>> {
+    Ok(tx
+        .query_row(
+            r#"
+            SELECT id FROM conversations
+            WHERE deleted_at IS NOT NULL
+              AND (fingerprint = ?1 OR (?2 IS NOT NULL AND source = ?3 AND source_conversation_id = ?2))
+            ORDER BY (source = ?3 AND source_conversation_id IS ?2) DESC, deleted_at DESC
+            LIMIT 1
+            "#,
+            params![fingerprint, source_conversation_id, source],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::test_support::{conversation, TempDatabase};
+    use crate::models::NormalizedConversation;
+
+    fn trashed_conversation() -> NormalizedConversation {
+        conversation(
+            "chatgpt-trashed",
+            "回收站里的会话",
+            &[("user", "你好"), ("assistant", "你好，有什么可以帮你？")],
+        )
+    }
+
+    /// Imports the conversation, tags it and moves it to the trash; returns
+    /// its id.
+    fn trash_one(db: &TempDatabase) -> String {
+        db.import(trashed_conversation(), "skip");
+        let id = db.list_conversations(None).unwrap().items[0]
+            .conversation
+            .id
+            .clone();
+        let tag = db.create_tag("保留".to_string(), None).unwrap();
+        db.tag_conversations(vec![id.clone()], vec![tag.id])
+            .unwrap();
+        db.trash_conversation(id.clone()).unwrap();
+        id
+    }
+
+    #[test]
+    fn reimporting_a_trashed_conversation_restores_it() {
+        let db = TempDatabase::new();
+        let id = trash_one(&db);
+
+        db.import(trashed_conversation(), "overwrite");
+        assert!(db.list_trash().unwrap().is_empty());
+        let live = db.list_conversations(None).unwrap().items;
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].conversation.id, id);
+        assert_eq!(db.list_tags().unwrap()[0].conversation_count, 1);
+    }
+
+    #[test]
+    fn skip_and_duplicate_leave_the_trash_alone() {
+        for strategy in ["skip", "duplicate", "unknown"] {
+            let db = TempDatabase::new();
+            let id = trash_one(&db);
+
+            db.import(trashed_conversation(), strategy);
+            let trash = db.list_trash().unwrap();
+            assert_eq!(trash.len(), 1, "{strategy}");
+            assert_eq!(trash[0].conversation.id, id);
+            let live = db.list_conversations(None).unwrap().items;
+            assert_eq!(live.len(), 1, "{strategy}");
+            assert_ne!(live[0].conversation.id, id);
+
+            db.restore_conversation(id).unwrap();
+            assert_eq!(db.list_conversations(None).unwrap().items.len(), 2);
+        }
+    }
+}
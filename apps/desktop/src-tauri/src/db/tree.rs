@@ -0,0 +1,321 @@
+use rusqlite::{params, OptionalExtension, Transaction};
+use std::collections::HashMap;
+
+use super::{row_to_message, Database, MESSAGE_COLUMNS};
+use crate::error::{AppError, AppResult};
+use crate::models::{ConversationDetail, Message, NormalizedTurn};
+
+/// Tree position of `turns[i]`; `parent` indexes into the same slice.
+pub(super) struct TurnPlacement {
+    pub parent: Option<usize>,
+    pub branch_index: i64,
+    pub on_active_path: bool,
+}
+
+impl Database {
+    /// The message and its siblings (same parent), in branch order.
+    pub fn list_message_branches(&self, message_id: String) -> AppResult<Vec<Message>> {
+        let conn = self.open()?;
+        let anchor: Option<(String, Option<String>)> = conn
+            .query_row(
+                "SELECT conversation_id, parent_id FROM messages WHERE id = ?1",
+                params![message_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        let Some((conversation_id, parent_id)) = anchor else {
+            return Err(AppError::NotFound(format!(
+                "message {message_id} not found"
+            )));
+        };
+
+        let mut stmt = conn.prepare_cached(&format!(
+            r#"
+            SELECT {MESSAGE_COLUMNS}
+            FROM messages
+            WHERE conversation_id = ?1 AND parent_id IS ?2
+            ORDER BY branch_index ASC, seq ASC
+            "#
+        ))?;
+        let rows = stmt.query_map(params![conversation_id, parent_id], row_to_message)?;
+
+        let mut siblings = Vec::new();
+        for row in rows {
+            siblings.push(row?);
+        }
+        Ok(siblings)
+    }
+
+    /// Like `open_conversation`, but `messages` is the path through `message_id`:
+    /// its ancestors, the message itself, then down the active (or first) child.
+    pub fn open_conversation_branch(
+        &self,
+        id: String,
+        message_id: String,
+    ) -> AppResult<Option<ConversationDetail>> {
+        let Some(mut detail) = self.open_conversation(id)? else {
+            return Ok(None);
+        };
+        if !detail.tree.iter().any(|message| message.id == message_id) {
+            return Err(AppError::NotFound(format!(
+                "message {message_id} not found"
+            )));
+        }
+
+        detail.messages = path_through(&detail.tree, &message_id);
+        Ok(Some(detail))
+    }
+}
+
+fn path_through(tree: &[Message], message_id: &str) -> Vec<Message> {
+    let by_id: HashMap<&str, &Message> = tree.iter().map(|m| (m.id.as_str(), m)).collect();
+    let mut children: HashMap<&str, Vec<&Message>> = HashMap::new();
+    for message in tree {
+        if let Some(parent_id) = message.parent_id.as_deref() {
+            children.entry(parent_id).or_default().push(message);
+        }
+    }
+
+    let mut path: Vec<Message> = Vec::new();
+    let mut cursor = by_id.get(message_id).copied();
+    while let Some(message) = cursor {
+        // Guards against a corrupt parent chain looping back on itself.
+        if path.len() > tree.len() {
+            break;
+        }
+        path.push(message.clone());
+        cursor = message
+            .parent_id
+            .as_deref()
+            .and_then(|parent| by_id.get(parent).copied());
+    }
+    path.reverse();
+
+    let mut cursor = message_id;
+    while let Some(next) = children.get(cursor).and_then(|kids| {
+        kids.iter()
+            .find(|kid| kid.on_active_path)
+            .or_else(|| kids.iter().min_by_key(|kid| (kid.branch_index, kid.seq)))
+    }) {
+        if path.len() > tree.len() {
+            break;
+        }
+        path.push((*next).clone());
+        cursor = next.id.as_str();
+    }
+    path
+}
+
+/// Resolves imported turns into tree positions. Turns without source ids form
+/// a linear chain; otherwise each turn hangs off the earlier turn its
+/// `parent_id` names, or becomes a root. The active path runs from
+/// `current_turn_id` (or the last turn) up to its root.
+pub(super) fn plan_message_tree(
+    turns: &[NormalizedTurn],
+    current_turn_id: Option<&str>,
+) -> Vec<TurnPlacement> {
+    let structured = turns.iter().any(|turn| turn.id.is_some());
+    let mut index_by_id: HashMap<&str, usize> = HashMap::new();
+    let mut next_branch: HashMap<Option<usize>, i64> = HashMap::new();
+    let mut placements: Vec<TurnPlacement> = Vec::with_capacity(turns.len());
+
+    for (idx, turn) in turns.iter().enumerate() {
+        let parent = if structured {
+            turn.parent_id
+                .as_deref()
+                .and_then(|parent_id| index_by_id.get(parent_id).copied())
+        } else {
+            idx.checked_sub(1)
+        };
+        let next = next_branch.entry(parent).or_insert(0);
+        let branch_index = turn.branch_index.unwrap_or(*next);
+        *next = (*next).max(branch_index + 1);
+
+        if let Some(id) = turn.id.as_deref() {
+            index_by_id.insert(id, idx);
+        }
+        placements.push(TurnPlacement {
+            parent,
+            branch_index,
+            on_active_path: false,
+        });
+    }
+
+    let leaf = current_turn_id
+        .and_then(|id| index_by_id.get(id).copied())
+        .or(turns.len().checked_sub(1));
+    let mut cursor = leaf;
+    while let Some(idx) = cursor {
+        placements[idx].on_active_path = true;
+        cursor = placements[idx].parent;
+    }
+    placements
+}
+
+pub(super) fn next_branch_index(
+    tx: &Transaction<'_>,
+    conversation_id: &str,
+    parent_id: Option<&str>,
+) -> AppResult<i64> {
+    Ok(tx.query_row(
+        "SELECT COALESCE(MAX(branch_index), -1) + 1 FROM messages WHERE conversation_id = ?1 AND parent_id IS ?2",
+        params![conversation_id, parent_id],
+        |row| row.get(0),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempDatabase;
+    use crate::models::NormalizedConversation;
+
+    /// `packages/test-fixtures/chatgpt/branched-chatgpt.json` as the parser
+    /// emits it: the prompt was edited, then the answer to the edit regenerated.
+    fn branched_conversation() -> NormalizedConversation {
+        let turn = |id: &str, parent: Option<&str>, role: &str, text: &str| {
+            serde_json::json!({
+                "id": id, "parentId": parent, "role": role, "contentMarkdown": text,
+            })
+        };
+        serde_json::from_value(serde_json::json!({
+            "source": "chatgpt",
+            "sourceConversationId": "chatgpt-branched",
+            "title": "编辑过的提问",
+            "currentTurnId": "a2-regenerated",
+            "turns": [
+                turn("u1", None, "user", "解释一下 RAG"),
+                turn("a1", Some("u1"), "assistant", "RAG 是检索增强生成。"),
+                turn("u1-edited", None, "user", "用一句话解释 RAG"),
+                turn("a2", Some("u1-edited"), "assistant", "先检索，再生成。"),
+                turn("a2-regenerated", Some("u1-edited"), "assistant", "检索相关文档后据此生成回答。"),
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn plans_edits_and_regenerations_as_branches() {
+        let conv = branched_conversation();
+        let placements = plan_message_tree(&conv.turns, conv.current_turn_id.as_deref());
+        let planned: Vec<(Option<usize>, i64, bool)> = placements
+            .iter()
+            .map(|p| (p.parent, p.branch_index, p.on_active_path))
+            .collect();
+        assert_eq!(
+            planned,
+            vec![
+                (None, 0, false),
+                (Some(0), 0, false),
+                (None, 1, true),
+                (Some(2), 0, false),
+                (Some(2), 1, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn linear_turns_chain_and_end_on_the_last_turn() {
+        let mut conv = branched_conversation();
+        for turn in &mut conv.turns {
+            turn.id = None;
+            turn.parent_id = None;
+        }
+        let placements = plan_message_tree(&conv.turns, None);
+        assert!(placements
+            .iter()
+            .enumerate()
+            .all(|(idx, p)| p.parent == idx.checked_sub(1)
+                && p.branch_index == 0
+                && p.on_active_path));
+    }
+
+    #[test]
+    fn stores_parent_branch_and_active_path() {
+        let db = TempDatabase::new();
+        db.import(branched_conversation(), "skip");
+
+        let id = db.list_conversations(None).unwrap().items[0]
+            .conversation
+            .id
+            .clone();
+        let detail = db.open_conversation(id).unwrap().unwrap();
+        let by_text = |text: &str| {
+            detail
+                .tree
+                .iter()
+                .find(|m| m.content_markdown == text)
+                .unwrap()
+        };
+        let stored = |text: &str| {
+            let message = by_text(text);
+            let parent = message.parent_id.as_deref().map(|parent_id| {
+                detail
+                    .tree
+                    .iter()
+                    .find(|m| m.id == parent_id)
+                    .unwrap()
+                    .content_markdown
+                    .as_str()
+            });
+            (parent, message.branch_index, message.on_active_path)
+        };
+        assert_eq!(stored("解释一下 RAG"), (None, 0, false));
+        assert_eq!(
+            stored("RAG 是检索增强生成。"),
+            (Some("解释一下 RAG"), 0, false)
+        );
+        assert_eq!(stored("用一句话解释 RAG"), (None, 1, true));
+        assert_eq!(
+            stored("先检索，再生成。"),
+            (Some("用一句话解释 RAG"), 0, false)
+        );
+        assert_eq!(
+            stored("检索相关文档后据此生成回答。"),
+            (Some("用一句话解释 RAG"), 1, true)
+        );
+        assert_eq!(
+            detail
+                .messages
+                .iter()
+                .map(|m| m.content_markdown.as_str())
+                .collect::<Vec<_>>(),
+            ["用一句话解释 RAG", "检索相关文档后据此生成回答。"]
+        );
+
+        let branches = db
+            .list_message_branches(by_text("先检索，再生成。").id.clone())
+            .unwrap();
+        assert_eq!(branches.len(), 2);
+    }
+
+    #[test]
+    fn reimporting_an_unchanged_branched_conversation_keeps_no_revision() {
+        let db = TempDatabase::new();
+        db.import(branched_conversation(), "skip");
+        let id = db.list_conversations(None).unwrap().items[0]
+            .conversation
+            .id
+            .clone();
+
+        db.import(branched_conversation(), "overwrite");
+        db.import(branched_conversation(), "overwrite");
+        assert!(db
+            .list_conversation_revisions(id.clone())
+            .unwrap()
+            .is_empty());
+
+        // Switching to the other answer changes the active path.
+        let mut conv = branched_conversation();
+        conv.current_turn_id = Some("a2".to_string());
+        db.import(conv, "overwrite");
+        let id = db.list_conversations(None).unwrap().items[0]
+            .conversation
+            .id
+            .clone();
+        let revisions = db.list_conversation_revisions(id).unwrap();
+        assert_eq!(revisions.len(), 1);
+        // The snapshot keeps every branch, not just the old path.
+        assert_eq!(revisions[0].message_count, 5);
+    }
+}
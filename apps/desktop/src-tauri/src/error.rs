@@ -0,0 +1,108 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Error surfaced to the webview (as `{ code, message }`) and to the bridge
+/// (as `{ error, message }` with a matching HTTP status). `code` is stable and
+/// meant for programmatic handling; `message` is for display only.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "code", content = "message", rename_all = "snake_case")]
+pub enum AppError {
+    NotFound(String),
+    Conflict(String),
+    InvalidInput(String),
+    Io(String),
+    Network(String),
+    Schema(String),
+    Locked(String),
+    Database(String),
+    Internal(String),
+}
+
+pub type AppResult<T> = Result<T, AppError>;
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::InvalidInput(_) => "invalid_input",
+            AppError::Io(_) => "io",
+            AppError::Network(_) => "network",
+            AppError::Schema(_) => "schema",
+            AppError::Locked(_) => "locked",
+            AppError::Database(_) => "database",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::NotFound(message)
+            | AppError::Conflict(message)
+            | AppError::InvalidInput(message)
+            | AppError::Io(message)
+            | AppError::Network(message)
+            | AppError::Schema(message)
+            | AppError::Locked(message)
+            | AppError::Database(message)
+            | AppError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code(), self.message())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> Self {
+        use rusqlite::ErrorCode;
+
+        match &err {
+            rusqlite::Error::QueryReturnedNoRows => AppError::NotFound(err.to_string()),
+            rusqlite::Error::SqliteFailure(failure, _) => match failure.code {
+                ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => {
+                    AppError::Locked(err.to_string())
+                }
+                ErrorCode::ConstraintViolation => AppError::Conflict(err.to_string()),
+                _ => AppError::Database(err.to_string()),
+            },
+            _ => AppError::Database(err.to_string()),
+        }
+    }
+}
+
+impl From<r2d2::Error> for AppError {
+    // r2d2 only fails checkouts on timeout, i.e. every connection is busy.
+    fn from(err: r2d2::Error) -> Self {
+        AppError::Locked(format!("open db failed: {err}"))
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Io(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for AppError {
+    fn from(err: reqwest::Error) -> Self {
+        AppError::Network(err.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for AppError {
+    fn from(err: zip::result::ZipError) -> Self {
+        AppError::Io(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(err: serde_json::Error) -> Self {
+        AppError::InvalidInput(err.to_string())
+    }
+}
@@ -14,6 +14,7 @@ use tower_http::cors::{Any, CorsLayer};
 use uuid::Uuid;
 
 use crate::db::Database;
+use crate::error::{AppError, AppResult};
 use crate::models::{ImportResult, LiveCaptureRequest, SessionResponse};
 
 #[derive(Clone)]
@@ -30,7 +31,7 @@ impl BridgeState {
         }
     }
 
-    pub fn issue_session(&self) -> Result<SessionResponse, String> {
+    pub fn issue_session(&self) -> AppResult<SessionResponse> {
         let token = Uuid::new_v4().to_string();
         let expires_at = Instant::now() + Duration::from_secs(600);
         let iso = (Utc::now() + chrono::Duration::seconds(600)).to_rfc3339();
@@ -38,7 +39,7 @@ impl BridgeState {
         let mut sessions = self
             .sessions
             .lock()
-            .map_err(|_| "failed to lock bridge session".to_string())?;
+            .map_err(|_| AppError::Internal("failed to lock bridge session".to_string()))?;
         sessions.insert(token.clone(), expires_at);
 
         Ok(SessionResponse {
@@ -61,7 +62,7 @@ impl BridgeState {
     }
 }
 
-pub async fn start_bridge_server(state: BridgeState) -> Result<(), String> {
+pub async fn start_bridge_server(state: BridgeState) -> AppResult<()> {
     let app = Router::new()
         .route("/v1/health", get(health))
         .route("/v1/session/start", post(session_start))
@@ -79,11 +80,11 @@ pub async fn start_bridge_server(state: BridgeState) -> Result<(), String> {
 
     let addr: SocketAddr = "127.0.0.1:48765"
         .parse()
-        .map_err(|e| format!("invalid bridge addr: {e}"))?;
+        .map_err(|e| AppError::Internal(format!("invalid bridge addr: {e}")))?;
 
     let listener = tokio::net::TcpListener::bind(addr)
         .await
-        .map_err(|e| format!("bind bridge port failed: {e}"))?;
+        .map_err(|e| AppError::Io(format!("bind bridge port failed: {e}")))?;
 
     tokio::spawn(async move {
         if let Err(e) = axum::serve(listener, app).await {
@@ -112,11 +113,7 @@ async fn session_start(
 
     match state.issue_session() {
         Ok(session) => (StatusCode::OK, Json(json!(session))).into_response(),
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": err})),
-        )
-            .into_response(),
+        Err(err) => err.into_response(),
     }
 }
 
@@ -142,11 +139,29 @@ async fn import_live(
             let out: ImportResult = result;
             (StatusCode::OK, Json(json!(out))).into_response()
         }
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"error": err})),
+        Err(err) => err.into_response(),
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> axum::response::Response {
+        let status = match &self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::InvalidInput(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Network(_) => StatusCode::BAD_GATEWAY,
+            AppError::Locked(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Io(_)
+            | AppError::Schema(_)
+            | AppError::Database(_)
+            | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+
+        (
+            status,
+            Json(json!({"error": self.code(), "message": self.message()})),
         )
-            .into_response(),
+            .into_response()
     }
 }
 
@@ -0,0 +1,688 @@
+//! Importer for the zip produced by ChatGPT's "Export data" feature.
+//!
+//! `conversations.json` is one large array; it is streamed element by element so
+//! memory stays bounded by a single conversation plus one import batch. Files
+//! referenced from messages (uploads, DALL·E output) are bundled next to it as
+//! `file-<id>-<name>` / `file_<id>-<name>` and are copied into the assets dir.
+
+use serde::de::{DeserializeSeed, Error as _, SeqAccess, Visitor};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::BufReader;
+use std::rc::Rc;
+
+use super::{cached_attachment, export_attachment_url, BatchImporter, ExportArchive};
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ArchiveImportInput, ImportProgress, ImportResult, NormalizedAttachment, NormalizedConversation,
+    NormalizedTurn,
+};
+
+const CONVERSATIONS_ENTRY: &str = "conversations.json";
+
+#[derive(Deserialize)]
+struct ExportConversation {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    update_time: Option<f64>,
+    #[serde(default)]
+    current_node: Option<String>,
+    #[serde(default)]
+    mapping: HashMap<String, ExportNode>,
+}
+
+#[derive(Deserialize)]
+struct ExportNode {
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+    #[serde(default)]
+    message: Option<ExportMessage>,
+}
+
+#[derive(Deserialize)]
+struct ExportMessage {
+    #[serde(default)]
+    author: Option<ExportAuthor>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    content: Option<ExportContent>,
+    #[serde(default)]
+    metadata: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct ExportAuthor {
+    #[serde(default)]
+    role: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExportContent {
+    #[serde(default)]
+    content_type: Option<String>,
+    #[serde(default)]
+    parts: Vec<serde_json::Value>,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    thoughts: Vec<ExportThought>,
+}
+
+#[derive(Deserialize)]
+struct ExportThought {
+    #[serde(default)]
+    summary: Option<String>,
+    #[serde(default)]
+    content: Option<String>,
+}
+
+pub fn import_export_zip(
+    db: &Database,
+    input: &ArchiveImportInput,
+    on_progress: impl FnMut(&ImportProgress),
+) -> AppResult<ImportResult> {
+    let mut conversations_archive = ExportArchive::open(&input.path)?;
+    let mut files = ExportArchive::open(&input.path)?;
+    let file_index = index_bundled_files(&files);
+
+    let Some(total_bytes) = conversations_archive.entry_size(CONVERSATIONS_ENTRY)? else {
+        return Err(AppError::InvalidInput(
+            "导出文件中没有 conversations.json".to_string(),
+        ));
+    };
+
+    let bytes_read = Rc::new(Cell::new(0_u64));
+    let mut importer = BatchImporter::new(db, "chatgpt", input, on_progress);
+    let mut failure: Option<AppError> = None;
+
+    let streamed =
+        conversations_archive.stream(CONVERSATIONS_ENTRY, bytes_read.clone(), |reader| {
+            let mut deserializer = serde_json::Deserializer::from_reader(BufReader::new(reader));
+            let mut on_conversation = |conversation: ExportConversation| -> AppResult<()> {
+                let Some(normalized) =
+                    normalize_conversation(db, &mut files, &file_index, conversation)?
+                else {
+                    return Ok(());
+                };
+                importer.push(normalized, bytes_read.get(), total_bytes)
+            };
+            let stream = ConversationStream {
+                on_conversation: &mut on_conversation,
+                failure: &mut failure,
+            };
+            match stream.deserialize(&mut deserializer) {
+                Ok(()) => Ok(()),
+                Err(err) => Err(failure.take().unwrap_or_else(|| {
+                    AppError::InvalidInput(format!("conversations.json 解析失败: {err}"))
+                })),
+            }
+        })?;
+    if streamed.is_none() {
+        return Err(AppError::InvalidInput(
+            "导出文件中没有 conversations.json".to_string(),
+        ));
+    }
+
+    importer.finish(total_bytes)
+}
+
+/// Visits the top-level array one conversation at a time. Errors raised by the
+/// callback are parked in `failure` so the caller gets the original `AppError`
+/// rather than serde's stringly wrapper.
+struct ConversationStream<'a, F: FnMut(ExportConversation) -> AppResult<()>> {
+    on_conversation: &'a mut F,
+    failure: &'a mut Option<AppError>,
+}
+
+impl<'de, F: FnMut(ExportConversation) -> AppResult<()>> DeserializeSeed<'de>
+    for ConversationStream<'_, F>
+{
+    type Value = ();
+
+    fn deserialize<D: serde::Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, F: FnMut(ExportConversation) -> AppResult<()>> Visitor<'de>
+    for ConversationStream<'_, F>
+{
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("an array of ChatGPT conversations")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(conversation) = seq.next_element::<ExportConversation>()? {
+            if let Err(err) = (self.on_conversation)(conversation) {
+                let message = err.to_string();
+                *self.failure = Some(err);
+                return Err(A::Error::custom(message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Maps file ids (`file-abc123`, `file_00ab…`) to archive entry names.
+fn index_bundled_files(archive: &ExportArchive) -> HashMap<String, String> {
+    let mut index = HashMap::new();
+    for name in archive.file_names() {
+        if let Some(id) = bundled_file_id(name) {
+            index
+                .entry(id.to_string())
+                .or_insert_with(|| name.to_string());
+        }
+    }
+    index
+}
+
+fn bundled_file_id(name: &str) -> Option<&str> {
+    let rest = name
+        .strip_prefix("file-")
+        .or_else(|| name.strip_prefix("file_"))?;
+    let id_len = rest
+        .find(|ch: char| !ch.is_ascii_alphanumeric())
+        .unwrap_or(rest.len());
+    if id_len == 0 {
+        return None;
+    }
+    Some(&name[..5 + id_len])
+}
+
+/// `file-service://file-abc` / `sediment://file_abc` → `file-abc` / `file_abc`.
+fn asset_pointer_id(pointer: &str) -> Option<&str> {
+    let id = pointer.rsplit("://").next()?.trim_matches('/');
+    if id.starts_with("file-") || id.starts_with("file_") {
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// Export times are fractional unix seconds.
+fn timestamp_string(value: Option<f64>) -> Option<String> {
+    let seconds = value.filter(|v| v.is_finite() && *v > 0.0)?;
+    chrono::DateTime::from_timestamp_millis((seconds * 1000.0) as i64).map(|t| t.to_rfc3339())
+}
+
+fn normalize_conversation(
+    db: &Database,
+    files: &mut ExportArchive,
+    file_index: &HashMap<String, String>,
+    conversation: ExportConversation,
+) -> AppResult<Option<NormalizedConversation>> {
+    let (turns, current_turn_id) = walk_mapping(db, files, file_index, &conversation)?;
+    if turns.is_empty() {
+        return Ok(None);
+    }
+
+    let title = conversation
+        .title
+        .as_deref()
+        .map(str::trim)
+        .filter(|title| !title.is_empty())
+        .unwrap_or("Untitled ChatGPT Conversation")
+        .to_string();
+
+    Ok(Some(NormalizedConversation {
+        source: "chatgpt".to_string(),
+        source_conversation_id: conversation.conversation_id.or(conversation.id),
+        title,
+        summary: None,
+        created_at: timestamp_string(conversation.create_time),
+        updated_at: timestamp_string(conversation.update_time),
+        captured_at: None,
+        current_turn_id,
+        turns,
+        meta: Some(serde_json::json!({ "importedFrom": "chatgpt-export" })),
+        tags: Vec::new(),
+    }))
+}
+
+/// Depth-first walk from the roots so every turn follows its parent. Nodes that
+/// render nothing (root/system/hidden nodes, tool chatter) are skipped and their
+/// children re-parented to the nearest emitted ancestor; reasoning nodes are
+/// carried down onto the next assistant turn as its thought.
+fn walk_mapping(
+    db: &Database,
+    files: &mut ExportArchive,
+    file_index: &HashMap<String, String>,
+    conversation: &ExportConversation,
+) -> AppResult<(Vec<NormalizedTurn>, Option<String>)> {
+    let mapping = &conversation.mapping;
+    let mut roots: Vec<&String> = mapping
+        .iter()
+        .filter(|(_, node)| {
+            node.parent
+                .as_ref()
+                .is_none_or(|parent| !mapping.contains_key(parent))
+        })
+        .map(|(id, _)| id)
+        .collect();
+    roots.sort();
+
+    struct Frame<'m> {
+        id: &'m str,
+        parent_turn: Option<String>,
+        pending_thought: Option<String>,
+    }
+
+    let mut turns = Vec::new();
+    let mut emitted_ancestor: HashMap<&str, Option<String>> = HashMap::new();
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut stack: Vec<Frame<'_>> = roots
+        .into_iter()
+        .rev()
+        .map(|id| Frame {
+            id,
+            parent_turn: None,
+            pending_thought: None,
+        })
+        .collect();
+
+    while let Some(frame) = stack.pop() {
+        if !visited.insert(frame.id) {
+            continue;
+        }
+        let Some(node) = mapping.get(frame.id) else {
+            continue;
+        };
+
+        let mut own_turn = frame.parent_turn.clone();
+        let mut pending_thought = frame.pending_thought;
+        if let Some(message) = node.message.as_ref() {
+            match convert_message(db, files, file_index, message)? {
+                Converted::Turn(mut turn) => {
+                    // Reasoning belongs to the answer right after it; any
+                    // other turn in between ends it.
+                    let thought = pending_thought.take();
+                    if turn.role == "assistant" {
+                        turn.thought_markdown = thought;
+                    }
+                    turn.id = Some(frame.id.to_string());
+                    turn.parent_id = frame.parent_turn.clone();
+                    turns.push(turn);
+                    own_turn = Some(frame.id.to_string());
+                }
+                Converted::Thought(thought) => {
+                    pending_thought = Some(match pending_thought {
+                        Some(previous) => format!("{previous}\n\n{thought}"),
+                        None => thought,
+                    });
+                }
+                Converted::Skip => {}
+            }
+        }
+        emitted_ancestor.insert(frame.id, own_turn.clone());
+
+        for child in node.children.iter().rev() {
+            stack.push(Frame {
+                id: child,
+                parent_turn: own_turn.clone(),
+                pending_thought: pending_thought.clone(),
+            });
+        }
+    }
+
+    let current_turn_id = conversation
+        .current_node
+        .as_deref()
+        .and_then(|id| emitted_ancestor.get(id).cloned())
+        .flatten();
+    Ok((turns, current_turn_id))
+}
+
+enum Converted {
+    Turn(NormalizedTurn),
+    Thought(String),
+    Skip,
+}
+
+fn convert_message(
+    db: &Database,
+    files: &mut ExportArchive,
+    file_index: &HashMap<String, String>,
+    message: &ExportMessage,
+) -> AppResult<Converted> {
+    let metadata = message.metadata.as_ref();
+    let hidden = metadata
+        .and_then(|m| m.get("is_visually_hidden_from_conversation"))
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let role = message
+        .author
+        .as_ref()
+        .and_then(|a| a.role.as_deref())
+        .unwrap_or("assistant");
+    if hidden || role == "system" {
+        return Ok(Converted::Skip);
+    }
+    let Some(content) = message.content.as_ref() else {
+        return Ok(Converted::Skip);
+    };
+    let content_type = content.content_type.as_deref().unwrap_or("text");
+
+    if content_type == "thoughts" {
+        let text = content
+            .thoughts
+            .iter()
+            .filter_map(|thought| {
+                let body = thought.content.as_deref().unwrap_or("").trim();
+                let summary = thought.summary.as_deref().unwrap_or("").trim();
+                match (summary.is_empty(), body.is_empty()) {
+                    (true, true) => None,
+                    (false, true) => Some(format!("**{summary}**")),
+                    (true, false) => Some(body.to_string()),
+                    (false, false) => Some(format!("**{summary}**\n\n{body}")),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        return Ok(if text.is_empty() {
+            Converted::Skip
+        } else {
+            Converted::Thought(text)
+        });
+    }
+    if content_type == "reasoning_recap" {
+        return Ok(Converted::Skip);
+    }
+
+    let mut text_parts: Vec<String> = Vec::new();
+    let mut file_ids: Vec<String> = Vec::new();
+    match content_type {
+        "code" | "execution_output" => {
+            if let Some(text) = content.text.as_deref().filter(|t| !t.trim().is_empty()) {
+                text_parts.push(format!("```\n{}\n```", text.trim_end()));
+            }
+        }
+        _ => {
+            if let Some(text) = content.text.as_deref().filter(|t| !t.trim().is_empty()) {
+                text_parts.push(text.to_string());
+            }
+            for part in &content.parts {
+                match part {
+                    serde_json::Value::String(text) if !text.trim().is_empty() => {
+                        text_parts.push(text.clone());
+                    }
+                    serde_json::Value::Object(object) => {
+                        if let Some(id) = object
+                            .get("asset_pointer")
+                            .and_then(|v| v.as_str())
+                            .and_then(asset_pointer_id)
+                        {
+                            file_ids.push(id.to_string());
+                        } else if let Some(text) = object.get("text").and_then(|v| v.as_str()) {
+                            if !text.trim().is_empty() {
+                                text_parts.push(text.to_string());
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut named: HashMap<String, (Option<String>, Option<String>)> = HashMap::new();
+    if let Some(listed) = metadata
+        .and_then(|m| m.get("attachments"))
+        .and_then(|v| v.as_array())
+    {
+        for attachment in listed {
+            let Some(id) = attachment.get("id").and_then(|v| v.as_str()) else {
+                continue;
+            };
+            let name = attachment
+                .get("name")
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            let mime = attachment
+                .get("mime_type")
+                .or_else(|| attachment.get("mimeType"))
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+            named.insert(id.to_string(), (name, mime));
+            file_ids.push(id.to_string());
+        }
+    }
+
+    let mut attachments: Vec<NormalizedAttachment> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    for id in file_ids {
+        if !seen.insert(id.clone()) {
+            continue;
+        }
+        let Some(entry_name) = file_index.get(&id) else {
+            continue;
+        };
+        let Some(bytes) = files.read(entry_name)? else {
+            continue;
+        };
+        let (listed_name, mime) = named.remove(&id).unwrap_or((None, None));
+        let file_name = listed_name.unwrap_or_else(|| {
+            entry_name
+                .strip_prefix(id.as_str())
+                .map(|rest| rest.trim_start_matches('-'))
+                .filter(|rest| !rest.is_empty())
+                .unwrap_or(entry_name)
+                .to_string()
+        });
+        let url = export_attachment_url("chatgpt", &id, &file_name);
+        attachments.push(cached_attachment(db, &bytes, url, mime)?);
+    }
+
+    let content_markdown = text_parts.join("\n\n");
+    // Tool turns are noise unless they carry generated files (e.g. images).
+    if role == "tool" && attachments.is_empty() {
+        return Ok(Converted::Skip);
+    }
+    if content_markdown.trim().is_empty() && attachments.is_empty() {
+        return Ok(Converted::Skip);
+    }
+
+    Ok(Converted::Turn(NormalizedTurn {
+        role: if role == "tool" { "assistant" } else { role }.to_string(),
+        content_markdown,
+        thought_markdown: None,
+        attachments: if attachments.is_empty() {
+            None
+        } else {
+            Some(attachments)
+        },
+        model: metadata
+            .and_then(|m| m.get("model_slug"))
+            .and_then(|v| v.as_str())
+            .map(str::to_string),
+        timestamp: timestamp_string(message.create_time),
+        token_count: None,
+        id: None,
+        parent_id: None,
+        branch_index: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempDatabase;
+    use std::io::Write;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn node(
+        parent: Option<&str>,
+        children: &[&str],
+        message: serde_json::Value,
+    ) -> serde_json::Value {
+        serde_json::json!({ "parent": parent, "children": children, "message": message })
+    }
+
+    fn message(role: &str, content: serde_json::Value) -> serde_json::Value {
+        serde_json::json!({ "author": { "role": role }, "content": content })
+    }
+
+    fn text(role: &str, text: &str) -> serde_json::Value {
+        message(
+            role,
+            serde_json::json!({ "content_type": "text", "parts": [text] }),
+        )
+    }
+
+    /// The first prompt was edited; the edit carries an uploaded image and its
+    /// answer sits behind a hidden context node and a reasoning node.
+    fn branched_conversation(current_node: &str) -> ExportConversation {
+        let mut hidden = text("user", "用户偏好：简洁");
+        hidden["metadata"] = serde_json::json!({ "is_visually_hidden_from_conversation": true });
+        let mut answer = text("assistant", "一张流程图。");
+        answer["metadata"] = serde_json::json!({ "model_slug": "gpt-4o" });
+
+        serde_json::from_value(serde_json::json!({
+            "conversation_id": "conv-1",
+            "title": "看图",
+            "current_node": current_node,
+            "mapping": {
+                "root": node(None, &["sys"], serde_json::Value::Null),
+                "sys": node(Some("root"), &["u1", "u1-edited"], text("system", "You are ChatGPT")),
+                "u1": node(Some("sys"), &["a1"], text("user", "这是什么")),
+                "a1": node(Some("u1"), &[], text("assistant", "请上传图片。")),
+                "u1-edited": node(Some("sys"), &["hidden"], message("user", serde_json::json!({
+                    "content_type": "multimodal_text",
+                    "parts": [{ "asset_pointer": "sediment://file_00ab" }, "这张图是什么"],
+                }))),
+                "hidden": node(Some("u1-edited"), &["think"], hidden),
+                "think": node(Some("hidden"), &["a2"], message("assistant", serde_json::json!({
+                    "content_type": "thoughts",
+                    "thoughts": [{ "summary": "看图", "content": "有方框和箭头。" }],
+                }))),
+                "a2": node(Some("think"), &[], answer),
+            },
+        }))
+        .unwrap()
+    }
+
+    fn walk(current_node: &str) -> (Vec<NormalizedTurn>, Option<String>) {
+        walk_conversation(&branched_conversation(current_node))
+    }
+
+    fn walk_conversation(
+        conversation: &ExportConversation,
+    ) -> (Vec<NormalizedTurn>, Option<String>) {
+        let db = TempDatabase::new();
+        let zip_path = db.dir().join("export.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        zip.start_file("file_00ab-图.png", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        zip.write_all(PNG).unwrap();
+        zip.finish().unwrap();
+
+        let mut files = ExportArchive::open(zip_path.to_str().unwrap()).unwrap();
+        let file_index = index_bundled_files(&files);
+        walk_mapping(&db, &mut files, &file_index, conversation).unwrap()
+    }
+
+    #[test]
+    fn walks_branches_past_hidden_nodes() {
+        let (turns, current_turn_id) = walk("a2");
+        let shape: Vec<(&str, Option<&str>, &str)> = turns
+            .iter()
+            .map(|turn| {
+                (
+                    turn.id.as_deref().unwrap(),
+                    turn.parent_id.as_deref(),
+                    turn.content_markdown.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            shape,
+            [
+                ("u1", None, "这是什么"),
+                ("a1", Some("u1"), "请上传图片。"),
+                ("u1-edited", None, "这张图是什么"),
+                ("a2", Some("u1-edited"), "一张流程图。"),
+            ]
+        );
+        assert_eq!(current_turn_id.as_deref(), Some("a2"));
+
+        let attachments = turns[2].attachments.as_ref().unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(
+            attachments[0].original_url,
+            "aihistory://export/chatgpt/file_00ab/%E5%9B%BE.png"
+        );
+        assert_eq!(
+            turns[3].thought_markdown.as_deref(),
+            Some("**看图**\n\n有方框和箭头。")
+        );
+        assert_eq!(turns[3].model.as_deref(), Some("gpt-4o"));
+        assert!(turns[1].thought_markdown.is_none());
+    }
+
+    #[test]
+    fn reasoning_does_not_carry_past_a_user_turn() {
+        let conversation: ExportConversation = serde_json::from_value(serde_json::json!({
+            "conversation_id": "conv-2",
+            "title": "打断",
+            "current_node": "a1",
+            "mapping": {
+                "root": node(None, &["think"], serde_json::Value::Null),
+                "think": node(Some("root"), &["u1"], message("assistant", serde_json::json!({
+                    "content_type": "thoughts",
+                    "thoughts": [{ "summary": "先想想", "content": "还没回答就被打断了。" }],
+                }))),
+                "u1": node(Some("think"), &["a1"], text("user", "换个问题")),
+                "a1": node(Some("u1"), &[], text("assistant", "好的。")),
+            },
+        }))
+        .unwrap();
+
+        let (turns, _) = walk_conversation(&conversation);
+        assert_eq!(turns.len(), 2);
+        assert!(turns.iter().all(|turn| turn.thought_markdown.is_none()));
+    }
+
+    #[test]
+    fn current_node_on_a_skipped_node_resolves_to_its_emitted_ancestor() {
+        assert_eq!(walk("think").1.as_deref(), Some("u1-edited"));
+        assert_eq!(walk("sys").1, None);
+    }
+
+    #[test]
+    fn extracts_bundled_file_ids() {
+        assert_eq!(
+            bundled_file_id("file-abc123-report.pdf"),
+            Some("file-abc123")
+        );
+        assert_eq!(bundled_file_id("file_00ab-图.png"), Some("file_00ab"));
+        assert_eq!(bundled_file_id("file-abc"), Some("file-abc"));
+        assert_eq!(bundled_file_id("file--report.pdf"), None);
+        assert_eq!(bundled_file_id("notes.txt"), None);
+    }
+
+    #[test]
+    fn extracts_asset_pointer_ids() {
+        assert_eq!(
+            asset_pointer_id("file-service://file-abc"),
+            Some("file-abc")
+        );
+        assert_eq!(asset_pointer_id("sediment://file_00ab"), Some("file_00ab"));
+        assert_eq!(asset_pointer_id("file_00ab"), Some("file_00ab"));
+        assert_eq!(asset_pointer_id("https://example.com/file.png"), None);
+    }
+}
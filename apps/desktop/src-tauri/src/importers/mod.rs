@@ -0,0 +1,274 @@
+pub mod chatgpt;
+pub mod takeout;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::rc::Rc;
+
+use zip::ZipArchive;
+
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ArchiveImportInput, ImportBatch, ImportProgress, ImportResult, NormalizedAttachment,
+    NormalizedConversation,
+};
+
+pub const IMPORT_PROGRESS_EVENT: &str = "import-progress";
+
+/// Conversations per `import_files` call; each batch is one transaction.
+const IMPORT_BATCH_SIZE: usize = 100;
+
+/// Feeds parsed conversations to `Database::import_files` in fixed-size
+/// batches and reports running totals after every batch.
+pub(crate) struct BatchImporter<'a, F: FnMut(&ImportProgress)> {
+    db: &'a Database,
+    source: &'static str,
+    strategy: String,
+    folder_id: Option<String>,
+    pending: Vec<NormalizedConversation>,
+    processed: i64,
+    totals: ImportResult,
+    on_progress: F,
+}
+
+impl<'a, F: FnMut(&ImportProgress)> BatchImporter<'a, F> {
+    pub fn new(
+        db: &'a Database,
+        source: &'static str,
+        input: &ArchiveImportInput,
+        on_progress: F,
+    ) -> Self {
+        Self {
+            db,
+            source,
+            strategy: input.strategy.clone(),
+            folder_id: input.folder_id.clone(),
+            pending: Vec::with_capacity(IMPORT_BATCH_SIZE),
+            processed: 0,
+            totals: ImportResult::default(),
+            on_progress,
+        }
+    }
+
+    pub fn push(
+        &mut self,
+        conversation: NormalizedConversation,
+        bytes_read: u64,
+        total_bytes: u64,
+    ) -> AppResult<()> {
+        self.pending.push(conversation);
+        if self.pending.len() >= IMPORT_BATCH_SIZE {
+            self.flush(bytes_read, total_bytes, false)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self, total_bytes: u64) -> AppResult<ImportResult> {
+        self.flush(total_bytes, total_bytes, true)?;
+        Ok(self.totals)
+    }
+
+    fn flush(&mut self, bytes_read: u64, total_bytes: u64, done: bool) -> AppResult<()> {
+        if !self.pending.is_empty() {
+            let conversations = std::mem::take(&mut self.pending);
+            self.processed += conversations.len() as i64;
+            let result = self.db.import_files(ImportBatch {
+                conversations,
+                strategy: self.strategy.clone(),
+                folder_id: self.folder_id.clone(),
+            })?;
+            self.totals.imported += result.imported;
+            self.totals.skipped += result.skipped;
+            self.totals.conflicts += result.conflicts;
+        }
+
+        (self.on_progress)(&ImportProgress {
+            source: self.source.to_string(),
+            processed: self.processed,
+            imported: self.totals.imported,
+            skipped: self.totals.skipped,
+            conflicts: self.totals.conflicts,
+            bytes_read,
+            total_bytes,
+            done,
+        });
+        Ok(())
+    }
+}
+
+/// Read-side view of an export zip. Entries can be looked up by full path or
+/// by base name, since vendors nest the same layout under different top-level
+/// folders (and localize some of them).
+pub(crate) struct ExportArchive {
+    archive: ZipArchive<File>,
+    by_name: HashMap<String, usize>,
+    by_path: HashMap<String, usize>,
+}
+
+impl ExportArchive {
+    pub fn open(path: &str) -> AppResult<Self> {
+        let file = File::open(path)
+            .map_err(|e| AppError::InvalidInput(format!("无法打开导出文件 {path}: {e}")))?;
+        let archive = ZipArchive::new(file)
+            .map_err(|e| AppError::InvalidInput(format!("不是有效的 zip 导出文件: {e}")))?;
+
+        let mut by_name = HashMap::new();
+        let mut by_path = HashMap::new();
+        for index in 0..archive.len() {
+            let Some(name) = archive.name_for_index(index) else {
+                continue;
+            };
+            if name.ends_with('/') {
+                continue;
+            }
+            by_name.entry(base_name(name).to_string()).or_insert(index);
+            by_path.insert(name.to_string(), index);
+        }
+        Ok(Self {
+            archive,
+            by_name,
+            by_path,
+        })
+    }
+
+    pub fn file_names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(String::as_str)
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.by_path.keys().map(String::as_str)
+    }
+
+    pub fn entry_size(&mut self, name: &str) -> AppResult<Option<u64>> {
+        let Some(&index) = self.by_name.get(name) else {
+            return Ok(None);
+        };
+        Ok(Some(self.archive.by_index(index)?.size()))
+    }
+
+    pub fn path_size(&mut self, path: &str) -> AppResult<Option<u64>> {
+        let Some(&index) = self.by_path.get(path) else {
+            return Ok(None);
+        };
+        Ok(Some(self.archive.by_index(index)?.size()))
+    }
+
+    pub fn read(&mut self, name: &str) -> AppResult<Option<Vec<u8>>> {
+        let Some(&index) = self.by_name.get(name) else {
+            return Ok(None);
+        };
+        self.read_index(index).map(Some)
+    }
+
+    pub fn read_path(&mut self, path: &str) -> AppResult<Option<Vec<u8>>> {
+        let Some(&index) = self.by_path.get(path) else {
+            return Ok(None);
+        };
+        self.read_index(index).map(Some)
+    }
+
+    /// Last-modified time stored in the zip, which Takeout sets to the time
+    /// the underlying file was last changed.
+    pub fn modified_at(&mut self, path: &str) -> AppResult<Option<String>> {
+        let Some(&index) = self.by_path.get(path) else {
+            return Ok(None);
+        };
+        let Some(modified) = self.archive.by_index(index)?.last_modified() else {
+            return Ok(None);
+        };
+        let timestamp = chrono::NaiveDate::from_ymd_opt(
+            i32::from(modified.year()),
+            u32::from(modified.month()),
+            u32::from(modified.day()),
+        )
+        .and_then(|date| {
+            date.and_hms_opt(
+                u32::from(modified.hour()),
+                u32::from(modified.minute()),
+                u32::from(modified.second()),
+            )
+        });
+        Ok(timestamp.map(|t| t.and_utc().to_rfc3339()))
+    }
+
+    fn read_index(&mut self, index: usize) -> AppResult<Vec<u8>> {
+        let mut entry = self.archive.by_index(index)?;
+        let mut bytes = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Streams one entry through `consume` without buffering it in memory.
+    /// The reader counts consumed bytes into the shared cell.
+    pub fn stream<T>(
+        &mut self,
+        name: &str,
+        bytes_read: Rc<Cell<u64>>,
+        consume: impl FnOnce(CountingReader<&mut dyn Read>) -> AppResult<T>,
+    ) -> AppResult<Option<T>> {
+        let Some(&index) = self.by_name.get(name) else {
+            return Ok(None);
+        };
+        let mut entry = self.archive.by_index(index)?;
+        let reader = CountingReader {
+            inner: &mut entry as &mut dyn Read,
+            count: bytes_read,
+        };
+        consume(reader).map(Some)
+    }
+}
+
+pub(crate) struct CountingReader<R> {
+    inner: R,
+    count: Rc<Cell<u64>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.set(self.count.get() + read as u64);
+        Ok(read)
+    }
+}
+
+pub(crate) fn base_name(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+/// Copies an archive member into the assets dir and describes it as an
+/// already-cached attachment. `original_url` is a stable `aihistory://export/`
+/// reference so re-imports dedupe against the same row.
+pub(crate) fn cached_attachment(
+    db: &Database,
+    bytes: &[u8],
+    original_url: String,
+    mime: Option<String>,
+) -> AppResult<NormalizedAttachment> {
+    let stored = db.store_asset(bytes, &original_url, mime.as_deref())?;
+    Ok(NormalizedAttachment {
+        kind: "file".to_string(),
+        original_url,
+        mime: stored.mime,
+        status: Some("cached".to_string()),
+        local_path: Some(stored.local_path),
+        sha256: Some(stored.sha256),
+        size_bytes: Some(stored.size_bytes),
+    })
+}
+
+pub(crate) fn export_attachment_url(vendor: &str, file_id: &str, file_name: &str) -> String {
+    let encoded: String = file_name
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.' | b'~') {
+                (byte as char).to_string()
+            } else {
+                format!("%{byte:02X}")
+            }
+        })
+        .collect();
+    format!("aihistory://export/{vendor}/{file_id}/{encoded}")
+}
@@ -0,0 +1,1088 @@
+//! Importer for Google Takeout archives: Gemini Apps activity from My Activity
+//! (JSON or HTML export format) and AI Studio prompt files from Drive.
+//!
+//! Takeout has no notion of a Gemini conversation; each activity record is a
+//! single prompt and its reply. Records less than `SESSION_GAP_MINUTES` apart
+//! are grouped into one conversation, keyed by the time of its first record so
+//! re-importing a newer Takeout lines up with the earlier import.
+
+use base64::engine::general_purpose::STANDARD as BASE64_STANDARD;
+use base64::Engine as _;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+
+use super::{base_name, cached_attachment, export_attachment_url, BatchImporter, ExportArchive};
+use crate::db::Database;
+use crate::error::{AppError, AppResult};
+use crate::models::{
+    ArchiveImportInput, ImportProgress, ImportResult, NormalizedAttachment, NormalizedConversation,
+    NormalizedTurn,
+};
+
+const SESSION_GAP_MINUTES: i64 = 30;
+const TITLE_MAX_CHARS: usize = 60;
+
+struct ActivityRecord {
+    time: DateTime<Utc>,
+    prompt: String,
+    response: String,
+    attachments: Vec<NormalizedAttachment>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonActivity {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    time: Option<String>,
+    #[serde(default)]
+    safe_html_item: Vec<JsonHtmlItem>,
+    #[serde(default)]
+    attached_files: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonHtmlItem {
+    #[serde(default)]
+    html: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StudioPrompt {
+    #[serde(default)]
+    run_settings: Option<StudioRunSettings>,
+    #[serde(default)]
+    system_instruction: Option<serde_json::Value>,
+    chunked_prompt: StudioChunks,
+}
+
+#[derive(Deserialize)]
+struct StudioRunSettings {
+    #[serde(default)]
+    model: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StudioChunks {
+    #[serde(default)]
+    chunks: Vec<StudioChunk>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StudioChunk {
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    is_thought: bool,
+    #[serde(default)]
+    token_count: Option<i64>,
+    #[serde(default)]
+    inline_image: Option<StudioInlineData>,
+    #[serde(default)]
+    inline_data: Option<StudioInlineData>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StudioInlineData {
+    #[serde(default)]
+    mime_type: Option<String>,
+    #[serde(default)]
+    data: Option<String>,
+}
+
+pub fn import_takeout_zip(
+    db: &Database,
+    input: &ArchiveImportInput,
+    on_progress: impl FnMut(&ImportProgress),
+) -> AppResult<ImportResult> {
+    let mut archive = ExportArchive::open(&input.path)?;
+    let mut paths: Vec<String> = archive.paths().map(str::to_string).collect();
+    paths.sort();
+
+    let activity_files: Vec<String> = paths
+        .iter()
+        .filter(|path| is_gemini_activity_path(path))
+        .cloned()
+        .collect();
+    let prompt_files: Vec<String> = paths
+        .iter()
+        .filter(|path| is_ai_studio_path(path))
+        .cloned()
+        .collect();
+    if activity_files.is_empty() && prompt_files.is_empty() {
+        return Err(AppError::InvalidInput(
+            "Takeout 压缩包中没有 Gemini 或 AI Studio 记录".to_string(),
+        ));
+    }
+
+    let mut total_bytes = 0_u64;
+    for path in activity_files.iter().chain(&prompt_files) {
+        total_bytes += archive.path_size(path)?.unwrap_or(0);
+    }
+
+    let mut importer = BatchImporter::new(db, "takeout", input, on_progress);
+    let mut bytes_read = 0_u64;
+
+    let mut records = Vec::new();
+    for path in &activity_files {
+        let Some(bytes) = archive.read_path(path)? else {
+            continue;
+        };
+        bytes_read += bytes.len() as u64;
+        let text = String::from_utf8_lossy(&bytes);
+        let parsed = if path.to_lowercase().ends_with(".json") {
+            parse_activity_json(db, &mut archive, path, &text)?
+        } else {
+            parse_activity_html(db, &mut archive, path, &text)?
+        };
+        records.extend(parsed);
+    }
+    for conversation in group_sessions(records) {
+        importer.push(conversation, bytes_read, total_bytes)?;
+    }
+
+    for path in &prompt_files {
+        let Some(bytes) = archive.read_path(path)? else {
+            continue;
+        };
+        bytes_read += bytes.len() as u64;
+        let modified_at = archive.modified_at(path)?;
+        if let Some(conversation) = parse_studio_prompt(db, path, &bytes, modified_at)? {
+            importer.push(conversation, bytes_read, total_bytes)?;
+        }
+    }
+
+    importer.finish(total_bytes)
+}
+
+fn path_dirs(path: &str) -> impl Iterator<Item = &str> {
+    let mut segments: Vec<&str> = path.split(['/', '\\']).collect();
+    segments.pop();
+    segments.into_iter()
+}
+
+/// `…/My Activity/Gemini Apps/MyActivity.{json,html}`; folder names are
+/// localized, so only the "Gemini" product folder is required.
+fn is_gemini_activity_path(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    (lower.ends_with(".json") || lower.ends_with(".html"))
+        && path_dirs(path)
+            .last()
+            .is_some_and(|dir| dir.to_lowercase().contains("gemini"))
+}
+
+/// Anything under a Drive folder named like "Google AI Studio". Prompt files
+/// carry no extension; non-prompt files are rejected when parsing.
+fn is_ai_studio_path(path: &str) -> bool {
+    path_dirs(path).any(|dir| dir.to_lowercase().contains("ai studio"))
+}
+
+fn parse_activity_json(
+    db: &Database,
+    archive: &mut ExportArchive,
+    path: &str,
+    text: &str,
+) -> AppResult<Vec<ActivityRecord>> {
+    let activities: Vec<JsonActivity> = serde_json::from_str(text)
+        .map_err(|e| AppError::InvalidInput(format!("{path} 解析失败: {e}")))?;
+
+    let mut records = Vec::new();
+    for activity in activities {
+        let Some(time) = activity.time.as_deref().and_then(parse_rfc3339) else {
+            continue;
+        };
+        let prompt = strip_prompted_prefix(activity.title.as_deref().unwrap_or(""));
+        let response = activity
+            .safe_html_item
+            .iter()
+            .filter_map(|item| item.html.as_deref())
+            .map(html_to_markdown)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        if prompt.is_empty() && response.is_empty() {
+            continue;
+        }
+        let attachments = attach_archive_files(db, archive, path, &activity.attached_files)?;
+        records.push(ActivityRecord {
+            time,
+            prompt,
+            response,
+            attachments,
+        });
+    }
+    Ok(records)
+}
+
+/// The HTML format has one `outer-cell` per record. Its first `content-cell`
+/// holds `Prompted <prompt><br>` (plus links to attached files), then the
+/// timestamp line, then the reply HTML.
+fn parse_activity_html(
+    db: &Database,
+    archive: &mut ExportArchive,
+    path: &str,
+    html: &str,
+) -> AppResult<Vec<ActivityRecord>> {
+    let mut records = Vec::new();
+    for block in html.split("class=\"outer-cell").skip(1) {
+        let Some(cell_start) = block.find("content-cell") else {
+            continue;
+        };
+        let cell = &block[cell_start..];
+        let Some(open_end) = cell.find('>') else {
+            continue;
+        };
+        let body = &cell[open_end + 1..];
+        let body = &body[..body.find("</div>").unwrap_or(body.len())];
+
+        let segments: Vec<&str> = split_br(body);
+        let Some((time_index, time)) = segments.iter().enumerate().find_map(|(index, segment)| {
+            parse_activity_time(&html_to_markdown(segment)).map(|time| (index, time))
+        }) else {
+            continue;
+        };
+
+        let mut prompt_lines = Vec::new();
+        let mut file_names = Vec::new();
+        for segment in &segments[..time_index] {
+            let hrefs = relative_hrefs(segment);
+            if !hrefs.is_empty() {
+                file_names.extend(hrefs);
+                continue;
+            }
+            let line = html_to_markdown(segment);
+            if line.starts_with("Attached ") {
+                continue;
+            }
+            prompt_lines.push(line);
+        }
+        let prompt = strip_prompted_prefix(&prompt_lines.join("\n"));
+        let response = html_to_markdown(&segments[time_index + 1..].join("<br>"));
+        if prompt.is_empty() && response.is_empty() {
+            continue;
+        }
+
+        let attachments = attach_archive_files(db, archive, path, &file_names)?;
+        records.push(ActivityRecord {
+            time,
+            prompt,
+            response,
+            attachments,
+        });
+    }
+    Ok(records)
+}
+
+fn group_sessions(mut records: Vec<ActivityRecord>) -> Vec<NormalizedConversation> {
+    records.sort_by_key(|record| record.time);
+
+    let mut sessions: Vec<Vec<ActivityRecord>> = Vec::new();
+    for record in records {
+        let starts_new = sessions
+            .last()
+            .and_then(|session| session.last())
+            .is_none_or(|previous| {
+                (record.time - previous.time).num_minutes() > SESSION_GAP_MINUTES
+            });
+        if starts_new {
+            sessions.push(Vec::new());
+        }
+        if let Some(session) = sessions.last_mut() {
+            session.push(record);
+        }
+    }
+
+    sessions
+        .into_iter()
+        .filter_map(|session| {
+            let first = session.first()?;
+            let last = session.last()?;
+            let started = first.time.to_rfc3339();
+            let ended = last.time.to_rfc3339();
+            let title = session
+                .iter()
+                .map(|record| record.prompt.as_str())
+                .find(|prompt| !prompt.is_empty())
+                .map(title_from_prompt)
+                .unwrap_or_else(|| "Gemini".to_string());
+
+            let mut turns = Vec::new();
+            for record in session {
+                let timestamp = Some(record.time.to_rfc3339());
+                turns.push(text_turn(
+                    "user",
+                    record.prompt,
+                    timestamp.clone(),
+                    record.attachments,
+                ));
+                if !record.response.is_empty() {
+                    turns.push(text_turn(
+                        "assistant",
+                        record.response,
+                        timestamp,
+                        Vec::new(),
+                    ));
+                }
+            }
+
+            Some(NormalizedConversation {
+                source: "gemini".to_string(),
+                source_conversation_id: Some(format!("takeout:gemini:{started}")),
+                title,
+                summary: None,
+                created_at: Some(started),
+                updated_at: Some(ended),
+                captured_at: None,
+                current_turn_id: None,
+                turns,
+                meta: Some(serde_json::json!({ "importedFrom": "google-takeout" })),
+                tags: Vec::new(),
+            })
+        })
+        .collect()
+}
+
+fn parse_studio_prompt(
+    db: &Database,
+    path: &str,
+    bytes: &[u8],
+    modified_at: Option<String>,
+) -> AppResult<Option<NormalizedConversation>> {
+    // Drive folders also hold uploads and other files; only prompt JSON counts.
+    let Ok(prompt) = serde_json::from_slice::<StudioPrompt>(bytes) else {
+        return Ok(None);
+    };
+    let model = prompt
+        .run_settings
+        .as_ref()
+        .and_then(|settings| settings.model.as_deref())
+        .map(|model| model.trim_start_matches("models/").to_string());
+
+    let mut turns: Vec<NormalizedTurn> = Vec::new();
+    if let Some(instruction) = prompt
+        .system_instruction
+        .as_ref()
+        .and_then(system_instruction_text)
+    {
+        turns.push(text_turn("system", instruction, None, Vec::new()));
+    }
+
+    let mut pending_thought: Option<String> = None;
+    for (index, chunk) in prompt.chunked_prompt.chunks.iter().enumerate() {
+        let role = match chunk.role.as_deref() {
+            Some("model") => "assistant",
+            Some(other) => other,
+            None => "user",
+        };
+        let text = chunk.text.clone().unwrap_or_default();
+        if chunk.is_thought {
+            if !text.trim().is_empty() {
+                pending_thought = Some(match pending_thought {
+                    Some(previous) => format!("{previous}\n\n{text}"),
+                    None => text,
+                });
+            }
+            continue;
+        }
+
+        let mut attachments = Vec::new();
+        if let Some(inline) = chunk.inline_image.as_ref().or(chunk.inline_data.as_ref()) {
+            if let Some(data) = inline.data.as_deref() {
+                if let Ok(decoded) = BASE64_STANDARD.decode(data.trim()) {
+                    let name = format!("{}-{index}", base_name(path));
+                    let url = export_attachment_url("ai_studio", &name, &name);
+                    attachments.push(cached_attachment(
+                        db,
+                        &decoded,
+                        url,
+                        inline.mime_type.clone(),
+                    )?);
+                }
+            }
+        }
+        if text.trim().is_empty() && attachments.is_empty() {
+            continue;
+        }
+
+        // Consecutive chunks from one speaker (e.g. an image then its caption)
+        // are one turn in the AI Studio UI.
+        if let Some(previous) = turns.last_mut().filter(|turn| turn.role == role) {
+            if !text.trim().is_empty() {
+                if !previous.content_markdown.is_empty() {
+                    previous.content_markdown.push_str("\n\n");
+                }
+                previous.content_markdown.push_str(&text);
+            }
+            previous
+                .attachments
+                .get_or_insert_with(Vec::new)
+                .extend(attachments);
+            continue;
+        }
+
+        let mut turn = text_turn(role, text, None, attachments);
+        turn.token_count = chunk.token_count;
+        if role == "assistant" {
+            turn.model = model.clone();
+            turn.thought_markdown = pending_thought.take();
+        }
+        turns.push(turn);
+    }
+
+    if !turns.iter().any(|turn| turn.role != "system") {
+        return Ok(None);
+    }
+
+    Ok(Some(NormalizedConversation {
+        source: "ai_studio".to_string(),
+        source_conversation_id: Some(format!("takeout:ai_studio:{path}")),
+        title: base_name(path).to_string(),
+        summary: None,
+        created_at: None,
+        updated_at: modified_at,
+        captured_at: None,
+        current_turn_id: None,
+        turns,
+        meta: Some(serde_json::json!({ "importedFrom": "google-takeout" })),
+        tags: Vec::new(),
+    }))
+}
+
+fn system_instruction_text(value: &serde_json::Value) -> Option<String> {
+    let text = match value {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Object(object) => object
+            .get("parts")
+            .and_then(|parts| parts.as_array())
+            .map(|parts| {
+                parts
+                    .iter()
+                    .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+            .or_else(|| {
+                object
+                    .get("text")
+                    .and_then(|t| t.as_str())
+                    .map(str::to_string)
+            })
+            .unwrap_or_default(),
+        _ => String::new(),
+    };
+    let trimmed = text.trim();
+    (!trimmed.is_empty()).then(|| trimmed.to_string())
+}
+
+fn text_turn(
+    role: &str,
+    content_markdown: String,
+    timestamp: Option<String>,
+    attachments: Vec<NormalizedAttachment>,
+) -> NormalizedTurn {
+    NormalizedTurn {
+        role: role.to_string(),
+        content_markdown,
+        thought_markdown: None,
+        attachments: if attachments.is_empty() {
+            None
+        } else {
+            Some(attachments)
+        },
+        model: None,
+        timestamp,
+        token_count: None,
+        id: None,
+        parent_id: None,
+        branch_index: None,
+    }
+}
+
+/// Resolves file names listed next to an activity record, preferring the
+/// record's own folder and falling back to a base-name match anywhere.
+fn attach_archive_files(
+    db: &Database,
+    archive: &mut ExportArchive,
+    activity_path: &str,
+    file_names: &[String],
+) -> AppResult<Vec<NormalizedAttachment>> {
+    let folder = activity_path
+        .rsplit_once('/')
+        .map(|(folder, _)| folder)
+        .unwrap_or("");
+
+    let mut attachments = Vec::new();
+    for file_name in file_names {
+        let name = decode_href(file_name);
+        let sibling = format!("{folder}/{name}");
+        let bytes = match archive.read_path(&sibling)? {
+            Some(bytes) => Some(bytes),
+            None => archive.read(base_name(&name))?,
+        };
+        let Some(bytes) = bytes else {
+            continue;
+        };
+        let url = export_attachment_url("gemini", "takeout", base_name(&name));
+        attachments.push(cached_attachment(db, &bytes, url, None)?);
+    }
+    Ok(attachments)
+}
+
+fn strip_prompted_prefix(text: &str) -> String {
+    let trimmed = text.trim();
+    trimmed
+        .strip_prefix("Prompted")
+        .map(str::trim_start)
+        .unwrap_or(trimmed)
+        .to_string()
+}
+
+fn title_from_prompt(prompt: &str) -> String {
+    let first_line = prompt.lines().next().unwrap_or("").trim();
+    let mut title: String = first_line.chars().take(TITLE_MAX_CHARS).collect();
+    if first_line.chars().count() > TITLE_MAX_CHARS {
+        title.push('…');
+    }
+    title
+}
+
+fn parse_rfc3339(raw: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(raw.trim())
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// HTML exports print local times like `May 1, 2024, 10:00:00 AM PDT`. The zone
+/// abbreviation is ambiguous, so it is dropped and the time read as UTC; the
+/// result is only used for ordering and session grouping.
+fn parse_activity_time(line: &str) -> Option<DateTime<Utc>> {
+    let cleaned = line.replace(['\u{202f}', '\u{a0}'], " ");
+    let cleaned = cleaned.trim();
+    let without_zone = match cleaned.rsplit_once(' ') {
+        Some((rest, zone))
+            if zone.chars().all(|ch| {
+                ch.is_ascii_alphabetic() || matches!(ch, '+' | '-' | ':') || ch.is_ascii_digit()
+            }) && zone.chars().any(|ch| ch.is_ascii_alphabetic())
+                && !zone.eq_ignore_ascii_case("AM")
+                && !zone.eq_ignore_ascii_case("PM") =>
+        {
+            rest
+        }
+        _ => cleaned,
+    };
+
+    [
+        "%b %d, %Y, %I:%M:%S %p",
+        "%d %b %Y, %H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(without_zone, format).ok())
+    .map(|time| time.and_utc())
+}
+
+fn split_br(html: &str) -> Vec<&str> {
+    let lower = html.to_ascii_lowercase();
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut search = 0;
+    while let Some(offset) = lower[search..].find("<br") {
+        let tag_start = search + offset;
+        let Some(tag_len) = lower[tag_start..].find('>') else {
+            break;
+        };
+        segments.push(&html[start..tag_start]);
+        start = tag_start + tag_len + 1;
+        search = start;
+    }
+    segments.push(&html[start..]);
+    segments
+        .into_iter()
+        .filter(|segment| !segment.trim().is_empty())
+        .collect()
+}
+
+/// `href` values without a scheme: Takeout's links to files saved alongside.
+fn relative_hrefs(html: &str) -> Vec<String> {
+    let mut hrefs = Vec::new();
+    let mut rest = html;
+    while let Some(pos) = rest.find("href=\"") {
+        rest = &rest[pos + 6..];
+        let Some(end) = rest.find('"') else {
+            break;
+        };
+        let href = &rest[..end];
+        if !href.is_empty() && !href.contains("://") && !href.starts_with('#') {
+            hrefs.push(href.to_string());
+        }
+        rest = &rest[end..];
+    }
+    hrefs
+}
+
+fn decode_href(href: &str) -> String {
+    let bytes = href.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        // Works on bytes: a `%` followed by a multi-byte character must not be
+        // sliced as a `&str`.
+        if bytes[index] == b'%'
+            && index + 3 <= bytes.len()
+            && bytes[index + 1].is_ascii_hexdigit()
+            && bytes[index + 2].is_ascii_hexdigit()
+        {
+            out.push((hex_value(bytes[index + 1]) << 4) | hex_value(bytes[index + 2]));
+            index += 3;
+            continue;
+        }
+        out.push(bytes[index]);
+        index += 1;
+    }
+    decode_entities(&String::from_utf8_lossy(&out))
+}
+
+fn hex_value(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semi) = rest.find(';').filter(|semi| *semi <= 10) else {
+            out.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..semi];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => entity
+                .strip_prefix("#x")
+                .or_else(|| entity.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(ch) => {
+                out.push(ch);
+                rest = &rest[semi + 1..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Small HTML → Markdown pass for the reply HTML Takeout embeds: paragraphs,
+/// line breaks, lists, headings, code, emphasis and links. Unknown tags are
+/// dropped and their text kept.
+fn html_to_markdown(html: &str) -> String {
+    let mut out = String::new();
+    let mut in_pre = 0_usize;
+    let mut link_href: Vec<Option<String>> = Vec::new();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(lt) = rest.find('<') else {
+            push_text(&mut out, rest, in_pre > 0);
+            break;
+        };
+        push_text(&mut out, &rest[..lt], in_pre > 0);
+        rest = &rest[lt..];
+        let Some(gt) = rest.find('>') else {
+            push_text(&mut out, rest, in_pre > 0);
+            break;
+        };
+        let tag = &rest[1..gt];
+        rest = &rest[gt + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|ch| ch.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        match (name.as_str(), closing) {
+            ("br", _) => out.push('\n'),
+            ("p" | "div" | "ul" | "ol" | "table" | "blockquote", _) => out.push_str("\n\n"),
+            ("tr", true) => out.push('\n'),
+            ("td" | "th", true) => out.push_str(" | "),
+            ("li", false) => out.push_str("\n- "),
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", false) => {
+                let level = name[1..].parse::<usize>().unwrap_or(1);
+                out.push_str("\n\n");
+                out.push_str(&"#".repeat(level));
+                out.push(' ');
+            }
+            ("h1" | "h2" | "h3" | "h4" | "h5" | "h6", true) => out.push_str("\n\n"),
+            ("pre", false) => {
+                in_pre += 1;
+                out.push_str("\n\n```\n");
+            }
+            ("pre", true) => {
+                in_pre = in_pre.saturating_sub(1);
+                out.push_str("\n```\n\n");
+            }
+            ("code", _) if in_pre == 0 => out.push('`'),
+            ("strong" | "b", _) => out.push_str("**"),
+            ("em" | "i", _) => out.push('*'),
+            ("a", false) => {
+                let href = tag
+                    .split("href=\"")
+                    .nth(1)
+                    .and_then(|value| value.split('"').next())
+                    .map(decode_entities);
+                if href.is_some() {
+                    out.push('[');
+                }
+                link_href.push(href);
+            }
+            ("a", true) => {
+                if let Some(Some(href)) = link_href.pop() {
+                    out.push_str(&format!("]({href})"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    collapse_blank_lines(&out)
+}
+
+fn push_text(out: &mut String, raw: &str, preformatted: bool) {
+    let text = decode_entities(raw);
+    if preformatted {
+        out.push_str(&text);
+        return;
+    }
+    let mut last_space = out.ends_with([' ', '\n']) || out.is_empty();
+    for ch in text.chars() {
+        if ch.is_whitespace() {
+            if !last_space {
+                out.push(' ');
+                last_space = true;
+            }
+        } else {
+            out.push(ch);
+            last_space = false;
+        }
+    }
+}
+
+/// Keeps at most one blank line in a row; other lines, and their indentation
+/// inside `<pre>`, are left alone.
+fn collapse_blank_lines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut blank_run = 0;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_support::TempDatabase;
+    use std::io::Write;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    /// A database and a Takeout zip holding `files` in its data dir.
+    fn open_export(files: &[(&str, &[u8])]) -> (TempDatabase, ExportArchive) {
+        let db = TempDatabase::new();
+        let zip_path = db.dir().join("takeout.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for (name, bytes) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(bytes).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let archive = ExportArchive::open(zip_path.to_str().unwrap()).unwrap();
+        (db, archive)
+    }
+
+    fn record(time: &str, prompt: &str, response: &str) -> ActivityRecord {
+        ActivityRecord {
+            time: parse_rfc3339(time).unwrap(),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            attachments: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn converts_reply_html_to_markdown() {
+        assert_eq!(
+            html_to_markdown(
+                "<h2>标题</h2><p>一段 <strong>重点</strong> 和 <em>强调</em>，\
+                 见 <a href=\"https://example.com/?a=1&amp;b=2\">链接</a>。</p>\
+                 <ul><li>第一项</li><li>第二项</li></ul>\
+                 <p>调用 <code>run()</code><br>换行</p>"
+            ),
+            "## 标题\n\n一段 **重点** 和 *强调*，见 [链接](https://example.com/?a=1&b=2)。\n\n\
+             - 第一项\n- 第二项\n\n调用 `run()`\n换行"
+        );
+    }
+
+    #[test]
+    fn keeps_indentation_inside_pre() {
+        assert_eq!(
+            html_to_markdown(
+                "<p>示例：</p><pre><code>fn main() {\n    if ok {\n        run();\n    }\n}</code></pre>"
+            ),
+            "示例：\n\n```\nfn main() {\n    if ok {\n        run();\n    }\n}\n```"
+        );
+    }
+
+    #[test]
+    fn collapses_only_runs_of_blank_lines() {
+        assert_eq!(
+            collapse_blank_lines("a\n\n\n\n  b  \n   \n\n    c"),
+            "a\n\n  b\n\n    c"
+        );
+    }
+
+    #[test]
+    fn parses_each_activity_time_format() {
+        let expected = parse_rfc3339("2024-05-01T15:04:05Z");
+        assert_eq!(parse_activity_time("May 1, 2024, 3:04:05 PM PDT"), expected);
+        assert_eq!(
+            parse_activity_time("May 1, 2024, 3:04:05\u{202f}PM GMT+08:00"),
+            expected
+        );
+        assert_eq!(parse_activity_time("May 1, 2024, 3:04:05 PM"), expected);
+        assert_eq!(parse_activity_time("1 May 2024, 15:04:05 CEST"), expected);
+        assert_eq!(parse_activity_time("2024-05-01 15:04:05"), expected);
+        assert_eq!(parse_activity_time("Prompted 天气如何"), None);
+        assert_eq!(parse_activity_time(""), None);
+    }
+
+    #[test]
+    fn parses_json_activity() {
+        let folder = "Takeout/My Activity/Gemini Apps";
+        let (db, mut archive) = open_export(&[(&format!("{folder}/photo.png"), PNG)]);
+        let text = r#"[
+            {
+                "title": "Prompted 这张图里是什么？",
+                "time": "2024-05-01T10:00:00.123Z",
+                "safeHtmlItem": [{ "html": "<p>一只猫。</p>" }, { "html": "<p>在睡觉。</p>" }],
+                "attachedFiles": ["photo.png", "missing.png"]
+            },
+            { "title": "Prompted 没有时间", "safeHtmlItem": [] },
+            { "title": "Prompted", "time": "2024-05-01T10:05:00Z" }
+        ]"#;
+
+        let records = parse_activity_json(
+            &db,
+            &mut archive,
+            &format!("{folder}/MyActivity.json"),
+            text,
+        )
+        .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].time,
+            parse_rfc3339("2024-05-01T10:00:00.123Z").unwrap()
+        );
+        assert_eq!(records[0].prompt, "这张图里是什么？");
+        assert_eq!(records[0].response, "一只猫。\n\n在睡觉。");
+        assert_eq!(records[0].attachments.len(), 1);
+        assert_eq!(
+            records[0].attachments[0].original_url,
+            "aihistory://export/gemini/takeout/photo.png"
+        );
+
+        assert!(parse_activity_json(&db, &mut archive, "MyActivity.json", "{").is_err());
+    }
+
+    #[test]
+    fn parses_html_activity() {
+        let folder = "Takeout/My Activity/Gemini Apps";
+        let (db, mut archive) = open_export(&[(&format!("{folder}/图片 一.png"), PNG)]);
+        let html = r#"<div class="outer-cell mdl-cell"><div class="header-cell">Gemini Apps</div>
+            <div class="content-cell mdl-cell">Prompted 比较这两种做法<br>
+            Attached 1 file.<br><a href="%E5%9B%BE%E7%89%87%20%E4%B8%80.png">图片 一.png</a><br>
+            May 1, 2024, 10:00:00 AM PDT<br><p>第一种更快。</p><br>但第二种更稳。</div></div>
+            <div class="outer-cell mdl-cell"><div class="content-cell mdl-cell">没有时间的记录</div></div>
+            <div class="outer-cell mdl-cell"><div class="content-cell mdl-cell">Prompted 继续<br>
+            May 1, 2024, 10:20:00 AM PDT<br><ul><li>好的</li></ul></div></div>"#;
+
+        let records = parse_activity_html(
+            &db,
+            &mut archive,
+            &format!("{folder}/MyActivity.html"),
+            html,
+        )
+        .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].time,
+            parse_rfc3339("2024-05-01T10:00:00Z").unwrap()
+        );
+        assert_eq!(records[0].prompt, "比较这两种做法");
+        assert_eq!(records[0].response, "第一种更快。\n\n但第二种更稳。");
+        assert_eq!(records[0].attachments.len(), 1);
+        assert_eq!(records[1].prompt, "继续");
+        assert_eq!(records[1].response, "- 好的");
+        assert!(records[1].attachments.is_empty());
+    }
+
+    #[test]
+    fn groups_records_less_than_the_gap_apart() {
+        let conversations = group_sessions(vec![
+            record("2024-05-01T11:01:00Z", "", "隔了半小时以上"),
+            record("2024-05-01T10:00:00Z", "第一个问题", "第一个回答"),
+            record("2024-05-01T10:30:00Z", "追问", ""),
+        ]);
+        assert_eq!(conversations.len(), 2);
+
+        let first = &conversations[0];
+        assert_eq!(
+            first.source_conversation_id.as_deref(),
+            Some("takeout:gemini:2024-05-01T10:00:00+00:00")
+        );
+        assert_eq!(first.title, "第一个问题");
+        assert_eq!(
+            first.updated_at.as_deref(),
+            Some("2024-05-01T10:30:00+00:00")
+        );
+        let turns: Vec<(&str, &str)> = first
+            .turns
+            .iter()
+            .map(|turn| (turn.role.as_str(), turn.content_markdown.as_str()))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                ("user", "第一个问题"),
+                ("assistant", "第一个回答"),
+                ("user", "追问"),
+            ]
+        );
+
+        assert_eq!(conversations[1].title, "Gemini");
+        assert_eq!(conversations[1].turns.len(), 2);
+    }
+
+    #[test]
+    fn parses_studio_prompt() {
+        let (db, _) = open_export(&[]);
+        let image = BASE64_STANDARD.encode(PNG);
+        let prompt = serde_json::json!({
+            "runSettings": { "model": "models/gemini-1.5-pro" },
+            "systemInstruction": { "parts": [{ "text": "用中文回答" }] },
+            "chunkedPrompt": { "chunks": [
+                { "role": "user", "inlineImage": { "mimeType": "image/png", "data": image } },
+                { "role": "user", "text": "描述这张图" },
+                { "role": "model", "text": "先看构图……", "isThought": true },
+                { "role": "model", "text": "一张流程图。", "tokenCount": 12 },
+                { "role": "user", "text": "   " },
+            ]},
+        });
+        let path = "Takeout/Drive/Google AI Studio/流程图";
+
+        let conv = parse_studio_prompt(
+            &db,
+            path,
+            prompt.to_string().as_bytes(),
+            Some("2024-05-01T10:00:00Z".to_string()),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(conv.source, "ai_studio");
+        assert_eq!(
+            conv.source_conversation_id.as_deref(),
+            Some("takeout:ai_studio:Takeout/Drive/Google AI Studio/流程图")
+        );
+        assert_eq!(conv.title, "流程图");
+        assert_eq!(conv.updated_at.as_deref(), Some("2024-05-01T10:00:00Z"));
+
+        let turns: Vec<(&str, &str)> = conv
+            .turns
+            .iter()
+            .map(|turn| (turn.role.as_str(), turn.content_markdown.as_str()))
+            .collect();
+        assert_eq!(
+            turns,
+            [
+                ("system", "用中文回答"),
+                ("user", "描述这张图"),
+                ("assistant", "一张流程图。"),
+            ]
+        );
+        assert_eq!(conv.turns[1].attachments.as_ref().map(Vec::len), Some(1));
+        assert_eq!(conv.turns[2].model.as_deref(), Some("gemini-1.5-pro"));
+        assert_eq!(
+            conv.turns[2].thought_markdown.as_deref(),
+            Some("先看构图……")
+        );
+        assert_eq!(conv.turns[2].token_count, Some(12));
+
+        assert!(parse_studio_prompt(&db, path, PNG, None).unwrap().is_none());
+        let system_only = serde_json::json!({
+            "systemInstruction": "只有系统提示",
+            "chunkedPrompt": { "chunks": [] },
+        });
+        assert!(
+            parse_studio_prompt(&db, path, system_only.to_string().as_bytes(), None)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn decodes_escapes_around_non_ascii_text() {
+        assert_eq!(decode_href("%é1.png"), "%é1.png");
+        assert_eq!(decode_href("图片%20一.png"), "图片 一.png");
+        assert_eq!(decode_href("%E5%9B%BE.png"), "图.png");
+    }
+
+    #[test]
+    fn decodes_a_trailing_escape() {
+        assert_eq!(decode_href("report%2Epdf%21"), "report.pdf!");
+        assert_eq!(decode_href("a%2"), "a%2");
+        assert_eq!(decode_href("a%zz"), "a%zz");
+    }
+
+    #[test]
+    fn decodes_entities_after_escapes() {
+        assert_eq!(decode_href("Q%26A&amp;notes.txt"), "Q&A&notes.txt");
+    }
+}
@@ -2,7 +2,9 @@
 
 mod commands;
 mod db;
+mod error;
 mod http;
+mod importers;
 mod models;
 
 use std::path::PathBuf;
@@ -31,7 +33,22 @@ fn main() {
     tauri::Builder::default()
         .setup(|app| {
             let db_path = build_db_path(app.handle())?;
-            let db = Database::new(db_path).map_err(std::io::Error::other)?;
+            let mut db = Database::new(db_path).map_err(std::io::Error::other)?;
+            match app.path().resource_dir() {
+                Ok(dir) => db = db.with_embedding_model_dir(dir.join("embedding-model")),
+                Err(err) => eprintln!("failed to resolve resource dir: {err}"),
+            }
+
+            let purge_db = db.clone();
+            tauri::async_runtime::spawn_blocking(move || {
+                if let Err(err) = purge_db.purge_expired_trash() {
+                    eprintln!("trash auto-purge failed: {err}");
+                }
+            });
+
+            if let Err(err) = db.start_download_worker() {
+                eprintln!("attachment download worker failed to start: {err}");
+            }
 
             let app_state = AppState { db: db.clone() };
             app.manage(app_state);
@@ -50,15 +67,60 @@ fn main() {
         .invoke_handler(tauri::generate_handler![
             commands::list_folders,
             commands::create_folder,
+            commands::rename_folder,
+            commands::reorder_folders,
             commands::move_folder,
             commands::delete_folder,
+            commands::create_smart_folder,
+            commands::update_smart_folder,
+            commands::delete_smart_folder,
+            commands::list_tags,
+            commands::create_tag,
+            commands::rename_tag,
+            commands::set_tag_color,
+            commands::merge_tags,
+            commands::delete_tag,
+            commands::tag_conversations,
+            commands::untag_conversations,
+            commands::list_import_rules,
+            commands::create_import_rule,
+            commands::update_import_rule,
+            commands::delete_import_rule,
+            commands::reorder_import_rules,
+            commands::apply_import_rules,
             commands::move_conversation,
             commands::list_conversations,
+            commands::conversation_counts,
+            commands::trash_conversation,
+            commands::restore_conversation,
+            commands::list_trash,
+            commands::purge_trash,
             commands::open_conversation,
+            commands::open_conversation_branch,
+            commands::list_message_branches,
+            commands::list_conversation_revisions,
+            commands::open_conversation_revision,
+            commands::diff_conversation_revisions,
             commands::list_conversation_attachments,
+            commands::download_queue_status,
+            commands::pause_downloads,
+            commands::resume_downloads,
+            commands::retry_all_downloads,
+            commands::collect_asset_garbage,
+            commands::storage_report,
+            commands::verify_assets,
+            commands::last_asset_verify_report,
             commands::import_files,
+            commands::import_chatgpt_export,
+            commands::import_google_takeout,
             commands::import_live_capture,
             commands::search_conversations,
+            commands::rebuild_search_index,
+            commands::update_semantic_index,
+            commands::clear_semantic_index,
+            commands::semantic_search,
+            commands::get_settings,
+            commands::update_settings,
             commands::export_backup_zip,
             commands::fetch_url_html,
             commands::start_bridge_session,
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -9,6 +10,50 @@ pub struct Folder {
     pub sort_order: i64,
     pub created_at: String,
     pub updated_at: String,
+    /// Live conversations directly in the folder, or matching a smart
+    /// folder's filter.
+    #[serde(default)]
+    pub conversation_count: i64,
+    /// Set only for smart folders, whose contents are whatever matches it.
+    #[serde(default)]
+    pub filter: Option<SmartFolderFilter>,
+}
+
+/// Saved query behind a smart folder. Every criterion that is set must hold.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SmartFolderFilter {
+    pub sources: Vec<String>,
+    pub tag_ids: Vec<String>,
+    /// `any` (default) or `all` of `tag_ids`.
+    pub tag_match: Option<String>,
+    /// Bounds on the last activity, as `YYYY-MM-DD` or RFC 3339, like the
+    /// `after:`/`before:` search filters.
+    pub after: Option<String>,
+    pub before: Option<String>,
+    /// Active within this many days of now; keeps rolling windows current.
+    pub within_days: Option<i64>,
+    /// Search query in the syntax of `search_conversations`.
+    pub query: Option<String>,
+    pub has_attachments: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SmartFolderInput {
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub filter: SmartFolderFilter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tag {
+    pub id: String,
+    pub name: String,
+    pub color: Option<String>,
+    /// Live (not trashed) conversations carrying the tag.
+    pub conversation_count: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +69,9 @@ pub struct Conversation {
     pub updated_at: String,
     pub fingerprint: String,
     pub meta_json: String,
+    pub deleted_at: Option<String>,
+    pub imported_at: Option<String>,
+    pub captured_at: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -38,6 +86,16 @@ pub struct Message {
     pub model: Option<String>,
     pub timestamp: Option<String>,
     pub token_count: Option<i64>,
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub branch_index: i64,
+    #[serde(default = "default_true")]
+    pub on_active_path: bool,
+}
+
+fn default_true() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,11 +128,58 @@ pub struct ConversationSummary {
 pub struct ConversationDetail {
     #[serde(flatten)]
     pub conversation: Conversation,
+    /// The active path, root first; what a linear transcript view renders.
     pub messages: Vec<Message>,
+    /// Every stored message including other branches, ordered by `seq`.
+    pub tree: Vec<Message>,
     pub tags: Vec<String>,
     pub attachments: Vec<Attachment>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationRevision {
+    pub id: String,
+    pub conversation_id: String,
+    pub revision: i64,
+    pub title: String,
+    /// Every message in the snapshot, branches included.
+    pub message_count: i64,
+    pub source_updated_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationRevisionDetail {
+    #[serde(flatten)]
+    pub revision: ConversationRevision,
+    /// The whole tree in `seq` order; `on_active_path` marks the path that
+    /// was shown.
+    pub messages: Vec<Message>,
+}
+
+/// One changed turn; `kind` is `added`, `removed` or `edited`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageChange {
+    pub kind: String,
+    pub old: Option<Message>,
+    pub new: Option<Message>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevisionDiff {
+    pub conversation_id: String,
+    pub from_revision: i64,
+    pub to_revision: Option<i64>,
+    pub added: i64,
+    pub removed: i64,
+    pub edited: i64,
+    pub changes: Vec<MessageChange>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NormalizedAttachment {
@@ -82,6 +187,14 @@ pub struct NormalizedAttachment {
     pub original_url: String,
     pub mime: Option<String>,
     pub status: Option<String>,
+    /// Set only by in-process importers that already wrote the file into the
+    /// assets dir; never accepted from the webview or the bridge.
+    #[serde(skip)]
+    pub local_path: Option<String>,
+    #[serde(skip)]
+    pub sha256: Option<String>,
+    #[serde(skip)]
+    pub size_bytes: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,6 +207,14 @@ pub struct NormalizedTurn {
     pub model: Option<String>,
     pub timestamp: Option<String>,
     pub token_count: Option<i64>,
+    /// Source-side node id; only needed when turns form a tree.
+    #[serde(default)]
+    pub id: Option<String>,
+    /// Source-side id of the parent turn, which must appear earlier in `turns`.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub branch_index: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -105,8 +226,16 @@ pub struct NormalizedConversation {
     pub summary: Option<String>,
     pub created_at: Option<String>,
     pub updated_at: Option<String>,
+    #[serde(default)]
+    pub captured_at: Option<String>,
+    /// Leaf of the active branch; defaults to the last turn.
+    #[serde(default)]
+    pub current_turn_id: Option<String>,
     pub turns: Vec<NormalizedTurn>,
     pub meta: Option<serde_json::Value>,
+    /// Tag names to attach; missing tags are created.
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -117,7 +246,7 @@ pub struct ImportBatch {
     pub folder_id: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ImportResult {
     pub imported: i64,
@@ -125,11 +254,110 @@ pub struct ImportResult {
     pub conflicts: i64,
 }
 
+/// A vendor export archive on disk, imported natively instead of through the
+/// webview parsers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveImportInput {
+    pub path: String,
+    pub strategy: String,
+    pub folder_id: Option<String>,
+}
+
+/// Payload of the `import-progress` event. Byte counts refer to the
+/// uncompressed entry being streamed, so `total_bytes` is known up front even
+/// when the number of conversations is not.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
+pub struct ImportProgress {
+    pub source: String,
+    pub processed: i64,
+    pub imported: i64,
+    pub skipped: i64,
+    pub conflicts: i64,
+    pub bytes_read: u64,
+    pub total_bytes: u64,
+    pub done: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct ListConversationsInput {
     pub folder_id: Option<String>,
     pub source: Option<String>,
+    /// Only conversations carrying these tags.
+    #[serde(default)]
+    pub tag_ids: Option<Vec<String>>,
+    /// `any` (default) or `all` of `tag_ids`.
+    #[serde(default)]
+    pub tag_match: Option<String>,
+    /// `created` (default), `updated`, `title`, `messageCount` or `captured`.
+    #[serde(default)]
+    pub sort: Option<String>,
+    /// `asc` or `desc`; titles default to ascending, other keys to descending.
+    #[serde(default)]
+    pub direction: Option<String>,
+    /// `nextCursor` of the previous page, with the same sort and direction.
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Page size, capped at 200; omitted returns every match.
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationPage {
+    pub items: Vec<ConversationSummary>,
+    /// Matches across all pages.
+    pub total: i64,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationCounts {
+    pub total: i64,
+    /// Keyed by folder id; conversations without one count as uncategorized.
+    pub by_folder: BTreeMap<String, i64>,
+    pub by_source: BTreeMap<String, i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchInput {
+    pub query: String,
+    #[serde(default)]
+    pub offset: i64,
+    #[serde(default)]
+    pub limit: Option<i64>,
+}
+
+/// `[start, end)` in UTF-16 code units, so the webview can slice strings
+/// with it directly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextRange {
+    pub start: i64,
+    pub end: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub message_id: String,
+    pub seq: i64,
+    pub role: String,
+    /// Which text the snippet and highlights are taken from: `content`,
+    /// `thoughts` or `attachments` (the message's attachment file names).
+    pub field: String,
+    pub snippet: String,
+    /// Matches within `field`.
+    pub highlights: Vec<TextRange>,
+    pub score: f64,
+    /// False for a message on another branch, which the UI has to open with
+    /// `open_conversation_branch`.
+    pub on_active_path: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -137,6 +365,21 @@ pub struct ListConversationsInput {
 pub struct SearchResult {
     pub conversation: ConversationSummary,
     pub snippet: String,
+    /// Higher is more relevant.
+    pub score: f64,
+    /// The conversation's title or summary matched.
+    pub title_match: bool,
+    /// Every matching message, in conversation order.
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    pub total: i64,
+    pub offset: i64,
+    pub next_offset: Option<i64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -148,6 +391,8 @@ pub struct LiveCaptureRequest {
     pub turns: Vec<NormalizedTurn>,
     pub captured_at: String,
     pub version: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -156,3 +401,286 @@ pub struct SessionResponse {
     pub token: String,
     pub expires_at: String,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AppSettings {
+    /// Days a trashed conversation is kept before it is purged on startup; 0 keeps it forever.
+    pub trash_retention_days: i64,
+    pub semantic_search: SemanticSearchSettings,
+    pub attachments: AttachmentSettings,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            trash_retention_days: 30,
+            semantic_search: SemanticSearchSettings::default(),
+            attachments: AttachmentSettings::default(),
+        }
+    }
+}
+
+/// Limits on what the download queue caches; 0 means no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AttachmentSettings {
+    /// Largest single attachment, in MiB.
+    pub max_file_size_mb: i64,
+    /// Total size of the assets dir the queue will fill up to, in MiB.
+    pub max_total_size_mb: i64,
+}
+
+impl Default for AttachmentSettings {
+    fn default() -> Self {
+        Self {
+            max_file_size_mb: 100,
+            max_total_size_mb: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SemanticSearchSettings {
+    /// Off by default: building the index embeds every message.
+    pub enabled: bool,
+    /// `bundled` (the model shipped with the app, run on the CPU) or
+    /// `openai` (any OpenAI-compatible `/embeddings` endpoint, e.g. Ollama).
+    pub provider: String,
+    /// Base URL for the `openai` provider, up to but excluding `/embeddings`.
+    pub endpoint: String,
+    pub model: String,
+    /// Redacted when settings are read; see `Database::get_settings`.
+    pub api_key: Option<String>,
+}
+
+impl Default for SemanticSearchSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: "bundled".to_string(),
+            endpoint: "http://localhost:11434/v1".to_string(),
+            model: "nomic-embed-text".to_string(),
+            api_key: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticSearchInput {
+    pub query: String,
+    #[serde(default)]
+    pub offset: i64,
+    pub limit: Option<i64>,
+    /// Rank by embeddings alone instead of fusing with keyword search.
+    #[serde(default)]
+    pub semantic_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticIndexProgress {
+    pub processed: i64,
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticIndexReport {
+    /// Provider and model the index was built with.
+    pub model: String,
+    pub embedded_messages: i64,
+    pub embedded_chunks: i64,
+    /// Messages whose embeddings were already current.
+    pub unchanged_messages: i64,
+    /// Chunks dropped because they came from a different model.
+    pub removed_chunks: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeResult {
+    pub purged: i64,
+    pub removed_files: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AssetGcInput {
+    /// Report what would be removed without removing anything.
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetGcReport {
+    pub dry_run: bool,
+    /// Attachment rows whose message or conversation no longer exists.
+    pub orphan_rows: i64,
+    /// Files in the assets dir that no attachment row points at.
+    pub orphan_files: Vec<OrphanAsset>,
+    pub freed_bytes: i64,
+    pub removed_rows: i64,
+    pub removed_files: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrphanAsset {
+    pub path: String,
+    pub size_bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageReport {
+    /// Distinct cached files and their combined size.
+    pub file_count: i64,
+    pub total_bytes: i64,
+    pub by_source: Vec<StorageBucket>,
+    /// Keyed by folder id; trashed conversations are grouped under `trash`.
+    pub by_folder: Vec<StorageBucket>,
+    pub by_kind: Vec<StorageBucket>,
+    /// Unreferenced files a garbage collection would remove.
+    pub orphan_files: i64,
+    pub orphan_bytes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageBucket {
+    pub key: String,
+    pub files: i64,
+    pub bytes: i64,
+    pub attachments: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct VerifyAssetsInput {
+    /// Restore or re-download bad files instead of only reporting them.
+    pub repair: bool,
+    /// Archive to restore from; defaults to the backups in the data dir.
+    pub backup_path: Option<String>,
+}
+
+/// Counts are per attachment; several attachments can share one file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetVerifyReport {
+    pub checked_at: String,
+    pub repair: bool,
+    pub checked: i64,
+    pub healthy: i64,
+    pub missing: i64,
+    /// Hash mismatches and HTML pages cached in place of the attachment.
+    pub corrupt: i64,
+    pub restored: i64,
+    pub requeued: i64,
+    pub unrecoverable: i64,
+    pub issues: Vec<AssetIssue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AssetIssue {
+    pub attachment_id: String,
+    pub conversation_id: String,
+    pub original_url: String,
+    pub local_path: String,
+    /// `missing`, `corrupt` or `html`.
+    pub problem: String,
+    /// `restored`, `requeued` or `unrecoverable`; unset when not repairing.
+    pub resolution: Option<String>,
+}
+
+/// Evaluated by every import that does not name a folder. All conditions that
+/// are set must match; a list condition matches when any of its entries does.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRule {
+    pub id: String,
+    pub name: String,
+    pub enabled: bool,
+    /// Rules run in ascending order; the first matching rule with a folder wins.
+    pub position: i64,
+    pub conditions: ImportRuleConditions,
+    pub folder_id: Option<String>,
+    pub tags: Vec<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ImportRuleConditions {
+    pub sources: Vec<String>,
+    /// Case-insensitive regular expression searched for in the title.
+    pub title_pattern: Option<String>,
+    /// Case-insensitive substrings of any message.
+    pub content_keywords: Vec<String>,
+    /// Case-insensitive substrings of any turn's model name.
+    pub models: Vec<String>,
+    /// Page URL hosts; `chatgpt.com` also matches its subdomains.
+    pub url_hosts: Vec<String>,
+    /// `image`, `pdf` or `file`.
+    pub attachment_kinds: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRuleInput {
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub conditions: ImportRuleConditions,
+    pub folder_id: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ApplyImportRulesInput {
+    /// Report what would change without writing anything.
+    pub dry_run: bool,
+    /// Also move conversations already filed outside the uncategorized folder.
+    pub include_filed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRuleChange {
+    pub conversation_id: String,
+    pub title: String,
+    pub from_folder_id: Option<String>,
+    /// Set only when the conversation moves.
+    pub to_folder_id: Option<String>,
+    /// Tags the conversation does not carry yet.
+    pub added_tags: Vec<String>,
+    pub rule_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApplyImportRulesReport {
+    pub dry_run: bool,
+    pub scanned: i64,
+    pub moved: i64,
+    pub tagged: i64,
+    pub changes: Vec<ImportRuleChange>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadQueueStatus {
+    pub paused: bool,
+    pub queued: i64,
+    pub running: i64,
+    /// Out of attempts; waiting for `retry_all_downloads`.
+    pub failed: i64,
+    /// When the earliest queued download is due.
+    pub next_attempt_at: Option<String>,
+}
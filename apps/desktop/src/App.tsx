@@ -37,10 +37,10 @@ export function App() {
 
   const listData = useMemo(() => {
     if (searchQuery.trim() && search.data) {
-      return search.data.map((entry) => entry.conversation);
+      return search.data.results.map((entry) => entry.conversation);
     }
 
-    return conversations.data ?? [];
+    return conversations.data?.items ?? [];
   }, [searchQuery, search.data, conversations.data]);
 
   React.useEffect(() => {
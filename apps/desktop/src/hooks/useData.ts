@@ -111,7 +111,7 @@ export function useImportFiles() {
 export function useSearch(query: string) {
   return useQuery({
     queryKey: ["search", query],
-    queryFn: () => api.searchConversations(query),
+    queryFn: () => api.searchConversations({ query }),
     enabled: query.trim().length > 0
   });
 }
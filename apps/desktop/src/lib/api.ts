@@ -1,12 +1,14 @@
 import type { AttachmentRef, Folder, LiveCaptureRequest } from "@ai-history/core-types";
 import { parseImportPayload } from "@ai-history/parsers";
 import type {
+  ConversationCounts,
   ConversationDetail,
-  ConversationSummary,
+  ConversationPage,
   ImportBatch,
   ImportResult,
   ListConversationsInput,
-  SearchResult,
+  SearchInput,
+  SearchPage,
   UrlImportInput
 } from "./types";
 import { isTauri, invokeSafe } from "./tauri";
@@ -48,12 +50,19 @@ export const api = {
 
     return invokeSafe<void>("move_conversation", { id, folderId });
   },
-  listConversations: async (input?: ListConversationsInput): Promise<ConversationSummary[]> => {
+  listConversations: async (input?: ListConversationsInput): Promise<ConversationPage> => {
     if (!isTauri) {
       return mockApi.listConversations(input);
     }
 
-    return invokeSafe<ConversationSummary[]>("list_conversations", { input });
+    return invokeSafe<ConversationPage>("list_conversations", { input });
+  },
+  conversationCounts: async (): Promise<ConversationCounts> => {
+    if (!isTauri) {
+      return mockApi.conversationCounts();
+    }
+
+    return invokeSafe<ConversationCounts>("conversation_counts");
   },
   openConversation: async (id: string): Promise<ConversationDetail | null> => {
     if (!isTauri) {
@@ -103,12 +112,12 @@ export const api = {
       folderId: input.folderId ?? null
     });
   },
-  searchConversations: async (query: string): Promise<SearchResult[]> => {
+  searchConversations: async (input: SearchInput): Promise<SearchPage> => {
     if (!isTauri) {
-      return mockApi.searchConversations(query);
+      return mockApi.searchConversations(input);
     }
 
-    return invokeSafe<SearchResult[]>("search_conversations", { query });
+    return invokeSafe<SearchPage>("search_conversations", { input });
   },
   exportBackupZip: async (): Promise<string> => {
     if (!isTauri) {
@@ -1,10 +1,15 @@
 import type {
+  ConversationCounts,
   ConversationDetail,
+  ConversationPage,
   ConversationSummary,
   Folder,
   ImportBatch,
   ImportResult,
   ListConversationsInput,
+  SearchHit,
+  SearchInput,
+  SearchPage,
   SearchResult,
   UrlImportInput
 } from "./types";
@@ -75,10 +80,10 @@ export const mockApi = {
     conversation.folderId = folderId ?? UNCATEGORIZED_FOLDER_ID;
     conversation.updatedAt = new Date().toISOString();
   },
-  listConversations: async (input?: ListConversationsInput): Promise<ConversationSummary[]> => {
+  listConversations: async (input?: ListConversationsInput): Promise<ConversationPage> => {
     const targetFolder = input?.folderId;
     const source = input?.source;
-    return mockConversations
+    const items: ConversationSummary[] = mockConversations
       .filter((c) =>
         targetFolder
           ? targetFolder === UNCATEGORIZED_FOLDER_ID
@@ -89,6 +94,17 @@ export const mockApi = {
       .filter((c) => (source && source !== "all" ? c.source === source : true))
       .sort((a, b) => b.createdAt.localeCompare(a.createdAt))
       .map((item) => ({ ...item, messageCount: item.messages.length }));
+    return { items, total: items.length, nextCursor: null };
+  },
+  conversationCounts: async (): Promise<ConversationCounts> => {
+    const byFolder: Record<string, number> = {};
+    const bySource: Record<string, number> = {};
+    for (const item of mockConversations) {
+      const folderId = item.folderId ?? UNCATEGORIZED_FOLDER_ID;
+      byFolder[folderId] = (byFolder[folderId] ?? 0) + 1;
+      bySource[item.source] = (bySource[item.source] ?? 0) + 1;
+    }
+    return { total: mockConversations.length, byFolder, bySource };
   },
   openConversation: async (id: string): Promise<ConversationDetail | null> => {
     return mockConversations.find((item) => item.id === id) ?? null;
@@ -125,8 +141,26 @@ export const mockApi = {
       }
 
       const id = crypto.randomUUID();
-      const createdAt = new Date().toISOString();
-      const updatedAt = createdAt;
+      const messages = conv.turns.map((turn, index) => ({
+        id: crypto.randomUUID(),
+        conversationId: id,
+        seq: index,
+        role: turn.role,
+        contentMarkdown: turn.contentMarkdown,
+        thoughtMarkdown: turn.thoughtMarkdown ?? null,
+        model: turn.model ?? null,
+        timestamp: turn.timestamp ?? null,
+        tokenCount: turn.tokenCount ?? null,
+        parentId: null as string | null,
+        branchIndex: 0,
+        onActivePath: true
+      }));
+      messages.forEach((message, index) => {
+        message.parentId = index > 0 ? messages[index - 1].id : null;
+      });
+      const importedAt = new Date().toISOString();
+      const createdAt = conv.createdAt ?? conv.updatedAt ?? conv.capturedAt ?? importedAt;
+      const updatedAt = conv.updatedAt ?? conv.capturedAt ?? createdAt;
       mockConversations.unshift({
         id,
         source: conv.source,
@@ -141,19 +175,13 @@ export const mockApi = {
             ? `${incomingFingerprint}#dup-${crypto.randomUUID()}`
             : incomingFingerprint,
         metaJson: JSON.stringify(conv.meta ?? {}),
+        deletedAt: null,
+        importedAt,
+        capturedAt: conv.capturedAt ?? null,
         tags: [],
         attachments: [],
-        messages: conv.turns.map((turn, index) => ({
-          id: crypto.randomUUID(),
-          conversationId: id,
-          seq: index,
-          role: turn.role,
-          contentMarkdown: turn.contentMarkdown,
-          thoughtMarkdown: turn.thoughtMarkdown ?? null,
-          model: turn.model ?? null,
-          timestamp: turn.timestamp ?? null,
-          tokenCount: turn.tokenCount ?? null
-        }))
+        messages,
+        tree: messages
       });
       imported += 1;
     }
@@ -183,21 +211,50 @@ export const mockApi = {
       folderId: UNCATEGORIZED_FOLDER_ID
     });
   },
-  searchConversations: async (query: string): Promise<SearchResult[]> => {
-    const q = query.trim().toLowerCase();
+  searchConversations: async (input: SearchInput): Promise<SearchPage> => {
+    const q = input.query.trim().toLowerCase();
+    const offset = input.offset ?? 0;
     if (!q) {
-      return [];
+      return { results: [], total: 0, offset, nextOffset: null };
     }
 
-    return mockConversations
-      .filter((item) => item.title.toLowerCase().includes(q) || item.messages.some((m) => m.contentMarkdown.toLowerCase().includes(q)))
+    const results: SearchResult[] = mockConversations
       .map((item) => {
-        const first = item.messages.find((m) => m.contentMarkdown.toLowerCase().includes(q));
+        const hits: SearchHit[] = item.messages
+          .filter((m) => m.contentMarkdown.toLowerCase().includes(q))
+          .map((m) => {
+            const start = m.contentMarkdown.toLowerCase().indexOf(q);
+            return {
+              messageId: m.id,
+              seq: m.seq,
+              role: m.role,
+              field: "content",
+              snippet: m.contentMarkdown.slice(0, 120),
+              highlights: [{ start, end: start + q.length }],
+              score: 1,
+              onActivePath: true
+            };
+          });
+        const titleMatch = item.title.toLowerCase().includes(q);
         return {
           conversation: { ...item, messageCount: item.messages.length },
-          snippet: first ? first.contentMarkdown.slice(0, 120) : item.title
+          snippet: hits[0]?.snippet ?? item.title,
+          score: hits.length + (titleMatch ? 10 : 0),
+          titleMatch,
+          hits
         };
-      });
+      })
+      .filter((result) => result.titleMatch || result.hits.length > 0)
+      .sort((a, b) => b.score - a.score);
+
+    const limit = input.limit ?? 50;
+    const next = offset + limit;
+    return {
+      results: results.slice(offset, next),
+      total: results.length,
+      offset,
+      nextOffset: next < results.length ? next : null
+    };
   },
   exportBackupZip: async (): Promise<string> => {
     return `mock-backup-${Date.now()}.zip`;
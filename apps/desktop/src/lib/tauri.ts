@@ -2,6 +2,42 @@ import { invoke } from "@tauri-apps/api/core";
 
 export const isTauri = Boolean((window as unknown as { __TAURI_INTERNALS__?: unknown }).__TAURI_INTERNALS__);
 
+export type CommandErrorCode =
+  | "not_found"
+  | "conflict"
+  | "invalid_input"
+  | "io"
+  | "network"
+  | "schema"
+  | "locked"
+  | "database"
+  | "internal";
+
+export class CommandError extends Error {
+  readonly code: CommandErrorCode;
+
+  constructor(code: CommandErrorCode, message: string) {
+    super(message);
+    this.name = "CommandError";
+    this.code = code;
+  }
+}
+
+function toCommandError(error: unknown): Error {
+  if (error instanceof Error) {
+    return error;
+  }
+  if (error && typeof error === "object" && "code" in error) {
+    const { code, message } = error as { code: CommandErrorCode; message?: unknown };
+    return new CommandError(code, typeof message === "string" ? message : code);
+  }
+  return new CommandError("internal", String(error));
+}
+
 export async function invokeSafe<T>(command: string, args?: Record<string, unknown>): Promise<T> {
-  return invoke<T>(command, args);
+  try {
+    return await invoke<T>(command, args);
+  } catch (error) {
+    throw toCommandError(error);
+  }
 }
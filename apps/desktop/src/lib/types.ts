@@ -6,6 +6,7 @@ export interface ConversationSummary extends Conversation {
 
 export interface ConversationDetail extends Conversation {
   messages: Message[];
+  tree: Message[];
   tags: string[];
   attachments: AttachmentRef[];
 }
@@ -35,14 +36,172 @@ export interface UrlImportInput {
   folderId?: string | null;
 }
 
+export interface SearchInput {
+  query: string;
+  offset?: number;
+  limit?: number | null;
+}
+
+/** `[start, end)` in UTF-16 code units of the message's `contentMarkdown`. */
+export interface TextRange {
+  start: number;
+  end: number;
+}
+
+export interface SearchHit {
+  messageId: string;
+  seq: number;
+  role: string;
+  field: "content" | "thoughts" | "attachments";
+  snippet: string;
+  highlights: TextRange[];
+  score: number;
+  /** False for a message on another branch; open it with `openConversationBranch`. */
+  onActivePath: boolean;
+}
+
 export interface SearchResult {
   conversation: ConversationSummary;
   snippet: string;
+  score: number;
+  titleMatch: boolean;
+  hits: SearchHit[];
+}
+
+export interface SearchPage {
+  results: SearchResult[];
+  total: number;
+  offset: number;
+  nextOffset: number | null;
 }
 
 export interface ListConversationsInput {
   folderId?: string | null;
   source?: Conversation["source"] | "all";
+  tagIds?: string[];
+  tagMatch?: "any" | "all";
+  sort?: "created" | "updated" | "title" | "messageCount" | "captured";
+  direction?: "asc" | "desc";
+  cursor?: string | null;
+  limit?: number;
+}
+
+export interface ConversationPage {
+  items: ConversationSummary[];
+  total: number;
+  nextCursor: string | null;
+}
+
+export interface ConversationCounts {
+  total: number;
+  byFolder: Record<string, number>;
+  bySource: Record<string, number>;
+}
+
+export interface ImportRuleConditions {
+  sources?: string[];
+  titlePattern?: string | null;
+  contentKeywords?: string[];
+  models?: string[];
+  urlHosts?: string[];
+  attachmentKinds?: Array<"image" | "pdf" | "file">;
+}
+
+export interface ImportRule {
+  id: string;
+  name: string;
+  enabled: boolean;
+  position: number;
+  conditions: ImportRuleConditions;
+  folderId: string | null;
+  tags: string[];
+  createdAt: string;
+  updatedAt: string;
+}
+
+export interface ImportRuleChange {
+  conversationId: string;
+  title: string;
+  fromFolderId: string | null;
+  toFolderId: string | null;
+  addedTags: string[];
+  ruleIds: string[];
+}
+
+export interface ApplyImportRulesReport {
+  dryRun: boolean;
+  scanned: number;
+  moved: number;
+  tagged: number;
+  changes: ImportRuleChange[];
+}
+
+export interface DownloadQueueStatus {
+  paused: boolean;
+  queued: number;
+  running: number;
+  failed: number;
+  nextAttemptAt: string | null;
+}
+
+export interface Tag {
+  id: string;
+  name: string;
+  color: string | null;
+  conversationCount: number;
 }
 
 export type { Folder, Message };
+
+export interface OrphanAsset {
+  path: string;
+  sizeBytes: number;
+}
+
+export interface AssetGcReport {
+  dryRun: boolean;
+  orphanRows: number;
+  orphanFiles: OrphanAsset[];
+  freedBytes: number;
+  removedRows: number;
+  removedFiles: number;
+}
+
+export interface StorageBucket {
+  key: string;
+  files: number;
+  bytes: number;
+  attachments: number;
+}
+
+export interface StorageReport {
+  fileCount: number;
+  totalBytes: number;
+  bySource: StorageBucket[];
+  byFolder: StorageBucket[];
+  byKind: StorageBucket[];
+  orphanFiles: number;
+  orphanBytes: number;
+}
+
+export interface AssetIssue {
+  attachmentId: string;
+  conversationId: string;
+  originalUrl: string;
+  localPath: string;
+  problem: "missing" | "corrupt" | "html";
+  resolution: "restored" | "requeued" | "unrecoverable" | null;
+}
+
+export interface AssetVerifyReport {
+  checkedAt: string;
+  repair: boolean;
+  checked: number;
+  healthy: number;
+  missing: number;
+  corrupt: number;
+  restored: number;
+  requeued: number;
+  unrecoverable: number;
+  issues: AssetIssue[];
+}
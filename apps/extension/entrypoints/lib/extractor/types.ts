@@ -23,4 +23,5 @@ export interface CapturePayload {
   turns: CaptureTurn[];
   capturedAt: string;
   version: string;
+  tags?: string[];
 }
@@ -11,6 +11,20 @@ export interface Folder {
   sortOrder: number;
   createdAt: string;
   updatedAt: string;
+  conversationCount?: number;
+  /** Set for smart folders. */
+  filter?: SmartFolderFilter | null;
+}
+
+export interface SmartFolderFilter {
+  sources?: string[];
+  tagIds?: string[];
+  tagMatch?: "any" | "all" | null;
+  after?: string | null;
+  before?: string | null;
+  withinDays?: number | null;
+  query?: string | null;
+  hasAttachments?: boolean | null;
 }
 
 export interface Conversation {
@@ -24,6 +38,9 @@ export interface Conversation {
   updatedAt: string;
   fingerprint: string;
   metaJson: string;
+  deletedAt: string | null;
+  importedAt: string | null;
+  capturedAt: string | null;
 }
 
 export interface Message {
@@ -36,6 +53,9 @@ export interface Message {
   model: string | null;
   timestamp: string | null;
   tokenCount: number | null;
+  parentId: string | null;
+  branchIndex: number;
+  onActivePath: boolean;
 }
 
 export interface AttachmentInput {
@@ -68,6 +88,9 @@ export interface NormalizedTurn {
   model?: string | null;
   timestamp?: string | null;
   tokenCount?: number | null;
+  id?: string | null;
+  parentId?: string | null;
+  branchIndex?: number | null;
 }
 
 export interface NormalizedConversation {
@@ -77,8 +100,11 @@ export interface NormalizedConversation {
   summary?: string | null;
   createdAt?: string | null;
   updatedAt?: string | null;
+  capturedAt?: string | null;
+  currentTurnId?: string | null;
   turns: NormalizedTurn[];
   meta?: Record<string, unknown>;
+  tags?: string[];
 }
 
 export interface LiveCaptureRequest {
@@ -88,6 +114,7 @@ export interface LiveCaptureRequest {
   turns: NormalizedTurn[];
   capturedAt: string;
   version: string;
+  tags?: string[];
 }
 
 export interface ImportSummary {
@@ -8,6 +8,7 @@ interface BuildImportedConversationInput {
   updatedAt: string | null;
   turns: NormalizedTurn[];
   importedFrom: string;
+  currentTurnId?: string | null;
   meta?: Record<string, unknown>;
 }
 
@@ -18,6 +19,7 @@ export function buildImportedConversation(input: BuildImportedConversationInput)
     title: input.title,
     createdAt: input.createdAt,
     updatedAt: input.updatedAt,
+    currentTurnId: input.currentTurnId ?? null,
     turns: input.turns,
     meta: {
       importedFrom: input.importedFrom,
@@ -26,20 +26,62 @@ interface ChatGptConversation {
   title?: string;
   create_time?: number;
   update_time?: number;
+  current_node?: string | null;
   mapping?: Record<string, ChatGptNode>;
 }
 
-function flattenMapping(mapping: Record<string, ChatGptNode> = {}): NormalizedTurn[] {
-  const nodes = Object.values(mapping)
-    .map((node) => ({
-      role: normalizeRole(node.message?.author?.role),
-      contentMarkdown: toText(node.message?.content?.parts ?? node.message?.content?.text ?? ""),
-      timestamp: toIsoString(node.message?.create_time)
-    }))
-    .filter((turn) => turn.contentMarkdown.trim().length > 0)
-    .filter(nonEmpty);
+interface ChatGptTree {
+  turns: NormalizedTurn[];
+  currentTurnId: string | null;
+}
+
+// Walks the mapping from its roots so every turn follows its parent. Nodes
+// without visible content (system/root nodes) are skipped and their children
+// re-parented to the nearest emitted ancestor, keeping regenerations and
+// edits as sibling branches instead of a flattened duplicate list.
+function flattenMapping(mapping: Record<string, ChatGptNode> = {}, currentNode?: string | null): ChatGptTree {
+  const turns: NormalizedTurn[] = [];
+  const emittedAncestor = new Map<string, string | null>();
+  const roots = Object.entries(mapping)
+    .filter(([, node]) => !node.parent || !mapping[node.parent])
+    .map(([id]) => id);
+  const stack: Array<{ id: string; parentTurnId: string | null }> = roots
+    .reverse()
+    .map((id) => ({ id, parentTurnId: null }));
+
+  while (stack.length > 0) {
+    const { id, parentTurnId } = stack.pop()!;
+    if (emittedAncestor.has(id)) {
+      continue;
+    }
+
+    const node = mapping[id];
+    const contentMarkdown = toText(node?.message?.content?.parts ?? node?.message?.content?.text ?? "");
+    let ownTurnId = parentTurnId;
+    if (contentMarkdown.trim().length > 0) {
+      turns.push({
+        id,
+        parentId: parentTurnId,
+        role: normalizeRole(node?.message?.author?.role),
+        contentMarkdown,
+        timestamp: toIsoString(node?.message?.create_time)
+      });
+      ownTurnId = id;
+    }
+    emittedAncestor.set(id, ownTurnId);
 
-  return nodes;
+    const children = node?.children ?? [];
+    for (let index = children.length - 1; index >= 0; index -= 1) {
+      if (mapping[children[index]]) {
+        stack.push({ id: children[index], parentTurnId: ownTurnId });
+      }
+    }
+  }
+
+  return {
+    turns,
+    currentTurnId: currentNode ? emittedAncestor.get(currentNode) ?? null : null
+  };
 }
 
 export const chatGptParser: Parser = {
@@ -69,7 +111,7 @@ export const chatGptParser: Parser = {
     return parsed
       .map((item) => {
         const conv = item as ChatGptConversation;
-        const turns = flattenMapping(conv.mapping);
+        const { turns, currentTurnId } = flattenMapping(conv.mapping, conv.current_node);
 
         if (turns.length === 0) {
           return null;
@@ -82,7 +124,8 @@ export const chatGptParser: Parser = {
           createdAt: toIsoString(conv.create_time),
           updatedAt: toIsoString(conv.update_time),
           turns,
-          importedFrom: payload.filename
+          importedFrom: payload.filename,
+          currentTurnId
         });
       })
       .filter(nonEmpty);
@@ -29,6 +29,7 @@ export function liveCaptureToConversation(request: LiveCaptureRequest): Normaliz
     title: request.title,
     createdAt: request.capturedAt,
     updatedAt: request.capturedAt,
+    capturedAt: request.capturedAt,
     turns,
     meta: {
       pageUrl: request.pageUrl,
@@ -0,0 +1,34 @@
+import fs from "node:fs";
+import path from "node:path";
+import { Readable } from "node:stream";
+import { pipeline } from "node:stream/promises";
+
+// The semantic search model bundled with the desktop app; see
+// apps/desktop/src-tauri/src/db/bundled_model.rs.
+const repo = "sentence-transformers/paraphrase-multilingual-MiniLM-L12-v2";
+const files = ["config.json", "tokenizer.json", "model.safetensors"];
+const target = "apps/desktop/src-tauri/resources/embedding-model";
+
+fs.mkdirSync(target, { recursive: true });
+
+for (const name of files) {
+  const dest = path.join(target, name);
+  if (fs.existsSync(dest)) {
+    continue;
+  }
+
+  const url = `https://huggingface.co/${repo}/resolve/main/${name}`;
+  console.log(`Downloading ${url}`);
+  const response = await fetch(url);
+  if (!response.ok || !response.body) {
+    console.error(`Failed to download ${name}: HTTP ${response.status}`);
+    process.exit(1);
+  }
+
+  // Written under a temporary name so an interrupted download is retried.
+  const partial = `${dest}.part`;
+  await pipeline(Readable.fromWeb(response.body), fs.createWriteStream(partial));
+  fs.renameSync(partial, dest);
+}
+
+console.log(`Embedding model ready in ${target}`);
@@ -22,6 +22,25 @@ describe("parser registry", () => {
     expect(result[0]?.turns.length).toBe(2);
   });
 
+  it("keeps chatgpt edits and regenerations as branches", async () => {
+    const text = fs.readFileSync(path.join(root, "chatgpt/branched-chatgpt.json"), "utf8");
+    const result = await parseImportPayload({
+      filename: "conversations.json",
+      mime: "application/json",
+      text
+    });
+
+    expect(result.length).toBe(1);
+    expect(result[0]?.turns.map((turn) => [turn.id, turn.parentId, turn.role])).toEqual([
+      ["u1", null, "user"],
+      ["a1", "u1", "assistant"],
+      ["u1-edited", null, "user"],
+      ["a2", "u1-edited", "assistant"],
+      ["a2-regenerated", "u1-edited", "assistant"]
+    ]);
+    expect(result[0]?.currentTurnId).toBe("a2-regenerated");
+  });
+
   it("parses gemini export json", async () => {
     const text = fs.readFileSync(path.join(root, "gemini/sample-gemini.json"), "utf8");
     const result = await parseImportPayload({